@@ -0,0 +1,123 @@
+# 生成xmldsig测试用的签名样例。
+# 被签名的断言直接按排他XML规范化（exc-c14n）的结果手写，摘要和签名用cryptography独立计算，
+# 不经过被测试的规范化实现。运行：python3 fixtures/xmldsig/generate.py
+import base64
+import datetime
+import hashlib
+import pathlib
+
+from cryptography import x509
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import padding, rsa
+from cryptography.x509.oid import NameOID
+
+OUT = pathlib.Path(__file__).parent
+
+NS_SAML = "urn:oasis:names:tc:SAML:2.0:assertion"
+NS_SAMLP = "urn:oasis:names:tc:SAML:2.0:protocol"
+NS_DS = "http://www.w3.org/2000/09/xmldsig#"
+EXC_C14N = "http://www.w3.org/2001/10/xml-exc-c14n#"
+ENVELOPED = "http://www.w3.org/2000/09/xmldsig#enveloped-signature"
+RSA_SHA256 = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256"
+SHA256 = "http://www.w3.org/2001/04/xmlenc#sha256"
+
+
+def certificate(common_name):
+    key = rsa.generate_private_key(public_exponent=65537, key_size=2048)
+    name = x509.Name([x509.NameAttribute(NameOID.COMMON_NAME, common_name)])
+    now = datetime.datetime(2024, 1, 1, tzinfo=datetime.timezone.utc)
+    cert = (
+        x509.CertificateBuilder()
+        .subject_name(name)
+        .issuer_name(name)
+        .public_key(key.public_key())
+        .serial_number(x509.random_serial_number())
+        .not_valid_before(now)
+        .not_valid_after(now + datetime.timedelta(days=36500))
+        .sign(key, hashes.SHA256())
+    )
+    der = cert.public_bytes(serialization.Encoding.DER)
+    return key, base64.b64encode(der).decode()
+
+
+def assertion_body(name_id, signature=""):
+    # Issuer之后插入签名，规范化时整个Signature元素被去掉
+    return (
+        f'<saml:Issuer>https://idp.example.com</saml:Issuer>{signature}'
+        f'<saml:Subject><saml:NameID>{name_id}</saml:NameID></saml:Subject>'
+    )
+
+
+def assertion(assertion_id, name_id, signature="", declare_namespace=False):
+    xmlns = f' xmlns:saml="{NS_SAML}"' if declare_namespace else ""
+    return (
+        f'<saml:Assertion{xmlns} ID="{assertion_id}" IssueInstant="2024-01-01T00:00:00Z" Version="2.0">'
+        f"{assertion_body(name_id, signature)}</saml:Assertion>"
+    )
+
+
+def signed_info(reference_id, digest, declare_namespace):
+    xmlns = f' xmlns:ds="{NS_DS}"' if declare_namespace else ""
+    return (
+        f"<ds:SignedInfo{xmlns}>"
+        f'<ds:CanonicalizationMethod Algorithm="{EXC_C14N}"></ds:CanonicalizationMethod>'
+        f'<ds:SignatureMethod Algorithm="{RSA_SHA256}"></ds:SignatureMethod>'
+        f'<ds:Reference URI="#{reference_id}"><ds:Transforms>'
+        f'<ds:Transform Algorithm="{ENVELOPED}"></ds:Transform>'
+        f'<ds:Transform Algorithm="{EXC_C14N}"></ds:Transform>'
+        f'</ds:Transforms><ds:DigestMethod Algorithm="{SHA256}"></ds:DigestMethod>'
+        f"<ds:DigestValue>{digest}</ds:DigestValue></ds:Reference></ds:SignedInfo>"
+    )
+
+
+def signature(key, cert, assertion_id, name_id):
+    # 断言的规范化结果：命名空间声明从祖先元素移到被签名元素上，Signature被去掉
+    canonical = assertion(assertion_id, name_id, declare_namespace=True)
+    digest = base64.b64encode(hashlib.sha256(canonical.encode()).digest()).decode()
+    canonical_signed_info = signed_info(assertion_id, digest, declare_namespace=True)
+    value = key.sign(canonical_signed_info.encode(), padding.PKCS1v15(), hashes.SHA256())
+    return (
+        f'<ds:Signature xmlns:ds="{NS_DS}">{signed_info(assertion_id, digest, declare_namespace=False)}'
+        f"<ds:SignatureValue>{base64.b64encode(value).decode()}</ds:SignatureValue>"
+        f"<ds:KeyInfo><ds:X509Data><ds:X509Certificate>{cert}</ds:X509Certificate></ds:X509Data></ds:KeyInfo>"
+        f"</ds:Signature>"
+    )
+
+
+def response(*children):
+    return (
+        f'<samlp:Response xmlns:samlp="{NS_SAMLP}" xmlns:saml="{NS_SAML}" ID="_response" Version="2.0">'
+        f'{"".join(children)}</samlp:Response>\n'
+    )
+
+
+def main():
+    key, cert = certificate("xmldsig fixture idp")
+    _, other_cert = certificate("xmldsig fixture other")
+    sig = signature(key, cert, "_assertion", "alice@example.com")
+    original = assertion("_assertion", "alice@example.com", sig)
+
+    fixtures = {
+        "valid.xml": response(original),
+        # 签名后修改了断言内容
+        "tampered.xml": response(assertion("_assertion", "mallory@example.com", sig)),
+        # 签名包装：伪造的断言带着原签名，原断言被移到Extensions中
+        "wrapped.xml": response(
+            assertion("_evil", "mallory@example.com", sig),
+            f"<samlp:Extensions>{original}</samlp:Extensions>",
+        ),
+        # 签名包装：伪造的断言使用与原断言相同的ID
+        "wrapped_duplicate_id.xml": response(
+            assertion("_assertion", "mallory@example.com", sig),
+            f"<samlp:Extensions>{original}</samlp:Extensions>",
+        ),
+        "unsigned.xml": response(assertion("_assertion", "alice@example.com")),
+    }
+    for name, xml in fixtures.items():
+        (OUT / name).write_text(xml)
+    (OUT / "idp.crt").write_text(cert + "\n")
+    (OUT / "other.crt").write_text(other_cert + "\n")
+
+
+if __name__ == "__main__":
+    main()
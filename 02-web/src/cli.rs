@@ -0,0 +1,108 @@
+use std::path::Path;
+use sqlx::PgPool;
+use tokio::io::AsyncWriteExt;
+use crate::name_pinyin;
+use crate::model::UserStore;
+use crate::model::event_sourced_user::EventSourcedUserStore;
+use crate::model::organization::OrganizationStore;
+use crate::model::user_import::{ImportOptions, UserImportStore, UserImporter, IMPORT_FAILED};
+
+const IMPORT_USAGE: &str = "用法: import-users <文件> [--organization <slug>] [--format csv|ndjson] \
+[--on-duplicate skip|update|fail] [--mapping 字段:列名,...] [--dry-run] [--errors <错误报告.csv>]";
+
+// 运维命令，以 `cargo run -- <命令> [参数]` 执行，完成后退出而不启动服务。
+// 命令在数据库迁移之前执行，因为迁移会重建所有表
+pub async fn run(pool: &PgPool, args: &[String]) -> anyhow::Result<()> {
+    match args[0].as_str() {
+        // 从用户事件流重建快照和users表中的投影
+        "replay-users" => {
+            let report = EventSourcedUserStore::replay(pool).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            Ok(())
+        }
+        // 从CSV或NDJSON文件导入用户
+        "import-users" => import_users(pool, &args[1..]).await,
+        // 修改拼音覆盖词典后，按新词典重新计算已有用户的姓名拼音
+        "refresh-pinyin" => {
+            name_pinyin::init();
+            let count = UserStore::refresh_name_pinyin(pool).await?;
+            println!("已更新 {} 个用户的姓名拼音", count);
+            Ok(())
+        }
+        other => anyhow::bail!("未知的命令: {}", other),
+    }
+}
+
+async fn import_users(pool: &PgPool, args: &[String]) -> anyhow::Result<()> {
+    let mut file = None;
+    let mut organization = None;
+    let mut format = None;
+    let mut on_duplicate = None;
+    let mut mapping = None;
+    let mut dry_run = false;
+    let mut errors_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or_else(|| anyhow::anyhow!("{}缺少参数值\n{}", arg, IMPORT_USAGE));
+        match arg.as_str() {
+            "--organization" => organization = Some(value()?),
+            "--format" => format = Some(value()?),
+            "--on-duplicate" => on_duplicate = Some(value()?),
+            "--mapping" => mapping = Some(value()?),
+            "--errors" => errors_path = Some(value()?),
+            "--dry-run" => dry_run = true,
+            other if !other.starts_with("--") && file.is_none() => file = Some(other.to_string()),
+            other => anyhow::bail!("未知的参数: {}\n{}", other, IMPORT_USAGE),
+        }
+    }
+    let file = file.ok_or_else(|| anyhow::anyhow!("{}", IMPORT_USAGE))?;
+    let path = Path::new(&file);
+
+    // 未指定格式时按扩展名判断
+    let format = format.unwrap_or_else(|| match path.extension().and_then(|extension| extension.to_str()) {
+        Some("ndjson" | "jsonl") => "ndjson".to_string(),
+        _ => "csv".to_string(),
+    });
+    let options = ImportOptions::parse(&format, on_duplicate.as_deref(), dry_run, mapping.as_deref())?;
+    let organization_id = match organization {
+        Some(slug) => OrganizationStore::find_by_slug(pool, &slug)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("组织不存在: {}", slug))?
+            .id,
+        None => OrganizationStore::default_id(pool).await?,
+    };
+
+    let job = UserImporter::create_job(pool, organization_id, None, &options, path).await?;
+
+    // 导入期间每秒输出一次进度
+    let progress_pool = pool.clone();
+    let import_id = job.id;
+    let progress = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            if let Ok(job) = UserImportStore::find_by_id(&progress_pool, organization_id, import_id).await {
+                eprintln!("已处理 {}/{} 行", job.processed_rows, job.total_rows);
+            }
+        }
+    });
+    let job = UserImporter::run(pool, &job, &options, path).await;
+    progress.abort();
+    let job = job?;
+    println!("{}", serde_json::to_string_pretty(&job)?);
+
+    if let Some(errors_path) = errors_path {
+        let mut report = tokio::fs::File::create(&errors_path).await?;
+        let mut chunks = UserImportStore::error_report(pool, organization_id, job.id).await?;
+        while let Some(chunk) = chunks.recv().await {
+            report.write_all(&chunk?).await?;
+        }
+        report.flush().await?;
+    }
+
+    if job.status == IMPORT_FAILED {
+        anyhow::bail!("导入失败: {}", job.error.unwrap_or_default());
+    }
+    Ok(())
+}
@@ -1,14 +1,23 @@
 use std::env;
 use sqlx::postgres::PgPoolOptions;
-use sqlx::{Pool, Postgres};
+use sqlx::{Pool, Postgres, Transaction};
+use uuid::Uuid;
 
 pub type DbPool = Pool<Postgres>;
 
+// 租户事务使用的数据库角色，users表的行级安全策略对该角色生效
+const TENANT_ROLE: &str = "app_tenant";
+
+// 连接池大小，由DATABASE_MAX_CONNECTIONS配置
+fn max_connections() -> u32 {
+    env::var("DATABASE_MAX_CONNECTIONS").ok().and_then(|v| v.parse().ok()).unwrap_or(5)
+}
+
 // 创建数据库连接池
 pub async fn create_pool() -> anyhow::Result<DbPool> {
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let pool = PgPoolOptions::new()
-        .max_connections(5)
+        .max_connections(max_connections())
         .connect(&database_url)
         .await?;
     Ok(pool)
@@ -17,7 +26,25 @@ pub async fn create_pool() -> anyhow::Result<DbPool> {
 // 运行数据库迁移
 pub async fn run_migrations(pool: &DbPool) -> anyhow::Result<()> {
     // 先删除表，确保使用更新后的结构（实际生产环境中应使用ALTER TABLE）
-    sqlx::query("DROP TABLE IF EXISTS users")
+    sqlx::query("DROP TABLE IF EXISTS user_import_errors, user_imports, user_snapshots, user_stream_events, outbox_events, webhook_attempts, webhook_deliveries, webhooks, user_events, user_versions, scim_tokens, invitations, group_members, groups, user_status_transitions, api_keys, saml_assertions, saml_requests, saml_connections, external_login_states, external_identities, identity_providers, oauth_refresh_tokens, oauth_access_tokens, oauth_authorization_codes, oidc_consents, oauth_clients, signing_keys, magic_link_requests, magic_links, webauthn_challenges, passkeys, login_throttles, role_mfa_policies, mfa_challenges, recovery_codes, user_totp, sessions, audit_logs, password_reset_tokens, users, organizations")
+        .execute(pool)
+        .await?;
+
+    // 创建组织表，并写入默认组织
+    sqlx::query(
+        r#"
+        CREATE TABLE organizations (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            slug VARCHAR(63) NOT NULL UNIQUE,
+            name VARCHAR(100) NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("INSERT INTO organizations (slug, name) VALUES ('default', 'Default')")
         .execute(pool)
         .await?;
 
@@ -28,21 +55,69 @@ pub async fn run_migrations(pool: &DbPool) -> anyhow::Result<()> {
             id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
             name VARCHAR(100) NOT NULL,
             email VARCHAR(100) NOT NULL UNIQUE,
-            password VARCHAR(100) NOT NULL,
+            password VARCHAR(255) NOT NULL,
+            role VARCHAR(20) NOT NULL DEFAULT 'user',
+            status VARCHAR(20) NOT NULL DEFAULT 'active',
+            organization_id UUID NOT NULL REFERENCES organizations(id),
+            external_id VARCHAR(255),
+            password_changed_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
             created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
-            updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            -- 用户搜索的全文检索向量，邮箱按@和.拆开后也加入，便于按邮箱的一部分搜索
+            -- 姓名的全拼（按音节以空格分隔）和首字母，由应用在写入姓名时计算
+            name_pinyin TEXT NOT NULL DEFAULT '',
+            name_initials VARCHAR(100) NOT NULL DEFAULT '',
+            search_vector TSVECTOR GENERATED ALWAYS AS (
+                to_tsvector('simple', name || ' ' || email || ' ' || translate(email, '@.', '  '))
+            ) STORED
         )
         "#
     )
     .execute(pool)
     .await?;
 
-    // 创建更新时间的函数
+    sqlx::query("CREATE INDEX users_search_vector_idx ON users USING GIN (search_vector)")
+        .execute(pool)
+        .await?;
+
+    // 相似度搜索依赖pg_trgm扩展，无法启用时搜索退化为全文检索加子串匹配
+    match sqlx::query("CREATE EXTENSION IF NOT EXISTS pg_trgm").execute(pool).await {
+        Ok(_) => {
+            sqlx::query("CREATE INDEX users_name_trgm_idx ON users USING GIN (name gin_trgm_ops)")
+                .execute(pool)
+                .await?;
+            sqlx::query("CREATE INDEX users_email_trgm_idx ON users USING GIN (email gin_trgm_ops)")
+                .execute(pool)
+                .await?;
+            // 拼音匹配使用正则，trigram索引同样可以加速正则查询
+            sqlx::query("CREATE INDEX users_name_pinyin_trgm_idx ON users USING GIN (name_pinyin gin_trgm_ops)")
+                .execute(pool)
+                .await?;
+            sqlx::query("CREATE INDEX users_name_initials_trgm_idx ON users USING GIN (name_initials gin_trgm_ops)")
+                .execute(pool)
+                .await?;
+        }
+        Err(err) => {
+            tracing::warn!("无法启用pg_trgm扩展，用户搜索不使用相似度匹配: {}", err);
+            sqlx::query("CREATE INDEX users_name_initials_idx ON users (name_initials text_pattern_ops)")
+                .execute(pool)
+                .await?;
+        }
+    }
+
+    // 创建更新时间的函数，只修改拼音等派生字段时不更新updated_at；
+    // BEFORE触发器中生成列尚未计算，比较时一并排除
     sqlx::query(
         r#"
         CREATE OR REPLACE FUNCTION update_updated_at()
         RETURNS TRIGGER AS $$
+        DECLARE
+            derived CONSTANT TEXT[] := ARRAY['name_pinyin', 'name_initials', 'search_vector', 'updated_at'];
         BEGIN
+            IF (NEW.name_pinyin, NEW.name_initials) IS DISTINCT FROM (OLD.name_pinyin, OLD.name_initials)
+                AND to_jsonb(NEW) - derived = to_jsonb(OLD) - derived THEN
+                RETURN NEW;
+            END IF;
             NEW.updated_at = CURRENT_TIMESTAMP;
             RETURN NEW;
         END;
@@ -74,5 +149,1099 @@ pub async fn run_migrations(pool: &DbPool) -> anyhow::Result<()> {
     .execute(pool)
     .await?;
 
-    Ok(())
-}
\ No newline at end of file
+    // 创建密码重置令牌表
+    sqlx::query(
+        r#"
+        CREATE TABLE password_reset_tokens (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            token_hash VARCHAR(64) NOT NULL UNIQUE,
+            expires_at TIMESTAMPTZ NOT NULL,
+            used_at TIMESTAMPTZ,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建审计日志表（不设外键，用户删除后仍保留记录）
+    sqlx::query(
+        r#"
+        CREATE TABLE audit_logs (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            actor_id UUID,
+            user_id UUID,
+            action VARCHAR(50) NOT NULL,
+            detail TEXT,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建会话表
+    sqlx::query(
+        r#"
+        CREATE TABLE sessions (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            token_hash VARCHAR(64) NOT NULL UNIQUE,
+            csrf_token VARCHAR(64) NOT NULL,
+            mfa_enrollment_required BOOLEAN NOT NULL DEFAULT FALSE,
+            ip_address VARCHAR(45),
+            user_agent TEXT,
+            expires_at TIMESTAMPTZ NOT NULL,
+            last_seen_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            revoked_at TIMESTAMPTZ,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建两步验证相关的表
+    sqlx::query(
+        r#"
+        CREATE TABLE user_totp (
+            user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+            secret VARCHAR(64) NOT NULL,
+            enabled_at TIMESTAMPTZ,
+            last_used_step BIGINT,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE recovery_codes (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            code_hash VARCHAR(64) NOT NULL,
+            used_at TIMESTAMPTZ,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE mfa_challenges (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            token_hash VARCHAR(64) NOT NULL UNIQUE,
+            attempts INT NOT NULL DEFAULT 0,
+            expires_at TIMESTAMPTZ NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE role_mfa_policies (
+            role VARCHAR(20) PRIMARY KEY,
+            require_totp BOOLEAN NOT NULL DEFAULT FALSE
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建登录限制表，按账号和IP分别计数
+    sqlx::query(
+        r#"
+        CREATE TABLE login_throttles (
+            scope VARCHAR(10) NOT NULL,
+            key VARCHAR(255) NOT NULL,
+            failures INT NOT NULL DEFAULT 0,
+            last_failure_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            locked_until TIMESTAMPTZ,
+            PRIMARY KEY (scope, key)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建通行密钥表
+    sqlx::query(
+        r#"
+        CREATE TABLE passkeys (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            credential_id BYTEA NOT NULL UNIQUE,
+            passkey TEXT NOT NULL,
+            name VARCHAR(100) NOT NULL,
+            sign_count BIGINT NOT NULL DEFAULT 0,
+            last_used_at TIMESTAMPTZ,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建WebAuthn挑战表，保存注册/认证过程中的服务端状态
+    sqlx::query(
+        r#"
+        CREATE TABLE webauthn_challenges (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+            kind VARCHAR(20) NOT NULL,
+            state TEXT NOT NULL,
+            expires_at TIMESTAMPTZ NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建登录链接表
+    sqlx::query(
+        r#"
+        CREATE TABLE magic_links (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            token_hash VARCHAR(64) NOT NULL UNIQUE,
+            nonce_hash VARCHAR(64) NOT NULL,
+            expires_at TIMESTAMPTZ NOT NULL,
+            used_at TIMESTAMPTZ,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建登录链接申请记录表，用于按邮箱和IP限制申请频率，只保留最近1小时
+    sqlx::query(
+        r#"
+        CREATE TABLE magic_link_requests (
+            id BIGSERIAL PRIMARY KEY,
+            email_key VARCHAR(255) NOT NULL,
+            ip VARCHAR(45) NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX magic_link_requests_email_idx ON magic_link_requests (email_key, created_at)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX magic_link_requests_ip_idx ON magic_link_requests (ip, created_at)")
+        .execute(pool)
+        .await?;
+
+    // 创建ID令牌签名密钥表
+    sqlx::query(
+        r#"
+        CREATE TABLE signing_keys (
+            kid VARCHAR(64) PRIMARY KEY,
+            private_key_pem TEXT NOT NULL,
+            modulus TEXT NOT NULL,
+            exponent TEXT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建OAuth客户端表，公开客户端没有密钥；client_credentials令牌只能访问客户端所属的组织
+    sqlx::query(
+        r#"
+        CREATE TABLE oauth_clients (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+            client_id VARCHAR(64) NOT NULL UNIQUE,
+            client_secret_hash VARCHAR(64),
+            name VARCHAR(100) NOT NULL,
+            redirect_uris TEXT[] NOT NULL,
+            scopes TEXT[] NOT NULL,
+            grant_types TEXT[] NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            revoked_at TIMESTAMPTZ
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建用户授权同意表
+    sqlx::query(
+        r#"
+        CREATE TABLE oidc_consents (
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            client_id VARCHAR(64) NOT NULL REFERENCES oauth_clients(client_id) ON DELETE CASCADE,
+            scopes TEXT[] NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (user_id, client_id)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建授权码表
+    sqlx::query(
+        r#"
+        CREATE TABLE oauth_authorization_codes (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            code_hash VARCHAR(64) NOT NULL UNIQUE,
+            client_id VARCHAR(64) NOT NULL REFERENCES oauth_clients(client_id) ON DELETE CASCADE,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            redirect_uri TEXT NOT NULL,
+            scopes TEXT[] NOT NULL,
+            nonce TEXT,
+            code_challenge VARCHAR(128) NOT NULL,
+            auth_time TIMESTAMPTZ NOT NULL,
+            expires_at TIMESTAMPTZ NOT NULL,
+            used_at TIMESTAMPTZ,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建访问令牌表
+    sqlx::query(
+        r#"
+        CREATE TABLE oauth_access_tokens (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            token_hash VARCHAR(64) NOT NULL UNIQUE,
+            client_id VARCHAR(64) NOT NULL REFERENCES oauth_clients(client_id) ON DELETE CASCADE,
+            user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+            authorization_code_id UUID REFERENCES oauth_authorization_codes(id) ON DELETE CASCADE,
+            scopes TEXT[] NOT NULL,
+            expires_at TIMESTAMPTZ NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建刷新令牌表，同一授权码派生的令牌共用authorization_code_id，重复使用时整组吊销
+    sqlx::query(
+        r#"
+        CREATE TABLE oauth_refresh_tokens (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            token_hash VARCHAR(64) NOT NULL UNIQUE,
+            client_id VARCHAR(64) NOT NULL REFERENCES oauth_clients(client_id) ON DELETE CASCADE,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            authorization_code_id UUID NOT NULL REFERENCES oauth_authorization_codes(id) ON DELETE CASCADE,
+            scopes TEXT[] NOT NULL,
+            expires_at TIMESTAMPTZ NOT NULL,
+            used_at TIMESTAMPTZ,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建外部身份提供方表
+    sqlx::query(
+        r#"
+        CREATE TABLE identity_providers (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+            slug VARCHAR(50) NOT NULL UNIQUE,
+            name VARCHAR(100) NOT NULL,
+            issuer TEXT NOT NULL,
+            client_id TEXT NOT NULL,
+            client_secret TEXT,
+            scopes TEXT[] NOT NULL,
+            jit_provisioning BOOLEAN NOT NULL DEFAULT FALSE,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建外部身份绑定表，一个用户可以绑定多个外部身份
+    sqlx::query(
+        r#"
+        CREATE TABLE external_identities (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            provider_id UUID NOT NULL REFERENCES identity_providers(id) ON DELETE CASCADE,
+            subject TEXT NOT NULL,
+            email VARCHAR(100),
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE (provider_id, subject)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建外部登录状态表，保存PKCE校验码和nonce
+    sqlx::query(
+        r#"
+        CREATE TABLE external_login_states (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            state_hash VARCHAR(64) NOT NULL UNIQUE,
+            provider_id UUID NOT NULL REFERENCES identity_providers(id) ON DELETE CASCADE,
+            user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+            code_verifier TEXT NOT NULL,
+            nonce TEXT NOT NULL,
+            expires_at TIMESTAMPTZ NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建SAML连接表，每个租户一个IdP
+    sqlx::query(
+        r#"
+        CREATE TABLE saml_connections (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+            tenant VARCHAR(50) NOT NULL UNIQUE,
+            idp_entity_id TEXT NOT NULL,
+            idp_sso_url TEXT,
+            idp_certificates TEXT[] NOT NULL,
+            email_domains TEXT[] NOT NULL,
+            email_attribute TEXT NOT NULL,
+            name_attribute TEXT NOT NULL,
+            jit_provisioning BOOLEAN NOT NULL DEFAULT FALSE,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建SAML请求表，用于校验InResponseTo
+    sqlx::query(
+        r#"
+        CREATE TABLE saml_requests (
+            id VARCHAR(100) PRIMARY KEY,
+            connection_id UUID NOT NULL REFERENCES saml_connections(id) ON DELETE CASCADE,
+            expires_at TIMESTAMPTZ NOT NULL
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建已使用断言表，用于防重放
+    sqlx::query(
+        r#"
+        CREATE TABLE saml_assertions (
+            connection_id UUID NOT NULL REFERENCES saml_connections(id) ON DELETE CASCADE,
+            assertion_id VARCHAR(255) NOT NULL,
+            expires_at TIMESTAMPTZ NOT NULL,
+            PRIMARY KEY (connection_id, assertion_id)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建API密钥表
+    sqlx::query(
+        r#"
+        CREATE TABLE api_keys (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            name VARCHAR(100) NOT NULL,
+            prefix VARCHAR(16) NOT NULL UNIQUE,
+            key_hash VARCHAR(64) NOT NULL,
+            scopes TEXT[] NOT NULL,
+            allowed_ips TEXT[] NOT NULL DEFAULT '{}',
+            expires_at TIMESTAMPTZ NOT NULL,
+            last_used_at TIMESTAMPTZ,
+            last_used_ip VARCHAR(45),
+            request_count BIGINT NOT NULL DEFAULT 0,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            revoked_at TIMESTAMPTZ
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建账号状态转换记录表
+    sqlx::query(
+        r#"
+        CREATE TABLE user_status_transitions (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            from_status VARCHAR(20) NOT NULL,
+            to_status VARCHAR(20) NOT NULL,
+            actor_id UUID REFERENCES users(id) ON DELETE SET NULL,
+            reason TEXT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建分组表，parent_id指向上级分组，删除上级分组时下级分组移动到顶层
+    sqlx::query(
+        r#"
+        CREATE TABLE groups (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+            parent_id UUID REFERENCES groups(id) ON DELETE SET NULL,
+            name VARCHAR(100) NOT NULL,
+            description TEXT,
+            external_id VARCHAR(255),
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE (organization_id, name),
+            CHECK (parent_id <> id)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX groups_parent_id_idx ON groups (parent_id)")
+        .execute(pool)
+        .await?;
+
+    // 创建分组成员表
+    sqlx::query(
+        r#"
+        CREATE TABLE group_members (
+            group_id UUID NOT NULL REFERENCES groups(id) ON DELETE CASCADE,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (group_id, user_id)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX group_members_user_id_idx ON group_members (user_id)")
+        .execute(pool)
+        .await?;
+
+    // 创建邀请表，接受或撤销后删除对应的行；token_id为当前有效令牌的jti
+    sqlx::query(
+        r#"
+        CREATE TABLE invitations (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+            email VARCHAR(100) NOT NULL,
+            role VARCHAR(20) NOT NULL,
+            group_id UUID REFERENCES groups(id) ON DELETE SET NULL,
+            invited_by UUID REFERENCES users(id) ON DELETE SET NULL,
+            token_id UUID NOT NULL,
+            expires_at TIMESTAMPTZ NOT NULL,
+            sent_count INTEGER NOT NULL DEFAULT 1,
+            last_sent_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE UNIQUE INDEX invitations_email_idx ON invitations (organization_id, lower(email))")
+        .execute(pool)
+        .await?;
+
+    // 创建SCIM令牌表，每个令牌属于一个组织，只保存摘要
+    sqlx::query(
+        r#"
+        CREATE TABLE scim_tokens (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+            name VARCHAR(100) NOT NULL,
+            token_hash VARCHAR(64) NOT NULL UNIQUE,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            last_used_at TIMESTAMPTZ
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建用户版本表，每次创建、修改、删除用户时由触发器在同一事务中写入一行快照；
+    // 不引用users表，用户被删除后仍保留历史
+    sqlx::query(
+        r#"
+        CREATE TABLE user_versions (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL,
+            organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+            version INTEGER NOT NULL,
+            operation VARCHAR(10) NOT NULL,
+            name VARCHAR(100) NOT NULL,
+            email VARCHAR(100) NOT NULL,
+            role VARCHAR(20) NOT NULL,
+            status VARCHAR(20) NOT NULL,
+            external_id VARCHAR(255),
+            password_changed_at TIMESTAMPTZ NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL,
+            valid_from TIMESTAMPTZ NOT NULL,
+            UNIQUE (user_id, version)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 只有记录的字段变化时才生成新版本，updated_at、拼音等派生字段的变化不计入
+    sqlx::query(
+        r#"
+        CREATE OR REPLACE FUNCTION record_user_version()
+        RETURNS TRIGGER AS $$
+        DECLARE
+            snapshot users%ROWTYPE;
+        BEGIN
+            IF TG_OP = 'DELETE' THEN
+                snapshot := OLD;
+            ELSIF TG_OP = 'UPDATE'
+                AND (NEW.name, NEW.email, NEW.role, NEW.status, NEW.external_id, NEW.password_changed_at)
+                    IS NOT DISTINCT FROM
+                    (OLD.name, OLD.email, OLD.role, OLD.status, OLD.external_id, OLD.password_changed_at) THEN
+                RETURN NEW;
+            ELSE
+                snapshot := NEW;
+            END IF;
+
+            INSERT INTO user_versions (user_id, organization_id, version, operation, name, email, role, status,
+                                       external_id, password_changed_at, created_at, valid_from)
+            SELECT snapshot.id, snapshot.organization_id, COALESCE(MAX(version), 0) + 1, lower(TG_OP),
+                   snapshot.name, snapshot.email, snapshot.role, snapshot.status, snapshot.external_id,
+                   snapshot.password_changed_at, snapshot.created_at,
+                   CASE WHEN TG_OP = 'DELETE' THEN CURRENT_TIMESTAMP ELSE snapshot.updated_at END
+            FROM user_versions
+            WHERE user_id = snapshot.id;
+
+            RETURN NULL;
+        END;
+        $$ LANGUAGE plpgsql;
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TRIGGER record_user_version
+        AFTER INSERT OR UPDATE OR DELETE ON users
+        FOR EACH ROW
+        EXECUTE FUNCTION record_user_version()
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建用户变更事件表，自增的id是事件序号，客户端断线后按Last-Event-ID从中补发。
+    // 序号在事务中分配，提交顺序可能与序号不同，tx_id记录写入事件的事务，推送按(tx_id, id)排序，
+    // 只推送比所有未结束事务更早的事务写入的事件，之后不会再出现排在已推送事件之前的事件
+    sqlx::query(
+        r#"
+        CREATE TABLE user_events (
+            id BIGSERIAL PRIMARY KEY,
+            tx_id BIGINT NOT NULL DEFAULT pg_current_xact_id()::text::bigint,
+            organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+            user_id UUID NOT NULL,
+            event_type VARCHAR(20) NOT NULL,
+            name VARCHAR(100) NOT NULL,
+            email VARCHAR(100) NOT NULL,
+            role VARCHAR(20) NOT NULL,
+            status VARCHAR(20) NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX idx_user_events_position ON user_events(tx_id, id)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX idx_user_events_organization ON user_events(organization_id, tx_id, id)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX idx_user_events_created_at ON user_events(created_at)")
+        .execute(pool)
+        .await?;
+
+    // 用户变化时写入事件并通过NOTIFY唤醒所有实例从事件表读取，通知在事务提交后才会送达；
+    // 标记删除按deleted事件处理，只有对外可见的字段变化才生成updated事件
+    sqlx::query(
+        r#"
+        CREATE OR REPLACE FUNCTION record_user_event()
+        RETURNS TRIGGER AS $$
+        DECLARE
+            snapshot users%ROWTYPE;
+            kind VARCHAR(20);
+            event user_events%ROWTYPE;
+        BEGIN
+            IF TG_OP = 'INSERT' THEN
+                snapshot := NEW;
+                kind := 'created';
+            ELSIF TG_OP = 'DELETE' THEN
+                snapshot := OLD;
+                kind := 'deleted';
+            ELSIF (NEW.name, NEW.email, NEW.role, NEW.status)
+                    IS NOT DISTINCT FROM (OLD.name, OLD.email, OLD.role, OLD.status) THEN
+                RETURN NULL;
+            ELSE
+                snapshot := NEW;
+                kind := CASE WHEN NEW.status = 'deleted' THEN 'deleted' ELSE 'updated' END;
+            END IF;
+
+            INSERT INTO user_events (organization_id, user_id, event_type, name, email, role, status)
+            VALUES (snapshot.organization_id, snapshot.id, kind, snapshot.name, snapshot.email, snapshot.role, snapshot.status)
+            RETURNING * INTO event;
+
+            PERFORM pg_notify('user_events', event.id::text);
+            RETURN NULL;
+        END;
+        $$ LANGUAGE plpgsql;
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TRIGGER record_user_event
+        AFTER INSERT OR UPDATE OR DELETE ON users
+        FOR EACH ROW
+        EXECUTE FUNCTION record_user_event()
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建Webhook订阅表，secret用于对推送内容签名，需要保存原文
+    sqlx::query(
+        r#"
+        CREATE TABLE webhooks (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+            url TEXT NOT NULL,
+            event_types TEXT[] NOT NULL,
+            secret VARCHAR(255) NOT NULL,
+            active BOOLEAN NOT NULL DEFAULT TRUE,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建Webhook投递表，每个事件对每个匹配的订阅生成一条投递，status为pending/delivered/dead
+    sqlx::query(
+        r#"
+        CREATE TABLE webhook_deliveries (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            webhook_id UUID NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+            event_id BIGINT NOT NULL,
+            event_type VARCHAR(50) NOT NULL,
+            payload JSONB NOT NULL,
+            status VARCHAR(20) NOT NULL DEFAULT 'pending',
+            attempts INTEGER NOT NULL DEFAULT 0,
+            next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            last_status_code INTEGER,
+            last_latency_ms INTEGER,
+            last_error TEXT,
+            delivered_at TIMESTAMPTZ,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX idx_webhook_deliveries_due ON webhook_deliveries(next_attempt_at) WHERE status = 'pending'")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX idx_webhook_deliveries_webhook ON webhook_deliveries(webhook_id, created_at)")
+        .execute(pool)
+        .await?;
+
+    // 创建Webhook投递尝试记录表，保存每次请求的响应码和耗时
+    sqlx::query(
+        r#"
+        CREATE TABLE webhook_attempts (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            delivery_id UUID NOT NULL REFERENCES webhook_deliveries(id) ON DELETE CASCADE,
+            attempted_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            status_code INTEGER,
+            latency_ms INTEGER NOT NULL,
+            error TEXT
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX idx_webhook_attempts_delivery ON webhook_attempts(delivery_id, attempted_at)")
+        .execute(pool)
+        .await?;
+
+    // 用户事件写入时在同一事务中为匹配的订阅生成投递，事件提交后投递就不会丢失；
+    // 以函数所有者身份执行，租户角色不需要访问Webhook表
+    sqlx::query(
+        r#"
+        CREATE OR REPLACE FUNCTION enqueue_webhook_deliveries()
+        RETURNS TRIGGER
+        SECURITY DEFINER
+        SET search_path = public
+        AS $$
+        BEGIN
+            INSERT INTO webhook_deliveries (webhook_id, event_id, event_type, payload)
+            SELECT w.id, NEW.id, 'user.' || NEW.event_type,
+                   jsonb_build_object(
+                       'event_id', NEW.id,
+                       'type', 'user.' || NEW.event_type,
+                       'created_at', NEW.created_at,
+                       'data', jsonb_build_object(
+                           'id', NEW.user_id,
+                           'name', NEW.name,
+                           'email', NEW.email,
+                           'role', NEW.role,
+                           'status', NEW.status
+                       )
+                   )
+            FROM webhooks w
+            WHERE w.organization_id = NEW.organization_id
+              AND w.active
+              AND 'user.' || NEW.event_type = ANY(w.event_types);
+            RETURN NULL;
+        END;
+        $$ LANGUAGE plpgsql;
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TRIGGER enqueue_webhook_deliveries
+        AFTER INSERT ON user_events
+        FOR EACH ROW
+        EXECUTE FUNCTION enqueue_webhook_deliveries()
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建领域事件发件箱，事件与用户的修改在同一事务中写入，由后台任务发布后标记published_at
+    sqlx::query(
+        r#"
+        CREATE TABLE outbox_events (
+            id BIGSERIAL PRIMARY KEY,
+            organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+            aggregate_type VARCHAR(50) NOT NULL,
+            aggregate_id UUID NOT NULL,
+            event_type VARCHAR(50) NOT NULL,
+            payload JSONB NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            published_at TIMESTAMPTZ,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            last_error TEXT
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE INDEX idx_outbox_events_unpublished ON outbox_events(aggregate_type, aggregate_id, id) WHERE published_at IS NULL",
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX idx_outbox_events_published_at ON outbox_events(published_at) WHERE published_at IS NOT NULL")
+        .execute(pool)
+        .await?;
+
+    // 创建用户事件流，USER_STORE=events时用户的修改以事件的形式只追加写入，
+    // (stream_id, version)唯一，两个事务基于同一版本写入时后提交的一方失败
+    sqlx::query(
+        r#"
+        CREATE TABLE user_stream_events (
+            stream_id UUID NOT NULL,
+            version INTEGER NOT NULL,
+            organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+            event_type VARCHAR(50) NOT NULL,
+            data JSONB NOT NULL,
+            recorded_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (stream_id, version)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 用户聚合的快照，每个事件流只保留最新的一份，重建时从快照之后的事件开始应用
+    sqlx::query(
+        r#"
+        CREATE TABLE user_snapshots (
+            stream_id UUID PRIMARY KEY,
+            organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+            version INTEGER NOT NULL,
+            state JSONB NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建用户导入任务表，status为pending/running/completed/failed，计数在每批处理后更新
+    sqlx::query(
+        r#"
+        CREATE TABLE user_imports (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+            created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+            format VARCHAR(10) NOT NULL,
+            on_duplicate VARCHAR(10) NOT NULL,
+            dry_run BOOLEAN NOT NULL,
+            status VARCHAR(20) NOT NULL DEFAULT 'pending',
+            total_rows BIGINT NOT NULL DEFAULT 0,
+            processed_rows BIGINT NOT NULL DEFAULT 0,
+            created_count BIGINT NOT NULL DEFAULT 0,
+            updated_count BIGINT NOT NULL DEFAULT 0,
+            skipped_count BIGINT NOT NULL DEFAULT 0,
+            error_count BIGINT NOT NULL DEFAULT 0,
+            error TEXT,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            started_at TIMESTAMPTZ,
+            finished_at TIMESTAMPTZ
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 导入时每行的错误，row_number为文件中的行号
+    sqlx::query(
+        r#"
+        CREATE TABLE user_import_errors (
+            import_id UUID NOT NULL REFERENCES user_imports(id) ON DELETE CASCADE,
+            row_number BIGINT NOT NULL,
+            email TEXT,
+            message TEXT NOT NULL,
+            PRIMARY KEY (import_id, row_number)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 创建租户角色，角色是集群级对象，已存在时跳过
+    sqlx::query(&format!(
+        r#"
+        DO $$
+        BEGIN
+            IF NOT EXISTS (SELECT FROM pg_roles WHERE rolname = '{role}') THEN
+                CREATE ROLE {role} NOLOGIN;
+            END IF;
+        END
+        $$
+        "#,
+        role = TENANT_ROLE
+    ))
+    .execute(pool)
+    .await?;
+
+    // 当前连接用户需要是租户角色的成员才能在事务中切换过去
+    sqlx::query(&format!("GRANT {} TO CURRENT_USER", TENANT_ROLE))
+        .execute(pool)
+        .await?;
+
+    sqlx::query(&format!(
+        "GRANT SELECT, INSERT, UPDATE, DELETE ON users, sessions, audit_logs, user_status_transitions, groups, group_members, invitations, user_versions, user_events, outbox_events, user_stream_events, user_snapshots TO {}",
+        TENANT_ROLE
+    ))
+    .execute(pool)
+    .await?;
+
+    // users表按组织隔离：租户角色只能读写app.organization_id指定组织的用户，未设置时看不到任何行
+    sqlx::query("ALTER TABLE users ENABLE ROW LEVEL SECURITY")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE POLICY tenant_isolation ON users
+            USING (organization_id = NULLIF(current_setting('app.organization_id', true), '')::uuid)
+            WITH CHECK (organization_id = NULLIF(current_setting('app.organization_id', true), '')::uuid)
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 分组按组织隔离，成员关系跟随所在分组，两端都必须在当前组织内
+    sqlx::query("ALTER TABLE groups ENABLE ROW LEVEL SECURITY")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE POLICY tenant_isolation ON groups
+            USING (organization_id = NULLIF(current_setting('app.organization_id', true), '')::uuid)
+            WITH CHECK (organization_id = NULLIF(current_setting('app.organization_id', true), '')::uuid)
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("ALTER TABLE group_members ENABLE ROW LEVEL SECURITY")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE POLICY tenant_isolation ON group_members
+            USING (
+                EXISTS (SELECT 1 FROM groups WHERE groups.id = group_id)
+                AND EXISTS (SELECT 1 FROM users WHERE users.id = user_id)
+            )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("ALTER TABLE invitations ENABLE ROW LEVEL SECURITY")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE POLICY tenant_isolation ON invitations
+            USING (organization_id = NULLIF(current_setting('app.organization_id', true), '')::uuid)
+            WITH CHECK (organization_id = NULLIF(current_setting('app.organization_id', true), '')::uuid)
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(&format!("GRANT USAGE ON SEQUENCE user_events_id_seq, outbox_events_id_seq TO {}", TENANT_ROLE))
+        .execute(pool)
+        .await?;
+
+    // 用户版本按组织隔离，触发器以租户角色写入时也受该策略约束
+    sqlx::query("ALTER TABLE user_versions ENABLE ROW LEVEL SECURITY")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE POLICY tenant_isolation ON user_versions
+            USING (organization_id = NULLIF(current_setting('app.organization_id', true), '')::uuid)
+            WITH CHECK (organization_id = NULLIF(current_setting('app.organization_id', true), '')::uuid)
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 用户事件同样按组织隔离
+    sqlx::query("ALTER TABLE user_events ENABLE ROW LEVEL SECURITY")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE POLICY tenant_isolation ON user_events
+            USING (organization_id = NULLIF(current_setting('app.organization_id', true), '')::uuid)
+            WITH CHECK (organization_id = NULLIF(current_setting('app.organization_id', true), '')::uuid)
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 发件箱同样按组织隔离，租户事务只能写入当前组织的事件
+    sqlx::query("ALTER TABLE outbox_events ENABLE ROW LEVEL SECURITY")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE POLICY tenant_isolation ON outbox_events
+            USING (organization_id = NULLIF(current_setting('app.organization_id', true), '')::uuid)
+            WITH CHECK (organization_id = NULLIF(current_setting('app.organization_id', true), '')::uuid)
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // 事件流和快照按组织隔离
+    for table in ["user_stream_events", "user_snapshots"] {
+        sqlx::query(&format!("ALTER TABLE {} ENABLE ROW LEVEL SECURITY", table))
+            .execute(pool)
+            .await?;
+
+        sqlx::query(&format!(
+            r#"
+            CREATE POLICY tenant_isolation ON {}
+                USING (organization_id = NULLIF(current_setting('app.organization_id', true), '')::uuid)
+                WITH CHECK (organization_id = NULLIF(current_setting('app.organization_id', true), '')::uuid)
+            "#,
+            table
+        ))
+        .execute(pool)
+        .await?;
+    }
+
+    // 邮箱在所有组织中唯一，但租户事务看不到其他组织的用户；
+    // 该函数以表所有者身份执行，不受行级安全限制，只返回邮箱是否已被占用（不区分大小写）
+    sqlx::query(
+        r#"
+        CREATE OR REPLACE FUNCTION email_taken(candidate TEXT, excluded UUID) RETURNS BOOLEAN
+        LANGUAGE sql STABLE SECURITY DEFINER SET search_path = public
+        AS $$
+            SELECT EXISTS (SELECT 1 FROM users WHERE lower(email) = lower(candidate) AND id IS DISTINCT FROM excluded)
+        $$
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// 开启租户事务：切换到租户角色并设置当前组织，事务结束后两者都会还原
+pub async fn begin_tenant(pool: &DbPool, organization_id: Uuid) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(&format!("SET LOCAL ROLE {}", TENANT_ROLE))
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("SELECT set_config('app.organization_id', $1, true)")
+        .bind(organization_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(tx)
+}
@@ -0,0 +1,309 @@
+use std::{sync::Arc, time::Duration};
+use sqlx::postgres::PgListener;
+use tokio::sync::{broadcast, mpsc};
+use uuid::Uuid;
+use crate::db::{self, DbPool};
+use crate::model::user_event::{EventPosition, UserEvent, UserEventFilter, UserEventStore};
+
+// 触发器发送通知的频道
+const CHANNEL: &str = "user_events";
+
+// 进程内广播的缓冲，订阅者落后超过该数量时从事件表补发
+const BROADCAST_CAPACITY: usize = 1024;
+
+// 每个订阅者待发送事件的缓冲
+const SUBSCRIBER_CAPACITY: usize = 64;
+
+// 没有通知时定时读取事件表的间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// 监听连接断开后的重连间隔
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+// 用户事件分发中心：每个实例用一个连接LISTEN数据库通知，再广播给本实例的所有订阅者，
+// 因此无论变更发生在哪个实例，连接到任意实例的客户端都能收到
+pub struct UserEventHub {
+    sender: broadcast::Sender<UserEvent>,
+}
+
+pub type SharedUserEventHub = Arc<UserEventHub>;
+
+impl UserEventHub {
+    // 创建分发中心并在后台开始监听
+    pub fn start(pool: DbPool) -> SharedUserEventHub {
+        let (sender, _) = broadcast::channel(BROADCAST_CAPACITY);
+        let hub = Arc::new(UserEventHub { sender });
+        tokio::spawn(hub.clone().listen(pool));
+        hub
+    }
+
+    // 通知只用于唤醒，事件按推送顺序从事件表读取后广播；
+    // 回滚的事务和较早提交的长事务不会发出通知，因此同时定时读取
+    async fn listen(self: Arc<Self>, pool: DbPool) {
+        // 已广播的最后位置，重连后从这里继续读取断线期间的事件
+        let mut position: Option<EventPosition> = None;
+        loop {
+            let mut listener = match PgListener::connect_with(&pool).await {
+                Ok(listener) => listener,
+                Err(err) => {
+                    tracing::error!("连接用户事件监听失败: {}", err);
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+            };
+            if let Err(err) = listener.listen(CHANNEL).await {
+                tracing::error!("监听用户事件失败: {}", err);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+
+            // 先LISTEN再读取，读取之后提交的事件都会再唤醒一次
+            let mut current = match position {
+                Some(position) => position,
+                None => match UserEventStore::head(&pool).await {
+                    Ok(head) => head,
+                    Err(err) => {
+                        tracing::error!("读取用户事件位置失败: {}", err);
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                        continue;
+                    }
+                },
+            };
+
+            let mut poll = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                if let Err(err) = self.broadcast_settled(&pool, &mut current).await {
+                    tracing::error!("读取用户事件失败: {}", err);
+                }
+                position = Some(current);
+
+                tokio::select! {
+                    notification = listener.try_recv() => match notification {
+                        Ok(Some(_)) => {}
+                        Ok(None) => {
+                            tracing::warn!("用户事件监听连接已断开，正在重连");
+                            break;
+                        }
+                        Err(err) => {
+                            tracing::error!("接收用户事件失败: {}", err);
+                            break;
+                        }
+                    },
+                    _ = poll.tick() => {}
+                }
+            }
+        }
+    }
+
+    async fn broadcast_settled(&self, pool: &DbPool, position: &mut EventPosition) -> Result<(), sqlx::Error> {
+        loop {
+            let events = UserEventStore::after(pool, *position).await?;
+            if events.is_empty() {
+                return Ok(());
+            }
+            for event in events {
+                *position = event.position();
+                let _ = self.sender.send(event);
+            }
+        }
+    }
+
+    // 订阅当前组织符合条件的事件。指定last_event_id时先从事件表补发之后的事件，再转发实时事件；
+    // 接收端被丢弃后后台任务随之结束
+    pub fn subscribe(
+        &self,
+        pool: DbPool,
+        organization_id: Uuid,
+        filter: UserEventFilter,
+        last_event_id: Option<i64>,
+    ) -> mpsc::Receiver<UserEvent> {
+        let mut live = self.sender.subscribe();
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_CAPACITY);
+
+        tokio::spawn(async move {
+            let subscriber = Subscriber { pool, organization_id, filter, tx };
+            // 已发送的最后位置，实时事件按位置递增广播，不晚于它的已经发送过
+            let mut position = match subscriber.start_position(last_event_id).await {
+                Ok(position) => position,
+                Err(err) => {
+                    tracing::error!("读取用户事件位置失败: {}", err);
+                    return;
+                }
+            };
+
+            match subscriber.replay(&mut position).await {
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(err) => {
+                    tracing::error!("补发用户事件失败: {}", err);
+                    return;
+                }
+            }
+
+            loop {
+                let event = tokio::select! {
+                    _ = subscriber.tx.closed() => return,
+                    event = live.recv() => event,
+                };
+                match event {
+                    Ok(event) => {
+                        if event.organization_id != subscriber.organization_id
+                            || !subscriber.filter.matches(&event)
+                            || event.position() <= position
+                        {
+                            continue;
+                        }
+                        position = event.position();
+                        if subscriber.tx.send(event).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!(skipped, "用户事件订阅者处理过慢，从事件表补发");
+                        match subscriber.replay(&mut position).await {
+                            Ok(true) => {}
+                            Ok(false) => return,
+                            Err(err) => {
+                                tracing::error!("补发用户事件失败: {}", err);
+                                return;
+                            }
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+
+        rx
+    }
+}
+
+struct Subscriber {
+    pool: DbPool,
+    organization_id: Uuid,
+    filter: UserEventFilter,
+    tx: mpsc::Sender<UserEvent>,
+}
+
+impl Subscriber {
+    // 未指定last_event_id时从当前位置开始，只接收订阅之后的事件
+    async fn start_position(&self, last_event_id: Option<i64>) -> Result<EventPosition, sqlx::Error> {
+        let mut tx = db::begin_tenant(&self.pool, self.organization_id).await?;
+        let position = match last_event_id {
+            Some(last_event_id) => UserEventStore::resume_position(&mut tx, last_event_id).await?,
+            None => UserEventStore::head(&mut *tx).await?,
+        };
+        tx.commit().await?;
+        Ok(position)
+    }
+
+    // 在租户事务中从事件表补发position之后的事件，订阅者已断开时返回false
+    async fn replay(&self, position: &mut EventPosition) -> Result<bool, sqlx::Error> {
+        loop {
+            let mut tx = db::begin_tenant(&self.pool, self.organization_id).await?;
+            let events = UserEventStore::after_filtered(&mut tx, *position, &self.filter).await?;
+            tx.commit().await?;
+            if events.is_empty() {
+                return Ok(true);
+            }
+            for event in events {
+                *position = event.position();
+                if self.tx.send(event).await.is_err() {
+                    return Ok(false);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use sqlx::PgConnection;
+    use tokio::{sync::mpsc, time::timeout};
+    use uuid::Uuid;
+    use crate::model::{user_event::{UserEvent, UserEventFilter}, ROLE_USER};
+    use crate::test_support::*;
+    use super::UserEventHub;
+
+    async fn rename(conn: &mut PgConnection, user_id: Uuid, name: &str) {
+        sqlx::query("UPDATE users SET name = $1 WHERE id = $2")
+            .bind(name)
+            .bind(user_id)
+            .execute(conn)
+            .await
+            .unwrap();
+    }
+
+    async fn next(events: &mut mpsc::Receiver<UserEvent>) -> UserEvent {
+        timeout(Duration::from_secs(5), events.recv()).await.expect("没有收到用户事件").unwrap()
+    }
+
+    // 序号较小的事务晚提交时，之后提交的事件要等它结束后按序号顺序推送，按Last-Event-ID恢复不会遗漏
+    #[tokio::test]
+    async fn events_wait_for_earlier_transactions() {
+        let Some(pool) = pool().await else { return };
+        let (organization_id, _) = create_organization(&pool).await;
+        let first = create_user(&pool, organization_id, ROLE_USER).await;
+        let second = create_user(&pool, organization_id, ROLE_USER).await;
+
+        let hub = UserEventHub::start(pool.clone());
+        let mut events = hub.subscribe(pool.clone(), organization_id, UserEventFilter::default(), None);
+
+        // 等待分发中心开始监听
+        for attempt in 0.. {
+            assert!(attempt < 50, "分发中心没有开始推送");
+            let name = format!("就绪{}", attempt);
+            rename(&mut pool.acquire().await.unwrap(), first.id, &name).await;
+            if let Ok(Some(event)) = timeout(Duration::from_millis(200), events.recv()).await {
+                if event.name == name {
+                    break;
+                }
+            }
+        }
+        while let Ok(Some(_)) = timeout(Duration::from_millis(200), events.recv()).await {}
+
+        let mut slow = pool.begin().await.unwrap();
+        rename(&mut slow, first.id, "慢事务").await;
+        let mut fast = pool.begin().await.unwrap();
+        rename(&mut fast, second.id, "快事务").await;
+        fast.commit().await.unwrap();
+
+        assert!(timeout(Duration::from_millis(1500), events.recv()).await.is_err(), "较早的事务未结束时推送了之后的事件");
+
+        slow.commit().await.unwrap();
+        let earlier = next(&mut events).await;
+        let later = next(&mut events).await;
+        assert_eq!((earlier.name.as_str(), later.name.as_str()), ("慢事务", "快事务"));
+        assert!(earlier.id < later.id);
+
+        let mut resumed = hub.subscribe(pool.clone(), organization_id, UserEventFilter::default(), Some(earlier.id));
+        assert_eq!(next(&mut resumed).await.id, later.id);
+    }
+
+    // Last-Event-ID对应的事件已被清理时，从仍保留的之后的事件开始补发
+    #[tokio::test]
+    async fn resume_after_pruned_event() {
+        let Some(pool) = pool().await else { return };
+        let (organization_id, _) = create_organization(&pool).await;
+        let user = create_user(&pool, organization_id, ROLE_USER).await;
+        rename(&mut pool.acquire().await.unwrap(), user.id, "保留的事件").await;
+
+        let ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM user_events WHERE user_id = $1 ORDER BY id")
+            .bind(user.id)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE user_events SET created_at = CURRENT_TIMESTAMP - INTERVAL '30 days' WHERE id = $1")
+            .bind(ids[0])
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(crate::model::user_event::UserEventStore::prune(&pool, 7).await.unwrap() >= 1);
+
+        let hub = UserEventHub::start(pool.clone());
+        let mut events = hub.subscribe(pool.clone(), organization_id, UserEventFilter::default(), Some(ids[0]));
+        let event = next(&mut events).await;
+        assert_eq!((event.id, event.name.as_str()), (ids[1], "保留的事件"));
+    }
+}
@@ -0,0 +1,124 @@
+use std::{convert::Infallible, net::SocketAddr};
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, Extension, FromRequestParts},
+    http::{header::{AUTHORIZATION, USER_AGENT}, request::Parts, StatusCode},
+};
+use axum_extra::extract::cookie::CookieJar;
+use uuid::Uuid;
+use crate::db::DbPool;
+use crate::model::{session::*, User, UserStore, ROLE_ADMIN};
+
+// 已登录用户
+pub struct AuthUser {
+    pub user: User,
+    pub session_id: Uuid,
+}
+
+// 已登录但可能尚未完成两步验证绑定的用户，只用于绑定相关接口
+pub struct PendingAuthUser(pub AuthUser);
+
+// 已登录的管理员
+pub struct AdminUser(pub AuthUser);
+
+// 从Authorization头中解析Bearer令牌
+pub fn bearer_token(parts: &Parts) -> Option<&str> {
+    parts
+        .headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+// 会话令牌优先从Authorization头读取，否则从会话Cookie读取
+async fn authenticate(parts: &mut Parts) -> Result<(AuthUser, bool), (StatusCode, String)> {
+    let unauthorized = || (StatusCode::UNAUTHORIZED, "未登录或登录已过期".to_string());
+
+    let Extension(pool) = Extension::<DbPool>::from_request_parts(parts, &())
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    let (token, from_cookie) = match bearer_token(parts) {
+        Some(token) => (token.to_string(), false),
+        None => {
+            let jar = CookieJar::from_headers(&parts.headers);
+            let token = jar.get(SESSION_COOKIE).map(|cookie| cookie.value().to_string());
+            (token.ok_or_else(unauthorized)?, true)
+        }
+    };
+
+    let session = SessionStore::find_by_token(&pool, &token)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+        .ok_or_else(unauthorized)?;
+
+    // Cookie会被浏览器自动携带，修改类请求必须同时提供会话的CSRF令牌
+    if from_cookie && !parts.method.is_safe() {
+        let csrf_token = parts.headers.get(CSRF_HEADER).and_then(|value| value.to_str().ok());
+        if csrf_token != Some(session.csrf_token.as_str()) {
+            return Err((StatusCode::FORBIDDEN, "CSRF令牌无效".to_string()));
+        }
+    }
+    let user = UserStore::find_by_id(&pool, session.user_id)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+        .filter(User::is_active)
+        .ok_or_else(unauthorized)?;
+
+    Ok((AuthUser { user, session_id: session.id }, session.mfa_enrollment_required))
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let (auth, mfa_enrollment_required) = authenticate(parts).await?;
+        if mfa_enrollment_required {
+            return Err((StatusCode::FORBIDDEN, "请先启用两步验证".to_string()));
+        }
+        Ok(auth)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for PendingAuthUser {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let (auth, _) = authenticate(parts).await?;
+        Ok(PendingAuthUser(auth))
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AdminUser {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let auth = AuthUser::from_request_parts(parts, state).await?;
+        if auth.user.role != ROLE_ADMIN {
+            return Err((StatusCode::FORBIDDEN, "需要管理员权限".to_string()));
+        }
+        Ok(AdminUser(auth))
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let ip_address = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        Ok(ClientInfo { ip_address, user_agent })
+    }
+}
@@ -1,15 +1,66 @@
 use uuid::Uuid;
-use axum::{extract::{Extension, Json, Path}, http::StatusCode};
-use crate::{model::*, db::DbPool};
+use axum::{extract::{Extension, Json, Path, Query}, http::StatusCode};
+use sqlx::{Postgres, Transaction};
+use crate::{model::*, db::{self, DbPool}};
+use crate::model::{group::GroupStore, organization::Tenant, user_history::{AsOfQuery, UserHistoryStore}};
+
+pub mod api_key;
+pub mod auth;
+pub mod group;
+pub mod identity_provider;
+pub mod invitation;
+pub mod lockout;
+pub mod magic_link;
+pub mod metrics;
+pub mod oidc;
+pub mod organization;
+pub mod passkey;
+pub mod saml;
+pub mod scim;
+pub mod session;
+pub mod two_factor;
+pub mod user_event;
+pub mod user_export;
+pub mod user_history;
+pub mod user_import;
+pub mod user_search;
+pub mod user_status;
+pub mod webhook;
+
+// 转义嵌入HTML页面的文本
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn internal_error(err: sqlx::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+}
+
+// 用户和分组的操作都在当前组织的租户事务中执行，由数据库的行级安全策略保证不会访问其他组织的数据
+async fn begin_tenant(pool: &DbPool, tenant: Tenant) -> Result<Transaction<'static, Postgres>, (StatusCode, String)> {
+    db::begin_tenant(pool, tenant.organization_id).await.map_err(internal_error)
+}
 
 // 创建用户
 pub async fn create_user(
     Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(users): Extension<SharedUserRepository>,
     Json(user_data): Json<CreateUserRequest>,
 ) -> Result<Json<User>, (StatusCode, String)> {
-    match UserStore::create(&pool, &user_data).await {
-        Ok(user) => Ok(Json(user)),
+    let mut tx = begin_tenant(&pool, tenant).await?;
+    match users.create(&mut tx, tenant.organization_id, &user_data).await {
+        Ok(user) => {
+            if !user_data.group_ids.is_empty() {
+                GroupStore::set_user_groups(&mut tx, user.id, &user_data.group_ids)
+                    .await
+                    .map_err(group::error_response)?;
+            }
+            tx.commit().await.map_err(internal_error)?;
+            Ok(Json(user))
+        }
         Err(UserError::EmailExists) => Err((StatusCode::CONFLICT, "邮箱已存在".to_string())),
+        Err(UserError::InvalidRole) => Err((StatusCode::BAD_REQUEST, "无效的角色".to_string())),
         Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
     }
 }
@@ -17,9 +68,13 @@ pub async fn create_user(
 // 获取所有用户
 pub async fn get_all_users(
     Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Query(query): Query<UserListQuery>,
 ) -> Result<Json<Vec<User>>, (StatusCode, String)> {
-    match UserStore::find_all(&pool).await {
+    let mut tx = begin_tenant(&pool, tenant).await?;
+    match UserStore::find_all(&mut *tx, &query).await {
         Ok(users) => Ok(Json(users)),
+        Err(UserError::InvalidStatus) => Err((StatusCode::BAD_REQUEST, "无效的账号状态".to_string())),
         Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
     }
 }
@@ -27,9 +82,20 @@ pub async fn get_all_users(
 // 获取单个用户
 pub async fn get_user(
     Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
     Path(user_id): Path<Uuid>,
+    Query(query): Query<AsOfQuery>,
 ) -> Result<Json<User>, (StatusCode, String)> {
-    match UserStore::find_by_id(&pool, user_id).await {
+    let mut tx = begin_tenant(&pool, tenant).await?;
+    // 指定as_of时从历史版本中读取当时的状态
+    if let Some(at) = query.as_of {
+        return match UserHistoryStore::as_of(&mut tx, user_id, at).await {
+            Ok(Some(user)) => Ok(Json(user)),
+            Ok(None) => Err((StatusCode::NOT_FOUND, "用户不存在".to_string())),
+            Err(err) => Err(internal_error(err)),
+        };
+    }
+    match UserStore::find_by_id(&mut *tx, user_id).await {
         Ok(Some(user)) => Ok(Json(user)),
         Ok(None) => Err((StatusCode::NOT_FOUND, "用户不存在".to_string())),
         Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
@@ -39,13 +105,26 @@ pub async fn get_user(
 // 更新用户
 pub async fn update_user(
     Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(users): Extension<SharedUserRepository>,
     Path(user_id): Path<Uuid>,
     Json(update_data): Json<UpdateUserRequest>,
 ) -> Result<Json<User>, (StatusCode, String)> {
-    match UserStore::update(&pool, user_id, &update_data).await {
-        Ok(user) => Ok(Json(user)),
+    let mut tx = begin_tenant(&pool, tenant).await?;
+    match users.update(&mut tx, user_id, &update_data).await {
+        Ok(user) => {
+            if let Some(group_ids) = &update_data.group_ids {
+                GroupStore::set_user_groups(&mut tx, user.id, group_ids)
+                    .await
+                    .map_err(group::error_response)?;
+            }
+            tx.commit().await.map_err(internal_error)?;
+            Ok(Json(user))
+        }
         Err(UserError::NotFound) => Err((StatusCode::NOT_FOUND, "用户不存在".to_string())),
         Err(UserError::EmailExists) => Err((StatusCode::CONFLICT, "邮箱已存在".to_string())),
+        Err(UserError::InvalidRole) => Err((StatusCode::BAD_REQUEST, "无效的角色".to_string())),
+        Err(UserError::VersionConflict) => Err((StatusCode::CONFLICT, "用户已被同时修改，请重试".to_string())),
         Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
     }
 }
@@ -53,11 +132,18 @@ pub async fn update_user(
 // 删除用户
 pub async fn delete_user(
     Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(users): Extension<SharedUserRepository>,
     Path(user_id): Path<Uuid>,
 ) -> Result<StatusCode, (StatusCode, String)> {
-    match UserStore::delete(&pool, user_id).await {
-        Ok(_) => Ok(StatusCode::NO_CONTENT),
+    let mut tx = begin_tenant(&pool, tenant).await?;
+    match users.delete(&mut tx, user_id).await {
+        Ok(_) => {
+            tx.commit().await.map_err(internal_error)?;
+            Ok(StatusCode::NO_CONTENT)
+        }
         Err(UserError::NotFound) => Err((StatusCode::NOT_FOUND, "用户不存在".to_string())),
+        Err(UserError::VersionConflict) => Err((StatusCode::CONFLICT, "用户已被同时修改，请重试".to_string())),
         Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,54 @@
+use axum::{extract::{Extension, Json, Path}, http::StatusCode};
+use uuid::Uuid;
+use crate::{db::DbPool, extractor::AuthUser};
+use crate::model::api_key::*;
+
+// 中间件中API密钥认证失败时也使用该映射
+pub fn error_response(err: ApiKeyError) -> (StatusCode, String) {
+    match err {
+        ApiKeyError::NotFound => (StatusCode::NOT_FOUND, err.to_string()),
+        ApiKeyError::MissingScope
+        | ApiKeyError::InvalidScope(_)
+        | ApiKeyError::InvalidExpiry
+        | ApiKeyError::InvalidIp(_) => (StatusCode::BAD_REQUEST, err.to_string()),
+        ApiKeyError::InvalidKey => (StatusCode::UNAUTHORIZED, err.to_string()),
+        ApiKeyError::IpNotAllowed => (StatusCode::FORBIDDEN, err.to_string()),
+        ApiKeyError::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+// 获取当前用户的API密钥
+pub async fn list_api_keys(
+    Extension(pool): Extension<DbPool>,
+    auth: AuthUser,
+) -> Result<Json<Vec<ApiKey>>, (StatusCode, String)> {
+    match ApiKeyStore::find_by_user(&pool, auth.user.id).await {
+        Ok(api_keys) => Ok(Json(api_keys)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// 创建API密钥，密钥只返回这一次
+pub async fn create_api_key(
+    Extension(pool): Extension<DbPool>,
+    auth: AuthUser,
+    Json(req): Json<CreateApiKeyRequest>,
+) -> Result<(StatusCode, Json<CreatedApiKeyResponse>), (StatusCode, String)> {
+    match ApiKeyStore::create(&pool, auth.user.id, &req).await {
+        Ok(api_key) => Ok((StatusCode::CREATED, Json(api_key))),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 吊销API密钥
+pub async fn revoke_api_key(
+    Extension(pool): Extension<DbPool>,
+    auth: AuthUser,
+    Path(api_key_id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match ApiKeyStore::revoke(&pool, auth.user.id, api_key_id).await {
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
@@ -0,0 +1,575 @@
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use axum::{
+    extract::{ConnectInfo, Extension, Json, Query},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use crate::{db::DbPool, extractor::PendingAuthUser, handler::escape_html, mailer::{app_base_url, Email, SharedMailer}};
+use crate::metrics::{Metrics, SharedMetrics};
+use crate::model::{User, UserStore, login_throttle::*, password_reset::*, session::*, two_factor::*};
+use crate::password::{hash_password, verify_password};
+
+// 登录接口的错误，被限制时通过Retry-After告知还需等待的秒数
+pub struct LoginError {
+    status: StatusCode,
+    message: String,
+    retry_after: Option<i64>,
+}
+
+impl From<(StatusCode, String)> for LoginError {
+    fn from((status, message): (StatusCode, String)) -> Self {
+        LoginError { status, message, retry_after: None }
+    }
+}
+
+impl LoginError {
+    // 被限制的请求，客户端应在retry_after秒后重试
+    pub fn throttled(status: StatusCode, message: String, retry_after: i64) -> Self {
+        LoginError { status, message, retry_after: Some(retry_after) }
+    }
+}
+
+impl IntoResponse for LoginError {
+    fn into_response(self) -> Response {
+        match self.retry_after {
+            Some(seconds) => (self.status, [(header::RETRY_AFTER, seconds.to_string())], self.message).into_response(),
+            None => (self.status, self.message).into_response(),
+        }
+    }
+}
+
+// 用户不存在时用于校验的哈希，使响应时间与用户存在时一致
+fn dummy_password_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| hash_password("dummy-password").unwrap_or_default())
+}
+
+// 登录
+pub async fn login(
+    Extension(pool): Extension<DbPool>,
+    Extension(metrics): Extension<SharedMetrics>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Json(req): Json<LoginRequest>,
+) -> Result<(CookieJar, Json<LoginResponse>), LoginError> {
+    let ip = addr.ip().to_string();
+
+    // 账号或IP处于延迟/锁定期内时直接拒绝，不论邮箱是否存在响应都相同
+    ensure_not_throttled(&pool, &metrics, &req.email, Some(&ip)).await?;
+
+    let user = UserStore::find_by_email(&pool, &req.email)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    let user = match user {
+        Some(user) if verify_password(&req.password, &user.password) => user,
+        found => {
+            if found.is_none() {
+                verify_password(&req.password, dummy_password_hash());
+            }
+            record_login_failure(&pool, &metrics, &req.email, &ip).await?;
+            return Err((StatusCode::UNAUTHORIZED, "邮箱或密码错误".to_string()).into());
+        }
+    };
+
+    Metrics::inc(&metrics.login_success_total);
+    let response = start_session(&pool, &metrics, &user, &client).await?;
+    let jar = set_session_cookies(&pool, jar, &response).await?;
+
+    Ok((jar, Json(response)))
+}
+
+// 会话Cookie和CSRF Cookie，CSRF Cookie需要被页面脚本读取，不设置HttpOnly
+fn session_cookie(name: &'static str, value: String, http_only: bool) -> Cookie<'static> {
+    Cookie::build((name, value))
+        .path("/")
+        .http_only(http_only)
+        .secure(app_base_url().starts_with("https://"))
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::hours(SESSION_TTL_HOURS))
+        .build()
+}
+
+// 登录成功后写入会话Cookie；请求中已有的旧会话一并注销，防止会话固定
+pub async fn set_session_cookies(
+    pool: &DbPool,
+    jar: CookieJar,
+    response: &LoginResponse,
+) -> Result<CookieJar, (StatusCode, String)> {
+    let (Some(token), Some(csrf_token)) = (&response.token, &response.csrf_token) else {
+        return Ok(jar);
+    };
+
+    if let Some(previous) = jar.get(SESSION_COOKIE) {
+        SessionStore::revoke_by_token(pool, previous.value())
+            .await
+            .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    }
+
+    Ok(jar
+        .add(session_cookie(SESSION_COOKIE, token.clone(), true))
+        .add(session_cookie(CSRF_COOKIE, csrf_token.clone(), false)))
+}
+
+// 账号不是正常状态时拒绝登录
+pub fn ensure_active(user: &User) -> Result<(), (StatusCode, String)> {
+    if !user.is_active() {
+        return Err((StatusCode::FORBIDDEN, "账号已被停用或尚未激活".to_string()));
+    }
+    Ok(())
+}
+
+// 第一因素验证通过后建立会话，已启用两步验证时先返回二次验证令牌。
+// 账号或IP被限制时，任何登录方式都不能建立会话
+pub async fn start_session(
+    pool: &DbPool,
+    metrics: &Metrics,
+    user: &User,
+    client: &ClientInfo,
+) -> Result<LoginResponse, LoginError> {
+    ensure_active(user)?;
+    ensure_not_throttled(pool, metrics, &user.email, client.ip_address.as_deref()).await?;
+
+    let totp_enabled = TwoFactorStore::is_enabled(pool, user.id)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    if totp_enabled {
+        let mfa_token = TwoFactorStore::create_challenge(pool, user.id)
+            .await
+            .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+        return Ok(LoginResponse {
+            mfa_required: true,
+            mfa_token: Some(mfa_token),
+            ..Default::default()
+        });
+    }
+
+    let enrollment_required = mfa_enrollment_required(pool, user).await?;
+    issue_session(pool, user, enrollment_required, client).await
+}
+
+// 角色强制两步验证但用户尚未绑定TOTP时，会话只能用于绑定；所有登录方式按同样的规则计算
+pub async fn mfa_enrollment_required(pool: &DbPool, user: &User) -> Result<bool, (StatusCode, String)> {
+    let internal_error = |err: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+
+    if TwoFactorStore::is_enabled(pool, user.id).await.map_err(internal_error)? {
+        return Ok(false);
+    }
+    TwoFactorStore::role_requires_totp(pool, &user.role).await.map_err(internal_error)
+}
+
+// 全部验证通过后建立会话，此时才清除账号的失败计数；
+// 只通过第一因素时清除计数，会使每次重新登录都重置验证码的错误次数
+pub async fn issue_session(
+    pool: &DbPool,
+    user: &User,
+    mfa_enrollment_required: bool,
+    client: &ClientInfo,
+) -> Result<LoginResponse, LoginError> {
+    let response = SessionStore::create(pool, user.id, mfa_enrollment_required, client)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    LoginThrottleStore::record_success(pool, &user.email)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+    Ok(response)
+}
+
+// 账号或IP处于延迟/锁定期内时拒绝本次验证：锁定返回423，递增延迟返回429，均带Retry-After
+pub async fn ensure_not_throttled(
+    pool: &DbPool,
+    metrics: &Metrics,
+    email: &str,
+    ip: Option<&str>,
+) -> Result<(), LoginError> {
+    let retry_after = LoginThrottleStore::check(pool, email, ip)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    let Some(retry_after) = retry_after else {
+        return Ok(());
+    };
+
+    Metrics::inc(&metrics.login_throttled_total);
+    let (status, message) = if retry_after.locked {
+        (StatusCode::LOCKED, format!("登录失败次数过多，已临时锁定，请在{}秒后重试", retry_after.seconds))
+    } else {
+        (StatusCode::TOO_MANY_REQUESTS, format!("登录尝试过于频繁，请在{}秒后重试", retry_after.seconds))
+    };
+    Err(LoginError::throttled(status, message, retry_after.seconds))
+}
+
+// 记录失败登录，触发锁定时输出日志和指标
+pub async fn record_login_failure(
+    pool: &DbPool,
+    metrics: &Metrics,
+    email: &str,
+    ip: &str,
+) -> Result<(), (StatusCode, String)> {
+    Metrics::inc(&metrics.login_failure_total);
+
+    let locked_scopes = LoginThrottleStore::record_failure(pool, email, ip)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    for scope in locked_scopes {
+        if scope == SCOPE_IP {
+            Metrics::inc(&metrics.login_lockout_ip_total);
+            tracing::warn!(ip = %ip, "IP登录失败次数过多，已临时锁定");
+        } else {
+            Metrics::inc(&metrics.login_lockout_account_total);
+            tracing::warn!(email = %account_key(email), "账号登录失败次数过多，已临时锁定");
+        }
+    }
+
+    Ok(())
+}
+
+// 登录二次验证
+pub async fn login_totp(
+    Extension(pool): Extension<DbPool>,
+    Extension(metrics): Extension<SharedMetrics>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Json(req): Json<LoginTotpRequest>,
+) -> Result<(CookieJar, Json<LoginResponse>), LoginError> {
+    let user_id = TwoFactorStore::attempt_challenge(&pool, &req.mfa_token)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+        .ok_or((StatusCode::UNAUTHORIZED, "二次验证已失效，请重新登录".to_string()))?;
+    let user = UserStore::find_by_id(&pool, user_id)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+        .ok_or((StatusCode::UNAUTHORIZED, "二次验证已失效，请重新登录".to_string()))?;
+    ensure_active(&user)?;
+
+    // 验证码错误与密码错误计入同一账号和IP的失败次数，重新登录换取新的二次验证令牌也无法绕过锁定
+    let ip = addr.ip().to_string();
+    ensure_not_throttled(&pool, &metrics, &user.email, Some(&ip)).await?;
+
+    let result = match (&req.code, &req.recovery_code) {
+        (Some(code), _) => TwoFactorStore::verify(&pool, user.id, &user.email, code).await,
+        (None, Some(recovery_code)) => TwoFactorStore::use_recovery_code(&pool, user.id, recovery_code).await,
+        (None, None) => Err(TwoFactorError::InvalidCode),
+    };
+    match result {
+        Ok(()) => {}
+        Err(err @ (TwoFactorError::InvalidCode | TwoFactorError::NotEnabled)) => {
+            record_login_failure(&pool, &metrics, &user.email, &ip).await?;
+            return Err((StatusCode::UNAUTHORIZED, err.to_string()).into());
+        }
+        Err(err) => return Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into()),
+    }
+
+    TwoFactorStore::complete_challenge(&pool, &req.mfa_token)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+    let response = issue_session(&pool, &user, false, &client).await?;
+    let jar = set_session_cookies(&pool, jar, &response).await?;
+
+    Ok((jar, Json(response)))
+}
+
+// 退出登录
+pub async fn logout(
+    Extension(pool): Extension<DbPool>,
+    PendingAuthUser(auth): PendingAuthUser,
+    jar: CookieJar,
+) -> Result<(CookieJar, StatusCode), (StatusCode, String)> {
+    match SessionStore::revoke(&pool, auth.session_id).await {
+        Ok(_) => Ok((
+            jar.remove(Cookie::build(SESSION_COOKIE).path("/"))
+                .remove(Cookie::build(CSRF_COOKIE).path("/")),
+            StatusCode::NO_CONTENT,
+        )),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// 重置密码链接地址，默认为本服务的重置页面；由前端处理时用PASSWORD_RESET_URL指定前端页面，令牌以token参数附加
+fn password_reset_url() -> String {
+    std::env::var("PASSWORD_RESET_URL").unwrap_or_else(|_| format!("{}/auth/password/reset", app_base_url()))
+}
+
+// 忘记密码：无论邮箱是否存在都返回相同的响应
+pub async fn forgot_password(
+    Extension(pool): Extension<DbPool>,
+    Extension(mailer): Extension<SharedMailer>,
+    Json(req): Json<ForgotPasswordRequest>,
+) -> StatusCode {
+    // 在后台查询和发信，避免通过响应时间判断邮箱是否存在
+    tokio::spawn(async move {
+        let user = match UserStore::find_by_email(&pool, &req.email).await {
+            Ok(Some(user)) => user,
+            Ok(None) => return,
+            Err(err) => {
+                tracing::error!("查询用户失败: {}", err);
+                return;
+            }
+        };
+
+        let token = match PasswordResetStore::issue(&pool, user.id).await {
+            Ok(token) => token,
+            Err(err) => {
+                tracing::error!("签发重置令牌失败: {}", err);
+                return;
+            }
+        };
+
+        let email = Email {
+            to: user.email,
+            subject: "重置密码".to_string(),
+            body: format!(
+                "请在{}分钟内打开以下链接重置密码：\n{}?token={}",
+                RESET_TOKEN_TTL_MINUTES,
+                password_reset_url(),
+                token
+            ),
+        };
+        if let Err(err) = mailer.send(email).await {
+            tracing::error!("发送重置邮件失败: {}", err);
+        }
+    });
+
+    StatusCode::ACCEPTED
+}
+
+// 重置链接的页面：令牌有效时显示设置新密码的表单，表单以JSON提交到POST /auth/password/reset
+pub async fn reset_password_page(
+    Extension(pool): Extension<DbPool>,
+    Query(query): Query<ResetTokenQuery>,
+) -> (StatusCode, Html<String>) {
+    let error = match PasswordResetStore::is_valid(&pool, &query.token).await {
+        Ok(true) => None,
+        Ok(false) => Some((StatusCode::BAD_REQUEST, PasswordResetError::InvalidToken.to_string())),
+        Err(err) => Some((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    };
+    if let Some((status, message)) = error {
+        let page = format!(
+            "<!DOCTYPE html>\n<html lang=\"zh-CN\"><head><meta charset=\"utf-8\"><title>重置密码</title></head>\
+             <body><p>{}</p></body></html>",
+            escape_html(&message)
+        );
+        return (status, Html(page));
+    }
+
+    let page = format!(
+        r#"<!DOCTYPE html>
+<html lang="zh-CN">
+<head><meta charset="utf-8"><title>重置密码</title></head>
+<body>
+<h1>重置密码</h1>
+<form id="reset">
+  <input type="hidden" name="token" value="{token}">
+  <p><label>新密码 <input name="password" type="password" minlength="{min_length}" required></label></p>
+  <p><button type="submit">重置密码</button></p>
+</form>
+<p id="result"></p>
+<script>
+document.getElementById("reset").addEventListener("submit", async (event) => {{
+  event.preventDefault();
+  const form = new FormData(event.target);
+  const response = await fetch("/auth/password/reset", {{
+    method: "POST",
+    headers: {{ "Content-Type": "application/json" }},
+    body: JSON.stringify(Object.fromEntries(form)),
+  }});
+  document.getElementById("result").textContent = response.ok ? "密码已重置，请使用新密码登录" : await response.text();
+  if (response.ok) event.target.remove();
+}});
+</script>
+</body>
+</html>
+"#,
+        token = escape_html(&query.token),
+        min_length = MIN_PASSWORD_LENGTH,
+    );
+    (StatusCode::OK, Html(page))
+}
+
+// 使用令牌重置密码
+pub async fn reset_password(
+    Extension(pool): Extension<DbPool>,
+    Json(req): Json<ResetPasswordRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match PasswordResetStore::reset(&pool, &req.token, &req.password).await {
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(err @ (PasswordResetError::InvalidToken | PasswordResetError::WeakPassword)) => {
+            Err((StatusCode::BAD_REQUEST, err.to_string()))
+        }
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+    use crate::mailer::app_base_url;
+    use crate::model::ROLE_USER;
+    use crate::test_support::*;
+
+    // 邮件中的重置链接可以直接在浏览器打开，重置后链接失效，新密码可以登录
+    #[tokio::test]
+    async fn reset_link_opens_reset_page() {
+        let Some(app) = TestApp::new().await else { return };
+        let organization_id = default_organization(&app.pool).await;
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+
+        let response = app
+            .request(Method::POST, "/auth/password/forgot", None, Some(json!({ "email": user.email })))
+            .await;
+        assert_eq!(response.status, StatusCode::ACCEPTED);
+
+        let body = app.mailer.wait_for(&user.email).await.body;
+        let link = body.lines().last().unwrap();
+        let path = link.strip_prefix(&app_base_url()).expect("链接应指向本服务");
+        assert!(path.starts_with("/auth/password/reset?token="), "{}", link);
+        let reset_token = path.split("token=").nth(1).unwrap().to_string();
+
+        let response = app.request(Method::GET, path, None, None).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.header("content-type").unwrap().starts_with("text/html"));
+        let Value::String(page) = &response.body else { panic!("应当返回HTML页面") };
+        assert!(page.contains(&reset_token));
+
+        let response = app
+            .request(Method::POST, "/auth/password/reset", None, Some(json!({ "token": reset_token, "password": "new-password-123" })))
+            .await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT, "{}", response.body);
+
+        let response = app.request(Method::GET, path, None, None).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        let response = app
+            .request(Method::POST, "/auth/login", None, Some(json!({ "email": user.email, "password": "new-password-123" })))
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    }
+
+    // 每次重新输入密码都会得到新的二次验证令牌，验证码的错误次数仍然累计到账号上
+    #[tokio::test]
+    async fn totp_failures_are_throttled_across_challenges() {
+        let Some(app) = TestApp::new().await else { return };
+        let organization_id = default_organization(&app.pool).await;
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+        let token = app.login(&user.email).await;
+        let secret = enable_totp(&app, &token, &user.email).await;
+        let wrong_code = if totp_code(&secret, &user.email, 0) == "000000" { "111111" } else { "000000" };
+
+        let credentials = json!({ "email": user.email, "password": PASSWORD });
+        for _ in 0..3 {
+            let response = app.request(Method::POST, "/auth/login", None, Some(credentials.clone())).await;
+            assert_eq!(response.status, StatusCode::OK);
+            let mfa_token = response.body["mfa_token"].as_str().unwrap().to_string();
+
+            let response = app
+                .request(Method::POST, "/auth/login/2fa", None, Some(json!({ "mfa_token": mfa_token, "code": wrong_code })))
+                .await;
+            assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        }
+
+        let response = app.request(Method::POST, "/auth/login", None, Some(credentials)).await;
+        assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn totp_login_completes_with_valid_code() {
+        let Some(app) = TestApp::new().await else { return };
+        let organization_id = default_organization(&app.pool).await;
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+        let token = app.login(&user.email).await;
+        let secret = enable_totp(&app, &token, &user.email).await;
+
+        let response = app
+            .request(Method::POST, "/auth/login", None, Some(json!({ "email": user.email, "password": PASSWORD })))
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.body.get("token").is_none());
+        let mfa_token = response.body["mfa_token"].as_str().unwrap().to_string();
+
+        // 绑定时已使用当前步长的验证码，登录使用下一个步长的验证码
+        let code = totp_code(&secret, &user.email, 1);
+        let response = app
+            .request(Method::POST, "/auth/login/2fa", None, Some(json!({ "mfa_token": mfa_token, "code": code })))
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert!(response.body["token"].is_string());
+    }
+
+    // 递增延迟期间返回429并带Retry-After
+    #[tokio::test]
+    async fn repeated_failures_are_delayed_with_retry_after() {
+        let Some(app) = TestApp::new().await else { return };
+        let organization_id = default_organization(&app.pool).await;
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+
+        let wrong = json!({ "email": user.email, "password": "wrong-password" });
+        for _ in 0..3 {
+            let response = app.request(Method::POST, "/auth/login", None, Some(wrong.clone())).await;
+            assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        }
+
+        let response = app
+            .request(Method::POST, "/auth/login", None, Some(json!({ "email": user.email, "password": PASSWORD })))
+            .await;
+        assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.header("retry-after"), Some("1"));
+    }
+
+    // 锁定期间返回423，正确的密码和二次验证都不能登录
+    #[tokio::test]
+    async fn locked_account_rejects_every_factor() {
+        let Some(app) = TestApp::new().await else { return };
+        let organization_id = default_organization(&app.pool).await;
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+        let token = app.login(&user.email).await;
+        let secret = enable_totp(&app, &token, &user.email).await;
+
+        let response = app
+            .request(Method::POST, "/auth/login", None, Some(json!({ "email": user.email, "password": PASSWORD })))
+            .await;
+        let mfa_token = response.body["mfa_token"].as_str().unwrap().to_string();
+
+        sqlx::query(r#"
+            INSERT INTO login_throttles (scope, key, failures, last_failure_at, locked_until)
+            VALUES ('account', $1, 0, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP + INTERVAL '15 minutes')
+            "#)
+            .bind(&user.email)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        let response = app
+            .request(Method::POST, "/auth/login", None, Some(json!({ "email": user.email, "password": PASSWORD })))
+            .await;
+        assert_eq!(response.status, StatusCode::LOCKED);
+        let retry_after: i64 = response.header("retry-after").unwrap().parse().unwrap();
+        assert!((890..=900).contains(&retry_after), "Retry-After: {}", retry_after);
+
+        let code = totp_code(&secret, &user.email, 1);
+        let response = app
+            .request(Method::POST, "/auth/login/2fa", None, Some(json!({ "mfa_token": mfa_token, "code": code })))
+            .await;
+        assert_eq!(response.status, StatusCode::LOCKED);
+        assert!(response.header("retry-after").is_some());
+    }
+
+    // 未登录时不能创建用户，更不能指定管理员角色
+    #[tokio::test]
+    async fn anonymous_caller_cannot_create_admin() {
+        let Some(app) = TestApp::new().await else { return };
+        let email = format!("{}@example.com", unique("anon"));
+        let response = app
+            .request(
+                Method::POST,
+                "/users",
+                None,
+                Some(json!({ "name": "x", "email": email, "password": PASSWORD, "role": "admin" })),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    }
+}
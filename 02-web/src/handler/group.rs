@@ -0,0 +1,152 @@
+use axum::{extract::{Extension, Json, Path, Query}, http::StatusCode};
+use uuid::Uuid;
+use crate::db::DbPool;
+use crate::model::{group::*, organization::Tenant, UserStore};
+use super::{begin_tenant, internal_error};
+
+// 创建、更新用户时调整分组也使用该映射
+pub fn error_response(err: GroupError) -> (StatusCode, String) {
+    match err {
+        GroupError::NotFound => (StatusCode::NOT_FOUND, err.to_string()),
+        GroupError::NameExists => (StatusCode::CONFLICT, err.to_string()),
+        GroupError::ParentNotFound
+        | GroupError::Cycle
+        | GroupError::UserNotFound(_) => (StatusCode::BAD_REQUEST, err.to_string()),
+        GroupError::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+// 创建分组
+pub async fn create_group(
+    Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<CreateGroupRequest>,
+) -> Result<(StatusCode, Json<Group>), (StatusCode, String)> {
+    let mut tx = begin_tenant(&pool, tenant).await?;
+    let group = GroupStore::create(&mut tx, tenant.organization_id, &req).await.map_err(error_response)?;
+    tx.commit().await.map_err(internal_error)?;
+    Ok((StatusCode::CREATED, Json(group)))
+}
+
+// 获取所有分组
+pub async fn list_groups(
+    Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+) -> Result<Json<Vec<Group>>, (StatusCode, String)> {
+    let mut tx = begin_tenant(&pool, tenant).await?;
+    match GroupStore::find_all(&mut *tx).await {
+        Ok(groups) => Ok(Json(groups)),
+        Err(err) => Err(internal_error(err)),
+    }
+}
+
+// 获取单个分组
+pub async fn get_group(
+    Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Path(group_id): Path<Uuid>,
+) -> Result<Json<Group>, (StatusCode, String)> {
+    let mut tx = begin_tenant(&pool, tenant).await?;
+    match GroupStore::find_by_id(&mut *tx, group_id).await {
+        Ok(Some(group)) => Ok(Json(group)),
+        Ok(None) => Err(error_response(GroupError::NotFound)),
+        Err(err) => Err(internal_error(err)),
+    }
+}
+
+// 更新分组
+pub async fn update_group(
+    Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Path(group_id): Path<Uuid>,
+    Json(req): Json<UpdateGroupRequest>,
+) -> Result<Json<Group>, (StatusCode, String)> {
+    let mut tx = begin_tenant(&pool, tenant).await?;
+    let group = GroupStore::update(&mut tx, group_id, &req).await.map_err(error_response)?;
+    tx.commit().await.map_err(internal_error)?;
+    Ok(Json(group))
+}
+
+// 删除分组
+pub async fn delete_group(
+    Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Path(group_id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut tx = begin_tenant(&pool, tenant).await?;
+    GroupStore::delete(&mut tx, group_id).await.map_err(error_response)?;
+    tx.commit().await.map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// 获取分组成员，?transitive=true 时包含下级分组的成员
+pub async fn list_members(
+    Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Path(group_id): Path<Uuid>,
+    Query(query): Query<MemberListQuery>,
+) -> Result<Json<Vec<GroupMember>>, (StatusCode, String)> {
+    let mut tx = begin_tenant(&pool, tenant).await?;
+    match GroupStore::members(&mut tx, group_id, query.transitive).await {
+        Ok(members) => Ok(Json(members)),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 批量添加和移除成员，全部成功或全部不生效，返回更新后的直接成员
+pub async fn update_members(
+    Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Path(group_id): Path<Uuid>,
+    Json(req): Json<UpdateMembersRequest>,
+) -> Result<Json<Vec<GroupMember>>, (StatusCode, String)> {
+    let mut tx = begin_tenant(&pool, tenant).await?;
+    GroupStore::update_members(&mut tx, group_id, &req).await.map_err(error_response)?;
+    let members = GroupStore::members(&mut tx, group_id, false).await.map_err(error_response)?;
+    tx.commit().await.map_err(internal_error)?;
+    Ok(Json(members))
+}
+
+// 添加单个成员
+pub async fn add_member(
+    Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Path((group_id, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut tx = begin_tenant(&pool, tenant).await?;
+    let req = UpdateMembersRequest { add: vec![user_id], remove: Vec::new() };
+    GroupStore::update_members(&mut tx, group_id, &req).await.map_err(error_response)?;
+    tx.commit().await.map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// 移除单个成员
+pub async fn remove_member(
+    Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Path((group_id, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut tx = begin_tenant(&pool, tenant).await?;
+    let req = UpdateMembersRequest { add: Vec::new(), remove: vec![user_id] };
+    GroupStore::update_members(&mut tx, group_id, &req).await.map_err(error_response)?;
+    tx.commit().await.map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// 获取用户所属的全部分组，包括通过下级分组间接所属的上级分组
+pub async fn list_user_groups(
+    Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<Vec<UserGroup>>, (StatusCode, String)> {
+    let mut tx = begin_tenant(&pool, tenant).await?;
+    match UserStore::find_by_id(&mut *tx, user_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err((StatusCode::NOT_FOUND, "用户不存在".to_string())),
+        Err(err) => return Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+    match GroupStore::find_by_user(&mut tx, user_id).await {
+        Ok(groups) => Ok(Json(groups)),
+        Err(err) => Err(internal_error(err)),
+    }
+}
@@ -0,0 +1,371 @@
+use axum::{
+    extract::{Extension, Json, Path, Query},
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use uuid::Uuid;
+use crate::{db::DbPool, extractor::{AdminUser, AuthUser}, handler::auth::{set_session_cookies, start_session, LoginError}, mailer::app_base_url};
+use crate::metrics::SharedMetrics;
+use crate::model::{identity_provider::*, session::ClientInfo, UserError};
+
+fn error_response(err: IdentityProviderError) -> (StatusCode, String) {
+    match err {
+        IdentityProviderError::NotFound | IdentityProviderError::IdentityNotFound => {
+            (StatusCode::NOT_FOUND, err.to_string())
+        }
+        IdentityProviderError::SlugExists
+        | IdentityProviderError::IdentityLinked
+        | IdentityProviderError::User(UserError::EmailExists) => (StatusCode::CONFLICT, err.to_string()),
+        IdentityProviderError::InvalidState
+        | IdentityProviderError::InvalidIdToken(_)
+        | IdentityProviderError::EmailNotVerified
+        | IdentityProviderError::NoAccount => (StatusCode::UNAUTHORIZED, err.to_string()),
+        IdentityProviderError::AdminEmailLink => (StatusCode::FORBIDDEN, err.to_string()),
+        IdentityProviderError::Provider(_) => (StatusCode::BAD_GATEWAY, err.to_string()),
+        err => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+// 保存state的Cookie，回调时用于确认是同一个浏览器
+fn state_cookie(state: String) -> Cookie<'static> {
+    Cookie::build((EXTERNAL_LOGIN_STATE_COOKIE, state))
+        .path("/auth/oidc")
+        .http_only(true)
+        .secure(app_base_url().starts_with("https://"))
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::minutes(EXTERNAL_LOGIN_TTL_MINUTES))
+        .build()
+}
+
+// 跳转到外部身份提供方登录
+pub async fn start_login(
+    Extension(pool): Extension<DbPool>,
+    Extension(http): Extension<reqwest::Client>,
+    jar: CookieJar,
+    Path(slug): Path<String>,
+) -> Result<(CookieJar, Redirect), (StatusCode, String)> {
+    let (start, state) = IdentityProviderStore::start_login(&pool, &http, &slug, None)
+        .await
+        .map_err(error_response)?;
+
+    Ok((jar.add(state_cookie(state)), Redirect::to(&start.authorization_url)))
+}
+
+// 为当前用户绑定外部身份，返回需要在浏览器中打开的授权地址
+pub async fn start_link(
+    Extension(pool): Extension<DbPool>,
+    Extension(http): Extension<reqwest::Client>,
+    auth: AuthUser,
+    jar: CookieJar,
+    Path(slug): Path<String>,
+) -> Result<(CookieJar, Json<ExternalLoginStart>), (StatusCode, String)> {
+    let (start, state) = IdentityProviderStore::start_login(&pool, &http, &slug, Some(auth.user.id))
+        .await
+        .map_err(error_response)?;
+
+    Ok((jar.add(state_cookie(state)), Json(start)))
+}
+
+// 外部身份提供方回调
+pub async fn callback(
+    Extension(pool): Extension<DbPool>,
+    Extension(http): Extension<reqwest::Client>,
+    Extension(metrics): Extension<SharedMetrics>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Path(slug): Path<String>,
+    Query(query): Query<ExternalLoginCallback>,
+) -> Result<(CookieJar, Response), LoginError> {
+    let cookie_state = jar
+        .get(EXTERNAL_LOGIN_STATE_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .ok_or_else(|| error_response(IdentityProviderError::InvalidState))?;
+    let jar = jar.remove(Cookie::build(EXTERNAL_LOGIN_STATE_COOKIE).path("/auth/oidc"));
+
+    let outcome = IdentityProviderStore::finish_login(&pool, &http, &slug, &query, &cookie_state)
+        .await
+        .map_err(error_response)?;
+
+    match outcome {
+        ExternalLoginOutcome::Login(user) => {
+            let response = start_session(&pool, &metrics, &user, &client).await?;
+            let jar = set_session_cookies(&pool, jar, &response).await?;
+            Ok((jar, Json(response).into_response()))
+        }
+        ExternalLoginOutcome::Linked(identity) => Ok((jar, (StatusCode::CREATED, Json(identity)).into_response())),
+    }
+}
+
+// 获取当前用户绑定的外部身份
+pub async fn list_identities(
+    Extension(pool): Extension<DbPool>,
+    auth: AuthUser,
+) -> Result<Json<Vec<ExternalIdentity>>, (StatusCode, String)> {
+    match IdentityProviderStore::list_identities(&pool, auth.user.id).await {
+        Ok(identities) => Ok(Json(identities)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// 解除外部身份绑定
+pub async fn unlink_identity(
+    Extension(pool): Extension<DbPool>,
+    auth: AuthUser,
+    Path(identity_id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match IdentityProviderStore::unlink(&pool, auth.user.id, identity_id).await {
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 为管理员所在组织添加外部身份提供方
+pub async fn create_provider(
+    Extension(pool): Extension<DbPool>,
+    Extension(http): Extension<reqwest::Client>,
+    AdminUser(admin): AdminUser,
+    Json(req): Json<CreateIdentityProviderRequest>,
+) -> Result<(StatusCode, Json<IdentityProvider>), (StatusCode, String)> {
+    match IdentityProviderStore::create(&pool, &http, admin.user.organization_id, admin.user.id, &req).await {
+        Ok(provider) => Ok((StatusCode::CREATED, Json(provider))),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 获取当前组织的外部身份提供方
+pub async fn list_providers(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+) -> Result<Json<Vec<IdentityProvider>>, (StatusCode, String)> {
+    match IdentityProviderStore::find_all(&pool, admin.user.organization_id).await {
+        Ok(providers) => Ok(Json(providers)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// 删除外部身份提供方
+pub async fn delete_provider(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Path(slug): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match IdentityProviderStore::delete(&pool, admin.user.organization_id, admin.user.id, &slug).await {
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use axum::{extract::State, http::{Method, StatusCode}, routing::{get, post}, Json, Router};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+    use openssl::rsa::Rsa;
+    use serde_json::{json, Value};
+    use url::{form_urlencoded, Url};
+    use uuid::Uuid;
+    use crate::model::{UserStore, ROLE_ADMIN, ROLE_USER};
+    use crate::test_support::*;
+
+    // 本地模拟的身份提供方，令牌端点返回用测试密钥签名的ID令牌，声明由测试指定
+    #[derive(Clone)]
+    struct MockProvider {
+        issuer: String,
+        private_key_pem: Arc<Vec<u8>>,
+        jwks: Value,
+        claims: Arc<Mutex<Value>>,
+    }
+
+    impl MockProvider {
+        async fn start() -> MockProvider {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let rsa = Rsa::generate(2048).unwrap();
+            let provider = MockProvider {
+                issuer: format!("http://{}", listener.local_addr().unwrap()),
+                private_key_pem: Arc::new(rsa.private_key_to_pem().unwrap()),
+                jwks: json!({ "keys": [{
+                    "kty": "RSA", "use": "sig", "alg": "RS256", "kid": "mock",
+                    "n": URL_SAFE_NO_PAD.encode(rsa.n().to_vec()),
+                    "e": URL_SAFE_NO_PAD.encode(rsa.e().to_vec()),
+                }] }),
+                claims: Arc::new(Mutex::new(Value::Null)),
+            };
+
+            let router = Router::new()
+                .route("/.well-known/openid-configuration", get(mock_discovery))
+                .route("/jwks", get(|State(provider): State<MockProvider>| async move { Json(provider.jwks) }))
+                .route("/token", post(mock_token))
+                .with_state(provider.clone());
+            tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+            provider
+        }
+    }
+
+    async fn mock_discovery(State(provider): State<MockProvider>) -> Json<Value> {
+        Json(json!({
+            "issuer": provider.issuer,
+            "authorization_endpoint": format!("{}/authorize", provider.issuer),
+            "token_endpoint": format!("{}/token", provider.issuer),
+            "jwks_uri": format!("{}/jwks", provider.issuer),
+        }))
+    }
+
+    async fn mock_token(State(provider): State<MockProvider>) -> Json<Value> {
+        let mut claims = provider.claims.lock().unwrap().clone();
+        claims["iss"] = json!(provider.issuer);
+        claims["aud"] = json!("mock-client");
+        claims["exp"] = json!(chrono::Utc::now().timestamp() + 300);
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some("mock".to_string());
+        let key = EncodingKey::from_rsa_pem(&provider.private_key_pem).unwrap();
+        let id_token = jsonwebtoken::encode(&header, &claims, &key).unwrap();
+        Json(json!({ "access_token": "mock-access-token", "token_type": "Bearer", "id_token": id_token }))
+    }
+
+    // 新组织的管理员通过接口添加指向模拟身份提供方的配置，返回组织ID和slug
+    async fn setup(app: &TestApp, provider: &MockProvider) -> (Uuid, String) {
+        let (organization_id, _) = create_organization(&app.pool).await;
+        let admin = create_user(&app.pool, organization_id, ROLE_ADMIN).await;
+        let token = app.login(&admin.email).await;
+
+        let slug = unique("idp");
+        let response = app
+            .request(Method::POST, "/admin/identity-providers", Some(&token), Some(json!({
+                "slug": slug,
+                "name": "模拟身份提供方",
+                "issuer": provider.issuer,
+                "client_id": "mock-client",
+                "client_secret": "mock-secret",
+                "jit_provisioning": true,
+            })))
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+        assert_eq!(response.body["organization_id"], organization_id.to_string());
+
+        (organization_id, slug)
+    }
+
+    // 走完浏览器跳转和回调，身份提供方返回指定的声明
+    async fn external_login(app: &TestApp, provider: &MockProvider, slug: &str, mut claims: Value) -> TestResponse {
+        let response = app.request(Method::GET, &format!("/auth/oidc/{}/login", slug), None, None).await;
+        assert!(response.status.is_redirection(), "{}", response.body);
+        let location = Url::parse(response.header("location").unwrap()).unwrap();
+        let param = |name: &str| location.query_pairs().find(|(key, _)| key == name).unwrap().1.into_owned();
+        let cookie = response.header("set-cookie").unwrap().split(';').next().unwrap().to_string();
+
+        claims["nonce"] = json!(param("nonce"));
+        *provider.claims.lock().unwrap() = claims;
+
+        let state: String = form_urlencoded::byte_serialize(param("state").as_bytes()).collect();
+        let uri = format!("/auth/oidc/{}/callback?code=mock-code&state={}", slug, state);
+        app.send(Method::GET, &uri, None, &[("cookie", &cookie)], None).await
+    }
+
+    async fn linked_user(app: &TestApp, subject: &str) -> Option<Uuid> {
+        sqlx::query_scalar("SELECT user_id FROM external_identities WHERE subject = $1")
+            .bind(subject)
+            .fetch_optional(&app.pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn jit_provisioning_creates_user_in_provider_organization() {
+        let Some(app) = TestApp::new().await else { return };
+        let provider = MockProvider::start().await;
+        let (organization_id, slug) = setup(&app, &provider).await;
+
+        let subject = unique("sub");
+        let email = format!("{}@example.com", unique("jit"));
+        let claims = json!({ "sub": subject, "email": email, "email_verified": true, "name": "外部用户" });
+        let response = external_login(&app, &provider, &slug, claims.clone()).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert!(response.body["token"].is_string());
+
+        let user = UserStore::find_by_email(&app.pool, &email).await.unwrap().unwrap();
+        assert_eq!(user.organization_id, organization_id);
+        assert_eq!(user.role, ROLE_USER);
+        assert_eq!(linked_user(&app, &subject).await, Some(user.id));
+
+        // 已绑定的身份直接登录
+        let response = external_login(&app, &provider, &slug, claims).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    }
+
+    #[tokio::test]
+    async fn email_match_is_limited_to_provider_organization() {
+        let Some(app) = TestApp::new().await else { return };
+        let provider = MockProvider::start().await;
+        let (organization_id, slug) = setup(&app, &provider).await;
+
+        // 其他组织的账号不能通过邮箱被关联，也不会重复创建
+        let other = create_user(&app.pool, default_organization(&app.pool).await, ROLE_USER).await;
+        let subject = unique("sub");
+        let response = external_login(&app, &provider, &slug, json!({
+            "sub": subject, "email": other.email, "email_verified": true,
+        }))
+        .await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED, "{}", response.body);
+        assert_eq!(linked_user(&app, &subject).await, None);
+
+        // 本组织的普通用户按已验证邮箱关联
+        let member = create_user(&app.pool, organization_id, ROLE_USER).await;
+        let subject = unique("sub");
+        let response = external_login(&app, &provider, &slug, json!({
+            "sub": subject, "email": member.email, "email_verified": true,
+        }))
+        .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(linked_user(&app, &subject).await, Some(member.id));
+
+        // 未验证的邮箱不关联
+        let subject = unique("sub");
+        let response = external_login(&app, &provider, &slug, json!({
+            "sub": subject, "email": format!("{}@example.com", unique("unverified")), "email_verified": false,
+        }))
+        .await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(linked_user(&app, &subject).await, None);
+    }
+
+    #[tokio::test]
+    async fn admin_accounts_are_not_linked_by_email() {
+        let Some(app) = TestApp::new().await else { return };
+        let provider = MockProvider::start().await;
+        let (organization_id, slug) = setup(&app, &provider).await;
+
+        let admin = create_user(&app.pool, organization_id, ROLE_ADMIN).await;
+        let subject = unique("sub");
+        let response = external_login(&app, &provider, &slug, json!({
+            "sub": subject, "email": admin.email, "email_verified": true,
+        }))
+        .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN, "{}", response.body);
+        assert_eq!(linked_user(&app, &subject).await, None);
+    }
+
+    // 其他组织的管理员看不到也不能删除该身份提供方
+    #[tokio::test]
+    async fn providers_are_scoped_to_organization() {
+        let Some(app) = TestApp::new().await else { return };
+        let provider = MockProvider::start().await;
+        let (_, slug) = setup(&app, &provider).await;
+
+        let (other_organization, _) = create_organization(&app.pool).await;
+        let other_admin = create_user(&app.pool, other_organization, ROLE_ADMIN).await;
+        let token = app.login(&other_admin.email).await;
+        let response = app.request(Method::GET, "/admin/identity-providers", Some(&token), None).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, json!([]));
+        let response = app
+            .request(Method::DELETE, &format!("/admin/identity-providers/{}", slug), Some(&token), None)
+            .await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+        let response = app.request(Method::GET, &format!("/auth/oidc/{}/login", slug), None, None).await;
+        assert!(response.status.is_redirection(), "{}", response.body);
+    }
+}
@@ -0,0 +1,206 @@
+use axum::{extract::{Extension, Json, Path, Query}, http::StatusCode, response::Html};
+use uuid::Uuid;
+use crate::{db::DbPool, extractor::AdminUser, handler::escape_html, mailer::{app_base_url, Email, SharedMailer}};
+use crate::model::{invitation::*, password_reset::MIN_PASSWORD_LENGTH, User};
+
+fn error_response(err: InvitationError) -> (StatusCode, String) {
+    match err {
+        InvitationError::NotFound => (StatusCode::NOT_FOUND, err.to_string()),
+        InvitationError::InvalidToken => (StatusCode::UNAUTHORIZED, err.to_string()),
+        InvitationError::AlreadyInvited | InvitationError::EmailExists => (StatusCode::CONFLICT, err.to_string()),
+        InvitationError::InvalidRole
+        | InvitationError::GroupNotFound
+        | InvitationError::WeakPassword
+        | InvitationError::User(_) => (StatusCode::BAD_REQUEST, err.to_string()),
+        InvitationError::Signing(_) | InvitationError::Database(_) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+        }
+    }
+}
+
+// 邀请链接地址，默认为本服务的接受页面；由前端处理时用INVITATION_ACCEPT_URL指定前端页面，令牌以token参数附加
+fn invitation_accept_url() -> String {
+    std::env::var("INVITATION_ACCEPT_URL").unwrap_or_else(|_| format!("{}/invitations/accept", app_base_url()))
+}
+
+// 发送邀请邮件，发送失败只记录日志，管理员可以重新发送
+async fn send_invitation(mailer: &SharedMailer, issued: &IssuedInvitation) {
+    let email = Email {
+        to: issued.invitation.email.clone(),
+        subject: "账号邀请".to_string(),
+        body: format!(
+            "你收到了一个账号邀请，请在{}天内打开以下链接设置姓名和密码：\n{}?token={}",
+            INVITATION_TTL_DAYS,
+            invitation_accept_url(),
+            issued.token
+        ),
+    };
+    if let Err(err) = mailer.send(email).await {
+        tracing::error!("发送邀请邮件失败: {}", err);
+    }
+}
+
+// 邀请用户加入管理员所在的组织
+pub async fn create_invitation(
+    Extension(pool): Extension<DbPool>,
+    Extension(mailer): Extension<SharedMailer>,
+    AdminUser(admin): AdminUser,
+    Json(req): Json<CreateInvitationRequest>,
+) -> Result<(StatusCode, Json<Invitation>), (StatusCode, String)> {
+    let issued = InvitationStore::create(&pool, admin.user.organization_id, admin.user.id, &req)
+        .await
+        .map_err(error_response)?;
+    send_invitation(&mailer, &issued).await;
+    Ok((StatusCode::CREATED, Json(issued.invitation)))
+}
+
+// 获取待接受的邀请
+pub async fn list_invitations(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+) -> Result<Json<Vec<Invitation>>, (StatusCode, String)> {
+    match InvitationStore::find_pending(&pool, admin.user.organization_id).await {
+        Ok(invitations) => Ok(Json(invitations)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// 重新发送邀请，之前的链接失效并重新计算有效期
+pub async fn resend_invitation(
+    Extension(pool): Extension<DbPool>,
+    Extension(mailer): Extension<SharedMailer>,
+    AdminUser(admin): AdminUser,
+    Path(invitation_id): Path<Uuid>,
+) -> Result<Json<Invitation>, (StatusCode, String)> {
+    let issued = InvitationStore::resend(&pool, admin.user.organization_id, admin.user.id, invitation_id)
+        .await
+        .map_err(error_response)?;
+    send_invitation(&mailer, &issued).await;
+    Ok(Json(issued.invitation))
+}
+
+// 撤销邀请
+pub async fn revoke_invitation(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Path(invitation_id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match InvitationStore::revoke(&pool, admin.user.organization_id, admin.user.id, invitation_id).await {
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 邀请链接的接受页面：令牌有效时显示设置姓名和密码的表单，表单以JSON提交到POST /invitations/accept
+pub async fn accept_invitation_page(
+    Extension(pool): Extension<DbPool>,
+    Query(query): Query<InvitationTokenQuery>,
+) -> (StatusCode, Html<String>) {
+    let invitation = match InvitationStore::find_by_token(&pool, &query.token).await {
+        Ok(invitation) => invitation,
+        Err(err) => {
+            let (status, message) = error_response(err);
+            let page = format!(
+                "<!DOCTYPE html>\n<html lang=\"zh-CN\"><head><meta charset=\"utf-8\"><title>账号邀请</title></head>\
+                 <body><p>{}</p></body></html>",
+                escape_html(&message)
+            );
+            return (status, Html(page));
+        }
+    };
+
+    let page = format!(
+        r#"<!DOCTYPE html>
+<html lang="zh-CN">
+<head><meta charset="utf-8"><title>账号邀请</title></head>
+<body>
+<h1>接受邀请</h1>
+<p>邀请邮箱：{email}</p>
+<form id="accept">
+  <input type="hidden" name="token" value="{token}">
+  <p><label>姓名 <input name="name" required></label></p>
+  <p><label>密码 <input name="password" type="password" minlength="{min_length}" required></label></p>
+  <p><button type="submit">创建账号</button></p>
+</form>
+<p id="result"></p>
+<script>
+document.getElementById("accept").addEventListener("submit", async (event) => {{
+  event.preventDefault();
+  const form = new FormData(event.target);
+  const response = await fetch("/invitations/accept", {{
+    method: "POST",
+    headers: {{ "Content-Type": "application/json" }},
+    body: JSON.stringify(Object.fromEntries(form)),
+  }});
+  document.getElementById("result").textContent = response.ok ? "账号已创建，请使用邮箱和密码登录" : await response.text();
+  if (response.ok) event.target.remove();
+}});
+</script>
+</body>
+</html>
+"#,
+        email = escape_html(&invitation.email),
+        token = escape_html(&query.token),
+        min_length = MIN_PASSWORD_LENGTH,
+    );
+    (StatusCode::OK, Html(page))
+}
+
+// 接受邀请，创建用户后可以正常登录
+pub async fn accept_invitation(
+    Extension(pool): Extension<DbPool>,
+    Json(req): Json<AcceptInvitationRequest>,
+) -> Result<(StatusCode, Json<User>), (StatusCode, String)> {
+    match InvitationStore::accept(&pool, &req).await {
+        Ok(user) => Ok((StatusCode::CREATED, Json(user))),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+    use crate::mailer::app_base_url;
+    use crate::model::ROLE_ADMIN;
+    use crate::test_support::*;
+
+    // 邮件中的链接可以直接在浏览器打开，接受后链接失效
+    #[tokio::test]
+    async fn invitation_link_opens_accept_page() {
+        let Some(app) = TestApp::new().await else { return };
+        let (organization_id, _) = create_organization(&app.pool).await;
+        let admin = create_user(&app.pool, organization_id, ROLE_ADMIN).await;
+        let token = app.login(&admin.email).await;
+
+        let email = format!("{}@example.com", unique("invitee"));
+        let response = app.request(Method::POST, "/invitations", Some(&token), Some(json!({ "email": email }))).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+
+        let body = app.mailer.wait_for(&email).await.body;
+        let link = body.lines().last().unwrap();
+        let path = link.strip_prefix(&app_base_url()).expect("链接应指向本服务");
+        assert!(path.starts_with("/invitations/accept?token="), "{}", link);
+        let invitation_token = path.split("token=").nth(1).unwrap().to_string();
+
+        let response = app.request(Method::GET, path, None, None).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.header("content-type").unwrap().starts_with("text/html"));
+        let Value::String(page) = &response.body else { panic!("应当返回HTML页面") };
+        assert!(page.contains(&email));
+        assert!(page.contains(&invitation_token));
+
+        let response = app
+            .request(Method::POST, "/invitations/accept", None, Some(json!({
+                "token": invitation_token, "name": "受邀用户", "password": PASSWORD,
+            })))
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+        assert_eq!(response.body["organization_id"], organization_id.to_string());
+
+        let response = app.request(Method::GET, path, None, None).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        let response = app.request(Method::GET, "/invitations/accept?token=invalid", None, None).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    }
+}
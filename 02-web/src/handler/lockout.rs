@@ -0,0 +1,35 @@
+use axum::{extract::{Extension, Json}, http::StatusCode};
+use crate::{db::DbPool, extractor::AdminUser, handler::organization::ensure_platform_admin};
+use crate::model::login_throttle::*;
+
+// 获取当前的登录限制记录；记录按邮箱和IP全局计数，只有平台管理员可以查看和解除
+pub async fn list_lockouts(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+) -> Result<Json<Vec<LoginThrottle>>, (StatusCode, String)> {
+    ensure_platform_admin(&pool, &admin).await?;
+    match LoginThrottleStore::list_active(&pool).await {
+        Ok(throttles) => Ok(Json(throttles)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// 解除账号或IP的锁定
+pub async fn unlock(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Json(req): Json<UnlockRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    ensure_platform_admin(&pool, &admin).await?;
+    let (scope, key) = match (&req.email, &req.ip) {
+        (Some(email), None) => (SCOPE_ACCOUNT, account_key(email)),
+        (None, Some(ip)) => (SCOPE_IP, ip.trim().to_string()),
+        _ => return Err((StatusCode::BAD_REQUEST, "请提供email或ip其中之一".to_string())),
+    };
+
+    match LoginThrottleStore::unlock(&pool, admin.user.id, scope, &key).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err((StatusCode::NOT_FOUND, "没有对应的锁定记录".to_string())),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
@@ -0,0 +1,163 @@
+use std::net::SocketAddr;
+use axum::{extract::{ConnectInfo, Extension, Json, Query}, http::StatusCode};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use crate::{db::DbPool, handler::auth::{set_session_cookies, start_session, LoginError}, mailer::{app_base_url, Email, SharedMailer}};
+use crate::metrics::SharedMetrics;
+use crate::model::{UserStore, magic_link::*, session::{ClientInfo, LoginResponse}};
+use crate::token::generate_token;
+
+// 申请登录链接：无论邮箱是否存在都设置nonce Cookie并返回相同的响应。
+// 同一邮箱有冷却时间和每小时上限，同一IP也有每小时上限，超出时返回429，防止向任意邮箱大量发信
+pub async fn request_magic_link(
+    Extension(pool): Extension<DbPool>,
+    Extension(mailer): Extension<SharedMailer>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    jar: CookieJar,
+    Json(req): Json<MagicLinkRequest>,
+) -> Result<(CookieJar, StatusCode), LoginError> {
+    let retry_after = MagicLinkStore::record_request(&pool, &req.email, &addr.ip().to_string())
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    if let Some(seconds) = retry_after {
+        return Err(LoginError::throttled(
+            StatusCode::TOO_MANY_REQUESTS,
+            format!("申请过于频繁，请在{}秒后重试", seconds),
+            seconds,
+        ));
+    }
+
+    let nonce = generate_token();
+    let cookie = Cookie::build((MAGIC_LINK_NONCE_COOKIE, nonce.clone()))
+        .path("/auth/magic-link")
+        .http_only(true)
+        .secure(app_base_url().starts_with("https://"))
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::minutes(MAGIC_LINK_TTL_MINUTES));
+
+    tokio::spawn(async move {
+        let user = match UserStore::find_by_email(&pool, &req.email).await {
+            Ok(Some(user)) => user,
+            Ok(None) => return,
+            Err(err) => {
+                tracing::error!("查询用户失败: {}", err);
+                return;
+            }
+        };
+
+        let token = match MagicLinkStore::issue(&pool, user.id, &nonce).await {
+            Ok(token) => token,
+            Err(err) => {
+                tracing::error!("签发登录链接失败: {}", err);
+                return;
+            }
+        };
+
+        let email = Email {
+            to: user.email,
+            subject: "登录链接".to_string(),
+            body: format!(
+                "请在{}分钟内使用申请登录的浏览器打开以下链接完成登录：\n{}/auth/magic-link/verify?token={}",
+                MAGIC_LINK_TTL_MINUTES,
+                app_base_url(),
+                token
+            ),
+        };
+        if let Err(err) = mailer.send(email).await {
+            tracing::error!("发送登录链接失败: {}", err);
+        }
+    });
+
+    Ok((jar.add(cookie), StatusCode::ACCEPTED))
+}
+
+// 打开登录链接，必须携带申请时设置的nonce Cookie
+pub async fn verify_magic_link(
+    Extension(pool): Extension<DbPool>,
+    Extension(metrics): Extension<SharedMetrics>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Query(query): Query<MagicLinkQuery>,
+) -> Result<(CookieJar, Json<LoginResponse>), LoginError> {
+    let invalid = || (StatusCode::UNAUTHORIZED, "登录链接无效或已过期".to_string());
+
+    let nonce = jar
+        .get(MAGIC_LINK_NONCE_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .ok_or_else(invalid)?;
+
+    let user_id = MagicLinkStore::redeem(&pool, &query.token, &nonce)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+        .ok_or_else(invalid)?;
+    let user = UserStore::find_by_id(&pool, user_id)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+        .ok_or_else(invalid)?;
+
+    let response = start_session(&pool, &metrics, &user, &client).await?;
+    let jar = jar.remove(Cookie::build(MAGIC_LINK_NONCE_COOKIE).path("/auth/magic-link"));
+    let jar = set_session_cookies(&pool, jar, &response).await?;
+
+    Ok((jar, Json(response)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+    use crate::model::ROLE_USER;
+    use crate::test_support::*;
+
+    #[tokio::test]
+    async fn magic_link_login() {
+        let Some(app) = TestApp::new().await else { return };
+        let organization_id = default_organization(&app.pool).await;
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+
+        let response = app
+            .request(Method::POST, "/auth/magic-link", None, Some(json!({ "email": user.email })))
+            .await;
+        assert_eq!(response.status, StatusCode::ACCEPTED);
+        let nonce = response.header("set-cookie").unwrap().split(';').next().unwrap().to_string();
+        let email = app.mailer.wait_for(&user.email).await;
+        let token = email.body.split("token=").nth(1).unwrap().trim();
+
+        let uri = format!("/auth/magic-link/verify?token={}", token);
+        let response = app.request(Method::GET, &uri, None, None).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+        let response = app.send(Method::GET, &uri, None, &[("cookie", &nonce)], None).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert!(response.body["token"].is_string());
+    }
+
+    // 同一邮箱在冷却时间内不能再次申请，不论邮箱是否存在
+    #[tokio::test]
+    async fn magic_link_requests_have_email_cooldown() {
+        let Some(app) = TestApp::new().await else { return };
+        let body = json!({ "email": format!("{}@example.com", unique("nobody")) });
+
+        let response = app.request(Method::POST, "/auth/magic-link", None, Some(body.clone())).await;
+        assert_eq!(response.status, StatusCode::ACCEPTED);
+        let response = app.request(Method::POST, "/auth/magic-link", None, Some(body)).await;
+        assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: i64 = response.header("retry-after").unwrap().parse().unwrap();
+        assert!((55..=60).contains(&retry_after), "Retry-After: {}", retry_after);
+    }
+
+    // 同一IP每小时最多申请20次，换邮箱也不能继续发信
+    #[tokio::test]
+    async fn magic_link_requests_are_limited_per_ip() {
+        let Some(app) = TestApp::new().await else { return };
+        for _ in 0..20 {
+            let body = json!({ "email": format!("{}@example.com", unique("nobody")) });
+            let response = app.request(Method::POST, "/auth/magic-link", None, Some(body)).await;
+            assert_eq!(response.status, StatusCode::ACCEPTED);
+        }
+
+        let body = json!({ "email": format!("{}@example.com", unique("nobody")) });
+        let response = app.request(Method::POST, "/auth/magic-link", None, Some(body)).await;
+        assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.header("retry-after").is_some());
+    }
+}
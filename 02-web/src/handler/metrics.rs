@@ -0,0 +1,7 @@
+use axum::extract::Extension;
+use crate::metrics::SharedMetrics;
+
+// 输出Prometheus格式的指标
+pub async fn get_metrics(Extension(metrics): Extension<SharedMetrics>) -> String {
+    metrics.render()
+}
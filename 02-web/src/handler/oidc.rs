@@ -0,0 +1,861 @@
+use axum::{
+    extract::{Extension, Form, Json, Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::json;
+use url::Url;
+use crate::{db::DbPool, extractor::{AdminUser, AuthUser}, handler::organization::ensure_platform_admin};
+use crate::model::{User, UserStore, oauth_client::*, oidc::*, signing_key::{JwkSet, SigningKeyStore}};
+
+fn client_error_response(err: OAuthClientError) -> (StatusCode, String) {
+    match err {
+        OAuthClientError::NotFound => (StatusCode::NOT_FOUND, err.to_string()),
+        OAuthClientError::MissingRedirectUri
+        | OAuthClientError::InvalidScope(_)
+        | OAuthClientError::InvalidGrantType(_)
+        | OAuthClientError::PublicClient => (StatusCode::BAD_REQUEST, err.to_string()),
+        OAuthClientError::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+// 令牌端点按RFC 6749返回JSON错误
+fn token_error_response(err: TokenError) -> Response {
+    let (status, description) = match &err {
+        TokenError::InvalidRequest(description) | TokenError::InvalidGrant(description) => {
+            (StatusCode::BAD_REQUEST, description.to_string())
+        }
+        TokenError::InvalidClient => (StatusCode::UNAUTHORIZED, "客户端认证失败".to_string()),
+        TokenError::UnsupportedGrantType => (StatusCode::BAD_REQUEST, "不支持的grant_type".to_string()),
+        TokenError::UnauthorizedClient => (StatusCode::BAD_REQUEST, "客户端无权使用该grant_type".to_string()),
+        TokenError::InvalidScope => (StatusCode::BAD_REQUEST, "客户端无权申请该scope".to_string()),
+        TokenError::Signing(_) | TokenError::Database(_) => {
+            tracing::error!("签发令牌失败: {}", err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": "server_error" }))).into_response();
+        }
+    };
+
+    (
+        status,
+        [(header::CACHE_CONTROL, "no-store")],
+        Json(json!({ "error": err.to_string(), "error_description": description })),
+    )
+        .into_response()
+}
+
+// 携带参数重定向回客户端
+fn redirect_to_client(redirect_uri: &str, state: Option<&str>, params: &[(&str, &str)]) -> Response {
+    let Ok(mut url) = Url::parse(redirect_uri) else {
+        return (StatusCode::BAD_REQUEST, "redirect_uri无效".to_string()).into_response();
+    };
+    {
+        let mut query = url.query_pairs_mut();
+        query.extend_pairs(params);
+        if let Some(state) = state {
+            query.append_pair("state", state);
+        }
+    }
+    Redirect::to(url.as_str()).into_response()
+}
+
+// 校验授权请求：客户端和回调地址无效时直接返回错误，其余错误重定向回客户端
+async fn validate_authorize(
+    pool: &DbPool,
+    req: &AuthorizeRequest,
+) -> Result<(OAuthClient, Vec<String>, String), Response> {
+    let client = OAuthClientStore::find_by_client_id(pool, &req.client_id)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response())?
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, "client_id无效".to_string()).into_response())?;
+    if !client.allows_redirect_uri(&req.redirect_uri) {
+        return Err((StatusCode::BAD_REQUEST, "redirect_uri未注册".to_string()).into_response());
+    }
+
+    let reject = |error: &str, description: &str| {
+        redirect_to_client(
+            &req.redirect_uri,
+            req.state.as_deref(),
+            &[("error", error), ("error_description", description)],
+        )
+    };
+
+    if req.response_type != "code" {
+        return Err(reject("unsupported_response_type", "只支持授权码模式"));
+    }
+
+    let scopes: Vec<String> = req.scope.split_whitespace().map(str::to_string).collect();
+    if !scopes.iter().any(|scope| scope == "openid") {
+        return Err(reject("invalid_scope", "缺少openid"));
+    }
+    if scopes.iter().any(|scope| !client.scopes.contains(scope)) {
+        return Err(reject("invalid_scope", "客户端无权申请该scope"));
+    }
+    // /users接口的scope只签发给client_credentials，不能让用户通过同意页面授予
+    if scopes.iter().any(|scope| API_SCOPES.contains(&scope.as_str())) {
+        return Err(reject("invalid_scope", "授权码模式不能申请服务间调用的scope"));
+    }
+
+    let code_challenge = match (&req.code_challenge, req.code_challenge_method.as_deref()) {
+        (Some(challenge), Some(CODE_CHALLENGE_METHOD)) => challenge.clone(),
+        _ => return Err(reject("invalid_request", "必须使用S256方式的PKCE")),
+    };
+
+    Ok((client, scopes, code_challenge))
+}
+
+// 签发授权码并重定向回客户端
+async fn issue_code_redirect(
+    pool: &DbPool,
+    auth: &AuthUser,
+    req: &AuthorizeRequest,
+    scopes: &[String],
+    code_challenge: &str,
+) -> Result<Response, (StatusCode, String)> {
+    let auth_time = OidcStore::session_auth_time(pool, auth.session_id)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    let code = OidcStore::issue_code(pool, auth.user.id, auth_time, req, scopes, code_challenge)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+    Ok(redirect_to_client(&req.redirect_uri, req.state.as_deref(), &[("code", &code)]))
+}
+
+// 发现文档
+pub async fn discovery() -> Json<DiscoveryDocument> {
+    Json(DiscoveryDocument::new(&OIDC_SCOPES))
+}
+
+// 公钥集合
+pub async fn jwks(
+    Extension(pool): Extension<DbPool>,
+) -> Result<Json<JwkSet>, (StatusCode, String)> {
+    match SigningKeyStore::jwks(&pool).await {
+        Ok(jwks) => Ok(Json(jwks)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// 授权端点：已同意过则直接签发授权码，否则返回需要确认的信息
+pub async fn authorize(
+    Extension(pool): Extension<DbPool>,
+    auth: AuthUser,
+    Query(req): Query<AuthorizeRequest>,
+) -> Result<Response, (StatusCode, String)> {
+    let (client, scopes, code_challenge) = match validate_authorize(&pool, &req).await {
+        Ok(validated) => validated,
+        Err(response) => return Ok(response),
+    };
+
+    let consented = OidcStore::has_consent(&pool, auth.user.id, &client.client_id, &scopes)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    if !consented {
+        return Ok(Json(ConsentRequiredResponse {
+            consent_required: true,
+            client_id: client.client_id,
+            client_name: client.name,
+            scopes,
+        })
+        .into_response());
+    }
+
+    issue_code_redirect(&pool, &auth, &req, &scopes, &code_challenge).await
+}
+
+// 用户同意或拒绝授权
+pub async fn consent(
+    Extension(pool): Extension<DbPool>,
+    auth: AuthUser,
+    Json(req): Json<ConsentRequest>,
+) -> Result<Response, (StatusCode, String)> {
+    let (client, scopes, code_challenge) = match validate_authorize(&pool, &req.authorize).await {
+        Ok(validated) => validated,
+        Err(response) => return Ok(response),
+    };
+
+    if !req.approve {
+        return Ok(redirect_to_client(
+            &req.authorize.redirect_uri,
+            req.authorize.state.as_deref(),
+            &[("error", "access_denied")],
+        ));
+    }
+
+    OidcStore::grant_consent(&pool, auth.user.id, &client.client_id, &scopes)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+    issue_code_redirect(&pool, &auth, &req.authorize, &scopes, &code_challenge).await
+}
+
+// 解析client_secret_basic认证头
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let encoded = headers.get(header::AUTHORIZATION)?.to_str().ok()?.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded).ok()?).ok()?;
+    let (client_id, client_secret) = decoded.split_once(':')?;
+    Some((client_id.to_string(), client_secret.to_string()))
+}
+
+// 令牌端点，支持authorization_code、refresh_token和client_credentials
+pub async fn token(
+    Extension(pool): Extension<DbPool>,
+    headers: HeaderMap,
+    Form(req): Form<TokenRequest>,
+) -> Response {
+    if !GRANT_TYPES.contains(&req.grant_type.as_str()) && req.grant_type != GRANT_REFRESH_TOKEN {
+        return token_error_response(TokenError::UnsupportedGrantType);
+    }
+
+    let (client_id, client_secret) = match basic_credentials(&headers) {
+        Some((client_id, client_secret)) => (client_id, Some(client_secret)),
+        None => match &req.client_id {
+            Some(client_id) => (client_id.clone(), req.client_secret.clone()),
+            None => return token_error_response(TokenError::InvalidClient),
+        },
+    };
+
+    let client = match OAuthClientStore::find_by_client_id(&pool, &client_id).await {
+        Ok(Some(client)) if client.verify_secret(client_secret.as_deref()) => client,
+        Ok(_) => return token_error_response(TokenError::InvalidClient),
+        Err(err) => return token_error_response(err.into()),
+    };
+    if !client.allows_grant_type(&req.grant_type) {
+        return token_error_response(TokenError::UnauthorizedClient);
+    }
+
+    let issued = match req.grant_type.as_str() {
+        GRANT_CLIENT_CREDENTIALS => OidcStore::issue_client_token(&pool, &client, req.scope.as_deref()).await,
+        GRANT_REFRESH_TOKEN => OidcStore::refresh(&pool, &client.client_id, &req).await,
+        _ => OidcStore::exchange_code(&pool, &client.client_id, &req).await,
+    };
+    match issued {
+        Ok(response) => (
+            [(header::CACHE_CONTROL, "no-store")],
+            Json(response),
+        )
+            .into_response(),
+        Err(err) => token_error_response(err),
+    }
+}
+
+// 用户信息端点，使用访问令牌认证
+pub async fn userinfo(
+    Extension(pool): Extension<DbPool>,
+    headers: HeaderMap,
+) -> Result<Json<UserInfo>, (StatusCode, String)> {
+    let unauthorized = || (StatusCode::UNAUTHORIZED, "访问令牌无效或已过期".to_string());
+
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(unauthorized)?;
+    let grant = OidcStore::find_access_token(&pool, token)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+        .ok_or_else(unauthorized)?;
+    let user_id = grant.user_id.ok_or_else(unauthorized)?;
+    let user = UserStore::find_by_id(&pool, user_id)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+        .filter(User::is_active)
+        .ok_or_else(unauthorized)?;
+
+    Ok(Json(UserInfo::new(&user, &grant.scopes)))
+}
+
+// 注册客户端
+pub async fn create_client(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Json(req): Json<CreateClientRequest>,
+) -> Result<(StatusCode, Json<CreatedClientResponse>), (StatusCode, String)> {
+    match OAuthClientStore::create(&pool, admin.user.organization_id, admin.user.id, &req).await {
+        Ok(client) => Ok((StatusCode::CREATED, Json(client))),
+        Err(err) => Err(client_error_response(err)),
+    }
+}
+
+// 获取当前组织的所有客户端
+pub async fn list_clients(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+) -> Result<Json<Vec<OAuthClient>>, (StatusCode, String)> {
+    match OAuthClientStore::find_all(&pool, admin.user.organization_id).await {
+        Ok(clients) => Ok(Json(clients)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// 删除客户端
+pub async fn delete_client(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Path(client_id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match OAuthClientStore::delete(&pool, admin.user.organization_id, admin.user.id, &client_id).await {
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => Err(client_error_response(err)),
+    }
+}
+
+// 轮换客户端密钥，新密钥只返回这一次
+pub async fn rotate_client_secret(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Path(client_id): Path<String>,
+) -> Result<Json<RotatedSecretResponse>, (StatusCode, String)> {
+    match OAuthClientStore::rotate_secret(&pool, admin.user.organization_id, admin.user.id, &client_id).await {
+        Ok(client_secret) => Ok(Json(RotatedSecretResponse { client_id, client_secret })),
+        Err(err) => Err(client_error_response(err)),
+    }
+}
+
+// 吊销客户端，已签发的访问令牌立即失效
+pub async fn revoke_client(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Path(client_id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match OAuthClientStore::revoke(&pool, admin.user.organization_id, admin.user.id, &client_id).await {
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => Err(client_error_response(err)),
+    }
+}
+
+// 立即轮换签名密钥，旧公钥仍保留在JWKS中；签名密钥全局共用，只有平台管理员可以轮换
+pub async fn rotate_signing_key(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+) -> Result<StatusCode, (StatusCode, String)> {
+    ensure_platform_admin(&pool, &admin).await?;
+    match SigningKeyStore::rotate(&pool).await {
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // 本地一致性测试客户端：按依赖方的方式走完整流程，用JWKS校验ID令牌
+    use axum::body::Body;
+    use axum::http::{header, Method, Request, StatusCode};
+    use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+    use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+    use serde_json::{json, Value};
+    use sha2::{Digest, Sha256};
+    use url::{form_urlencoded, Url};
+    use crate::model::{oidc::issuer, organization::DEFAULT_ORGANIZATION_SLUG, ROLE_ADMIN, ROLE_USER};
+    use crate::model::oauth_client::SCOPE_USERS_READ;
+    use crate::test_support::*;
+
+    const REDIRECT_URI: &str = "http://localhost:8080/callback";
+
+    struct TestClient {
+        client_id: String,
+        client_secret: String,
+    }
+
+    impl TestClient {
+        async fn register(app: &TestApp, admin_token: &str, body: Value) -> TestClient {
+            let response = app.request(Method::POST, "/admin/oauth/clients", Some(admin_token), Some(body)).await;
+            assert_eq!(response.status, StatusCode::CREATED, "注册客户端失败: {}", response.body);
+            TestClient {
+                client_id: response.body["client_id"].as_str().unwrap().to_string(),
+                client_secret: response.body["client_secret"].as_str().unwrap().to_string(),
+            }
+        }
+
+        // client_secret_basic认证的令牌请求
+        async fn token(&self, app: &TestApp, params: &[(&str, &str)]) -> TestResponse {
+            let credentials = STANDARD.encode(format!("{}:{}", self.client_id, self.client_secret));
+            let body = form_urlencoded::Serializer::new(String::new()).extend_pairs(params).finish();
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri("/oauth2/token")
+                .header(header::AUTHORIZATION, format!("Basic {}", credentials))
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Body::from(body))
+                .unwrap();
+            app.call(request).await
+        }
+
+        fn authorize_uri(&self, scope: &str, code_challenge: Option<&str>) -> String {
+            let mut query = form_urlencoded::Serializer::new(String::new());
+            query.extend_pairs([
+                ("response_type", "code"),
+                ("client_id", self.client_id.as_str()),
+                ("redirect_uri", REDIRECT_URI),
+                ("scope", scope),
+                ("state", "state-123"),
+                ("nonce", "nonce-456"),
+            ]);
+            if let Some(code_challenge) = code_challenge {
+                query.extend_pairs([("code_challenge", code_challenge), ("code_challenge_method", "S256")]);
+            }
+            format!("/oauth2/authorize?{}", query.finish())
+        }
+
+        // 同意授权并从回调地址中取出授权码
+        async fn authorize(&self, app: &TestApp, user_token: &str, scope: &str, code_verifier: &str) -> String {
+            let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+            let uri = self.authorize_uri(scope, Some(&challenge));
+
+            let response = app.request(Method::GET, &uri, Some(user_token), None).await;
+            assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+            assert_eq!(response.body["consent_required"], true);
+
+            let response = app
+                .request(Method::POST, "/oauth2/authorize", Some(user_token), Some(json!({
+                    "response_type": "code",
+                    "client_id": self.client_id,
+                    "redirect_uri": REDIRECT_URI,
+                    "scope": scope,
+                    "state": "state-123",
+                    "nonce": "nonce-456",
+                    "code_challenge": challenge,
+                    "code_challenge_method": "S256",
+                    "approve": true,
+                })))
+                .await;
+            let params = redirect_params(&response);
+            assert_eq!(params.get("state").map(String::as_str), Some("state-123"));
+            params.get("code").expect("回调地址缺少code").clone()
+        }
+    }
+
+    fn redirect_params(response: &TestResponse) -> std::collections::HashMap<String, String> {
+        assert!(response.status.is_redirection(), "应当重定向: {} {}", response.status, response.body);
+        let location = Url::parse(response.header("location").unwrap()).unwrap();
+        assert!(location.as_str().starts_with(REDIRECT_URI));
+        location.query_pairs().into_owned().collect()
+    }
+
+    async fn admin_token(app: &TestApp) -> String {
+        let organization_id = default_organization(&app.pool).await;
+        let admin = create_user(&app.pool, organization_id, ROLE_ADMIN).await;
+        app.login(&admin.email).await
+    }
+
+    async fn login_client(app: &TestApp, admin_token: &str) -> TestClient {
+        TestClient::register(app, admin_token, json!({
+            "name": "一致性测试客户端",
+            "redirect_uris": [REDIRECT_URI],
+        }))
+        .await
+    }
+
+    #[tokio::test]
+    async fn authorization_code_flow_with_pkce() {
+        let Some(app) = TestApp::new().await else { return };
+        let admin_token = admin_token(&app).await;
+        let client = login_client(&app, &admin_token).await;
+        let organization_id = default_organization(&app.pool).await;
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+        let user_token = app.login(&user.email).await;
+
+        let discovery = app.request(Method::GET, "/.well-known/openid-configuration", None, None).await;
+        assert_eq!(discovery.status, StatusCode::OK);
+        assert_eq!(discovery.body["code_challenge_methods_supported"], json!(["S256"]));
+        assert!(discovery.body["grant_types_supported"].as_array().unwrap().contains(&json!("refresh_token")));
+
+        let verifier = unique("verifier-abcdefghijklmnopqrstuvwxyz0123456789");
+        let code = client.authorize(&app, &user_token, "openid email profile", &verifier).await;
+
+        // 已同意过的scope不再询问，直接重定向
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(b"another-verifier"));
+        let response = app
+            .request(Method::GET, &client.authorize_uri("openid email", Some(&challenge)), Some(&user_token), None)
+            .await;
+        assert!(redirect_params(&response).contains_key("code"));
+
+        let response = client
+            .token(&app, &[
+                ("grant_type", "authorization_code"),
+                ("code", &code),
+                ("redirect_uri", REDIRECT_URI),
+                ("code_verifier", &verifier),
+            ])
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.header("cache-control"), Some("no-store"));
+        assert_eq!(response.body["token_type"], "Bearer");
+        let access_token = response.body["access_token"].as_str().unwrap().to_string();
+        assert!(response.body["refresh_token"].is_string());
+
+        // 用JWKS校验ID令牌签名和声明
+        let id_token = response.body["id_token"].as_str().unwrap();
+        let jwks: JwkSet = serde_json::from_value(app.request(Method::GET, "/oauth2/jwks", None, None).await.body).unwrap();
+        let kid = jsonwebtoken::decode_header(id_token).unwrap().kid.expect("ID令牌缺少kid");
+        let jwk = jwks.find(&kid).expect("JWKS中没有对应的公钥");
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&[&client.client_id]);
+        validation.set_issuer(&[issuer()]);
+        let claims = jsonwebtoken::decode::<Value>(id_token, &DecodingKey::from_jwk(jwk).unwrap(), &validation)
+            .unwrap()
+            .claims;
+        assert_eq!(claims["sub"], user.id.to_string());
+        assert_eq!(claims["email"], user.email);
+        assert_eq!(claims["name"], user.name);
+        assert_eq!(claims["nonce"], "nonce-456");
+
+        let response = app.request(Method::GET, "/oauth2/userinfo", Some(&access_token), None).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body["sub"], user.id.to_string());
+        assert_eq!(response.body["email"], user.email);
+
+        // 授权码只能使用一次，重复使用会吊销已签发的令牌
+        let response = client
+            .token(&app, &[
+                ("grant_type", "authorization_code"),
+                ("code", &code),
+                ("redirect_uri", REDIRECT_URI),
+                ("code_verifier", &verifier),
+            ])
+            .await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.body["error"], "invalid_grant");
+        let response = app.request(Method::GET, "/oauth2/userinfo", Some(&access_token), None).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn authorization_code_requires_pkce() {
+        let Some(app) = TestApp::new().await else { return };
+        let admin_token = admin_token(&app).await;
+        let client = login_client(&app, &admin_token).await;
+        let organization_id = default_organization(&app.pool).await;
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+        let user_token = app.login(&user.email).await;
+
+        // 缺少code_challenge时重定向回客户端报错
+        let response = app
+            .request(Method::GET, &client.authorize_uri("openid", None), Some(&user_token), None)
+            .await;
+        let params = redirect_params(&response);
+        assert_eq!(params.get("error").map(String::as_str), Some("invalid_request"));
+        assert_eq!(params.get("state").map(String::as_str), Some("state-123"));
+
+        // 未注册的回调地址不重定向
+        let uri = client.authorize_uri("openid", Some("challenge")).replace("localhost%3A8080", "evil.example.com");
+        let response = app.request(Method::GET, &uri, Some(&user_token), None).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+        // code_verifier错误时授权码作废
+        let verifier = unique("verifier-abcdefghijklmnopqrstuvwxyz0123456789");
+        let code = client.authorize(&app, &user_token, "openid", &verifier).await;
+        let response = client
+            .token(&app, &[
+                ("grant_type", "authorization_code"),
+                ("code", &code),
+                ("redirect_uri", REDIRECT_URI),
+                ("code_verifier", "wrong-verifier-abcdefghijklmnopqrstuvwxyz0123456789"),
+            ])
+            .await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.body["error"], "invalid_grant");
+        let response = client
+            .token(&app, &[
+                ("grant_type", "authorization_code"),
+                ("code", &code),
+                ("redirect_uri", REDIRECT_URI),
+                ("code_verifier", &verifier),
+            ])
+            .await;
+        assert_eq!(response.body["error"], "invalid_grant");
+    }
+
+    #[tokio::test]
+    async fn authorization_code_cannot_request_api_scopes() {
+        let Some(app) = TestApp::new().await else { return };
+        let admin_token = admin_token(&app).await;
+        let client = TestClient::register(&app, &admin_token, json!({
+            "name": "混合客户端",
+            "redirect_uris": [REDIRECT_URI],
+            "grant_types": ["authorization_code", "client_credentials"],
+            "scopes": ["openid", "users:read"],
+        }))
+        .await;
+        let organization_id = default_organization(&app.pool).await;
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+        let user_token = app.login(&user.email).await;
+
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(b"verifier"));
+        let response = app
+            .request(Method::GET, &client.authorize_uri("openid users:read", Some(&challenge)), Some(&user_token), None)
+            .await;
+        let params = redirect_params(&response);
+        assert_eq!(params.get("error").map(String::as_str), Some("invalid_scope"));
+        assert!(!params.contains_key("code"));
+    }
+
+    #[tokio::test]
+    async fn refresh_token_rotates_and_detects_reuse() {
+        let Some(app) = TestApp::new().await else { return };
+        let admin_token = admin_token(&app).await;
+        let client = login_client(&app, &admin_token).await;
+        let organization_id = default_organization(&app.pool).await;
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+        let user_token = app.login(&user.email).await;
+
+        let verifier = unique("verifier-abcdefghijklmnopqrstuvwxyz0123456789");
+        let code = client.authorize(&app, &user_token, "openid email", &verifier).await;
+        let response = client
+            .token(&app, &[
+                ("grant_type", "authorization_code"),
+                ("code", &code),
+                ("redirect_uri", REDIRECT_URI),
+                ("code_verifier", &verifier),
+            ])
+            .await;
+        let first_refresh = response.body["refresh_token"].as_str().unwrap().to_string();
+
+        // 不能超出原授权的scope，失败时刷新令牌仍然有效
+        let response = client
+            .token(&app, &[("grant_type", "refresh_token"), ("refresh_token", &first_refresh), ("scope", "openid profile")])
+            .await;
+        assert_eq!(response.body["error"], "invalid_scope");
+
+        let response = client
+            .token(&app, &[("grant_type", "refresh_token"), ("refresh_token", &first_refresh), ("scope", "openid")])
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.body["scope"], "openid");
+        let access_token = response.body["access_token"].as_str().unwrap().to_string();
+        let second_refresh = response.body["refresh_token"].as_str().unwrap().to_string();
+        assert_ne!(first_refresh, second_refresh);
+
+        let response = app.request(Method::GET, "/oauth2/userinfo", Some(&access_token), None).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.body.get("email").is_none());
+
+        // 其他客户端不能使用该刷新令牌
+        let other = login_client(&app, &admin_token).await;
+        let response = other
+            .token(&app, &[("grant_type", "refresh_token"), ("refresh_token", &second_refresh)])
+            .await;
+        assert_eq!(response.body["error"], "invalid_grant");
+
+        // 旧刷新令牌被重复使用时，同一授权下的全部令牌失效
+        let response = client
+            .token(&app, &[("grant_type", "refresh_token"), ("refresh_token", &first_refresh)])
+            .await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.body["error"], "invalid_grant");
+        let response = app.request(Method::GET, "/oauth2/userinfo", Some(&access_token), None).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn refresh_token_keeps_scope_and_stops_for_inactive_user() {
+        let Some(app) = TestApp::new().await else { return };
+        let admin_token = admin_token(&app).await;
+        let client = login_client(&app, &admin_token).await;
+        let organization_id = default_organization(&app.pool).await;
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+        let user_token = app.login(&user.email).await;
+
+        let verifier = unique("verifier-abcdefghijklmnopqrstuvwxyz0123456789");
+        let code = client.authorize(&app, &user_token, "openid email", &verifier).await;
+        let response = client
+            .token(&app, &[
+                ("grant_type", "authorization_code"),
+                ("code", &code),
+                ("redirect_uri", REDIRECT_URI),
+                ("code_verifier", &verifier),
+            ])
+            .await;
+        let refresh = response.body["refresh_token"].as_str().unwrap().to_string();
+
+        // 缩小scope后，新刷新令牌仍按原授权刷新
+        let response = client
+            .token(&app, &[("grant_type", "refresh_token"), ("refresh_token", &refresh), ("scope", "openid")])
+            .await;
+        let refresh = response.body["refresh_token"].as_str().unwrap().to_string();
+        let response = client.token(&app, &[("grant_type", "refresh_token"), ("refresh_token", &refresh)]).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.body["scope"], "openid email");
+        let refresh = response.body["refresh_token"].as_str().unwrap().to_string();
+
+        let response = app
+            .request(Method::POST, &format!("/users/{}/suspend", user.id), Some(&admin_token), Some(json!({ "reason": "测试" })))
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        let response = client.token(&app, &[("grant_type", "refresh_token"), ("refresh_token", &refresh)]).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.body["error"], "invalid_grant");
+    }
+
+    // 重置密码后，已签发给客户端的访问令牌和刷新令牌全部失效
+    #[tokio::test]
+    async fn password_reset_revokes_tokens() {
+        let Some(app) = TestApp::new().await else { return };
+        let admin_token = admin_token(&app).await;
+        let client = login_client(&app, &admin_token).await;
+        let organization_id = default_organization(&app.pool).await;
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+        let user_token = app.login(&user.email).await;
+
+        let verifier = unique("verifier-abcdefghijklmnopqrstuvwxyz0123456789");
+        let code = client.authorize(&app, &user_token, "openid email", &verifier).await;
+        let response = client
+            .token(&app, &[
+                ("grant_type", "authorization_code"),
+                ("code", &code),
+                ("redirect_uri", REDIRECT_URI),
+                ("code_verifier", &verifier),
+            ])
+            .await;
+        let access_token = response.body["access_token"].as_str().unwrap().to_string();
+        let refresh = response.body["refresh_token"].as_str().unwrap().to_string();
+
+        let response = app
+            .request(Method::POST, "/auth/password/forgot", None, Some(json!({ "email": user.email })))
+            .await;
+        assert_eq!(response.status, StatusCode::ACCEPTED);
+        let email = app.mailer.wait_for(&user.email).await;
+        let token = email.body.split("token=").nth(1).unwrap().trim().to_string();
+        let response = app
+            .request(Method::POST, "/auth/password/reset", None, Some(json!({ "token": token, "password": "new-password-123" })))
+            .await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT, "{}", response.body);
+
+        let response = client.token(&app, &[("grant_type", "refresh_token"), ("refresh_token", &refresh)]).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.body["error"], "invalid_grant");
+        let response = app.request(Method::GET, "/oauth2/userinfo", Some(&access_token), None).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn client_credentials_flow() {
+        let Some(app) = TestApp::new().await else { return };
+        let admin_token = admin_token(&app).await;
+        let service = TestClient::register(&app, &admin_token, json!({
+            "name": "报表服务",
+            "grant_types": ["client_credentials"],
+            "scopes": ["users:read"],
+        }))
+        .await;
+
+        let response = service.token(&app, &[("grant_type", "client_credentials")]).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.body["scope"], "users:read");
+        assert!(response.body.get("id_token").is_none());
+        assert!(response.body.get("refresh_token").is_none());
+        let access_token = response.body["access_token"].as_str().unwrap().to_string();
+
+        // 服务调用方不需要指定组织，默认访问客户端所属的组织
+        let response = app.request(Method::GET, "/users", Some(&access_token), None).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        let response = app
+            .request(Method::POST, "/users", Some(&access_token), Some(json!({
+                "name": "服务创建", "email": format!("{}@example.com", unique("svc")), "password": PASSWORD,
+            })))
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+        let response = service
+            .token(&app, &[("grant_type", "client_credentials"), ("scope", "users:write")])
+            .await;
+        assert_eq!(response.body["error"], "invalid_scope");
+
+        // 服务客户端不能使用授权码和刷新令牌，登录客户端不能使用client_credentials
+        let response = service.token(&app, &[("grant_type", "refresh_token"), ("refresh_token", "x")]).await;
+        assert_eq!(response.body["error"], "unauthorized_client");
+        let login = login_client(&app, &admin_token).await;
+        let response = login.token(&app, &[("grant_type", "client_credentials")]).await;
+        assert_eq!(response.body["error"], "unauthorized_client");
+
+        let wrong = TestClient { client_id: service.client_id.clone(), client_secret: "wrong".to_string() };
+        let response = wrong.token(&app, &[("grant_type", "client_credentials")]).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(response.body["error"], "invalid_client");
+
+        // 吊销客户端后令牌立即失效
+        let response = app
+            .request(Method::POST, &format!("/admin/oauth/clients/{}/revoke", service.client_id), Some(&admin_token), None)
+            .await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let response = app.request(Method::GET, "/users", Some(&access_token), None).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    }
+
+    // 租户的服务客户端只能访问本组织的用户；租户管理员看不到也不能操作其他组织的客户端
+    #[tokio::test]
+    async fn clients_and_tokens_are_scoped_to_organization() {
+        let Some(app) = TestApp::new().await else { return };
+        let platform_token = admin_token(&app).await;
+        let platform_client = login_client(&app, &platform_token).await;
+
+        let (organization_id, slug) = create_organization(&app.pool).await;
+        let tenant_admin = create_user(&app.pool, organization_id, ROLE_ADMIN).await;
+        let tenant_token = app.login(&tenant_admin.email).await;
+        let service = TestClient::register(&app, &tenant_token, json!({
+            "name": "租户服务",
+            "grant_types": ["client_credentials"],
+            "scopes": [SCOPE_USERS_READ],
+        }))
+        .await;
+        let response = service.token(&app, &[("grant_type", "client_credentials")]).await;
+        let access_token = response.body["access_token"].as_str().unwrap().to_string();
+
+        let member = create_user(&app.pool, organization_id, ROLE_USER).await;
+        let outsider = create_user(&app.pool, default_organization(&app.pool).await, ROLE_USER).await;
+        let response = app.request(Method::GET, &format!("/users/{}", member.id), Some(&access_token), None).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        let response = app.request(Method::GET, &format!("/users/{}", outsider.id), Some(&access_token), None).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        let response = app
+            .send(Method::GET, "/users", Some(&access_token), &[("x-organization", DEFAULT_ORGANIZATION_SLUG)], None)
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        let response = app
+            .send(Method::GET, "/users", Some(&access_token), &[("x-organization", slug.as_str())], None)
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+        let response = app.request(Method::GET, "/admin/oauth/clients", Some(&tenant_token), None).await;
+        let client_ids: Vec<&str> = response.body.as_array().unwrap().iter().map(|client| client["client_id"].as_str().unwrap()).collect();
+        assert_eq!(client_ids, vec![service.client_id.as_str()]);
+
+        for action in ["rotate", "revoke"] {
+            let uri = format!("/admin/oauth/clients/{}/{}", platform_client.client_id, action);
+            let response = app.request(Method::POST, &uri, Some(&tenant_token), None).await;
+            assert_eq!(response.status, StatusCode::NOT_FOUND, "{}", action);
+        }
+        let uri = format!("/admin/oauth/clients/{}", platform_client.client_id);
+        let response = app.request(Method::DELETE, &uri, Some(&tenant_token), None).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+        // 平台的客户端不受影响
+        let response = app.request(Method::GET, "/admin/oauth/clients", Some(&platform_token), None).await;
+        let client = response.body.as_array().unwrap().iter().find(|client| client["client_id"] == platform_client.client_id.as_str()).unwrap();
+        assert!(client["revoked_at"].is_null());
+    }
+
+    // 签名密钥、登录限制和角色两步验证策略全局共用，只有平台管理员可以操作
+    #[tokio::test]
+    async fn global_settings_require_platform_admin() {
+        let Some(app) = TestApp::new().await else { return };
+        let (organization_id, _) = create_organization(&app.pool).await;
+        let tenant_admin = create_user(&app.pool, organization_id, ROLE_ADMIN).await;
+        let tenant_token = app.login(&tenant_admin.email).await;
+        let platform_token = admin_token(&app).await;
+
+        let requests = [
+            (Method::POST, "/admin/oidc/keys/rotate", None),
+            (Method::GET, "/admin/lockouts", None),
+            (Method::POST, "/admin/lockouts/unlock", Some(json!({ "ip": "10.0.0.1" }))),
+            (Method::PUT, "/admin/roles/user/2fa", Some(json!({ "require_totp": false }))),
+        ];
+        for (method, uri, body) in requests {
+            let response = app.request(method.clone(), uri, Some(&tenant_token), body.clone()).await;
+            assert_eq!(response.status, StatusCode::FORBIDDEN, "{}", uri);
+            let response = app.request(method, uri, Some(&platform_token), body).await;
+            assert_ne!(response.status, StatusCode::FORBIDDEN, "{}", uri);
+        }
+    }
+}
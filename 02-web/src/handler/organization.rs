@@ -0,0 +1,47 @@
+use axum::{extract::{Extension, Json}, http::StatusCode};
+use crate::{db::DbPool, extractor::{AdminUser, AuthUser}};
+use crate::model::organization::*;
+
+fn error_response(err: OrganizationError) -> (StatusCode, String) {
+    match err {
+        OrganizationError::InvalidSlug => (StatusCode::BAD_REQUEST, err.to_string()),
+        OrganizationError::SlugExists => (StatusCode::CONFLICT, err.to_string()),
+        OrganizationError::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+// 组织管理等全局操作只对默认组织（平台）的管理员开放
+pub async fn ensure_platform_admin(pool: &DbPool, admin: &AuthUser) -> Result<(), (StatusCode, String)> {
+    let default_id = OrganizationStore::default_id(pool)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    if admin.user.organization_id != default_id {
+        return Err((StatusCode::FORBIDDEN, "需要平台管理员权限".to_string()));
+    }
+    Ok(())
+}
+
+// 获取所有组织
+pub async fn list_organizations(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+) -> Result<Json<Vec<Organization>>, (StatusCode, String)> {
+    ensure_platform_admin(&pool, &admin).await?;
+    match OrganizationStore::find_all(&pool).await {
+        Ok(organizations) => Ok(Json(organizations)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// 创建组织
+pub async fn create_organization(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Json(req): Json<CreateOrganizationRequest>,
+) -> Result<(StatusCode, Json<Organization>), (StatusCode, String)> {
+    ensure_platform_admin(&pool, &admin).await?;
+    match OrganizationStore::create(&pool, admin.user.id, &req).await {
+        Ok(organization) => Ok((StatusCode::CREATED, Json(organization))),
+        Err(err) => Err(error_response(err)),
+    }
+}
@@ -0,0 +1,255 @@
+use std::net::SocketAddr;
+use axum::{extract::{ConnectInfo, Extension, Json, Path}, http::StatusCode};
+use axum_extra::extract::cookie::CookieJar;
+use uuid::Uuid;
+use crate::{db::DbPool, extractor::AuthUser, metrics::SharedMetrics};
+use crate::handler::auth::{
+    ensure_active, ensure_not_throttled, issue_session, mfa_enrollment_required, record_login_failure,
+    set_session_cookies, LoginError,
+};
+use crate::model::{UserStore, passkey::*, session::{ClientInfo, LoginResponse}};
+
+fn error_response(err: PasskeyError) -> (StatusCode, String) {
+    match err {
+        PasskeyError::NotFound => (StatusCode::NOT_FOUND, err.to_string()),
+        PasskeyError::CredentialExists => (StatusCode::CONFLICT, err.to_string()),
+        PasskeyError::InvalidChallenge | PasskeyError::Webauthn(_) => {
+            (StatusCode::BAD_REQUEST, err.to_string())
+        }
+        PasskeyError::CounterRegression => (StatusCode::UNAUTHORIZED, err.to_string()),
+        err => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+// 开始注册通行密钥
+pub async fn start_registration(
+    Extension(pool): Extension<DbPool>,
+    Extension(webauthn): Extension<SharedWebauthn>,
+    auth: AuthUser,
+) -> Result<Json<StartRegistrationResponse>, (StatusCode, String)> {
+    match PasskeyStore::start_registration(&pool, &webauthn, &auth.user).await {
+        Ok(response) => Ok(Json(response)),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 完成注册通行密钥
+pub async fn finish_registration(
+    Extension(pool): Extension<DbPool>,
+    Extension(webauthn): Extension<SharedWebauthn>,
+    auth: AuthUser,
+    Json(req): Json<FinishRegistrationRequest>,
+) -> Result<Json<PasskeyInfo>, (StatusCode, String)> {
+    match PasskeyStore::finish_registration(&pool, &webauthn, auth.user.id, &req).await {
+        Ok(passkey) => Ok(Json(passkey)),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 开始通行密钥登录，账号或IP被限制时不签发挑战
+pub async fn start_login(
+    Extension(pool): Extension<DbPool>,
+    Extension(webauthn): Extension<SharedWebauthn>,
+    Extension(metrics): Extension<SharedMetrics>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<StartLoginRequest>,
+) -> Result<Json<StartLoginResponse>, LoginError> {
+    let unavailable = || (StatusCode::BAD_REQUEST, "无法使用通行密钥登录".to_string());
+    ensure_not_throttled(&pool, &metrics, &req.email, Some(&addr.ip().to_string())).await?;
+
+    let user = UserStore::find_by_email(&pool, &req.email)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+        .ok_or_else(unavailable)?;
+
+    match PasskeyStore::start_login(&pool, &webauthn, user.id).await {
+        Ok(response) => Ok(Json(response)),
+        Err(PasskeyError::NotFound) => Err(unavailable().into()),
+        Err(err) => Err(error_response(err).into()),
+    }
+}
+
+// 完成通行密钥登录，通行密钥已包含用户验证，不再要求TOTP。
+// 与密码登录共用失败计数和锁定，角色强制两步验证而用户尚未绑定TOTP时同样只建立绑定用的会话
+pub async fn finish_login(
+    Extension(pool): Extension<DbPool>,
+    Extension(webauthn): Extension<SharedWebauthn>,
+    Extension(metrics): Extension<SharedMetrics>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Json(req): Json<FinishLoginRequest>,
+) -> Result<(CookieJar, Json<LoginResponse>), LoginError> {
+    let internal_error = |err: crate::model::UserError| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+    let ip = addr.ip().to_string();
+
+    let user_id = PasskeyStore::login_challenge_user(&pool, req.challenge_id)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+        .ok_or_else(|| error_response(PasskeyError::InvalidChallenge))?;
+    let user = UserStore::find_by_id(&pool, user_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| error_response(PasskeyError::InvalidChallenge))?;
+    ensure_not_throttled(&pool, &metrics, &user.email, Some(&ip)).await?;
+
+    match PasskeyStore::finish_login(&pool, &webauthn, &req).await {
+        Ok(_) => {}
+        Err(err @ (PasskeyError::Webauthn(_) | PasskeyError::CounterRegression | PasskeyError::NotFound)) => {
+            record_login_failure(&pool, &metrics, &user.email, &ip).await?;
+            return Err(error_response(err).into());
+        }
+        Err(err) => return Err(error_response(err).into()),
+    }
+    ensure_active(&user)?;
+
+    let enrollment_required = mfa_enrollment_required(&pool, &user).await?;
+    let response = issue_session(&pool, &user, enrollment_required, &client).await?;
+    let jar = set_session_cookies(&pool, jar, &response).await?;
+
+    Ok((jar, Json(response)))
+}
+
+// 获取当前用户的通行密钥
+pub async fn list_passkeys(
+    Extension(pool): Extension<DbPool>,
+    auth: AuthUser,
+) -> Result<Json<Vec<PasskeyInfo>>, (StatusCode, String)> {
+    match PasskeyStore::list(&pool, auth.user.id).await {
+        Ok(passkeys) => Ok(Json(passkeys)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// 撤销通行密钥
+pub async fn delete_passkey(
+    Extension(pool): Extension<DbPool>,
+    auth: AuthUser,
+    Path(passkey_id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match PasskeyStore::delete(&pool, auth.user.id, passkey_id).await {
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+    use url::Url;
+    use webauthn_authenticator_rs::{softpasskey::SoftPasskey, WebauthnAuthenticator};
+    use crate::model::ROLE_USER;
+    use crate::test_support::*;
+
+    // 与create_webauthn的默认配置一致
+    fn origin() -> Url {
+        Url::parse("http://localhost:3000").unwrap()
+    }
+
+    // 使用软件认证器为当前会话的用户注册通行密钥
+    async fn register(app: &TestApp, authenticator: &mut WebauthnAuthenticator<SoftPasskey>, token: &str) {
+        let response = app.request(Method::POST, "/auth/passkeys/register/start", Some(token), None).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        let options = serde_json::from_value(response.body["options"].clone()).unwrap();
+        let credential = authenticator.do_registration(origin(), options).unwrap();
+
+        let response = app
+            .request(
+                Method::POST,
+                "/auth/passkeys/register/finish",
+                Some(token),
+                Some(json!({ "challenge_id": response.body["challenge_id"], "credential": credential })),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    }
+
+    // 开始登录并由认证器签名，返回完成登录的请求体
+    async fn sign_in(app: &TestApp, authenticator: &mut WebauthnAuthenticator<SoftPasskey>, email: &str) -> Value {
+        let response = app
+            .request(Method::POST, "/auth/passkeys/login/start", None, Some(json!({ "email": email })))
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        let options = serde_json::from_value(response.body["options"].clone()).unwrap();
+        let credential = authenticator.do_authentication(origin(), options).unwrap();
+        json!({ "challenge_id": response.body["challenge_id"], "credential": credential })
+    }
+
+    #[tokio::test]
+    async fn passkey_registration_and_login() {
+        let Some(app) = TestApp::new().await else { return };
+        let organization_id = default_organization(&app.pool).await;
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+        let token = app.login(&user.email).await;
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new(true));
+        register(&app, &mut authenticator, &token).await;
+
+        let finish = sign_in(&app, &mut authenticator, &user.email).await;
+        let response = app.request(Method::POST, "/auth/passkeys/login/finish", None, Some(finish.clone())).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert!(response.body["token"].is_string());
+        assert!(response.body.get("mfa_enrollment_required").is_none());
+
+        // 挑战只能使用一次
+        let response = app.request(Method::POST, "/auth/passkeys/login/finish", None, Some(finish)).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    }
+
+    // 角色强制两步验证时，通行密钥登录与密码登录一样只建立绑定用的会话
+    #[tokio::test]
+    async fn passkey_login_respects_role_totp_policy() {
+        let Some(app) = TestApp::new().await else { return };
+        let organization_id = default_organization(&app.pool).await;
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+        let token = app.login(&user.email).await;
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new(true));
+        register(&app, &mut authenticator, &token).await;
+
+        // 角色策略是全局的，使用只有该用户的角色，避免影响并行运行的其他测试
+        let role = unique("role");
+        sqlx::query("UPDATE users SET role = $1 WHERE id = $2").bind(&role).bind(user.id).execute(&app.pool).await.unwrap();
+        sqlx::query("INSERT INTO role_mfa_policies (role, require_totp) VALUES ($1, TRUE)")
+            .bind(&role)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        let finish = sign_in(&app, &mut authenticator, &user.email).await;
+        let response = app.request(Method::POST, "/auth/passkeys/login/finish", None, Some(finish)).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.body["mfa_enrollment_required"], true);
+
+        let session = response.body["token"].as_str().unwrap();
+        let response = app.request(Method::GET, "/auth/passkeys", Some(session), None).await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+    }
+
+    // 被锁定的账号不能开始或完成通行密钥登录
+    #[tokio::test]
+    async fn passkey_login_is_subject_to_lockout() {
+        let Some(app) = TestApp::new().await else { return };
+        let organization_id = default_organization(&app.pool).await;
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+        let token = app.login(&user.email).await;
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new(true));
+        register(&app, &mut authenticator, &token).await;
+        let finish = sign_in(&app, &mut authenticator, &user.email).await;
+
+        sqlx::query(r#"
+            INSERT INTO login_throttles (scope, key, failures, last_failure_at, locked_until)
+            VALUES ('account', $1, 0, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP + INTERVAL '15 minutes')
+            "#)
+            .bind(&user.email)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        let response = app.request(Method::POST, "/auth/passkeys/login/finish", None, Some(finish)).await;
+        assert_eq!(response.status, StatusCode::LOCKED);
+        let response = app
+            .request(Method::POST, "/auth/passkeys/login/start", None, Some(json!({ "email": user.email })))
+            .await;
+        assert_eq!(response.status, StatusCode::LOCKED);
+    }
+}
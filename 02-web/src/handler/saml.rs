@@ -0,0 +1,307 @@
+use axum::{
+    extract::{Extension, Form, Json, Path},
+    http::{header, StatusCode},
+    response::Redirect,
+};
+use axum_extra::extract::cookie::CookieJar;
+use crate::{db::DbPool, extractor::AdminUser, handler::auth::{set_session_cookies, start_session, LoginError}};
+use crate::metrics::SharedMetrics;
+use crate::model::{saml::*, session::{ClientInfo, LoginResponse}, UserError};
+
+fn error_response(err: SamlError) -> (StatusCode, String) {
+    match err {
+        SamlError::NotFound => (StatusCode::NOT_FOUND, err.to_string()),
+        SamlError::InvalidMetadata(_) | SamlError::MissingEmailDomain => (StatusCode::BAD_REQUEST, err.to_string()),
+        SamlError::TenantTaken | SamlError::User(UserError::EmailExists) => (StatusCode::CONFLICT, err.to_string()),
+        SamlError::InvalidResponse(_)
+        | SamlError::Signature(_)
+        | SamlError::Replay
+        | SamlError::EmailDomainNotAllowed
+        | SamlError::NoAccount => {
+            tracing::warn!("SAML登录失败: {}", err);
+            (StatusCode::UNAUTHORIZED, err.to_string())
+        }
+        err => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+// SP元数据，提供给IdP导入
+pub async fn metadata(
+    Extension(pool): Extension<DbPool>,
+    Path(tenant): Path<String>,
+) -> Result<([(header::HeaderName, &'static str); 1], String), (StatusCode, String)> {
+    match SamlStore::find_by_tenant(&pool, &tenant).await {
+        Ok(Some(_)) => Ok((
+            [(header::CONTENT_TYPE, "application/samlmetadata+xml")],
+            SamlStore::sp_metadata(&tenant),
+        )),
+        Ok(None) => Err(error_response(SamlError::NotFound)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// SP发起登录，跳转到IdP
+pub async fn login(
+    Extension(pool): Extension<DbPool>,
+    Path(tenant): Path<String>,
+) -> Result<Redirect, (StatusCode, String)> {
+    match SamlStore::start_login(&pool, &tenant).await {
+        Ok(url) => Ok(Redirect::to(&url)),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 断言消费服务（HTTP-POST绑定）
+pub async fn acs(
+    Extension(pool): Extension<DbPool>,
+    Extension(metrics): Extension<SharedMetrics>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Path(tenant): Path<String>,
+    Form(form): Form<SamlPostForm>,
+) -> Result<(CookieJar, Json<LoginResponse>), LoginError> {
+    let user = SamlStore::consume_response(&pool, &tenant, &form.saml_response)
+        .await
+        .map_err(error_response)?;
+
+    let response = start_session(&pool, &metrics, &user, &client).await?;
+    let jar = set_session_cookies(&pool, jar, &response).await?;
+
+    Ok((jar, Json(response)))
+}
+
+// 获取当前组织的SAML连接
+pub async fn list_connections(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+) -> Result<Json<Vec<SamlConnection>>, (StatusCode, String)> {
+    match SamlStore::find_all(&pool, admin.user.organization_id).await {
+        Ok(connections) => Ok(Json(connections)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// 为管理员所在组织配置租户的IdP元数据
+pub async fn put_connection(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Path(tenant): Path<String>,
+    Json(req): Json<SamlConnectionRequest>,
+) -> Result<Json<SamlConnection>, (StatusCode, String)> {
+    match SamlStore::upsert(&pool, admin.user.organization_id, admin.user.id, &tenant, &req).await {
+        Ok(connection) => Ok(Json(connection)),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 删除SAML连接
+pub async fn delete_connection(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Path(tenant): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match SamlStore::delete(&pool, admin.user.organization_id, admin.user.id, &tenant).await {
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{header, Method, Request, StatusCode};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use chrono::{Duration, Utc};
+    use openssl::{
+        asn1::Asn1Time, bn::BigNum, hash::MessageDigest, pkey::{PKey, Private}, rsa::Rsa, sign::Signer,
+        x509::{X509Builder, X509NameBuilder},
+    };
+    use roxmltree::Document;
+    use serde_json::json;
+    use url::form_urlencoded;
+    use uuid::Uuid;
+    use crate::model::{saml::{acs_url, sp_entity_id}, UserStore, ROLE_ADMIN, ROLE_USER};
+    use crate::test_support::*;
+    use crate::xmldsig::canonicalize;
+
+    const IDP_ENTITY_ID: &str = "https://idp.example.com";
+    const NS_SAML: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+    const NS_DS: &str = "http://www.w3.org/2000/09/xmldsig#";
+
+    // 测试用IdP：自签名证书，对断言做封装签名
+    struct TestIdp {
+        key: PKey<Private>,
+        certificate: String,
+    }
+
+    impl TestIdp {
+        fn new() -> TestIdp {
+            let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+            let mut name = X509NameBuilder::new().unwrap();
+            name.append_entry_by_text("CN", "test idp").unwrap();
+            let name = name.build();
+            let mut builder = X509Builder::new().unwrap();
+            builder.set_version(2).unwrap();
+            builder.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap()).unwrap();
+            builder.set_subject_name(&name).unwrap();
+            builder.set_issuer_name(&name).unwrap();
+            builder.set_pubkey(&key).unwrap();
+            builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+            builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+            builder.sign(&key, MessageDigest::sha256()).unwrap();
+            let certificate = STANDARD.encode(builder.build().to_der().unwrap());
+            TestIdp { key, certificate }
+        }
+
+        fn metadata(&self) -> String {
+            format!(
+                r#"<md:EntityDescriptor xmlns:md="urn:oasis:names:tc:SAML:2.0:metadata" xmlns:ds="{}" entityID="{}"><md:IDPSSODescriptor protocolSupportEnumeration="urn:oasis:names:tc:SAML:2.0:protocol"><md:KeyDescriptor use="signing"><ds:KeyInfo><ds:X509Data><ds:X509Certificate>{}</ds:X509Certificate></ds:X509Data></ds:KeyInfo></md:KeyDescriptor><md:SingleSignOnService Binding="urn:oasis:names:tc:SAML:2.0:bindings:HTTP-Redirect" Location="{}/sso"/></md:IDPSSODescriptor></md:EntityDescriptor>"#,
+                NS_DS, IDP_ENTITY_ID, self.certificate, IDP_ENTITY_ID,
+            )
+        }
+
+        // IdP发起登录的响应，断言已签名，返回Base64编码的SAMLResponse
+        fn response(&self, tenant: &str, email: &str) -> String {
+            let now = Utc::now();
+            let format_time = |time: chrono::DateTime<Utc>| time.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+            let assertion_id = format!("_{}", Uuid::new_v4().simple());
+            let body = format!(
+                r#"<saml:Issuer>{issuer}</saml:Issuer><saml:Subject><saml:NameID Format="urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress">{email}</saml:NameID><saml:SubjectConfirmation Method="urn:oasis:names:tc:SAML:2.0:cm:bearer"><saml:SubjectConfirmationData NotOnOrAfter="{expires}" Recipient="{acs}"/></saml:SubjectConfirmation></saml:Subject><saml:Conditions NotBefore="{issued}" NotOnOrAfter="{expires}"><saml:AudienceRestriction><saml:Audience>{audience}</saml:Audience></saml:AudienceRestriction></saml:Conditions>"#,
+                issuer = IDP_ENTITY_ID,
+                email = email,
+                expires = format_time(now + Duration::minutes(5)),
+                issued = format_time(now - Duration::minutes(1)),
+                acs = acs_url(tenant),
+                audience = sp_entity_id(tenant),
+            );
+            let assertion = |signature: &str| {
+                format!(
+                    r#"<saml:Assertion xmlns:saml="{}" ID="{}" IssueInstant="{}" Version="2.0">{}{}</saml:Assertion>"#,
+                    NS_SAML, assertion_id, format_time(now), signature, body,
+                )
+            };
+
+            let unsigned = assertion("");
+            let doc = Document::parse(&unsigned).unwrap();
+            let digest = STANDARD.encode(openssl::sha::sha256(canonicalize(doc.root_element(), None, &[]).as_bytes()));
+            let signed_info = format!(
+                r##"<ds:SignedInfo xmlns:ds="{}"><ds:CanonicalizationMethod Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/><ds:SignatureMethod Algorithm="http://www.w3.org/2001/04/xmldsig-more#rsa-sha256"/><ds:Reference URI="#{}"><ds:Transforms><ds:Transform Algorithm="http://www.w3.org/2000/09/xmldsig#enveloped-signature"/><ds:Transform Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/></ds:Transforms><ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"/><ds:DigestValue>{}</ds:DigestValue></ds:Reference></ds:SignedInfo>"##,
+                NS_DS, assertion_id, digest,
+            );
+            let doc = Document::parse(&signed_info).unwrap();
+            let mut signer = Signer::new(MessageDigest::sha256(), &self.key).unwrap();
+            signer.update(canonicalize(doc.root_element(), None, &[]).as_bytes()).unwrap();
+            let signature = format!(
+                r#"<ds:Signature xmlns:ds="{}">{}<ds:SignatureValue>{}</ds:SignatureValue></ds:Signature>"#,
+                NS_DS, signed_info, STANDARD.encode(signer.sign_to_vec().unwrap()),
+            );
+
+            let response = format!(
+                r#"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol" ID="_{}" Version="2.0" IssueInstant="{}" Destination="{}"><samlp:Status><samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Success"/></samlp:Status>{}</samlp:Response>"#,
+                Uuid::new_v4().simple(), format_time(now), acs_url(tenant), assertion(&signature),
+            );
+            STANDARD.encode(response)
+        }
+    }
+
+    async fn post_response(app: &TestApp, tenant: &str, saml_response: &str) -> TestResponse {
+        let body = form_urlencoded::Serializer::new(String::new())
+            .append_pair("SAMLResponse", saml_response)
+            .finish();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("/saml/{}/acs", tenant))
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap();
+        app.call(request).await
+    }
+
+    // 新组织的管理员为其配置SAML连接，返回组织ID、管理员令牌和租户标识
+    async fn setup(app: &TestApp, idp: &TestIdp) -> (Uuid, String, String) {
+        let (organization_id, _) = create_organization(&app.pool).await;
+        let admin = create_user(&app.pool, organization_id, ROLE_ADMIN).await;
+        let token = app.login(&admin.email).await;
+
+        let tenant = unique("saml");
+        let response = app
+            .request(Method::PUT, &format!("/admin/saml/{}", tenant), Some(&token), Some(json!({
+                "idp_metadata": idp.metadata(),
+                "email_domains": ["example.com"],
+                "jit_provisioning": true,
+            })))
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.body["organization_id"], organization_id.to_string());
+
+        (organization_id, token, tenant)
+    }
+
+    #[tokio::test]
+    async fn saml_login_is_scoped_to_connection_organization() {
+        let Some(app) = TestApp::new().await else { return };
+        let idp = TestIdp::new();
+        let (organization_id, _, tenant) = setup(&app, &idp).await;
+
+        // 其他组织中同域名的账号不能通过该连接登录
+        let other = create_user(&app.pool, default_organization(&app.pool).await, ROLE_USER).await;
+        let response = post_response(&app, &tenant, &idp.response(&tenant, &other.email)).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED, "{}", response.body);
+
+        let member = create_user(&app.pool, organization_id, ROLE_USER).await;
+        let response = post_response(&app, &tenant, &idp.response(&tenant, &member.email)).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert!(response.body["token"].is_string());
+
+        // 自动创建的用户归属连接所在的组织
+        let email = format!("{}@example.com", unique("saml-jit"));
+        let response = post_response(&app, &tenant, &idp.response(&tenant, &email)).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        let user = UserStore::find_by_email(&app.pool, &email).await.unwrap().unwrap();
+        assert_eq!(user.organization_id, organization_id);
+    }
+
+    #[tokio::test]
+    async fn saml_rejects_replay_and_foreign_signatures() {
+        let Some(app) = TestApp::new().await else { return };
+        let idp = TestIdp::new();
+        let (organization_id, _, tenant) = setup(&app, &idp).await;
+        let member = create_user(&app.pool, organization_id, ROLE_USER).await;
+
+        let saml_response = idp.response(&tenant, &member.email);
+        let response = post_response(&app, &tenant, &saml_response).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        let response = post_response(&app, &tenant, &saml_response).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+        let impostor = TestIdp::new();
+        let response = post_response(&app, &tenant, &impostor.response(&tenant, &member.email)).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn saml_connection_cannot_be_taken_over_by_other_organization() {
+        let Some(app) = TestApp::new().await else { return };
+        let idp = TestIdp::new();
+        let (_, _, tenant) = setup(&app, &idp).await;
+
+        let (other_organization, _) = create_organization(&app.pool).await;
+        let other_admin = create_user(&app.pool, other_organization, ROLE_ADMIN).await;
+        let token = app.login(&other_admin.email).await;
+        let attacker = TestIdp::new();
+        let response = app
+            .request(Method::PUT, &format!("/admin/saml/{}", tenant), Some(&token), Some(json!({
+                "idp_metadata": attacker.metadata(),
+                "email_domains": ["example.com"],
+            })))
+            .await;
+        assert_eq!(response.status, StatusCode::CONFLICT, "{}", response.body);
+
+        // 其他组织的管理员看不到也不能删除该连接
+        let response = app.request(Method::GET, "/admin/saml", Some(&token), None).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, json!([]));
+        let response = app.request(Method::DELETE, &format!("/admin/saml/{}", tenant), Some(&token), None).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,822 @@
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{Extension, FromRequestParts, Json, Path, Query},
+    http::{header::{CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH, LOCATION}, request::Parts, HeaderMap, HeaderName, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+use crate::{db::{self, DbPool}, extractor::{bearer_token, AdminUser}};
+use crate::model::scim::*;
+
+// SCIM响应的媒体类型
+const SCIM_CONTENT_TYPE: &str = "application/scim+json";
+
+// SCIM错误响应，格式见RFC 7644 3.12
+pub struct ScimErrorResponse {
+    status: StatusCode,
+    scim_type: Option<&'static str>,
+    detail: String,
+}
+
+impl ScimErrorResponse {
+    fn new(status: StatusCode, scim_type: Option<&'static str>, detail: impl Into<String>) -> Self {
+        ScimErrorResponse { status, scim_type, detail: detail.into() }
+    }
+}
+
+impl From<ScimError> for ScimErrorResponse {
+    fn from(err: ScimError) -> Self {
+        let (status, scim_type) = match &err {
+            ScimError::NotFound => (StatusCode::NOT_FOUND, None),
+            ScimError::Uniqueness(_) => (StatusCode::CONFLICT, Some("uniqueness")),
+            ScimError::InvalidFilter(_) => (StatusCode::BAD_REQUEST, Some("invalidFilter")),
+            ScimError::InvalidValue(_) => (StatusCode::BAD_REQUEST, Some("invalidValue")),
+            ScimError::InvalidSyntax(_) => (StatusCode::BAD_REQUEST, Some("invalidSyntax")),
+            ScimError::NoTarget(_) => (StatusCode::BAD_REQUEST, Some("noTarget")),
+            ScimError::PreconditionFailed => (StatusCode::PRECONDITION_FAILED, None),
+            ScimError::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, None),
+        };
+        ScimErrorResponse::new(status, scim_type, err.to_string())
+    }
+}
+
+impl From<sqlx::Error> for ScimErrorResponse {
+    fn from(err: sqlx::Error) -> Self {
+        ScimError::from(err).into()
+    }
+}
+
+impl IntoResponse for ScimErrorResponse {
+    fn into_response(self) -> Response {
+        let mut body = json!({
+            "schemas": [SCHEMA_ERROR],
+            "status": self.status.as_u16().to_string(),
+            "detail": self.detail,
+        });
+        if let Some(scim_type) = self.scim_type {
+            body["scimType"] = json!(scim_type);
+        }
+        (self.status, [(CONTENT_TYPE, SCIM_CONTENT_TYPE)], Json(body)).into_response()
+    }
+}
+
+// 通过SCIM令牌认证的租户，SCIM请求只能访问令牌所属组织的资源
+pub struct ScimTenant(pub Uuid);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ScimTenant {
+    type Rejection = ScimErrorResponse;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let unauthorized = || ScimErrorResponse::new(StatusCode::UNAUTHORIZED, None, "SCIM令牌无效");
+
+        let Extension(pool) = Extension::<DbPool>::from_request_parts(parts, &())
+            .await
+            .map_err(|err| ScimErrorResponse::new(StatusCode::INTERNAL_SERVER_ERROR, None, err.to_string()))?;
+        let token = bearer_token(parts).ok_or_else(unauthorized)?;
+        let organization_id = ScimTokenStore::authenticate(&pool, token).await?.ok_or_else(unauthorized)?;
+
+        Ok(ScimTenant(organization_id))
+    }
+}
+
+async fn begin_tenant(pool: &DbPool, organization_id: Uuid) -> Result<Transaction<'static, Postgres>, ScimErrorResponse> {
+    Ok(db::begin_tenant(pool, organization_id).await?)
+}
+
+// 资源ID不是UUID时按不存在处理
+fn parse_id(id: &str) -> Result<Uuid, ScimErrorResponse> {
+    id.parse().map_err(|_| ScimError::NotFound.into())
+}
+
+// 请求体按SCIM媒体类型提交，不依赖Content-Type解析
+fn parse_body<T: DeserializeOwned>(body: &Bytes) -> Result<T, ScimErrorResponse> {
+    serde_json::from_slice(body).map_err(|err| ScimError::InvalidSyntax(err.to_string()).into())
+}
+
+fn header(headers: &HeaderMap, name: HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn scim_json<T: Serialize>(status: StatusCode, body: T) -> Response {
+    (status, [(CONTENT_TYPE, SCIM_CONTENT_TYPE)], Json(body)).into_response()
+}
+
+// 单个资源的响应，带上ETag；创建时还返回Location
+fn resource_response<T: Serialize>(status: StatusCode, body: T, meta: (&str, &str)) -> Response {
+    let (version, location) = meta;
+    let mut response = scim_json(status, body);
+    let headers = response.headers_mut();
+    if let Ok(value) = version.parse() {
+        headers.insert(ETAG, value);
+    }
+    if status == StatusCode::CREATED {
+        if let Ok(value) = location.parse() {
+            headers.insert(LOCATION, value);
+        }
+    }
+    response
+}
+
+// If-None-Match与当前版本一致时返回304
+fn not_modified(headers: &HeaderMap, version: &str) -> bool {
+    header(headers, IF_NONE_MATCH)
+        .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == "*" || tag.trim() == version))
+}
+
+fn user_response(status: StatusCode, user: ScimUser) -> Response {
+    let (version, location) = (user.meta.version.clone(), user.meta.location.clone());
+    resource_response(status, user, (&version, &location))
+}
+
+fn group_response(status: StatusCode, group: ScimGroup) -> Response {
+    let (version, location) = (group.meta.version.clone(), group.meta.location.clone());
+    resource_response(status, group, (&version, &location))
+}
+
+// 查询用户，支持filter和分页
+pub async fn list_users(
+    Extension(pool): Extension<DbPool>,
+    ScimTenant(organization_id): ScimTenant,
+    Query(query): Query<ListQuery>,
+) -> Result<Response, ScimErrorResponse> {
+    let mut tx = begin_tenant(&pool, organization_id).await?;
+    let users = ScimStore::list_users(&mut tx, &query).await?;
+    Ok(scim_json(StatusCode::OK, users))
+}
+
+// 获取单个用户
+pub async fn get_user(
+    Extension(pool): Extension<DbPool>,
+    ScimTenant(organization_id): ScimTenant,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, ScimErrorResponse> {
+    let user_id = parse_id(&id)?;
+    let mut tx = begin_tenant(&pool, organization_id).await?;
+    let user = ScimStore::get_user(&mut tx, user_id).await?;
+    if not_modified(&headers, &user.meta.version) {
+        return Ok(StatusCode::NOT_MODIFIED.into_response());
+    }
+    Ok(user_response(StatusCode::OK, user))
+}
+
+// 创建用户
+pub async fn create_user(
+    Extension(pool): Extension<DbPool>,
+    ScimTenant(organization_id): ScimTenant,
+    body: Bytes,
+) -> Result<Response, ScimErrorResponse> {
+    let input = parse_body::<ScimUserInput>(&body)?;
+    let mut tx = begin_tenant(&pool, organization_id).await?;
+    let user = ScimStore::create_user(&mut tx, organization_id, input).await?;
+    tx.commit().await?;
+    Ok(user_response(StatusCode::CREATED, user))
+}
+
+// 替换用户
+pub async fn replace_user(
+    Extension(pool): Extension<DbPool>,
+    ScimTenant(organization_id): ScimTenant,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ScimErrorResponse> {
+    let user_id = parse_id(&id)?;
+    let input = parse_body::<ScimUserInput>(&body)?;
+    let mut tx = begin_tenant(&pool, organization_id).await?;
+    let user = ScimStore::replace_user(&mut tx, user_id, header(&headers, IF_MATCH), input).await?;
+    tx.commit().await?;
+    Ok(user_response(StatusCode::OK, user))
+}
+
+// 修改用户
+pub async fn patch_user(
+    Extension(pool): Extension<DbPool>,
+    ScimTenant(organization_id): ScimTenant,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ScimErrorResponse> {
+    let user_id = parse_id(&id)?;
+    let request = parse_body::<PatchRequest>(&body)?;
+    let mut tx = begin_tenant(&pool, organization_id).await?;
+    let user = ScimStore::patch_user(&mut tx, user_id, header(&headers, IF_MATCH), &request).await?;
+    tx.commit().await?;
+    Ok(user_response(StatusCode::OK, user))
+}
+
+// 删除用户
+pub async fn delete_user(
+    Extension(pool): Extension<DbPool>,
+    ScimTenant(organization_id): ScimTenant,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ScimErrorResponse> {
+    let user_id = parse_id(&id)?;
+    let mut tx = begin_tenant(&pool, organization_id).await?;
+    ScimStore::delete_user(&mut tx, user_id, header(&headers, IF_MATCH)).await?;
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// 查询分组，支持filter和分页
+pub async fn list_groups(
+    Extension(pool): Extension<DbPool>,
+    ScimTenant(organization_id): ScimTenant,
+    Query(query): Query<ListQuery>,
+) -> Result<Response, ScimErrorResponse> {
+    let mut tx = begin_tenant(&pool, organization_id).await?;
+    let groups = ScimStore::list_groups(&mut tx, &query).await?;
+    Ok(scim_json(StatusCode::OK, groups))
+}
+
+// 获取单个分组
+pub async fn get_group(
+    Extension(pool): Extension<DbPool>,
+    ScimTenant(organization_id): ScimTenant,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, ScimErrorResponse> {
+    let group_id = parse_id(&id)?;
+    let mut tx = begin_tenant(&pool, organization_id).await?;
+    let group = ScimStore::get_group(&mut tx, group_id).await?;
+    if not_modified(&headers, &group.meta.version) {
+        return Ok(StatusCode::NOT_MODIFIED.into_response());
+    }
+    Ok(group_response(StatusCode::OK, group))
+}
+
+// 创建分组
+pub async fn create_group(
+    Extension(pool): Extension<DbPool>,
+    ScimTenant(organization_id): ScimTenant,
+    body: Bytes,
+) -> Result<Response, ScimErrorResponse> {
+    let input = parse_body::<ScimGroupInput>(&body)?;
+    let mut tx = begin_tenant(&pool, organization_id).await?;
+    let group = ScimStore::create_group(&mut tx, organization_id, input).await?;
+    tx.commit().await?;
+    Ok(group_response(StatusCode::CREATED, group))
+}
+
+// 替换分组
+pub async fn replace_group(
+    Extension(pool): Extension<DbPool>,
+    ScimTenant(organization_id): ScimTenant,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ScimErrorResponse> {
+    let group_id = parse_id(&id)?;
+    let input = parse_body::<ScimGroupInput>(&body)?;
+    let mut tx = begin_tenant(&pool, organization_id).await?;
+    let group = ScimStore::replace_group(&mut tx, group_id, header(&headers, IF_MATCH), input).await?;
+    tx.commit().await?;
+    Ok(group_response(StatusCode::OK, group))
+}
+
+// 修改分组
+pub async fn patch_group(
+    Extension(pool): Extension<DbPool>,
+    ScimTenant(organization_id): ScimTenant,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ScimErrorResponse> {
+    let group_id = parse_id(&id)?;
+    let request = parse_body::<PatchRequest>(&body)?;
+    let mut tx = begin_tenant(&pool, organization_id).await?;
+    let group = ScimStore::patch_group(&mut tx, group_id, header(&headers, IF_MATCH), &request).await?;
+    tx.commit().await?;
+    Ok(group_response(StatusCode::OK, group))
+}
+
+// 删除分组
+pub async fn delete_group(
+    Extension(pool): Extension<DbPool>,
+    ScimTenant(organization_id): ScimTenant,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ScimErrorResponse> {
+    let group_id = parse_id(&id)?;
+    let mut tx = begin_tenant(&pool, organization_id).await?;
+    ScimStore::delete_group(&mut tx, group_id, header(&headers, IF_MATCH)).await?;
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn attribute(name: &str, attribute_type: &str, required: bool, uniqueness: &str) -> Value {
+    json!({
+        "name": name,
+        "type": attribute_type,
+        "multiValued": false,
+        "required": required,
+        "caseExact": false,
+        "mutability": "readWrite",
+        "returned": "default",
+        "uniqueness": uniqueness,
+    })
+}
+
+fn user_schema() -> Value {
+    json!({
+        "schemas": ["urn:ietf:params:scim:schemas:core:2.0:Schema"],
+        "id": SCHEMA_USER,
+        "name": "User",
+        "description": "用户",
+        "attributes": [
+            attribute("userName", "string", true, "server"),
+            attribute("externalId", "string", false, "none"),
+            {
+                "name": "name",
+                "type": "complex",
+                "multiValued": false,
+                "required": false,
+                "mutability": "readWrite",
+                "returned": "default",
+                "subAttributes": [
+                    attribute("formatted", "string", false, "none"),
+                    attribute("givenName", "string", false, "none"),
+                    attribute("familyName", "string", false, "none"),
+                ],
+            },
+            attribute("displayName", "string", false, "none"),
+            {
+                "name": "emails",
+                "type": "complex",
+                "multiValued": true,
+                "required": false,
+                "mutability": "readWrite",
+                "returned": "default",
+                "subAttributes": [
+                    attribute("value", "string", true, "server"),
+                    attribute("type", "string", false, "none"),
+                    attribute("primary", "boolean", false, "none"),
+                ],
+            },
+            attribute("active", "boolean", false, "none"),
+            {
+                "name": "password",
+                "type": "string",
+                "multiValued": false,
+                "required": false,
+                "mutability": "writeOnly",
+                "returned": "never",
+            },
+        ],
+        "meta": {
+            "resourceType": "Schema",
+            "location": format!("{}/Schemas/{}", scim_base_url(), SCHEMA_USER),
+        },
+    })
+}
+
+fn group_schema() -> Value {
+    json!({
+        "schemas": ["urn:ietf:params:scim:schemas:core:2.0:Schema"],
+        "id": SCHEMA_GROUP,
+        "name": "Group",
+        "description": "分组",
+        "attributes": [
+            attribute("displayName", "string", true, "server"),
+            attribute("externalId", "string", false, "none"),
+            {
+                "name": "members",
+                "type": "complex",
+                "multiValued": true,
+                "required": false,
+                "mutability": "readWrite",
+                "returned": "default",
+                "subAttributes": [
+                    attribute("value", "string", true, "none"),
+                    attribute("display", "string", false, "none"),
+                    {
+                        "name": "type",
+                        "type": "string",
+                        "multiValued": false,
+                        "required": false,
+                        "canonicalValues": ["User", "Group"],
+                        "mutability": "immutable",
+                        "returned": "default",
+                    },
+                ],
+            },
+        ],
+        "meta": {
+            "resourceType": "Schema",
+            "location": format!("{}/Schemas/{}", scim_base_url(), SCHEMA_GROUP),
+        },
+    })
+}
+
+fn resource_type_definitions() -> [Value; 2] {
+    let base_url = scim_base_url();
+    [
+        json!({
+            "schemas": ["urn:ietf:params:scim:schemas:core:2.0:ResourceType"],
+            "id": "User",
+            "name": "User",
+            "endpoint": "/Users",
+            "schema": SCHEMA_USER,
+            "meta": { "resourceType": "ResourceType", "location": format!("{}/ResourceTypes/User", base_url) },
+        }),
+        json!({
+            "schemas": ["urn:ietf:params:scim:schemas:core:2.0:ResourceType"],
+            "id": "Group",
+            "name": "Group",
+            "endpoint": "/Groups",
+            "schema": SCHEMA_GROUP,
+            "meta": { "resourceType": "ResourceType", "location": format!("{}/ResourceTypes/Group", base_url) },
+        }),
+    ]
+}
+
+fn list_of(resources: Vec<Value>) -> Response {
+    scim_json(StatusCode::OK, ListResponse {
+        schemas: [SCHEMA_LIST_RESPONSE],
+        total_results: resources.len() as i64,
+        start_index: 1,
+        items_per_page: resources.len(),
+        resources,
+    })
+}
+
+// 服务能力说明，发现接口不需要认证
+pub async fn service_provider_config() -> Response {
+    scim_json(StatusCode::OK, json!({
+        "schemas": ["urn:ietf:params:scim:schemas:core:2.0:ServiceProviderConfig"],
+        "patch": { "supported": true },
+        "bulk": { "supported": false, "maxOperations": 0, "maxPayloadSize": 0 },
+        "filter": { "supported": true, "maxResults": MAX_RESULTS },
+        "changePassword": { "supported": true },
+        "sort": { "supported": false },
+        "etag": { "supported": true },
+        "authenticationSchemes": [{
+            "type": "oauthbearertoken",
+            "name": "Bearer Token",
+            "description": "使用管理员创建的SCIM令牌认证",
+            "primary": true,
+        }],
+        "meta": {
+            "resourceType": "ServiceProviderConfig",
+            "location": format!("{}/ServiceProviderConfig", scim_base_url()),
+        },
+    }))
+}
+
+// 支持的schema
+pub async fn list_schemas() -> Response {
+    list_of(vec![user_schema(), group_schema()])
+}
+
+pub async fn get_schema(Path(id): Path<String>) -> Result<Response, ScimErrorResponse> {
+    [user_schema(), group_schema()]
+        .into_iter()
+        .find(|schema| schema["id"] == id.as_str())
+        .map(|schema| scim_json(StatusCode::OK, schema))
+        .ok_or_else(|| ScimError::NotFound.into())
+}
+
+// 支持的资源类型
+pub async fn list_resource_types() -> Response {
+    list_of(resource_type_definitions().into())
+}
+
+pub async fn get_resource_type(Path(id): Path<String>) -> Result<Response, ScimErrorResponse> {
+    resource_type_definitions()
+        .into_iter()
+        .find(|resource_type| resource_type["id"] == id.as_str())
+        .map(|resource_type| scim_json(StatusCode::OK, resource_type))
+        .ok_or_else(|| ScimError::NotFound.into())
+}
+
+// 为管理员所在组织创建SCIM令牌，令牌只在响应中返回一次
+pub async fn create_token(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Json(req): Json<CreateScimTokenRequest>,
+) -> Result<(StatusCode, Json<CreatedScimToken>), (StatusCode, String)> {
+    match ScimTokenStore::create(&pool, admin.user.organization_id, admin.user.id, &req).await {
+        Ok(token) => Ok((StatusCode::CREATED, Json(token))),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// 获取管理员所在组织的SCIM令牌
+pub async fn list_tokens(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+) -> Result<Json<Vec<ScimToken>>, (StatusCode, String)> {
+    match ScimTokenStore::find_by_organization(&pool, admin.user.organization_id).await {
+        Ok(tokens) => Ok(Json(tokens)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// 删除SCIM令牌，使用该令牌的同步立即失效
+pub async fn revoke_token(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Path(token_id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match ScimTokenStore::revoke(&pool, admin.user.organization_id, admin.user.id, token_id).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err((StatusCode::NOT_FOUND, "SCIM令牌不存在".to_string())),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // SCIM一致性测试：按身份提供方同步的方式调用接口，检查RFC 7644要求的状态码、scimType、ETag和列表格式
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+    use url::form_urlencoded::byte_serialize;
+    use uuid::Uuid;
+    use crate::model::{password_reset::MIN_PASSWORD_LENGTH, ROLE_ADMIN, ROLE_USER};
+    use crate::scim_filter::MAX_FILTER_DEPTH;
+    use crate::test_support::*;
+
+    const SCHEMA_USER: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+    const SCHEMA_ERROR: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+    const SCHEMA_LIST: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+    const SCHEMA_PATCH: &str = "urn:ietf:params:scim:api:messages:2.0:PatchOp";
+
+    // 在新组织中创建SCIM令牌
+    async fn scim_token(app: &TestApp) -> (Uuid, String) {
+        let (organization_id, _) = create_organization(&app.pool).await;
+        let admin = create_user(&app.pool, organization_id, ROLE_ADMIN).await;
+        let token = app.login(&admin.email).await;
+        let response = app
+            .request(Method::POST, "/admin/scim/tokens", Some(&token), Some(json!({ "name": "一致性测试" })))
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+        (organization_id, response.body["token"].as_str().unwrap().to_string())
+    }
+
+    fn user_body(user_name: &str) -> Value {
+        json!({
+            "schemas": [SCHEMA_USER],
+            "userName": user_name,
+            "externalId": unique("ext"),
+            "name": { "givenName": "三", "familyName": "张" },
+            "emails": [{ "value": user_name, "type": "work", "primary": true }],
+            "active": true,
+        })
+    }
+
+    fn assert_error(response: &TestResponse, status: StatusCode, scim_type: Option<&str>) {
+        assert_eq!(response.status, status, "{}", response.body);
+        assert_eq!(response.header("content-type"), Some("application/scim+json"));
+        assert_eq!(response.body["schemas"], json!([SCHEMA_ERROR]));
+        assert_eq!(response.body["status"], status.as_u16().to_string());
+        assert_eq!(response.body["scimType"].as_str(), scim_type);
+    }
+
+    fn filter_uri(filter: &str) -> String {
+        format!("/scim/v2/Users?filter={}", byte_serialize(filter.as_bytes()).collect::<String>())
+    }
+
+    #[tokio::test]
+    async fn discovery_endpoints_need_no_token() {
+        let Some(app) = TestApp::new().await else { return };
+        let response = app.request(Method::GET, "/scim/v2/ServiceProviderConfig", None, None).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body["patch"]["supported"], true);
+        assert_eq!(response.body["etag"]["supported"], true);
+
+        let response = app.request(Method::GET, "/scim/v2/ResourceTypes", None, None).await;
+        assert_eq!(response.body["schemas"], json!([SCHEMA_LIST]));
+        let response = app.request(Method::GET, &format!("/scim/v2/Schemas/{}", SCHEMA_USER), None, None).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body["id"], SCHEMA_USER);
+
+        let response = app.request(Method::GET, "/scim/v2/Users", None, None).await;
+        assert_error(&response, StatusCode::UNAUTHORIZED, None);
+    }
+
+    #[tokio::test]
+    async fn user_lifecycle() {
+        let Some(app) = TestApp::new().await else { return };
+        let (_, token) = scim_token(&app).await;
+        let user_name = format!("{}@example.com", unique("scim"));
+
+        let response = app.request(Method::POST, "/scim/v2/Users", Some(&token), Some(user_body(&user_name))).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+        assert_eq!(response.header("content-type"), Some("application/scim+json"));
+        let id = response.body["id"].as_str().unwrap().to_string();
+        let location = response.header("location").unwrap().to_string();
+        assert!(location.ends_with(&format!("/scim/v2/Users/{}", id)));
+        assert_eq!(response.body["meta"]["location"], location);
+        let etag = response.header("etag").unwrap().to_string();
+        assert_eq!(response.body["meta"]["version"], etag);
+        assert_eq!(response.body["userName"], user_name);
+        assert_eq!(response.body["active"], true);
+        assert!(response.body.get("password").is_none());
+
+        let uri = format!("/scim/v2/Users/{}", id);
+        let response = app.send(Method::GET, &uri, Some(&token), &[("if-none-match", &etag)], None).await;
+        assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+
+        // userName过滤不区分大小写
+        let response = app
+            .request(Method::GET, &filter_uri(&format!(r#"userName eq "{}""#, user_name.to_uppercase())), Some(&token), None)
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.body["schemas"], json!([SCHEMA_LIST]));
+        assert_eq!(response.body["totalResults"], 1);
+        assert_eq!(response.body["Resources"][0]["id"], id);
+
+        // 过期的If-Match返回412
+        let mut body = user_body(&user_name);
+        body["active"] = json!(false);
+        let response = app.send(Method::PUT, &uri, Some(&token), &[("if-match", "W/\"stale\"")], Some(body.clone())).await;
+        assert_error(&response, StatusCode::PRECONDITION_FAILED, None);
+        let response = app.send(Method::PUT, &uri, Some(&token), &[("if-match", &etag)], Some(body)).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.body["active"], false);
+        assert_ne!(response.header("etag").unwrap(), etag);
+
+        let response = app
+            .request(Method::PATCH, &uri, Some(&token), Some(json!({
+                "schemas": [SCHEMA_PATCH],
+                "Operations": [
+                    { "op": "replace", "path": "active", "value": true },
+                    { "op": "replace", "path": "displayName", "value": "李四" },
+                ],
+            })))
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.body["active"], true);
+        assert_eq!(response.body["displayName"], "李四");
+
+        let response = app
+            .request(Method::PATCH, &uri, Some(&token), Some(json!({
+                "schemas": [SCHEMA_PATCH],
+                "Operations": [{ "op": "replace", "path": r#"emails[type eq "home"].value"#, "value": "x@example.com" }],
+            })))
+            .await;
+        assert_error(&response, StatusCode::BAD_REQUEST, Some("noTarget"));
+
+        let response = app.request(Method::DELETE, &uri, Some(&token), None).await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let response = app.request(Method::GET, &uri, Some(&token), None).await;
+        assert_error(&response, StatusCode::NOT_FOUND, None);
+        let response = app.request(Method::GET, "/scim/v2/Users/not-a-uuid", Some(&token), None).await;
+        assert_error(&response, StatusCode::NOT_FOUND, None);
+    }
+
+    #[tokio::test]
+    async fn list_is_paginated() {
+        let Some(app) = TestApp::new().await else { return };
+        let (_, token) = scim_token(&app).await;
+        let domain = unique("page");
+        for index in 0..3 {
+            let user_name = format!("user{}@{}.example.com", index, domain);
+            let response = app.request(Method::POST, "/scim/v2/Users", Some(&token), Some(user_body(&user_name))).await;
+            assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+        }
+
+        let filter = filter_uri(&format!(r#"userName ew "@{}.example.com""#, domain));
+        let response = app.request(Method::GET, &format!("{}&startIndex=2&count=1", filter), Some(&token), None).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.body["totalResults"], 3);
+        assert_eq!(response.body["startIndex"], 2);
+        assert_eq!(response.body["itemsPerPage"], 1);
+        assert_eq!(response.body["Resources"].as_array().unwrap().len(), 1);
+
+        let response = app.request(Method::GET, &format!("{}&count=0", filter), Some(&token), None).await;
+        assert_eq!(response.body["totalResults"], 3);
+        assert_eq!(response.body["Resources"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn invalid_filters_are_rejected() {
+        let Some(app) = TestApp::new().await else { return };
+        let (_, token) = scim_token(&app).await;
+
+        let deep = format!("{}userName pr{}", "(".repeat(MAX_FILTER_DEPTH + 1), ")".repeat(MAX_FILTER_DEPTH + 1));
+        let chain = vec!["userName pr"; 1000].join(" or ");
+        for filter in [r#"userName eq"#, r#"title eq "x""#, r#"active gt true"#, deep.as_str(), chain.as_str()] {
+            let response = app.request(Method::GET, &filter_uri(filter), Some(&token), None).await;
+            assert_error(&response, StatusCode::BAD_REQUEST, Some("invalidFilter"));
+        }
+    }
+
+    // userName在所有组织中唯一且不区分大小写，冲突时返回uniqueness
+    #[tokio::test]
+    async fn user_name_must_be_unique_across_organizations() {
+        let Some(app) = TestApp::new().await else { return };
+        let (_, token) = scim_token(&app).await;
+
+        let other = create_user(&app.pool, default_organization(&app.pool).await, ROLE_USER).await;
+        for user_name in [other.email.clone(), other.email.to_uppercase()] {
+            let response = app.request(Method::POST, "/scim/v2/Users", Some(&token), Some(user_body(&user_name))).await;
+            assert_error(&response, StatusCode::CONFLICT, Some("uniqueness"));
+        }
+
+        let user_name = format!("{}@example.com", unique("scim"));
+        let response = app.request(Method::POST, "/scim/v2/Users", Some(&token), Some(user_body(&user_name))).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+        let uri = format!("/scim/v2/Users/{}", response.body["id"].as_str().unwrap());
+        let response = app.request(Method::POST, "/scim/v2/Users", Some(&token), Some(user_body(&user_name.to_uppercase()))).await;
+        assert_error(&response, StatusCode::CONFLICT, Some("uniqueness"));
+
+        // 改名为其他组织已有的邮箱同样冲突
+        let response = app
+            .request(Method::PATCH, &uri, Some(&token), Some(json!({
+                "schemas": [SCHEMA_PATCH],
+                "Operations": [{ "op": "replace", "path": "userName", "value": other.email.to_uppercase() }],
+            })))
+            .await;
+        assert_error(&response, StatusCode::CONFLICT, Some("uniqueness"));
+    }
+
+    #[tokio::test]
+    async fn passwords_respect_minimum_length() {
+        let Some(app) = TestApp::new().await else { return };
+        let (_, token) = scim_token(&app).await;
+        let short = "x".repeat(MIN_PASSWORD_LENGTH - 1);
+
+        let mut body = user_body(&format!("{}@example.com", unique("scim")));
+        body["password"] = json!(short);
+        let response = app.request(Method::POST, "/scim/v2/Users", Some(&token), Some(body.clone())).await;
+        assert_error(&response, StatusCode::BAD_REQUEST, Some("invalidValue"));
+
+        body["password"] = json!(PASSWORD);
+        let response = app.request(Method::POST, "/scim/v2/Users", Some(&token), Some(body.clone())).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+        let uri = format!("/scim/v2/Users/{}", response.body["id"].as_str().unwrap());
+        app.login(body["userName"].as_str().unwrap()).await;
+
+        let response = app
+            .request(Method::PATCH, &uri, Some(&token), Some(json!({
+                "schemas": [SCHEMA_PATCH],
+                "Operations": [{ "op": "replace", "path": "password", "value": short }],
+            })))
+            .await;
+        assert_error(&response, StatusCode::BAD_REQUEST, Some("invalidValue"));
+        app.login(body["userName"].as_str().unwrap()).await;
+    }
+
+    // 令牌只能访问所属组织的资源
+    #[tokio::test]
+    async fn tokens_are_scoped_to_organization() {
+        let Some(app) = TestApp::new().await else { return };
+        let (_, token) = scim_token(&app).await;
+        let (_, other_token) = scim_token(&app).await;
+
+        let user_name = format!("{}@example.com", unique("scim"));
+        let response = app.request(Method::POST, "/scim/v2/Users", Some(&token), Some(user_body(&user_name))).await;
+        let uri = format!("/scim/v2/Users/{}", response.body["id"].as_str().unwrap());
+
+        let response = app.request(Method::GET, &uri, Some(&other_token), None).await;
+        assert_error(&response, StatusCode::NOT_FOUND, None);
+        let response = app.request(Method::DELETE, &uri, Some(&other_token), None).await;
+        assert_error(&response, StatusCode::NOT_FOUND, None);
+        let response = app
+            .request(Method::GET, &filter_uri(&format!(r#"userName eq "{}""#, user_name)), Some(&other_token), None)
+            .await;
+        assert_eq!(response.body["totalResults"], 0);
+    }
+
+    #[tokio::test]
+    async fn group_membership() {
+        let Some(app) = TestApp::new().await else { return };
+        let (_, token) = scim_token(&app).await;
+        let user_name = format!("{}@example.com", unique("scim"));
+        let response = app.request(Method::POST, "/scim/v2/Users", Some(&token), Some(user_body(&user_name))).await;
+        let user_id = response.body["id"].as_str().unwrap().to_string();
+
+        let display_name = unique("group");
+        let response = app
+            .request(Method::POST, "/scim/v2/Groups", Some(&token), Some(json!({
+                "schemas": ["urn:ietf:params:scim:schemas:core:2.0:Group"],
+                "displayName": display_name,
+                "members": [{ "value": user_id }],
+            })))
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+        assert_eq!(response.body["members"][0]["value"], user_id);
+        let uri = format!("/scim/v2/Groups/{}", response.body["id"].as_str().unwrap());
+
+        let response = app
+            .request(Method::POST, "/scim/v2/Groups", Some(&token), Some(json!({ "displayName": display_name })))
+            .await;
+        assert_error(&response, StatusCode::CONFLICT, Some("uniqueness"));
+
+        let response = app
+            .request(Method::PATCH, &uri, Some(&token), Some(json!({
+                "schemas": [SCHEMA_PATCH],
+                "Operations": [{ "op": "remove", "path": format!(r#"members[value eq "{}"]"#, user_id) }],
+            })))
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.body["members"], json!([]));
+    }
+}
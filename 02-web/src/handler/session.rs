@@ -0,0 +1,44 @@
+use axum::{extract::{Extension, Json, Path}, http::StatusCode};
+use uuid::Uuid;
+use crate::{db::DbPool, extractor::AuthUser};
+use crate::model::session::*;
+
+// 获取当前用户的有效会话，标记出当前请求所用的会话
+pub async fn list_sessions(
+    Extension(pool): Extension<DbPool>,
+    auth: AuthUser,
+) -> Result<Json<Vec<SessionInfo>>, (StatusCode, String)> {
+    match SessionStore::find_active_by_user(&pool, auth.user.id).await {
+        Ok(mut sessions) => {
+            for session in &mut sessions {
+                session.current = session.id == auth.session_id;
+            }
+            Ok(Json(sessions))
+        }
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// 注销自己的某个会话
+pub async fn revoke_session(
+    Extension(pool): Extension<DbPool>,
+    auth: AuthUser,
+    Path(session_id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match SessionStore::revoke_for_user(&pool, auth.user.id, session_id).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err((StatusCode::NOT_FOUND, "会话不存在".to_string())),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// 注销除当前会话以外的全部会话
+pub async fn revoke_other_sessions(
+    Extension(pool): Extension<DbPool>,
+    auth: AuthUser,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match SessionStore::revoke_others(&pool, auth.user.id, auth.session_id).await {
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
@@ -0,0 +1,84 @@
+use axum::{extract::{Extension, Json, Path}, http::StatusCode};
+use crate::{db::DbPool, extractor::{AdminUser, AuthUser, PendingAuthUser}, handler::organization::ensure_platform_admin};
+use crate::model::{session::SessionStore, two_factor::*, ROLES};
+
+fn error_response(err: TwoFactorError) -> (StatusCode, String) {
+    match err {
+        TwoFactorError::AlreadyEnabled | TwoFactorError::RequiredByRole => {
+            (StatusCode::CONFLICT, err.to_string())
+        }
+        TwoFactorError::NotEnabled | TwoFactorError::InvalidCode => {
+            (StatusCode::BAD_REQUEST, err.to_string())
+        }
+        err => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+// 开始绑定两步验证
+pub async fn enroll(
+    Extension(pool): Extension<DbPool>,
+    PendingAuthUser(auth): PendingAuthUser,
+) -> Result<Json<EnrollResponse>, (StatusCode, String)> {
+    match TwoFactorStore::enroll(&pool, auth.user.id, &auth.user.email).await {
+        Ok(response) => Ok(Json(response)),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 使用第一个验证码确认绑定
+pub async fn confirm(
+    Extension(pool): Extension<DbPool>,
+    PendingAuthUser(auth): PendingAuthUser,
+    Json(req): Json<TotpCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, (StatusCode, String)> {
+    let recovery_codes = TwoFactorStore::confirm(&pool, auth.user.id, &auth.user.email, &req.code)
+        .await
+        .map_err(error_response)?;
+
+    SessionStore::clear_mfa_enrollment(&pool, auth.user.id)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+// 关闭两步验证
+pub async fn disable(
+    Extension(pool): Extension<DbPool>,
+    auth: AuthUser,
+    Json(req): Json<TotpCodeRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match TwoFactorStore::disable(&pool, auth.user.id, &auth.user.role, &auth.user.email, &req.code).await {
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 获取各角色的两步验证策略
+pub async fn list_role_policies(
+    Extension(pool): Extension<DbPool>,
+    _admin: AdminUser,
+) -> Result<Json<Vec<RoleMfaPolicy>>, (StatusCode, String)> {
+    match TwoFactorStore::list_role_policies(&pool).await {
+        Ok(policies) => Ok(Json(policies)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// 设置角色是否强制两步验证；策略对所有组织生效，只有平台管理员可以修改
+pub async fn set_role_policy(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Path(role): Path<String>,
+    Json(req): Json<RoleMfaPolicyRequest>,
+) -> Result<Json<RoleMfaPolicy>, (StatusCode, String)> {
+    ensure_platform_admin(&pool, &admin).await?;
+    if !ROLES.contains(&role.as_str()) {
+        return Err((StatusCode::BAD_REQUEST, "无效的角色".to_string()));
+    }
+
+    match TwoFactorStore::set_role_policy(&pool, admin.user.id, &role, req.require_totp).await {
+        Ok(policy) => Ok(Json(policy)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
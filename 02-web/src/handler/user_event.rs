@@ -0,0 +1,90 @@
+use std::convert::Infallible;
+use axum::{
+    extract::{ws::{Message, WebSocket, WebSocketUpgrade}, Extension, Query},
+    http::{HeaderMap, StatusCode},
+    response::{sse::{Event, KeepAlive, Sse}, Response},
+};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+use crate::db::DbPool;
+use crate::event_hub::SharedUserEventHub;
+use crate::model::{organization::Tenant, user_event::*};
+
+fn error_response(err: UserEventError) -> (StatusCode, String) {
+    match err {
+        UserEventError::InvalidType(_) | UserEventError::InvalidLastEventId => (StatusCode::BAD_REQUEST, err.to_string()),
+        UserEventError::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+// Last-Event-ID请求头优先于查询参数，EventSource断线重连时会自动携带该请求头
+fn last_event_id(headers: &HeaderMap, query: &UserEventQuery) -> Result<Option<i64>, UserEventError> {
+    match headers.get("last-event-id") {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .map(Some)
+            .ok_or(UserEventError::InvalidLastEventId),
+        None => Ok(query.last_event_id),
+    }
+}
+
+fn subscribe(
+    pool: DbPool,
+    tenant: Tenant,
+    hub: &SharedUserEventHub,
+    headers: &HeaderMap,
+    query: &UserEventQuery,
+) -> Result<mpsc::Receiver<UserEvent>, (StatusCode, String)> {
+    let filter = UserEventFilter::parse(query).map_err(error_response)?;
+    let last_event_id = last_event_id(headers, query).map_err(error_response)?;
+    Ok(hub.subscribe(pool, tenant.organization_id, filter, last_event_id))
+}
+
+// 以Server-Sent Events推送当前组织的用户变更，事件id为事件序号
+pub async fn user_events(
+    Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(hub): Extension<SharedUserEventHub>,
+    headers: HeaderMap,
+    Query(query): Query<UserEventQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    let events = subscribe(pool, tenant, &hub, &headers, &query)?;
+    let stream = ReceiverStream::new(events).map(|event| {
+        let data = serde_json::to_string(&event).unwrap_or_default();
+        Ok(Event::default().id(event.id.to_string()).event(&event.event_type).data(data))
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+// 以WebSocket推送当前组织的用户变更，每条文本消息是一个JSON事件；客户端发送的消息被忽略
+pub async fn user_events_ws(
+    Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(hub): Extension<SharedUserEventHub>,
+    headers: HeaderMap,
+    Query(query): Query<UserEventQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, (StatusCode, String)> {
+    let events = subscribe(pool, tenant, &hub, &headers, &query)?;
+    Ok(ws.on_upgrade(move |socket| forward(socket, events)))
+}
+
+async fn forward(mut socket: WebSocket, mut events: mpsc::Receiver<UserEvent>) {
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let Some(event) = event else { break };
+                let Ok(text) = serde_json::to_string(&event) else { continue };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
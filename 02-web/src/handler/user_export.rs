@@ -0,0 +1,70 @@
+use axum::{
+    body::Body,
+    extract::{Extension, Query},
+    http::{header, StatusCode},
+    response::Response,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use crate::db::DbPool;
+use crate::model::{organization::Tenant, user_export::*};
+use super::begin_tenant;
+
+fn error_response(err: UserExportError) -> (StatusCode, String) {
+    match err {
+        UserExportError::InvalidFormat(_)
+        | UserExportError::InvalidColumn(_)
+        | UserExportError::NoColumns
+        | UserExportError::InvalidStatus => (StatusCode::BAD_REQUEST, err.to_string()),
+        UserExportError::Encode(_) | UserExportError::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+// 以CSV或NDJSON流式导出当前组织的用户，例如 ?format=ndjson&status=active&columns=id,email&gzip=true。
+// 数据边读边写，不会把全部用户读入内存；同时进行的导出达到上限时返回429
+pub async fn export_users(
+    Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Query(query): Query<UserExportQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let export = UserExport::parse(&query).map_err(error_response)?;
+    let permit = try_acquire_permit()
+        .ok_or((StatusCode::TOO_MANY_REQUESTS, "同时进行的导出过多，请稍后重试".to_string()))?;
+    let tx = begin_tenant(&pool, tenant).await?;
+
+    let content_type = export.content_type();
+    let disposition = format!("attachment; filename=\"{}\"", export.file_name());
+    let chunks = export.stream(tx, permit);
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_DISPOSITION, disposition)
+        .body(Body::from_stream(ReceiverStream::new(chunks)))
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::Value;
+    use crate::model::{user_export::try_acquire_permit, ROLE_ADMIN};
+    use crate::test_support::*;
+
+    // 导出名额用完时拒绝新的导出，名额释放后可以继续导出
+    #[tokio::test]
+    async fn concurrent_exports_are_limited() {
+        let Some(app) = TestApp::new().await else { return };
+        let organization_id = default_organization(&app.pool).await;
+        let admin = create_user(&app.pool, organization_id, ROLE_ADMIN).await;
+        let token = app.login(&admin.email).await;
+
+        let permits: Vec<_> = std::iter::from_fn(try_acquire_permit).collect();
+        let response = app.request(Method::GET, "/users/export?columns=id", Some(&token), None).await;
+        assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+
+        drop(permits);
+        let response = app.request(Method::GET, "/users/export?columns=id", Some(&token), None).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        let Value::String(body) = &response.body else { panic!("应当返回CSV") };
+        assert!(body.contains(&admin.id.to_string()));
+    }
+}
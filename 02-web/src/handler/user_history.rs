@@ -0,0 +1,103 @@
+use axum::{extract::{Extension, Json, Path}, http::StatusCode};
+use uuid::Uuid;
+use crate::{db::{self, DbPool}, extractor::AdminUser};
+use crate::model::{organization::Tenant, user_history::*, SharedUserRepository, User, UserError};
+use super::{begin_tenant, internal_error};
+
+fn error_response(err: UserHistoryError) -> (StatusCode, String) {
+    match err {
+        UserHistoryError::NotFound | UserHistoryError::VersionNotFound => (StatusCode::NOT_FOUND, err.to_string()),
+        UserHistoryError::DeletedVersion => (StatusCode::BAD_REQUEST, err.to_string()),
+        UserHistoryError::User(UserError::NotFound) => (StatusCode::NOT_FOUND, err.to_string()),
+        UserHistoryError::User(UserError::EmailExists) => (StatusCode::CONFLICT, err.to_string()),
+        UserHistoryError::User(UserError::VersionConflict) => (StatusCode::CONFLICT, "用户已被同时修改，请重试".to_string()),
+        UserHistoryError::User(_) | UserHistoryError::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+// 获取用户的变更历史，包含每个版本相对上一版本的字段差异
+pub async fn user_history(
+    Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<Vec<UserHistoryEntry>>, (StatusCode, String)> {
+    let mut tx = begin_tenant(&pool, tenant).await?;
+    match UserHistoryStore::history(&mut tx, user_id).await {
+        Ok(entries) => Ok(Json(entries)),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 把用户恢复到指定版本（管理员）
+pub async fn revert_user(
+    Extension(pool): Extension<DbPool>,
+    Extension(users): Extension<SharedUserRepository>,
+    admin: AdminUser,
+    Path(user_id): Path<Uuid>,
+    Json(req): Json<RevertUserRequest>,
+) -> Result<Json<User>, (StatusCode, String)> {
+    let mut tx = db::begin_tenant(&pool, admin.0.user.organization_id).await.map_err(internal_error)?;
+    let user = UserHistoryStore::revert(&mut tx, users.as_ref(), admin.0.user.id, user_id, &req)
+        .await
+        .map_err(error_response)?;
+    tx.commit().await.map_err(internal_error)?;
+    Ok(Json(user))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+    use uuid::Uuid;
+    use crate::model::{event_sourced_user::EventSourcedUserStore, ROLE_ADMIN, ROLE_USER};
+    use crate::test_support::*;
+
+    // 启用事件溯源时，版本恢复也记录到用户的事件流，重放不会撤销恢复
+    #[tokio::test]
+    async fn revert_is_recorded_in_event_stream() {
+        let Some(app) = TestApp::with_repository(Arc::new(EventSourcedUserStore)).await else { return };
+        let organization_id = default_organization(&app.pool).await;
+        let admin = create_user(&app.pool, organization_id, ROLE_ADMIN).await;
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+        let token = app.login(&admin.email).await;
+
+        let uri = format!("/users/{}", user.id);
+        let response = app.request(Method::PUT, &uri, Some(&token), Some(json!({ "name": "改名之后" }))).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+        let uri = format!("/users/{}/revert", user.id);
+        let response = app.request(Method::POST, &uri, Some(&token), Some(json!({ "version": 1 }))).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.body["name"], "测试用户");
+
+        let events: Vec<String> = sqlx::query_scalar(
+            "SELECT event_type FROM user_stream_events WHERE stream_id = $1 ORDER BY version",
+        )
+        .bind(user.id)
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+        assert_eq!(events, ["Registered", "Renamed", "Renamed"]);
+
+        EventSourcedUserStore::replay(&app.pool).await.unwrap();
+        let name: String = sqlx::query_scalar("SELECT name FROM users WHERE id = $1")
+            .bind(user.id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(name, "测试用户");
+    }
+
+    #[tokio::test]
+    async fn revert_unknown_user_is_not_found() {
+        let Some(app) = TestApp::new().await else { return };
+        let organization_id = default_organization(&app.pool).await;
+        let admin = create_user(&app.pool, organization_id, ROLE_ADMIN).await;
+        let token = app.login(&admin.email).await;
+
+        let uri = format!("/users/{}/revert", Uuid::new_v4());
+        let response = app.request(Method::POST, &uri, Some(&token), Some(json!({ "version": 1 }))).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND, "{}", response.body);
+    }
+}
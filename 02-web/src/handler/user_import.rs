@@ -0,0 +1,130 @@
+use std::path::{Path as FilePath, PathBuf};
+use axum::{
+    body::Body,
+    extract::{Extension, Json, Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::Response,
+};
+use tokio::io::AsyncWriteExt;
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use uuid::Uuid;
+use crate::{db::DbPool, extractor::AdminUser};
+use crate::model::user_import::*;
+
+fn error_response(err: UserImportError) -> (StatusCode, String) {
+    match err {
+        UserImportError::NotFound => (StatusCode::NOT_FOUND, err.to_string()),
+        UserImportError::InvalidFormat(_)
+        | UserImportError::InvalidOnDuplicate(_)
+        | UserImportError::InvalidMapping(_)
+        | UserImportError::MissingColumn(_) => (StatusCode::BAD_REQUEST, err.to_string()),
+        UserImportError::TooLarge => (StatusCode::PAYLOAD_TOO_LARGE, err.to_string()),
+        UserImportError::Io(_) | UserImportError::PasswordHash | UserImportError::Database(_) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+        }
+    }
+}
+
+// 未指定format时按Content-Type判断，默认为CSV
+fn format_from_headers(headers: &HeaderMap) -> &'static str {
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or_default();
+    if content_type.starts_with("application/x-ndjson") || content_type.starts_with("application/jsonl") {
+        "ndjson"
+    } else {
+        "csv"
+    }
+}
+
+// 把请求体写入临时文件，出错或超过大小上限时删除文件
+async fn save_upload(body: Body) -> Result<PathBuf, UserImportError> {
+    let path = std::env::temp_dir().join(format!("user-import-{}", Uuid::new_v4()));
+    match write_upload(&path, body).await {
+        Ok(()) => Ok(path),
+        Err(err) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            Err(err)
+        }
+    }
+}
+
+async fn write_upload(path: &FilePath, body: Body) -> Result<(), UserImportError> {
+    let mut file = tokio::fs::File::create(path).await?;
+    let mut stream = body.into_data_stream();
+    let mut size = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(std::io::Error::other)?;
+        size += chunk.len();
+        if size > MAX_IMPORT_BYTES {
+            return Err(UserImportError::TooLarge);
+        }
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+// 上传CSV或NDJSON文件导入到管理员所在组织，任务在后台执行，立即返回202和任务状态
+pub async fn create_import(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    headers: HeaderMap,
+    Query(query): Query<UserImportQuery>,
+    body: Body,
+) -> Result<(StatusCode, Json<UserImport>), (StatusCode, String)> {
+    let format = query.format.as_deref().unwrap_or_else(|| format_from_headers(&headers));
+    let options = ImportOptions::parse(format, query.on_duplicate.as_deref(), query.dry_run, query.mapping.as_deref())
+        .map_err(error_response)?;
+
+    let path = save_upload(body).await.map_err(error_response)?;
+    let organization_id = admin.user.organization_id;
+    match UserImporter::create_job(&pool, organization_id, Some(admin.user.id), &options, &path).await {
+        Ok(job) => {
+            UserImporter::spawn(pool, job.clone(), options, path);
+            Ok((StatusCode::ACCEPTED, Json(job)))
+        }
+        Err(err) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            Err(error_response(err))
+        }
+    }
+}
+
+// 管理员所在组织最近的导入任务
+pub async fn list_imports(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+) -> Result<Json<Vec<UserImport>>, (StatusCode, String)> {
+    match UserImportStore::find_by_organization(&pool, admin.user.organization_id).await {
+        Ok(imports) => Ok(Json(imports)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+// 任务状态和进度，processed_rows/total_rows为已处理的比例
+pub async fn get_import(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Path(import_id): Path<Uuid>,
+) -> Result<Json<UserImport>, (StatusCode, String)> {
+    match UserImportStore::find_by_id(&pool, admin.user.organization_id, import_id).await {
+        Ok(import) => Ok(Json(import)),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 下载CSV格式的逐行错误报告
+pub async fn import_errors(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Path(import_id): Path<Uuid>,
+) -> Result<Response, (StatusCode, String)> {
+    let chunks = UserImportStore::error_report(&pool, admin.user.organization_id, import_id)
+        .await
+        .map_err(error_response)?;
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/csv; charset=utf-8")
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"user-import-{}-errors.csv\"", import_id))
+        .body(Body::from_stream(ReceiverStream::new(chunks)))
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+}
@@ -0,0 +1,18 @@
+use axum::{extract::{Extension, Json, Query}, http::StatusCode};
+use crate::db::DbPool;
+use crate::model::{organization::Tenant, user_search::*};
+use super::begin_tenant;
+
+// 按姓名或邮箱模糊搜索当前组织的用户
+pub async fn search_users(
+    Extension(pool): Extension<DbPool>,
+    Extension(tenant): Extension<Tenant>,
+    Query(query): Query<UserSearchQuery>,
+) -> Result<Json<Vec<UserSearchResult>>, (StatusCode, String)> {
+    let mut tx = begin_tenant(&pool, tenant).await?;
+    match UserSearchStore::search(&mut tx, &query).await {
+        Ok(results) => Ok(Json(results)),
+        Err(err @ UserSearchError::EmptyQuery) => Err((StatusCode::BAD_REQUEST, err.to_string())),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
@@ -0,0 +1,98 @@
+use axum::{extract::{Extension, Json, Path}, http::StatusCode};
+use uuid::Uuid;
+use crate::{db::DbPool, extractor::AdminUser};
+use crate::model::{User, user_status::*};
+
+fn error_response(err: UserStatusError) -> (StatusCode, String) {
+    match err {
+        UserStatusError::NotFound => (StatusCode::NOT_FOUND, err.to_string()),
+        UserStatusError::MissingReason => (StatusCode::BAD_REQUEST, err.to_string()),
+        UserStatusError::InvalidTransition { .. } => (StatusCode::CONFLICT, err.to_string()),
+        UserStatusError::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+async fn transition(
+    pool: &DbPool,
+    admin: &AdminUser,
+    user_id: Uuid,
+    transition: Transition,
+    req: &TransitionRequest,
+) -> Result<Json<User>, (StatusCode, String)> {
+    match UserStatusStore::transition(pool, admin.0.user.organization_id, admin.0.user.id, user_id, transition, &req.reason).await {
+        Ok(user) => Ok(Json(user)),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 激活待激活的账号
+pub async fn activate_user(
+    Extension(pool): Extension<DbPool>,
+    admin: AdminUser,
+    Path(user_id): Path<Uuid>,
+    Json(req): Json<TransitionRequest>,
+) -> Result<Json<User>, (StatusCode, String)> {
+    transition(&pool, &admin, user_id, Transition::Activate, &req).await
+}
+
+// 停用账号，用户的全部会话立即失效
+pub async fn suspend_user(
+    Extension(pool): Extension<DbPool>,
+    admin: AdminUser,
+    Path(user_id): Path<Uuid>,
+    Json(req): Json<TransitionRequest>,
+) -> Result<Json<User>, (StatusCode, String)> {
+    transition(&pool, &admin, user_id, Transition::Suspend, &req).await
+}
+
+// 恢复已停用的账号
+pub async fn reactivate_user(
+    Extension(pool): Extension<DbPool>,
+    admin: AdminUser,
+    Path(user_id): Path<Uuid>,
+    Json(req): Json<TransitionRequest>,
+) -> Result<Json<User>, (StatusCode, String)> {
+    transition(&pool, &admin, user_id, Transition::Reactivate, &req).await
+}
+
+// 锁定账号
+pub async fn lock_user(
+    Extension(pool): Extension<DbPool>,
+    admin: AdminUser,
+    Path(user_id): Path<Uuid>,
+    Json(req): Json<TransitionRequest>,
+) -> Result<Json<User>, (StatusCode, String)> {
+    transition(&pool, &admin, user_id, Transition::Lock, &req).await
+}
+
+// 解锁账号
+pub async fn unlock_user(
+    Extension(pool): Extension<DbPool>,
+    admin: AdminUser,
+    Path(user_id): Path<Uuid>,
+    Json(req): Json<TransitionRequest>,
+) -> Result<Json<User>, (StatusCode, String)> {
+    transition(&pool, &admin, user_id, Transition::Unlock, &req).await
+}
+
+// 标记账号为已删除，保留数据和记录
+pub async fn soft_delete_user(
+    Extension(pool): Extension<DbPool>,
+    admin: AdminUser,
+    Path(user_id): Path<Uuid>,
+    Json(req): Json<TransitionRequest>,
+) -> Result<Json<User>, (StatusCode, String)> {
+    transition(&pool, &admin, user_id, Transition::Delete, &req).await
+}
+
+// 获取账号的状态转换记录
+pub async fn status_history(
+    Extension(pool): Extension<DbPool>,
+    admin: AdminUser,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<Vec<StatusTransition>>, (StatusCode, String)> {
+    match UserStatusStore::history(&pool, admin.0.user.organization_id, user_id).await {
+        Ok(transitions) => Ok(Json(transitions)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
@@ -0,0 +1,261 @@
+use axum::{extract::{Extension, Json, Path, Query}, http::StatusCode};
+use uuid::Uuid;
+use crate::{db::DbPool, extractor::AdminUser};
+use crate::model::webhook::*;
+
+fn error_response(err: WebhookError) -> (StatusCode, String) {
+    match err {
+        WebhookError::NotFound | WebhookError::DeliveryNotFound => (StatusCode::NOT_FOUND, err.to_string()),
+        WebhookError::InvalidUrl
+        | WebhookError::InvalidEventType(_)
+        | WebhookError::NoEventTypes
+        | WebhookError::WeakSecret => (StatusCode::BAD_REQUEST, err.to_string()),
+        WebhookError::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+// 为管理员所在组织创建Webhook订阅，签名密钥只在响应中返回一次
+pub async fn create_webhook(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Json(req): Json<CreateWebhookRequest>,
+) -> Result<(StatusCode, Json<CreatedWebhook>), (StatusCode, String)> {
+    match WebhookStore::create(&pool, admin.user.organization_id, admin.user.id, &req).await {
+        Ok(webhook) => Ok((StatusCode::CREATED, Json(webhook))),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 获取管理员所在组织的Webhook订阅
+pub async fn list_webhooks(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+) -> Result<Json<Vec<Webhook>>, (StatusCode, String)> {
+    match WebhookStore::find_by_organization(&pool, admin.user.organization_id).await {
+        Ok(webhooks) => Ok(Json(webhooks)),
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+pub async fn get_webhook(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Path(webhook_id): Path<Uuid>,
+) -> Result<Json<Webhook>, (StatusCode, String)> {
+    match WebhookStore::find_by_id(&pool, admin.user.organization_id, webhook_id).await {
+        Ok(webhook) => Ok(Json(webhook)),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 修改订阅的URL、事件类型或启用状态
+pub async fn update_webhook(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Path(webhook_id): Path<Uuid>,
+    Json(req): Json<UpdateWebhookRequest>,
+) -> Result<Json<Webhook>, (StatusCode, String)> {
+    match WebhookStore::update(&pool, admin.user.organization_id, admin.user.id, webhook_id, &req).await {
+        Ok(webhook) => Ok(Json(webhook)),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+pub async fn delete_webhook(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Path(webhook_id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match WebhookStore::delete(&pool, admin.user.organization_id, admin.user.id, webhook_id).await {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 投递记录，可按状态过滤，例如 ?status=dead
+pub async fn list_deliveries(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Path(webhook_id): Path<Uuid>,
+    Query(query): Query<DeliveryListQuery>,
+) -> Result<Json<Vec<WebhookDelivery>>, (StatusCode, String)> {
+    match WebhookStore::deliveries(&pool, admin.user.organization_id, webhook_id, &query).await {
+        Ok(deliveries) => Ok(Json(deliveries)),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 投递详情，包含推送内容以及每次尝试的响应码和耗时
+pub async fn get_delivery(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Path((webhook_id, delivery_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<WebhookDeliveryDetail>, (StatusCode, String)> {
+    match WebhookStore::delivery(&pool, admin.user.organization_id, webhook_id, delivery_id).await {
+        Ok(delivery) => Ok(Json(delivery)),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+// 手动重新投递，常用于处理死信
+pub async fn redeliver(
+    Extension(pool): Extension<DbPool>,
+    AdminUser(admin): AdminUser,
+    Path((webhook_id, delivery_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<WebhookDelivery>, (StatusCode, String)> {
+    match WebhookStore::redeliver(&pool, admin.user.organization_id, admin.user.id, webhook_id, delivery_id).await {
+        Ok(delivery) => Ok(Json(delivery)),
+        Err(err) => Err(error_response(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, sync::{Arc, Mutex}};
+    use axum::{
+        extract::State,
+        http::{header, HeaderMap, Method, StatusCode},
+        routing::post,
+        Router,
+    };
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+    use crate::db::DbPool;
+    use crate::model::webhook::*;
+    use crate::model::{ROLE_ADMIN, ROLE_USER};
+    use crate::test_support::*;
+
+    // 进程内的接收方，记录收到的请求，按队列返回状态码，队列为空时返回200
+    #[derive(Default)]
+    struct Receiver {
+        requests: Vec<(HeaderMap, String)>,
+        statuses: VecDeque<StatusCode>,
+    }
+
+    type SharedReceiver = Arc<Mutex<Receiver>>;
+
+    async fn receive(State(receiver): State<SharedReceiver>, headers: HeaderMap, body: String) -> StatusCode {
+        let mut receiver = receiver.lock().unwrap();
+        receiver.requests.push((headers, body));
+        receiver.statuses.pop_front().unwrap_or(StatusCode::OK)
+    }
+
+    async fn redirect() -> (StatusCode, [(header::HeaderName, &'static str); 1]) {
+        (StatusCode::TEMPORARY_REDIRECT, [(header::LOCATION, "/hook")])
+    }
+
+    async fn start_receiver() -> (u16, SharedReceiver) {
+        let receiver = SharedReceiver::default();
+        let router = Router::new()
+            .route("/hook", post(receive))
+            .route("/redirect", post(redirect))
+            .with_state(receiver.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        (port, receiver)
+    }
+
+    async fn latest_delivery(pool: &DbPool, webhook_id: &str) -> Uuid {
+        sqlx::query_scalar("SELECT id FROM webhook_deliveries WHERE webhook_id = $1::uuid ORDER BY created_at DESC LIMIT 1")
+            .bind(webhook_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    // 签名、失败重试、投递记录、不跟随重定向和拒绝内网地址。
+    // 投递会领取所有到期的记录，这些场景放在同一个测试中依次执行
+    #[tokio::test]
+    async fn deliveries_reach_receiver() {
+        let Some(app) = TestApp::new().await else { return };
+        let (port, receiver) = start_receiver().await;
+        let (organization_id, _) = create_organization(&app.pool).await;
+        let admin = create_user(&app.pool, organization_id, ROLE_ADMIN).await;
+        let token = app.login(&admin.email).await;
+        let secret = "whsec_receiver_test_secret";
+        let trusted = WebhookClient::new(true).unwrap();
+        let guarded = WebhookClient::new(false).unwrap();
+
+        let response = app.request(Method::POST, "/admin/webhooks", Some(&token), Some(json!({
+            "url": format!("http://127.0.0.1:{}/hook", port),
+            "event_types": ["user.created"],
+            "secret": secret,
+        }))).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+        let webhook_id = response.body["id"].as_str().unwrap().to_string();
+
+        // 第一次返回500，按退避安排重试；重试成功后完成，投递记录包含两次尝试
+        receiver.lock().unwrap().statuses.push_back(StatusCode::INTERNAL_SERVER_ERROR);
+        create_user(&app.pool, organization_id, ROLE_USER).await;
+        let delivery_id = latest_delivery(&app.pool, &webhook_id).await;
+        WebhookDispatcher::run_due(&app.pool, &trusted).await.unwrap();
+
+        {
+            let receiver = receiver.lock().unwrap();
+            assert_eq!(receiver.requests.len(), 1);
+            let (headers, body) = &receiver.requests[0];
+            let signature = headers[SIGNATURE_HEADER].to_str().unwrap();
+            let timestamp: i64 = signature.strip_prefix("t=").unwrap().split(',').next().unwrap().parse().unwrap();
+            assert_eq!(signature, sign_payload(secret, timestamp, body));
+            assert_ne!(signature, sign_payload("whsec_another_secret_value", timestamp, body));
+            assert_eq!(headers[DELIVERY_HEADER].to_str().unwrap(), delivery_id.to_string());
+            assert_eq!(headers[EVENT_HEADER].to_str().unwrap(), "user.created");
+            assert!(serde_json::from_str::<Value>(body).is_ok());
+        }
+
+        let uri = format!("/admin/webhooks/{}/deliveries/{}", webhook_id, delivery_id);
+        let response = app.request(Method::GET, &uri, Some(&token), None).await;
+        assert_eq!(response.body["status"], DELIVERY_PENDING);
+        assert_eq!(response.body["attempts"], 1);
+        assert_eq!(response.body["last_status_code"], 500);
+
+        sqlx::query("UPDATE webhook_deliveries SET next_attempt_at = CURRENT_TIMESTAMP WHERE id = $1")
+            .bind(delivery_id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        WebhookDispatcher::run_due(&app.pool, &trusted).await.unwrap();
+
+        let response = app.request(Method::GET, &uri, Some(&token), None).await;
+        assert_eq!(response.body["status"], DELIVERY_DELIVERED, "{}", response.body);
+        let codes: Vec<i64> = response.body["attempts_log"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|attempt| attempt["status_code"].as_i64().unwrap())
+            .collect();
+        assert_eq!(codes, [500, 200]);
+        assert_eq!(receiver.lock().unwrap().requests.len(), 2);
+
+        // 重定向不会被跟随，按接收方返回的状态码记为失败
+        let webhook_uri = format!("/admin/webhooks/{}", webhook_id);
+        let response = app.request(Method::PUT, &webhook_uri, Some(&token), Some(json!({
+            "url": format!("http://127.0.0.1:{}/redirect", port),
+        }))).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        create_user(&app.pool, organization_id, ROLE_USER).await;
+        let delivery_id = latest_delivery(&app.pool, &webhook_id).await;
+        WebhookDispatcher::run_due(&app.pool, &trusted).await.unwrap();
+
+        let uri = format!("/admin/webhooks/{}/deliveries/{}", webhook_id, delivery_id);
+        let response = app.request(Method::GET, &uri, Some(&token), None).await;
+        assert_eq!(response.body["status"], DELIVERY_PENDING);
+        assert_eq!(response.body["last_status_code"], 307);
+        assert_eq!(receiver.lock().unwrap().requests.len(), 2);
+
+        // 默认的客户端拒绝IP形式和解析到内网的地址，不发出请求
+        for url in [format!("http://127.0.0.1:{}/hook", port), format!("http://localhost:{}/hook", port)] {
+            let response = app.request(Method::PUT, &webhook_uri, Some(&token), Some(json!({ "url": url }))).await;
+            assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+            let response = app.request(Method::POST, &format!("{}/redeliver", uri), Some(&token), None).await;
+            assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+            WebhookDispatcher::run_due(&app.pool, &guarded).await.unwrap();
+
+            let response = app.request(Method::GET, &uri, Some(&token), None).await;
+            let error = response.body["last_error"].as_str().unwrap_or_default();
+            assert!(error.contains("内网地址"), "{}: {}", url, response.body);
+            assert_eq!(receiver.lock().unwrap().requests.len(), 2);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+
+// 邮件内容
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// 邮件发送接口，便于替换为SMTP或第三方服务实现
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> anyhow::Result<()>;
+}
+
+pub type SharedMailer = Arc<dyn Mailer>;
+
+// 将邮件输出到日志，适用于本地开发
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: Email) -> anyhow::Result<()> {
+        tracing::info!(
+            to = %email.to,
+            subject = %email.subject,
+            "发送邮件:\n{}",
+            email.body
+        );
+        Ok(())
+    }
+}
+
+// 应用对外访问地址，用于拼接邮件中的链接
+pub fn app_base_url() -> String {
+    std::env::var("APP_BASE_URL").unwrap_or_else(|_| "http://127.0.0.1:3000".to_string())
+}
@@ -1,11 +1,26 @@
+mod cli;
 mod db;
+mod event_hub;
+mod extractor;
 mod handler;
+mod mailer;
+mod metrics;
+mod middleware;
 mod model;
+mod name_pinyin;
+mod password;
+mod publisher;
 mod router;
+mod scim_filter;
+#[cfg(test)]
+mod test_support;
+mod token;
+mod xmldsig;
 
 use axum::serve;
 use dotenv::dotenv;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use tokio::signal;
 use tower::ServiceBuilder;
 use tower_http::trace::TraceLayer;
@@ -23,13 +38,143 @@ async fn main() {
         .await
         .expect("Failed to create database pool");
 
+    // 带参数运行时执行运维命令后退出
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(err) = cli::run(&pool, &args).await {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // 运行数据库迁移
     db::run_migrations(&pool)
         .await
         .expect("Failed to run database migrations");
 
+    // 确保存在未过期的ID令牌签名密钥，之后每小时检查一次
+    model::signing_key::SigningKeyStore::rotate_if_due(&pool)
+        .await
+        .expect("Failed to prepare signing key");
+    let rotation_pool = pool.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            if let Err(err) = model::signing_key::SigningKeyStore::rotate_if_due(&rotation_pool).await {
+                tracing::error!("轮换签名密钥失败: {}", err);
+            }
+        }
+    });
+
+    // 每小时清理过期未接受的邀请
+    let cleanup_pool = pool.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            match model::invitation::InvitationStore::cleanup_expired(&cleanup_pool).await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!(count, "已清理过期邀请"),
+                Err(err) => tracing::error!("清理过期邀请失败: {}", err),
+            }
+        }
+    });
+
+    // 每小时清理超过保留天数的用户变更事件，保留天数由USER_EVENT_RETENTION_DAYS配置
+    let prune_pool = pool.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            let retention_days = model::user_event::retention_days();
+            match model::user_event::UserEventStore::prune(&prune_pool, retention_days).await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!(count, "已清理过期的用户变更事件"),
+                Err(err) => tracing::error!("清理用户变更事件失败: {}", err),
+            }
+        }
+    });
+
+    // 每小时清理发布超过保留天数的领域事件，保留天数由OUTBOX_RETENTION_DAYS配置
+    let outbox_prune_pool = pool.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            let retention_days = model::outbox::retention_days();
+            match model::outbox::OutboxRelay::prune(&outbox_prune_pool, retention_days).await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!(count, "已清理已发布的领域事件"),
+                Err(err) => tracing::error!("清理领域事件失败: {}", err),
+            }
+        }
+    });
+
+    // 加载拼音覆盖词典，词典变化后已有用户的拼音由refresh-pinyin命令重新计算
+    name_pinyin::init();
+
+    // 由USER_STORE选择用户写操作的实现
+    let users = model::repository_from_env().expect("Failed to configure user store");
+
+    // 发布发件箱中的领域事件，由OUTBOX_PUBLISHER选择发布方式
+    let publisher = publisher::from_env()
+        .await
+        .expect("Failed to configure event publisher");
+    let relay_pool = pool.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            if let Err(err) = model::outbox::OutboxRelay::run_pending(&relay_pool, publisher.as_ref()).await {
+                tracing::error!("发布领域事件失败: {}", err);
+            }
+        }
+    });
+
+    // /users需要管理员或服务凭据，通过环境变量创建初始管理员
+    if let (Ok(email), Ok(password)) = (std::env::var("ADMIN_EMAIL"), std::env::var("ADMIN_PASSWORD")) {
+        model::UserStore::ensure_admin(&pool, &email, &password)
+            .await
+            .expect("Failed to create initial admin");
+    }
+
+    // 邮件发送器（默认输出到日志）
+    let mailer: mailer::SharedMailer = Arc::new(mailer::LogMailer);
+
+    // 进程内指标
+    let metrics: metrics::SharedMetrics = Arc::new(metrics::Metrics::default());
+
+    // WebAuthn依赖方配置
+    let webauthn: model::passkey::SharedWebauthn = Arc::new(
+        model::passkey::create_webauthn().expect("Failed to configure WebAuthn"),
+    );
+
+    // 用户变更事件分发，通过数据库通知在多个实例间同步
+    let user_events = event_hub::UserEventHub::start(pool.clone());
+
+    // 访问外部身份提供方的HTTP客户端
+    let http = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+        .expect("Failed to build HTTP client");
+
+    // 每2秒投递到期的Webhook，多个实例同时运行时各自领取不同的投递
+    let webhook_pool = pool.clone();
+    let webhook_client = model::webhook::WebhookClient::from_env().expect("Failed to build webhook client");
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(2));
+        loop {
+            interval.tick().await;
+            if let Err(err) = model::webhook::WebhookDispatcher::run_due(&webhook_pool, &webhook_client).await {
+                tracing::error!("投递Webhook失败: {}", err);
+            }
+        }
+    });
+
     // 创建路由
-    let app = router::create_router(pool).layer(
+    let app = router::create_router(pool, mailer, metrics, webauthn, http, user_events, users).layer(
         ServiceBuilder::new()
             .layer(TraceLayer::new_for_http())
             .into_inner(),
@@ -42,7 +187,8 @@ async fn main() {
         .expect("Failed to bind address");
     tracing::info!("Server running on http://{}", addr);
 
-    serve(listener, app.into_make_service())
+    // 需要客户端地址用于登录频率限制
+    serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
         .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
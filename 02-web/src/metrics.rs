@@ -0,0 +1,41 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+// 进程内指标计数器，以Prometheus文本格式输出
+#[derive(Default)]
+pub struct Metrics {
+    pub login_success_total: AtomicU64,
+    pub login_failure_total: AtomicU64,
+    pub login_throttled_total: AtomicU64,
+    pub login_lockout_account_total: AtomicU64,
+    pub login_lockout_ip_total: AtomicU64,
+}
+
+pub type SharedMetrics = Arc<Metrics>;
+
+impl Metrics {
+    pub fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // 渲染为Prometheus文本格式
+    pub fn render(&self) -> String {
+        let counters = [
+            ("login_success_total", "成功登录次数", &self.login_success_total),
+            ("login_failure_total", "失败登录次数", &self.login_failure_total),
+            ("login_throttled_total", "因频率限制被拒绝的登录次数", &self.login_throttled_total),
+            ("login_lockout_account_total", "账号被锁定次数", &self.login_lockout_account_total),
+            ("login_lockout_ip_total", "IP被锁定次数", &self.login_lockout_ip_total),
+        ];
+
+        counters
+            .iter()
+            .map(|(name, help, value)| {
+                format!(
+                    "# HELP {name} {help}\n# TYPE {name} counter\n{name} {}\n",
+                    value.load(Ordering::Relaxed)
+                )
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,199 @@
+use std::net::SocketAddr;
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{header, request::Parts, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use uuid::Uuid;
+use crate::db::DbPool;
+use crate::extractor::{bearer_token, AuthUser};
+use crate::handler::api_key::error_response as api_key_error_response;
+use crate::model::{api_key::{ApiKeyStore, API_KEY_PREFIX}, oidc::OidcStore, User, UserStore, ROLE_ADMIN};
+use crate::model::organization::{OrganizationStore, Tenant};
+
+// 携带API密钥的请求头，也可以放在Authorization: Bearer中
+const API_KEY_HEADER: &str = "x-api-key";
+
+// 指定目标组织标识的请求头
+const ORGANIZATION_HEADER: &str = "x-organization";
+
+// 从请求头或子域名中取出目标组织标识；子域名需要配置TENANT_BASE_DOMAIN，如 acme.example.com -> acme
+fn requested_slug(parts: &Parts) -> Option<String> {
+    if let Some(slug) = parts.headers.get(ORGANIZATION_HEADER).and_then(|value| value.to_str().ok()) {
+        return Some(slug.trim().to_lowercase());
+    }
+
+    let base_domain = std::env::var("TENANT_BASE_DOMAIN").ok()?;
+    let host = parts.headers.get(header::HOST)?.to_str().ok()?;
+    let host = host.split(':').next().unwrap_or(host).to_lowercase();
+    let subdomain = host.strip_suffix(&base_domain.to_lowercase())?.strip_suffix('.')?;
+    (!subdomain.is_empty() && !subdomain.contains('.')).then(|| subdomain.to_string())
+}
+
+// 确定当前请求的组织：
+// 未指定目标组织时使用调用方所属组织；指定了其他组织时，只有默认组织（平台）的调用方可以访问。
+// 服务调用方属于其客户端所在的组织
+async fn resolve_tenant(pool: &DbPool, parts: &Parts, principal: Uuid) -> Result<Tenant, (StatusCode, String)> {
+    let internal_error = |err: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+
+    let requested = match requested_slug(parts) {
+        Some(slug) => Some(
+            OrganizationStore::find_by_slug(pool, &slug)
+                .await
+                .map_err(internal_error)?
+                .ok_or_else(|| (StatusCode::NOT_FOUND, "组织不存在".to_string()))?
+                .id,
+        ),
+        None => None,
+    };
+
+    let organization_id = match requested {
+        Some(requested) if requested != principal => {
+            if principal != OrganizationStore::default_id(pool).await.map_err(internal_error)? {
+                return Err((StatusCode::FORBIDDEN, "无权访问该组织".to_string()));
+            }
+            requested
+        }
+        _ => principal,
+    };
+
+    Ok(Tenant { organization_id })
+}
+
+// 按路由要求的scope放行请求：
+// API密钥必须包含该scope，并且只代表所属用户，所属用户需要是管理员；
+// 服务调用方携带client_credentials签发的访问令牌，令牌必须包含该scope，代表客户端所属的组织；
+// 代表用户签发的访问令牌同样要求用户是管理员；
+// 否则回退为用户会话（Authorization头或Cookie），只有管理员可以访问。
+// 通过后解析当前组织并放入请求扩展，后续的用户查询只能访问该组织
+pub async fn require_scope(
+    State(scope): State<&'static str>,
+    request: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, String)> {
+    let (mut parts, body) = request.into_parts();
+    let forbidden = || (StatusCode::FORBIDDEN, format!("缺少scope: {}", scope));
+
+    let pool = parts
+        .extensions
+        .get::<DbPool>()
+        .cloned()
+        .ok_or_else(|| (StatusCode::INTERNAL_SERVER_ERROR, "缺少数据库连接池".to_string()))?;
+    let api_key = parts
+        .headers
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| bearer_token(&parts).filter(|token| token.starts_with(API_KEY_PREFIX)))
+        .map(str::to_string);
+
+    let principal = if let Some(api_key) = api_key {
+        let ConnectInfo(addr) = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .cloned()
+            .ok_or_else(|| (StatusCode::INTERNAL_SERVER_ERROR, "无法获取客户端地址".to_string()))?;
+        let grant = ApiKeyStore::authenticate(&pool, &api_key, addr.ip())
+            .await
+            .map_err(api_key_error_response)?;
+        if !grant.scopes.iter().any(|granted| granted == scope) {
+            return Err(forbidden());
+        }
+        if grant.role != ROLE_ADMIN {
+            return Err((StatusCode::FORBIDDEN, "API密钥所属用户需要管理员权限".to_string()));
+        }
+        grant.organization_id
+    } else {
+        let grant = match bearer_token(&parts).map(str::to_string) {
+            Some(token) => OidcStore::find_access_token(&pool, &token)
+                .await
+                .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?,
+            None => None,
+        };
+        match grant {
+            Some(grant) if grant.scopes.iter().any(|granted| granted == scope) => match grant.user_id {
+                // 代表用户签发的令牌与API密钥相同：只代表该用户，用户需要是管理员，只能访问其所在的组织
+                Some(user_id) => {
+                    let user = UserStore::find_by_id(&pool, user_id)
+                        .await
+                        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+                        .filter(User::is_active)
+                        .ok_or_else(|| (StatusCode::UNAUTHORIZED, "访问令牌无效".to_string()))?;
+                    if user.role != ROLE_ADMIN {
+                        return Err((StatusCode::FORBIDDEN, "访问令牌所属用户需要管理员权限".to_string()));
+                    }
+                    user.organization_id
+                }
+                None => grant.organization_id,
+            },
+            Some(_) => return Err(forbidden()),
+            None => {
+                let auth = AuthUser::from_request_parts(&mut parts, &()).await?;
+                if auth.user.role != ROLE_ADMIN {
+                    return Err((StatusCode::FORBIDDEN, "需要管理员权限".to_string()));
+                }
+                auth.user.organization_id
+            }
+        }
+    };
+
+    let tenant = resolve_tenant(&pool, &parts, principal).await?;
+    parts.extensions.insert(tenant);
+
+    Ok(next.run(Request::from_parts(parts, body)).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use chrono::{Duration, Utc};
+    use uuid::Uuid;
+    use crate::model::oauth_client::{CreateClientRequest, OAuthClientStore, GRANT_AUTHORIZATION_CODE, GRANT_CLIENT_CREDENTIALS};
+    use crate::model::{ROLE_ADMIN, ROLE_USER};
+    use crate::test_support::*;
+    use crate::token::{generate_token, hash_token};
+
+    // 直接写入代表用户、带users:read的访问令牌（修复前授权码模式可以签发这样的令牌）
+    async fn user_access_token(app: &TestApp, organization_id: Uuid, user_id: Uuid) -> String {
+        let client = OAuthClientStore::create(&app.pool, organization_id, user_id, &CreateClientRequest {
+            name: "混合客户端".to_string(),
+            redirect_uris: vec!["http://localhost:8080/callback".to_string()],
+            scopes: Some(vec!["openid".to_string(), "users:read".to_string()]),
+            grant_types: Some(vec![GRANT_AUTHORIZATION_CODE.to_string(), GRANT_CLIENT_CREDENTIALS.to_string()]),
+            public: None,
+        })
+        .await
+        .unwrap();
+
+        let token = generate_token();
+        sqlx::query(r#"
+            INSERT INTO oauth_access_tokens (token_hash, client_id, user_id, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#)
+            .bind(hash_token(&token))
+            .bind(&client.client.client_id)
+            .bind(user_id)
+            .bind(vec!["openid".to_string(), "users:read".to_string()])
+            .bind(Utc::now() + Duration::hours(1))
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        token
+    }
+
+    #[tokio::test]
+    async fn user_bound_token_requires_admin() {
+        let Some(app) = TestApp::new().await else { return };
+        let organization_id = default_organization(&app.pool).await;
+
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+        let token = user_access_token(&app, organization_id, user.id).await;
+        let response = app.request(Method::GET, "/users", Some(&token), None).await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN, "{}", response.body);
+
+        let admin = create_user(&app.pool, organization_id, ROLE_ADMIN).await;
+        let token = user_access_token(&app, organization_id, admin.id).await;
+        let response = app.request(Method::GET, "/users", Some(&token), None).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    }
+}
@@ -1,8 +1,56 @@
+use std::sync::Arc;
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool};
+use sqlx::{FromRow, PgConnection, PgExecutor, PgPool};
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
 use thiserror::Error;
+use crate::password::hash_password;
+use crate::model::{organization::OrganizationStore, outbox::{DomainEvent, OutboxStore}};
+use crate::name_pinyin::transliterate;
+
+pub mod api_key;
+pub mod audit;
+pub mod event_sourced_user;
+pub mod group;
+pub mod identity_provider;
+pub mod invitation;
+pub mod login_throttle;
+pub mod magic_link;
+pub mod oauth_client;
+pub mod oidc;
+pub mod organization;
+pub mod outbox;
+pub mod passkey;
+pub mod password_reset;
+pub mod saml;
+pub mod scim;
+pub mod session;
+pub mod signing_key;
+pub mod two_factor;
+pub mod user_event;
+pub mod user_export;
+pub mod user_history;
+pub mod user_import;
+pub mod user_search;
+pub mod user_status;
+pub mod webhook;
+
+// 用户角色
+pub const ROLE_ADMIN: &str = "admin";
+pub const ROLE_USER: &str = "user";
+pub const ROLES: [&str; 2] = [ROLE_ADMIN, ROLE_USER];
+
+// 账号状态，状态之间的转换见user_status模块
+pub const STATUS_PENDING: &str = "pending";
+pub const STATUS_ACTIVE: &str = "active";
+pub const STATUS_SUSPENDED: &str = "suspended";
+pub const STATUS_LOCKED: &str = "locked";
+pub const STATUS_DELETED: &str = "deleted";
+pub const STATUSES: [&str; 5] = [STATUS_PENDING, STATUS_ACTIVE, STATUS_SUSPENDED, STATUS_LOCKED, STATUS_DELETED];
+
+// 重新计算姓名拼音时每批处理的用户数
+const PINYIN_REFRESH_BATCH: i64 = 1000;
 
 // 用户错误类型
 #[derive(Error, Debug)]
@@ -11,6 +59,14 @@ pub enum UserError {
     NotFound,
     #[error("邮箱已存在")]
     EmailExists,
+    #[error("无效的角色")]
+    InvalidRole,
+    #[error("无效的账号状态")]
+    InvalidStatus,
+    #[error("密码加密失败")]
+    PasswordHash,
+    #[error("用户已被同时修改，请重试")]
+    VersionConflict,
     #[error("数据库错误: {0}")]
     Database(#[from] sqlx::Error),
 }
@@ -21,17 +77,32 @@ pub struct User {
     pub id: Uuid,
     pub name: String,
     pub email: String,
+    #[serde(skip_serializing)]
     pub password: String,
+    pub role: String,
+    pub status: String,
+    pub organization_id: Uuid,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+impl User {
+    // 只有正常状态的账号可以登录和使用已有会话
+    pub fn is_active(&self) -> bool {
+        self.status == STATUS_ACTIVE
+    }
+}
+
 // 创建用户请求
 #[derive(Debug, Deserialize)]
 pub struct CreateUserRequest {
     pub name: String,
     pub email: String,
     pub password: String,
+    pub role: Option<String>,
+    // 创建后直接加入的分组
+    #[serde(default)]
+    pub group_ids: Vec<Uuid>,
 }
 
 // 更新用户请求
@@ -40,70 +111,234 @@ pub struct UpdateUserRequest {
     pub name: Option<String>,
     pub email: Option<String>,
     pub password: Option<String>,
+    pub role: Option<String>,
+    // 传入时替换用户直接所属的分组
+    pub group_ids: Option<Vec<Uuid>>,
+}
+
+// 用户列表查询参数，不指定状态时不返回已删除的用户
+#[derive(Debug, Deserialize)]
+pub struct UserListQuery {
+    pub status: Option<String>,
+}
+
+// 邮箱在所有组织中唯一；启用行级安全时看不到其他组织的用户，只能依赖唯一约束
+fn map_unique_email(err: sqlx::Error) -> UserError {
+    match &err {
+        sqlx::Error::Database(db_err) if db_err.is_unique_violation() => UserError::EmailExists,
+        _ => UserError::Database(err),
+    }
+}
+
+// 用户写操作接口，/users处理器通过它创建、修改和删除用户。
+// UserStore直接修改users表；EventSourcedUserStore把修改记录为事件流，再投影到users表。
+// 两种实现的读取都使用users表
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn create(&self, conn: &mut PgConnection, organization_id: Uuid, user_data: &CreateUserRequest) -> Result<User, UserError>;
+    async fn update(&self, conn: &mut PgConnection, user_id: Uuid, update_data: &UpdateUserRequest) -> Result<User, UserError>;
+    async fn delete(&self, conn: &mut PgConnection, user_id: Uuid) -> Result<(), UserError>;
+}
+
+pub type SharedUserRepository = Arc<dyn UserRepository>;
+
+// 按USER_STORE选择用户写操作的实现：rows（默认）或events（事件溯源）
+pub fn repository_from_env() -> anyhow::Result<SharedUserRepository> {
+    match std::env::var("USER_STORE").as_deref() {
+        Ok("events") => Ok(Arc::new(event_sourced_user::EventSourcedUserStore)),
+        Ok("rows") | Err(_) => Ok(Arc::new(UserStore)),
+        Ok(other) => anyhow::bail!("未知的USER_STORE: {}", other),
+    }
 }
 
 // 用户存储实现
+// 可以在db::begin_tenant开启的租户事务中调用，此时只能访问当前组织的用户
 pub struct UserStore;
 
+#[async_trait]
+impl UserRepository for UserStore {
+    async fn create(&self, conn: &mut PgConnection, organization_id: Uuid, user_data: &CreateUserRequest) -> Result<User, UserError> {
+        UserStore::create(conn, organization_id, user_data).await
+    }
+
+    async fn update(&self, conn: &mut PgConnection, user_id: Uuid, update_data: &UpdateUserRequest) -> Result<User, UserError> {
+        UserStore::update(conn, user_id, update_data).await
+    }
+
+    async fn delete(&self, conn: &mut PgConnection, user_id: Uuid) -> Result<(), UserError> {
+        UserStore::delete(conn, user_id).await
+    }
+}
+
 impl UserStore {
     // 创建新用户
-    pub async fn create(pool: &PgPool, user_data: &CreateUserRequest) -> Result<User, UserError> {
+    pub async fn create(
+        conn: &mut PgConnection,
+        organization_id: Uuid,
+        user_data: &CreateUserRequest,
+    ) -> Result<User, UserError> {
         // 检查邮箱是否已存在
-        if let Ok(Some(_)) = Self::find_by_email(pool, &user_data.email).await {
+        if let Ok(Some(_)) = Self::find_by_email(&mut *conn, &user_data.email).await {
             return Err(UserError::EmailExists);
         }
 
-        // 创建用户
+        let role = user_data.role.as_deref().unwrap_or(ROLE_USER);
+        if !ROLES.contains(&role) {
+            return Err(UserError::InvalidRole);
+        }
+
+        // 密码只保存哈希值
+        let password = hash_password(&user_data.password).map_err(|_| UserError::PasswordHash)?;
+
+        // 创建用户，同时保存姓名的拼音用于搜索
+        let pinyin = transliterate(&user_data.name);
         let user = sqlx::query_as::<_, User>(r#"
-            INSERT INTO users (name, email, password)
-            VALUES ($1, $2, $3)
-            RETURNING id, name, email, password, created_at, updated_at
+            INSERT INTO users (name, email, password, role, organization_id, name_pinyin, name_initials)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, name, email, password, role, status, organization_id, created_at, updated_at
             "#)
             .bind(&user_data.name)
             .bind(&user_data.email)
-            .bind(&user_data.password)
-            .fetch_one(pool)
-            .await?;
+            .bind(&password)
+            .bind(role)
+            .bind(organization_id)
+            .bind(&pinyin.full)
+            .bind(&pinyin.initials)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(map_unique_email)?;
+
+        OutboxStore::append(&mut *conn, organization_id, &DomainEvent::UserCreated { user: user.clone() }).await?;
 
         Ok(user)
     }
 
+    // 启动时在默认组织中确保存在初始管理员，已存在同邮箱用户时不做修改
+    pub async fn ensure_admin(pool: &PgPool, email: &str, password: &str) -> Result<(), UserError> {
+        if Self::find_by_email(pool, email).await?.is_some() {
+            return Ok(());
+        }
+
+        let organization_id = OrganizationStore::default_id(pool).await?;
+        let mut tx = pool.begin().await?;
+        Self::create(&mut tx, organization_id, &CreateUserRequest {
+            name: "admin".to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+            role: Some(ROLE_ADMIN.to_string()),
+            group_ids: Vec::new(),
+        })
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    // 重新计算所有用户的姓名拼音，只更新与当前词典结果不同的用户，返回更新数量。
+    // 按主键分批读取，每批用一条UPDATE写回；姓名在此期间被修改的用户跳过，由修改时重新计算
+    pub async fn refresh_name_pinyin(pool: &PgPool) -> Result<u64, sqlx::Error> {
+        let mut count = 0;
+        let mut after: Option<Uuid> = None;
+        loop {
+            let users = sqlx::query_as::<_, (Uuid, String)>(r#"
+                SELECT id, name FROM users
+                WHERE $1::UUID IS NULL OR id > $1
+                ORDER BY id
+                LIMIT $2
+                "#)
+                .bind(after)
+                .bind(PINYIN_REFRESH_BATCH)
+                .fetch_all(pool)
+                .await?;
+            let Some((last, _)) = users.last() else { break };
+            after = Some(*last);
+
+            let mut ids = Vec::with_capacity(users.len());
+            let mut names = Vec::with_capacity(users.len());
+            let mut fulls = Vec::with_capacity(users.len());
+            let mut initials = Vec::with_capacity(users.len());
+            for (user_id, name) in users {
+                let pinyin = transliterate(&name);
+                ids.push(user_id);
+                names.push(name);
+                fulls.push(pinyin.full);
+                initials.push(pinyin.initials);
+            }
+
+            let result = sqlx::query(r#"
+                UPDATE users u
+                SET name_pinyin = v.name_pinyin, name_initials = v.name_initials
+                FROM UNNEST($1::UUID[], $2::TEXT[], $3::TEXT[], $4::TEXT[]) AS v(id, name, name_pinyin, name_initials)
+                WHERE u.id = v.id
+                  AND u.name = v.name
+                  AND (u.name_pinyin <> v.name_pinyin OR u.name_initials <> v.name_initials)
+                "#)
+                .bind(&ids)
+                .bind(&names)
+                .bind(&fulls)
+                .bind(&initials)
+                .execute(pool)
+                .await?;
+            count += result.rows_affected();
+        }
+
+        Ok(count)
+    }
+
     // 获取所有用户
-    pub async fn find_all(pool: &PgPool) -> Result<Vec<User>, UserError> {
+    pub async fn find_all<'e, E: PgExecutor<'e>>(
+        executor: E,
+        query: &UserListQuery,
+    ) -> Result<Vec<User>, UserError> {
+        if let Some(status) = &query.status {
+            if !STATUSES.contains(&status.as_str()) {
+                return Err(UserError::InvalidStatus);
+            }
+        }
+
         let users = sqlx::query_as::<_, User>(r#"
-            SELECT id, name, email, password, created_at, updated_at
+            SELECT id, name, email, password, role, status, organization_id, created_at, updated_at
             FROM users
+            WHERE CASE WHEN $1::TEXT IS NULL THEN status <> $2 ELSE status = $1 END
             ORDER BY created_at DESC
             "#)
-            .fetch_all(pool)
+            .bind(&query.status)
+            .bind(STATUS_DELETED)
+            .fetch_all(executor)
             .await?;
 
         Ok(users)
     }
 
     // 根据ID查找用户
-    pub async fn find_by_id(pool: &PgPool, user_id: Uuid) -> Result<Option<User>, UserError> {
+    pub async fn find_by_id<'e, E: PgExecutor<'e>>(
+        executor: E,
+        user_id: Uuid,
+    ) -> Result<Option<User>, UserError> {
         let user = sqlx::query_as::<_, User>(r#"
-            SELECT id, name, email, password, created_at, updated_at
+            SELECT id, name, email, password, role, status, organization_id, created_at, updated_at
             FROM users
             WHERE id = $1
             "#)
             .bind(user_id)
-            .fetch_optional(pool)
+            .fetch_optional(executor)
             .await?;
 
         Ok(user)
     }
 
     // 根据邮箱查找用户
-    pub async fn find_by_email(pool: &PgPool, email: &str) -> Result<Option<User>, UserError> {
+    pub async fn find_by_email<'e, E: PgExecutor<'e>>(
+        executor: E,
+        email: &str,
+    ) -> Result<Option<User>, UserError> {
         let user = sqlx::query_as::<_, User>(r#"
-            SELECT id, name, email, password, created_at, updated_at
+            SELECT id, name, email, password, role, status, organization_id, created_at, updated_at
             FROM users
             WHERE email = $1
             "#)
             .bind(email)
-            .fetch_optional(pool)
+            .fetch_optional(executor)
             .await?;
 
         Ok(user)
@@ -111,56 +346,127 @@ impl UserStore {
 
     // 更新用户
     pub async fn update(
-        pool: &PgPool,
+        conn: &mut PgConnection,
         user_id: Uuid,
         update_data: &UpdateUserRequest
     ) -> Result<User, UserError> {
         // 检查用户是否存在
-        let existing_user = Self::find_by_id(pool, user_id).await?
+        let existing_user = Self::find_by_id(&mut *conn, user_id).await?
             .ok_or(UserError::NotFound)?;
 
         // 构建更新查询
         let name = update_data.name.as_ref().unwrap_or(&existing_user.name);
         let email = update_data.email.as_ref().unwrap_or(&existing_user.email);
-        let password = update_data.password.as_ref().unwrap_or(&existing_user.password);
+        let role = update_data.role.as_ref().unwrap_or(&existing_user.role);
+        if !ROLES.contains(&role.as_str()) {
+            return Err(UserError::InvalidRole);
+        }
+        let password = match &update_data.password {
+            Some(password) => hash_password(password).map_err(|_| UserError::PasswordHash)?,
+            None => existing_user.password.clone(),
+        };
 
         // 如果更新了邮箱，检查新邮箱是否已存在
         if email != &existing_user.email {
-            if let Ok(Some(_)) = Self::find_by_email(pool, email).await {
+            if let Ok(Some(_)) = Self::find_by_email(&mut *conn, email).await {
                 return Err(UserError::EmailExists);
             }
         }
 
-        // 执行更新
+        // 执行更新，姓名变化时拼音随之更新
+        let pinyin = transliterate(name);
         let updated_user = sqlx::query_as::<_, User>(r#"
             UPDATE users
-            SET name = $1, email = $2, password = $3
-            WHERE id = $4
-            RETURNING id, name, email, password, created_at, updated_at
+            SET name = $1, email = $2, password = $3, role = $4,
+                password_changed_at = CASE WHEN password <> $3
+                    THEN CURRENT_TIMESTAMP ELSE password_changed_at END,
+                name_pinyin = $6, name_initials = $7
+            WHERE id = $5
+            RETURNING id, name, email, password, role, status, organization_id, created_at, updated_at
             "#)
             .bind(name)
             .bind(email)
-            .bind(password)
+            .bind(&password)
+            .bind(role)
             .bind(user_id)
-            .fetch_one(pool)
-            .await?;
+            .bind(&pinyin.full)
+            .bind(&pinyin.initials)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(map_unique_email)?;
+
+        let changed: Vec<&'static str> = [
+            ("name", updated_user.name != existing_user.name),
+            ("email", updated_user.email != existing_user.email),
+            ("role", updated_user.role != existing_user.role),
+        ]
+        .into_iter()
+        .filter_map(|(field, changed)| changed.then_some(field))
+        .collect();
+        if !changed.is_empty() {
+            let event = DomainEvent::UserUpdated { user: updated_user.clone(), changed };
+            OutboxStore::append(&mut *conn, updated_user.organization_id, &event).await?;
+        }
+        if update_data.password.is_some() {
+            let event = DomainEvent::UserPasswordChanged { user_id };
+            OutboxStore::append(&mut *conn, updated_user.organization_id, &event).await?;
+        }
 
         Ok(updated_user)
     }
 
     // 删除用户
-    pub async fn delete(pool: &PgPool, user_id: Uuid) -> Result<(), UserError> {
+    pub async fn delete(conn: &mut PgConnection, user_id: Uuid) -> Result<(), UserError> {
         // 检查用户是否存在
-        if Self::find_by_id(pool, user_id).await?.is_none() {
-            return Err(UserError::NotFound);
-        }
+        let user = Self::find_by_id(&mut *conn, user_id).await?
+            .ok_or(UserError::NotFound)?;
 
         // 执行删除
         sqlx::query("DELETE FROM users WHERE id = $1")
             .bind(user_id)
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
 
+        let event = DomainEvent::UserDeleted { user_id, email: user.email };
+        OutboxStore::append(&mut *conn, user.organization_id, &event).await?;
+
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+    use crate::test_support::*;
+    use super::*;
+
+    // 重新计算拼音只修正与词典结果不同的用户，不改变updated_at
+    #[tokio::test]
+    async fn refresh_name_pinyin_keeps_updated_at() {
+        let Some(app) = TestApp::new().await else { return };
+        let organization_id = default_organization(&app.pool).await;
+        let user = create_user(&app.pool, organization_id, ROLE_USER).await;
+
+        sqlx::query("UPDATE users SET name_pinyin = 'stale', name_initials = 's' WHERE id = $1")
+            .bind(user.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let (updated_at,): (DateTime<Utc>,) = sqlx::query_as("SELECT updated_at FROM users WHERE id = $1")
+            .bind(user.id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(updated_at, user.updated_at);
+
+        assert!(UserStore::refresh_name_pinyin(&app.pool).await.unwrap() >= 1);
+        let (full, initials, updated_at): (String, String, DateTime<Utc>) =
+            sqlx::query_as("SELECT name_pinyin, name_initials, updated_at FROM users WHERE id = $1")
+                .bind(user.id)
+                .fetch_one(&app.pool)
+                .await
+                .unwrap();
+        let pinyin = transliterate(&user.name);
+        assert_eq!((full, initials), (pinyin.full, pinyin.initials));
+        assert_eq!(updated_at, user.updated_at);
+    }
+}
@@ -0,0 +1,230 @@
+use std::net::IpAddr;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use thiserror::Error;
+use crate::model::{audit::AuditStore, oauth_client::API_SCOPES, STATUS_ACTIVE};
+use crate::token::{generate_token, hash_token};
+
+// API密钥前缀，用于和会话令牌、访问令牌区分
+pub const API_KEY_PREFIX: &str = "ak_";
+
+// 默认和最长有效期（天）
+const DEFAULT_EXPIRES_IN_DAYS: i64 = 90;
+const MAX_EXPIRES_IN_DAYS: i64 = 365;
+
+// API密钥错误类型
+#[derive(Error, Debug)]
+pub enum ApiKeyError {
+    #[error("API密钥不存在")]
+    NotFound,
+    #[error("至少需要一个scope")]
+    MissingScope,
+    #[error("不支持的scope: {0}")]
+    InvalidScope(String),
+    #[error("有效期必须在1到365天之间")]
+    InvalidExpiry,
+    #[error("无效的IP地址或网段: {0}")]
+    InvalidIp(String),
+    #[error("API密钥无效、已吊销或已过期")]
+    InvalidKey,
+    #[error("当前IP不在API密钥的允许列表中")]
+    IpNotAllowed,
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+// API密钥信息，不包含密钥本身
+#[derive(Debug, Serialize, FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub allowed_ips: Vec<String>,
+    pub expires_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub last_used_ip: Option<String>,
+    pub request_count: i64,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+// 创建API密钥请求
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_in_days: Option<i64>,
+    // 为空时不限制来源IP，支持单个地址或CIDR网段
+    #[serde(default)]
+    pub allowed_ips: Vec<String>,
+}
+
+// 创建API密钥响应，密钥只返回这一次
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKeyResponse {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+// 通过API密钥认证后的调用方
+#[derive(Debug, FromRow)]
+pub struct ApiKeyGrant {
+    pub role: String,
+    pub organization_id: Uuid,
+    pub scopes: Vec<String>,
+}
+
+#[derive(FromRow)]
+struct ApiKeyLookup {
+    id: Uuid,
+    key_hash: String,
+    ip_allowed: bool,
+}
+
+// 校验单个IP地址或CIDR网段
+fn valid_ip_or_network(value: &str) -> bool {
+    let (address, prefix_len) = match value.split_once('/') {
+        Some((address, prefix_len)) => (address, Some(prefix_len)),
+        None => (value, None),
+    };
+    let Ok(address) = address.parse::<IpAddr>() else {
+        return false;
+    };
+    let max_len = if address.is_ipv4() { 32 } else { 128 };
+    match prefix_len {
+        Some(prefix_len) => prefix_len.parse::<u8>().is_ok_and(|len| len <= max_len),
+        None => true,
+    }
+}
+
+// API密钥存储实现
+pub struct ApiKeyStore;
+
+impl ApiKeyStore {
+    // 创建API密钥，格式为 ak_{前缀}_{密钥}，前缀明文保存用于查找，整个密钥只保存摘要
+    pub async fn create(
+        pool: &PgPool,
+        user_id: Uuid,
+        req: &CreateApiKeyRequest,
+    ) -> Result<CreatedApiKeyResponse, ApiKeyError> {
+        if req.scopes.is_empty() {
+            return Err(ApiKeyError::MissingScope);
+        }
+        if let Some(scope) = req.scopes.iter().find(|scope| !API_SCOPES.contains(&scope.as_str())) {
+            return Err(ApiKeyError::InvalidScope(scope.clone()));
+        }
+        let expires_in_days = req.expires_in_days.unwrap_or(DEFAULT_EXPIRES_IN_DAYS);
+        if !(1..=MAX_EXPIRES_IN_DAYS).contains(&expires_in_days) {
+            return Err(ApiKeyError::InvalidExpiry);
+        }
+        if let Some(ip) = req.allowed_ips.iter().find(|ip| !valid_ip_or_network(ip)) {
+            return Err(ApiKeyError::InvalidIp(ip.clone()));
+        }
+
+        let mut prefix_bytes = [0u8; 6];
+        rand::thread_rng().fill_bytes(&mut prefix_bytes);
+        let prefix = hex::encode(prefix_bytes);
+        let key = format!("{}{}_{}", API_KEY_PREFIX, prefix, generate_token());
+
+        let api_key = sqlx::query_as::<_, ApiKey>(r#"
+            INSERT INTO api_keys (user_id, name, prefix, key_hash, scopes, allowed_ips, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, name, prefix, scopes, allowed_ips, expires_at, last_used_at, last_used_ip,
+                      request_count, created_at, revoked_at
+            "#)
+            .bind(user_id)
+            .bind(&req.name)
+            .bind(&prefix)
+            .bind(hash_token(&key))
+            .bind(&req.scopes)
+            .bind(&req.allowed_ips)
+            .bind(Utc::now() + Duration::days(expires_in_days))
+            .fetch_one(pool)
+            .await?;
+
+        AuditStore::record(pool, Some(user_id), Some(user_id), "api_key_created", Some(&prefix)).await?;
+
+        Ok(CreatedApiKeyResponse { api_key, key })
+    }
+
+    // 获取用户的所有API密钥
+    pub async fn find_by_user(pool: &PgPool, user_id: Uuid) -> Result<Vec<ApiKey>, sqlx::Error> {
+        let api_keys = sqlx::query_as::<_, ApiKey>(r#"
+            SELECT id, name, prefix, scopes, allowed_ips, expires_at, last_used_at, last_used_ip,
+                   request_count, created_at, revoked_at
+            FROM api_keys
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#)
+            .bind(user_id)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(api_keys)
+    }
+
+    // 吊销用户自己的API密钥
+    pub async fn revoke(pool: &PgPool, user_id: Uuid, api_key_id: Uuid) -> Result<(), ApiKeyError> {
+        let prefix = sqlx::query_scalar::<_, String>(r#"
+            UPDATE api_keys
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+            RETURNING prefix
+            "#)
+            .bind(api_key_id)
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?
+            .ok_or(ApiKeyError::NotFound)?;
+
+        AuditStore::record(pool, Some(user_id), Some(user_id), "api_key_revoked", Some(&prefix)).await?;
+
+        Ok(())
+    }
+
+    // 校验API密钥和来源IP，通过后记录使用时间和次数；所属账号不是正常状态时密钥不可用
+    pub async fn authenticate(pool: &PgPool, key: &str, ip: IpAddr) -> Result<ApiKeyGrant, ApiKeyError> {
+        let prefix = key
+            .strip_prefix(API_KEY_PREFIX)
+            .and_then(|rest| rest.split_once('_'))
+            .map(|(prefix, _)| prefix)
+            .ok_or(ApiKeyError::InvalidKey)?;
+
+        let lookup = sqlx::query_as::<_, ApiKeyLookup>(r#"
+            SELECT id, key_hash,
+                   cardinality(allowed_ips) = 0 OR $2::inet <<= ANY(allowed_ips::inet[]) AS ip_allowed
+            FROM api_keys
+            WHERE prefix = $1 AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+            "#)
+            .bind(prefix)
+            .bind(ip.to_string())
+            .fetch_optional(pool)
+            .await?
+            .filter(|lookup| lookup.key_hash == hash_token(key))
+            .ok_or(ApiKeyError::InvalidKey)?;
+        if !lookup.ip_allowed {
+            return Err(ApiKeyError::IpNotAllowed);
+        }
+
+        let grant = sqlx::query_as::<_, ApiKeyGrant>(r#"
+            UPDATE api_keys
+            SET last_used_at = CURRENT_TIMESTAMP, last_used_ip = $2, request_count = request_count + 1
+            FROM users
+            WHERE api_keys.id = $1 AND users.id = api_keys.user_id AND users.status = $3
+            RETURNING users.role, users.organization_id, api_keys.scopes
+            "#)
+            .bind(lookup.id)
+            .bind(ip.to_string())
+            .bind(STATUS_ACTIVE)
+            .fetch_optional(pool)
+            .await?
+            .ok_or(ApiKeyError::InvalidKey)?;
+
+        Ok(grant)
+    }
+}
@@ -0,0 +1,29 @@
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+// 审计日志存储实现
+pub struct AuditStore;
+
+impl AuditStore {
+    // 记录审计事件，可在事务中调用
+    pub async fn record<'e, E: PgExecutor<'e>>(
+        executor: E,
+        actor_id: Option<Uuid>,
+        user_id: Option<Uuid>,
+        action: &str,
+        detail: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            INSERT INTO audit_logs (actor_id, user_id, action, detail)
+            VALUES ($1, $2, $3, $4)
+            "#)
+            .bind(actor_id)
+            .bind(user_id)
+            .bind(action)
+            .bind(detail)
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,580 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json, FromRow, PgConnection, PgPool};
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use crate::model::{
+    map_unique_email,
+    outbox::{DomainEvent, OutboxStore},
+    user_history::OPERATION_DELETE,
+    CreateUserRequest, UpdateUserRequest, User, UserError, UserRepository, UserStore, ROLES, ROLE_USER,
+};
+use crate::name_pinyin::transliterate;
+use crate::password::hash_password;
+use crate::token::generate_token;
+
+// 每隔多少个版本保存一次快照
+const SNAPSHOT_INTERVAL: i32 = 20;
+
+// 用户事件流中的事件。密码哈希不进入只能追加的事件流，只由投影写入users表
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum UserStreamEvent {
+    Registered { organization_id: Uuid, name: String, email: String, role: String },
+    Renamed { name: String },
+    EmailChanged { email: String },
+    RoleChanged { role: String },
+    PasswordChanged,
+    Deleted,
+    // 由其他途径直接写入users表的资料，追加后事件流与users表一致
+    Reconciled { name: String, email: String, role: String },
+}
+
+impl UserStreamEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            UserStreamEvent::Registered { .. } => "Registered",
+            UserStreamEvent::Renamed { .. } => "Renamed",
+            UserStreamEvent::EmailChanged { .. } => "EmailChanged",
+            UserStreamEvent::RoleChanged { .. } => "RoleChanged",
+            UserStreamEvent::PasswordChanged => "PasswordChanged",
+            UserStreamEvent::Deleted => "Deleted",
+            UserStreamEvent::Reconciled { .. } => "Reconciled",
+        }
+    }
+}
+
+// 由事件流重建的用户聚合，version为已应用的最后一个事件的版本。
+// 账号状态由状态机直接维护在users表中，不属于聚合
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserAggregate {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub name: String,
+    pub email: String,
+    pub role: String,
+    pub deleted: bool,
+    pub version: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl UserAggregate {
+    fn apply(&mut self, event: &UserStreamEvent, version: i32, recorded_at: DateTime<Utc>) {
+        match event {
+            UserStreamEvent::Registered { .. } => {}
+            UserStreamEvent::Renamed { name } => self.name = name.clone(),
+            UserStreamEvent::EmailChanged { email } => self.email = email.clone(),
+            UserStreamEvent::RoleChanged { role } => self.role = role.clone(),
+            UserStreamEvent::PasswordChanged => {}
+            UserStreamEvent::Deleted => self.deleted = true,
+            UserStreamEvent::Reconciled { name, email, role } => {
+                self.name = name.clone();
+                self.email = email.clone();
+                self.role = role.clone();
+            }
+        }
+        self.version = version;
+        self.updated_at = recorded_at;
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct StoredEvent {
+    version: i32,
+    data: Json<UserStreamEvent>,
+    recorded_at: DateTime<Utc>,
+}
+
+// 在快照（或空状态）上依次应用事件；事件流必须以Registered开始
+fn fold(mut aggregate: Option<UserAggregate>, stream_id: Uuid, events: Vec<StoredEvent>) -> Option<UserAggregate> {
+    for stored in events {
+        match (&mut aggregate, &stored.data.0) {
+            (Some(aggregate), event) => aggregate.apply(event, stored.version, stored.recorded_at),
+            (None, UserStreamEvent::Registered { organization_id, name, email, role }) => {
+                aggregate = Some(UserAggregate {
+                    id: stream_id,
+                    organization_id: *organization_id,
+                    name: name.clone(),
+                    email: email.clone(),
+                    role: role.clone(),
+                    deleted: false,
+                    version: stored.version,
+                    created_at: stored.recorded_at,
+                    updated_at: stored.recorded_at,
+                })
+            }
+            (None, event) => tracing::warn!(%stream_id, version = stored.version, "事件流没有以Registered开始，忽略{}事件", event.name()),
+        }
+    }
+    aggregate
+}
+
+// 重放结果
+#[derive(Debug, Default, Serialize)]
+pub struct ReplayReport {
+    pub streams: usize,
+    pub reconciled: usize,
+    pub projected: usize,
+    pub removed: usize,
+    pub snapshots: usize,
+}
+
+// 事件溯源的用户存储实现：每个用户是一个事件流，写入时按流版本做乐观并发控制，
+// 并在同一事务中把聚合的当前状态投影到users表，读取仍使用users表。
+// SCIM同步、批量导入等操作直接修改users表，追加事件和重放前先把这些修改补记到事件流中
+pub struct EventSourcedUserStore;
+
+impl EventSourcedUserStore {
+    // 从最新的快照和之后的事件重建聚合，用户不存在时返回None
+    pub async fn load(conn: &mut PgConnection, user_id: Uuid) -> Result<Option<UserAggregate>, UserError> {
+        let snapshot = sqlx::query_scalar::<_, Json<UserAggregate>>("SELECT state FROM user_snapshots WHERE stream_id = $1")
+            .bind(user_id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .map(|state| state.0);
+
+        let events = sqlx::query_as::<_, StoredEvent>(r#"
+            SELECT version, data, recorded_at
+            FROM user_stream_events
+            WHERE stream_id = $1 AND version > $2
+            ORDER BY version
+            "#)
+            .bind(user_id)
+            .bind(snapshot.as_ref().map_or(0, |snapshot| snapshot.version))
+            .fetch_all(&mut *conn)
+            .await?;
+
+        Ok(fold(snapshot, user_id, events))
+    }
+
+    // 加载未删除的用户并与users表核对；启用事件溯源之前创建、或由其他途径创建的用户还没有事件流，
+    // 以users表中的当前状态作为流的起点
+    async fn load_or_adopt(conn: &mut PgConnection, user_id: Uuid) -> Result<UserAggregate, UserError> {
+        match Self::load(&mut *conn, user_id).await? {
+            Some(aggregate) if !aggregate.deleted => match Self::reconcile(&mut *conn, aggregate).await? {
+                aggregate if aggregate.deleted => Err(UserError::NotFound),
+                aggregate => Ok(aggregate),
+            },
+            Some(_) => Err(UserError::NotFound),
+            None => {
+                let user = UserStore::find_by_id(&mut *conn, user_id).await?.ok_or(UserError::NotFound)?;
+                let registered = UserStreamEvent::Registered {
+                    organization_id: user.organization_id,
+                    name: user.name,
+                    email: user.email,
+                    role: user.role,
+                };
+                Self::append(&mut *conn, user_id, user.organization_id, None, vec![registered]).await
+            }
+        }
+    }
+
+    // 核对聚合与users表并锁定该行：行中的姓名、邮箱或角色与聚合不同时追加Reconciled事件，
+    // 行已被其他途径删除（最后一个历史版本是删除）时追加Deleted事件，之后追加的事件和重放都不会撤销这些修改。
+    // 行不存在且没有删除记录时是投影缺失，保留聚合，由投影重建
+    async fn reconcile(conn: &mut PgConnection, aggregate: UserAggregate) -> Result<UserAggregate, UserError> {
+        if aggregate.deleted {
+            return Ok(aggregate);
+        }
+
+        let row = sqlx::query_as::<_, (String, String, String)>("SELECT name, email, role FROM users WHERE id = $1 FOR UPDATE")
+            .bind(aggregate.id)
+            .fetch_optional(&mut *conn)
+            .await?;
+        let event = match row {
+            Some((name, email, role)) => {
+                if (&name, &email, &role) == (&aggregate.name, &aggregate.email, &aggregate.role) {
+                    return Ok(aggregate);
+                }
+                UserStreamEvent::Reconciled { name, email, role }
+            }
+            None => {
+                let deleted = sqlx::query_scalar::<_, bool>(
+                    "SELECT operation = $2 FROM user_versions WHERE user_id = $1 ORDER BY version DESC LIMIT 1",
+                )
+                .bind(aggregate.id)
+                .bind(OPERATION_DELETE)
+                .fetch_optional(&mut *conn)
+                .await?;
+                if deleted != Some(true) {
+                    return Ok(aggregate);
+                }
+                UserStreamEvent::Deleted
+            }
+        };
+
+        tracing::info!(stream_id = %aggregate.id, "用户在事件流之外被修改，追加{}事件", event.name());
+        let (stream_id, organization_id) = (aggregate.id, aggregate.organization_id);
+        Self::append(&mut *conn, stream_id, organization_id, Some(aggregate), vec![event]).await
+    }
+
+    // 在current的版本之后追加事件，返回应用事件后的聚合。其他事务已写入相同版本时返回VersionConflict
+    async fn append(
+        conn: &mut PgConnection,
+        stream_id: Uuid,
+        organization_id: Uuid,
+        current: Option<UserAggregate>,
+        events: Vec<UserStreamEvent>,
+    ) -> Result<UserAggregate, UserError> {
+        let expected_version = current.as_ref().map_or(0, |aggregate| aggregate.version);
+
+        let mut stored = Vec::with_capacity(events.len());
+        for (offset, event) in events.into_iter().enumerate() {
+            let version = expected_version + offset as i32 + 1;
+            let recorded_at = sqlx::query_scalar::<_, DateTime<Utc>>(r#"
+                INSERT INTO user_stream_events (stream_id, version, organization_id, event_type, data)
+                VALUES ($1, $2, $3, $4, $5)
+                RETURNING recorded_at
+                "#)
+                .bind(stream_id)
+                .bind(version)
+                .bind(organization_id)
+                .bind(event.name())
+                .bind(Json(&event))
+                .fetch_one(&mut *conn)
+                .await
+                .map_err(|err| match &err {
+                    sqlx::Error::Database(db_err) if db_err.is_unique_violation() => UserError::VersionConflict,
+                    _ => UserError::Database(err),
+                })?;
+            stored.push(StoredEvent { version, data: Json(event), recorded_at });
+        }
+
+        let aggregate = fold(current, stream_id, stored).ok_or(UserError::NotFound)?;
+        if aggregate.version / SNAPSHOT_INTERVAL > expected_version / SNAPSHOT_INTERVAL {
+            Self::save_snapshot(&mut *conn, &aggregate).await?;
+        }
+
+        Ok(aggregate)
+    }
+
+    async fn save_snapshot(conn: &mut PgConnection, aggregate: &UserAggregate) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            INSERT INTO user_snapshots (stream_id, organization_id, version, state)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (stream_id) DO UPDATE
+            SET version = EXCLUDED.version, state = EXCLUDED.state, created_at = CURRENT_TIMESTAMP
+            "#)
+            .bind(aggregate.id)
+            .bind(aggregate.organization_id)
+            .bind(aggregate.version)
+            .bind(Json(aggregate))
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    // 把聚合的当前状态写入users表，已删除的聚合删除对应的行，返回投影后的用户。
+    // password为新的密码哈希；读表中没有该用户且未提供密码时（重放时）使用随机密码，用户需要通过忘记密码重新设置
+    async fn project(
+        conn: &mut PgConnection,
+        aggregate: &UserAggregate,
+        password: Option<&str>,
+    ) -> Result<Option<User>, UserError> {
+        if aggregate.deleted {
+            sqlx::query("DELETE FROM users WHERE id = $1")
+                .bind(aggregate.id)
+                .execute(&mut *conn)
+                .await?;
+            return Ok(None);
+        }
+
+        let new_password = match password {
+            Some(password) => Some(password.to_string()),
+            None => match UserStore::find_by_id(&mut *conn, aggregate.id).await? {
+                Some(_) => None,
+                None => Some(hash_password(&generate_token()).map_err(|_| UserError::PasswordHash)?),
+            },
+        };
+
+        // 字段都没有变化时不更新，避免触发更新时间和变更记录
+        let pinyin = transliterate(&aggregate.name);
+        let user = sqlx::query_as::<_, User>(r#"
+            INSERT INTO users (id, name, email, password, role, organization_id, name_pinyin, name_initials, created_at)
+            VALUES ($1, $2, $3, COALESCE($4, ''), $5, $6, $7, $8, $9)
+            ON CONFLICT (id) DO UPDATE
+            SET name = EXCLUDED.name,
+                email = EXCLUDED.email,
+                role = EXCLUDED.role,
+                name_pinyin = EXCLUDED.name_pinyin,
+                name_initials = EXCLUDED.name_initials,
+                password = COALESCE($4, users.password),
+                password_changed_at = CASE WHEN $4 IS NULL THEN users.password_changed_at ELSE CURRENT_TIMESTAMP END
+            WHERE (users.name, users.email, users.role, users.name_pinyin, users.name_initials)
+                      IS DISTINCT FROM (EXCLUDED.name, EXCLUDED.email, EXCLUDED.role, EXCLUDED.name_pinyin, EXCLUDED.name_initials)
+               OR $4 IS NOT NULL
+            RETURNING id, name, email, password, role, status, organization_id, created_at, updated_at
+            "#)
+            .bind(aggregate.id)
+            .bind(&aggregate.name)
+            .bind(&aggregate.email)
+            .bind(&new_password)
+            .bind(&aggregate.role)
+            .bind(aggregate.organization_id)
+            .bind(&pinyin.full)
+            .bind(&pinyin.initials)
+            .bind(aggregate.created_at)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(map_unique_email)?;
+
+        match user {
+            Some(user) => Ok(Some(user)),
+            None => Ok(UserStore::find_by_id(&mut *conn, aggregate.id).await?),
+        }
+    }
+
+    // 从事件流重建所有投影：快照全部重新生成，users表中每个有事件流的用户按事件流的最终状态覆盖或删除。
+    // 事件流之外的修改先补记为事件，因此重放不会撤销它们；
+    // 按行覆盖而不是清空users表，用户的会话、分组成员等关联数据得以保留
+    pub async fn replay(pool: &PgPool) -> Result<ReplayReport, UserError> {
+        let mut tx = pool.begin().await?;
+        let mut report = ReplayReport::default();
+
+        sqlx::query("DELETE FROM user_snapshots").execute(&mut *tx).await?;
+
+        let stream_ids = sqlx::query_scalar::<_, Uuid>("SELECT DISTINCT stream_id FROM user_stream_events ORDER BY stream_id")
+            .fetch_all(&mut *tx)
+            .await?;
+        for stream_id in stream_ids {
+            let events = sqlx::query_as::<_, StoredEvent>(
+                "SELECT version, data, recorded_at FROM user_stream_events WHERE stream_id = $1 ORDER BY version",
+            )
+            .bind(stream_id)
+            .fetch_all(&mut *tx)
+            .await?;
+            report.streams += 1;
+
+            let Some(aggregate) = fold(None, stream_id, events) else {
+                continue;
+            };
+            let version = aggregate.version;
+            let aggregate = Self::reconcile(&mut tx, aggregate).await?;
+            if aggregate.version != version {
+                report.reconciled += 1;
+            }
+            match Self::project(&mut tx, &aggregate, None).await? {
+                Some(_) => report.projected += 1,
+                None => report.removed += 1,
+            }
+            if aggregate.version >= SNAPSHOT_INTERVAL {
+                Self::save_snapshot(&mut tx, &aggregate).await?;
+                report.snapshots += 1;
+            }
+        }
+
+        tx.commit().await?;
+        Ok(report)
+    }
+}
+
+#[async_trait]
+impl UserRepository for EventSourcedUserStore {
+    async fn create(&self, conn: &mut PgConnection, organization_id: Uuid, user_data: &CreateUserRequest) -> Result<User, UserError> {
+        if UserStore::find_by_email(&mut *conn, &user_data.email).await?.is_some() {
+            return Err(UserError::EmailExists);
+        }
+        let role = user_data.role.as_deref().unwrap_or(ROLE_USER);
+        if !ROLES.contains(&role) {
+            return Err(UserError::InvalidRole);
+        }
+        let password = hash_password(&user_data.password).map_err(|_| UserError::PasswordHash)?;
+
+        let registered = UserStreamEvent::Registered {
+            organization_id,
+            name: user_data.name.clone(),
+            email: user_data.email.clone(),
+            role: role.to_string(),
+        };
+        let aggregate = Self::append(&mut *conn, Uuid::new_v4(), organization_id, None, vec![registered]).await?;
+        let user = Self::project(&mut *conn, &aggregate, Some(&password)).await?.ok_or(UserError::NotFound)?;
+
+        OutboxStore::append(&mut *conn, organization_id, &DomainEvent::UserCreated { user: user.clone() }).await?;
+
+        Ok(user)
+    }
+
+    async fn update(&self, conn: &mut PgConnection, user_id: Uuid, update_data: &UpdateUserRequest) -> Result<User, UserError> {
+        let current = Self::load_or_adopt(&mut *conn, user_id).await?;
+
+        let mut events = Vec::new();
+        let mut changed = Vec::new();
+        if let Some(name) = update_data.name.as_ref().filter(|name| **name != current.name) {
+            events.push(UserStreamEvent::Renamed { name: name.clone() });
+            changed.push("name");
+        }
+        if let Some(email) = update_data.email.as_ref().filter(|email| **email != current.email) {
+            if UserStore::find_by_email(&mut *conn, email).await?.is_some() {
+                return Err(UserError::EmailExists);
+            }
+            events.push(UserStreamEvent::EmailChanged { email: email.clone() });
+            changed.push("email");
+        }
+        if let Some(role) = &update_data.role {
+            if !ROLES.contains(&role.as_str()) {
+                return Err(UserError::InvalidRole);
+            }
+            if *role != current.role {
+                events.push(UserStreamEvent::RoleChanged { role: role.clone() });
+                changed.push("role");
+            }
+        }
+        let password = match &update_data.password {
+            Some(password) => {
+                events.push(UserStreamEvent::PasswordChanged);
+                Some(hash_password(password).map_err(|_| UserError::PasswordHash)?)
+            }
+            None => None,
+        };
+
+        if events.is_empty() {
+            return UserStore::find_by_id(&mut *conn, user_id).await?.ok_or(UserError::NotFound);
+        }
+        let organization_id = current.organization_id;
+        let aggregate = Self::append(&mut *conn, user_id, organization_id, Some(current), events).await?;
+        let user = Self::project(&mut *conn, &aggregate, password.as_deref()).await?.ok_or(UserError::NotFound)?;
+
+        if !changed.is_empty() {
+            OutboxStore::append(&mut *conn, organization_id, &DomainEvent::UserUpdated { user: user.clone(), changed }).await?;
+        }
+        if password.is_some() {
+            OutboxStore::append(&mut *conn, organization_id, &DomainEvent::UserPasswordChanged { user_id }).await?;
+        }
+
+        Ok(user)
+    }
+
+    async fn delete(&self, conn: &mut PgConnection, user_id: Uuid) -> Result<(), UserError> {
+        let current = Self::load_or_adopt(&mut *conn, user_id).await?;
+        let organization_id = current.organization_id;
+        let email = current.email.clone();
+
+        let aggregate = Self::append(&mut *conn, user_id, organization_id, Some(current), vec![UserStreamEvent::Deleted]).await?;
+        Self::project(&mut *conn, &aggregate, None).await?;
+
+        OutboxStore::append(&mut *conn, organization_id, &DomainEvent::UserDeleted { user_id, email }).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+    use crate::db::DbPool;
+    use crate::model::{CreateUserRequest, UpdateUserRequest, User, UserError, UserRepository, UserStore, ROLE_ADMIN, ROLE_USER};
+    use crate::test_support::*;
+    use super::EventSourcedUserStore;
+
+    async fn register(pool: &DbPool) -> User {
+        let (organization_id, _) = create_organization(pool).await;
+        let mut tx = pool.begin().await.unwrap();
+        let user = EventSourcedUserStore
+            .create(&mut tx, organization_id, &CreateUserRequest {
+                name: "事件用户".to_string(),
+                email: format!("{}@example.com", unique("events")),
+                password: PASSWORD.to_string(),
+                role: Some(ROLE_USER.to_string()),
+                group_ids: Vec::new(),
+            })
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        user
+    }
+
+    // 模拟SCIM同步等不经过事件流的写入
+    async fn write_directly(pool: &DbPool, user_id: Uuid, name: &str, role: &str) {
+        sqlx::query("UPDATE users SET name = $1, role = $2 WHERE id = $3")
+            .bind(name)
+            .bind(role)
+            .bind(user_id)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    async fn event_types(pool: &DbPool, user_id: Uuid) -> Vec<String> {
+        sqlx::query_scalar("SELECT event_type FROM user_stream_events WHERE stream_id = $1 ORDER BY version")
+            .bind(user_id)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn find(pool: &DbPool, user_id: Uuid) -> Option<User> {
+        UserStore::find_by_id(pool, user_id).await.unwrap()
+    }
+
+    // 直接写入的修改在下一次追加前补记到事件流，之后的修改和重放都不会撤销它
+    #[tokio::test]
+    async fn direct_writes_are_reconciled_before_append() {
+        let Some(pool) = pool().await else { return };
+        let user = register(&pool).await;
+        write_directly(&pool, user.id, "SCIM改名", ROLE_ADMIN).await;
+
+        let email = format!("{}@example.com", unique("changed"));
+        let mut tx = pool.begin().await.unwrap();
+        let updated = EventSourcedUserStore
+            .update(&mut tx, user.id, &UpdateUserRequest {
+                name: None,
+                email: Some(email.clone()),
+                password: None,
+                role: None,
+                group_ids: None,
+            })
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        assert_eq!((updated.name.as_str(), updated.role.as_str(), updated.email.as_str()), ("SCIM改名", ROLE_ADMIN, email.as_str()));
+        assert_eq!(event_types(&pool, user.id).await, ["Registered", "Reconciled", "EmailChanged"]);
+
+        EventSourcedUserStore::replay(&pool).await.unwrap();
+        let replayed = find(&pool, user.id).await.unwrap();
+        assert_eq!((replayed.name.as_str(), replayed.role.as_str(), replayed.email.as_str()), ("SCIM改名", ROLE_ADMIN, email.as_str()));
+    }
+
+    // 重放时先补记事件流之外的修改和删除，不会恢复旧值或重建被删除的用户
+    #[tokio::test]
+    async fn replay_keeps_out_of_band_changes() {
+        let Some(pool) = pool().await else { return };
+        let renamed = register(&pool).await;
+        write_directly(&pool, renamed.id, "导入改名", ROLE_USER).await;
+        let deleted = register(&pool).await;
+        UserStore::delete(&mut pool.acquire().await.unwrap(), deleted.id).await.unwrap();
+
+        EventSourcedUserStore::replay(&pool).await.unwrap();
+
+        assert_eq!(find(&pool, renamed.id).await.unwrap().name, "导入改名");
+        assert_eq!(event_types(&pool, renamed.id).await, ["Registered", "Reconciled"]);
+        assert!(find(&pool, deleted.id).await.is_none());
+        assert_eq!(event_types(&pool, deleted.id).await, ["Registered", "Deleted"]);
+
+        let mut tx = pool.begin().await.unwrap();
+        let result = EventSourcedUserStore.delete(&mut tx, deleted.id).await;
+        assert!(matches!(result, Err(UserError::NotFound)));
+    }
+
+    // 投影缺失且没有删除记录时，重放按事件流重建
+    #[tokio::test]
+    async fn replay_restores_missing_projection() {
+        let Some(pool) = pool().await else { return };
+        let user = register(&pool).await;
+        sqlx::query("DELETE FROM user_versions WHERE user_id = $1")
+            .bind(user.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        // 不触发历史记录，模拟投影被清空
+        let mut tx = pool.begin().await.unwrap();
+        sqlx::query("SET LOCAL session_replication_role = replica").execute(&mut *tx).await.unwrap();
+        sqlx::query("DELETE FROM users WHERE id = $1").bind(user.id).execute(&mut *tx).await.unwrap();
+        tx.commit().await.unwrap();
+
+        EventSourcedUserStore::replay(&pool).await.unwrap();
+        assert_eq!(find(&pool, user.id).await.unwrap().name, "事件用户");
+        assert_eq!(event_types(&pool, user.id).await, ["Registered"]);
+    }
+}
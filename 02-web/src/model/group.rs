@@ -0,0 +1,396 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::{FromRow, PgConnection, PgExecutor};
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+
+// 分组错误类型
+#[derive(Error, Debug)]
+pub enum GroupError {
+    #[error("分组不存在")]
+    NotFound,
+    #[error("分组名称已存在")]
+    NameExists,
+    #[error("上级分组不存在")]
+    ParentNotFound,
+    #[error("不能把分组移动到自身或其下级分组之下")]
+    Cycle,
+    #[error("用户不存在: {0}")]
+    UserNotFound(Uuid),
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+// 分组模型，parent_id指向上级分组，上级分组的成员关系包含下级分组的成员
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Group {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub parent_id: Option<Uuid>,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// 用户所属分组，direct为false表示通过下级分组间接加入
+#[derive(Debug, Serialize, FromRow)]
+pub struct UserGroup {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub group: Group,
+    pub direct: bool,
+}
+
+// 分组成员，group_id为用户直接所在的分组
+#[derive(Debug, Serialize, FromRow)]
+pub struct GroupMember {
+    pub user_id: Uuid,
+    pub name: String,
+    pub email: String,
+    pub group_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+// 创建分组请求
+#[derive(Debug, Deserialize)]
+pub struct CreateGroupRequest {
+    pub name: String,
+    pub description: Option<String>,
+    pub parent_id: Option<Uuid>,
+}
+
+// 更新分组请求，parent_id传null时移动到顶层，不传时保持不变
+#[derive(Debug, Deserialize)]
+pub struct UpdateGroupRequest {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    pub parent_id: Option<Option<Uuid>>,
+}
+
+// 批量更新成员请求
+#[derive(Debug, Deserialize)]
+pub struct UpdateMembersRequest {
+    #[serde(default)]
+    pub add: Vec<Uuid>,
+    #[serde(default)]
+    pub remove: Vec<Uuid>,
+}
+
+// 成员列表查询参数，transitive为true时包含下级分组的成员
+#[derive(Debug, Deserialize)]
+pub struct MemberListQuery {
+    #[serde(default)]
+    pub transitive: bool,
+}
+
+// 区分字段缺失和显式的null
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+fn map_unique_name(err: sqlx::Error) -> GroupError {
+    match &err {
+        sqlx::Error::Database(db_err) if db_err.is_unique_violation() => GroupError::NameExists,
+        _ => GroupError::Database(err),
+    }
+}
+
+// 分组存储实现
+// 需要在db::begin_tenant开启的租户事务中调用，行级安全策略保证只能访问当前组织的分组和用户；
+// 成员变更使用调用方的事务，可以和UserStore的用户操作一起提交
+pub struct GroupStore;
+
+impl GroupStore {
+    // 调整层级前锁定当前组织的分组结构，避免并发移动形成环
+    async fn lock_hierarchy(conn: &mut PgConnection, organization_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext('groups:' || $1::text))")
+            .bind(organization_id)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    // 检查ancestor_id是否为group_id自身或其上级分组
+    async fn is_ancestor(conn: &mut PgConnection, ancestor_id: Uuid, group_id: Uuid) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar::<_, bool>(r#"
+            WITH RECURSIVE ancestors AS (
+                SELECT id, parent_id FROM groups WHERE id = $1
+                UNION
+                SELECT g.id, g.parent_id FROM groups g JOIN ancestors a ON g.id = a.parent_id
+            )
+            SELECT EXISTS (SELECT 1 FROM ancestors WHERE id = $2)
+            "#)
+            .bind(group_id)
+            .bind(ancestor_id)
+            .fetch_one(&mut *conn)
+            .await
+    }
+
+    async fn ensure_parent(conn: &mut PgConnection, parent_id: Uuid) -> Result<(), GroupError> {
+        if Self::find_by_id(&mut *conn, parent_id).await?.is_none() {
+            return Err(GroupError::ParentNotFound);
+        }
+        Ok(())
+    }
+
+    // 创建分组
+    pub async fn create(
+        conn: &mut PgConnection,
+        organization_id: Uuid,
+        req: &CreateGroupRequest,
+    ) -> Result<Group, GroupError> {
+        if let Some(parent_id) = req.parent_id {
+            Self::ensure_parent(&mut *conn, parent_id).await?;
+        }
+
+        let group = sqlx::query_as::<_, Group>(r#"
+            INSERT INTO groups (organization_id, parent_id, name, description)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, organization_id, parent_id, name, description, created_at, updated_at
+            "#)
+            .bind(organization_id)
+            .bind(req.parent_id)
+            .bind(&req.name)
+            .bind(&req.description)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(map_unique_name)?;
+
+        Ok(group)
+    }
+
+    // 获取所有分组
+    pub async fn find_all<'e, E: PgExecutor<'e>>(executor: E) -> Result<Vec<Group>, sqlx::Error> {
+        let groups = sqlx::query_as::<_, Group>(r#"
+            SELECT id, organization_id, parent_id, name, description, created_at, updated_at
+            FROM groups
+            ORDER BY name
+            "#)
+            .fetch_all(executor)
+            .await?;
+
+        Ok(groups)
+    }
+
+    // 根据ID查找分组
+    pub async fn find_by_id<'e, E: PgExecutor<'e>>(executor: E, group_id: Uuid) -> Result<Option<Group>, sqlx::Error> {
+        let group = sqlx::query_as::<_, Group>(r#"
+            SELECT id, organization_id, parent_id, name, description, created_at, updated_at
+            FROM groups
+            WHERE id = $1
+            "#)
+            .bind(group_id)
+            .fetch_optional(executor)
+            .await?;
+
+        Ok(group)
+    }
+
+    // 更新分组，修改上级分组时检查是否会形成环
+    pub async fn update(
+        conn: &mut PgConnection,
+        group_id: Uuid,
+        req: &UpdateGroupRequest,
+    ) -> Result<Group, GroupError> {
+        let existing = Self::find_by_id(&mut *conn, group_id).await?.ok_or(GroupError::NotFound)?;
+
+        let parent_id = match req.parent_id {
+            Some(Some(parent_id)) => {
+                Self::lock_hierarchy(&mut *conn, existing.organization_id).await?;
+                Self::ensure_parent(&mut *conn, parent_id).await?;
+                if Self::is_ancestor(&mut *conn, group_id, parent_id).await? {
+                    return Err(GroupError::Cycle);
+                }
+                Some(parent_id)
+            }
+            Some(None) => None,
+            None => existing.parent_id,
+        };
+
+        let group = sqlx::query_as::<_, Group>(r#"
+            UPDATE groups
+            SET name = $1, description = $2, parent_id = $3, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $4
+            RETURNING id, organization_id, parent_id, name, description, created_at, updated_at
+            "#)
+            .bind(req.name.as_ref().unwrap_or(&existing.name))
+            .bind(req.description.as_ref().or(existing.description.as_ref()))
+            .bind(parent_id)
+            .bind(group_id)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(map_unique_name)?;
+
+        Ok(group)
+    }
+
+    // 删除分组，下级分组移动到顶层，成员关系一并删除
+    pub async fn delete(conn: &mut PgConnection, group_id: Uuid) -> Result<(), GroupError> {
+        let result = sqlx::query("DELETE FROM groups WHERE id = $1")
+            .bind(group_id)
+            .execute(&mut *conn)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(GroupError::NotFound);
+        }
+        Ok(())
+    }
+
+    // 获取分组成员，transitive为true时包含下级分组的成员（每个用户只出现一次）
+    pub async fn members(
+        conn: &mut PgConnection,
+        group_id: Uuid,
+        transitive: bool,
+    ) -> Result<Vec<GroupMember>, GroupError> {
+        if Self::find_by_id(&mut *conn, group_id).await?.is_none() {
+            return Err(GroupError::NotFound);
+        }
+
+        let members = sqlx::query_as::<_, GroupMember>(r#"
+            WITH RECURSIVE descendants AS (
+                SELECT id FROM groups WHERE id = $1
+                UNION
+                SELECT g.id FROM groups g JOIN descendants d ON g.parent_id = d.id WHERE $2
+            )
+            SELECT * FROM (
+                SELECT DISTINCT ON (users.id)
+                       users.id AS user_id, users.name, users.email, m.group_id, m.created_at
+                FROM group_members m
+                JOIN descendants d ON d.id = m.group_id
+                JOIN users ON users.id = m.user_id
+                ORDER BY users.id, m.group_id = $1 DESC, m.created_at
+            ) members
+            ORDER BY name
+            "#)
+            .bind(group_id)
+            .bind(transitive)
+            .fetch_all(&mut *conn)
+            .await?;
+
+        Ok(members)
+    }
+
+    // 批量添加和移除成员，所有用户都必须属于当前组织
+    pub async fn update_members(
+        conn: &mut PgConnection,
+        group_id: Uuid,
+        req: &UpdateMembersRequest,
+    ) -> Result<(), GroupError> {
+        if Self::find_by_id(&mut *conn, group_id).await?.is_none() {
+            return Err(GroupError::NotFound);
+        }
+
+        if !req.add.is_empty() {
+            let found = sqlx::query_scalar::<_, Uuid>("SELECT id FROM users WHERE id = ANY($1)")
+                .bind(&req.add)
+                .fetch_all(&mut *conn)
+                .await?;
+            if let Some(missing) = req.add.iter().find(|user_id| !found.contains(user_id)) {
+                return Err(GroupError::UserNotFound(*missing));
+            }
+
+            sqlx::query(r#"
+                INSERT INTO group_members (group_id, user_id)
+                SELECT $1, user_id FROM UNNEST($2::uuid[]) AS user_id
+                ON CONFLICT DO NOTHING
+                "#)
+                .bind(group_id)
+                .bind(&req.add)
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        if !req.remove.is_empty() {
+            sqlx::query("DELETE FROM group_members WHERE group_id = $1 AND user_id = ANY($2)")
+                .bind(group_id)
+                .bind(&req.remove)
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    // 把分组的直接成员设置为user_ids，用于SCIM等按完整列表同步成员的场景
+    pub async fn replace_members(
+        conn: &mut PgConnection,
+        group_id: Uuid,
+        user_ids: &[Uuid],
+    ) -> Result<(), GroupError> {
+        let req = UpdateMembersRequest { add: user_ids.to_vec(), remove: Vec::new() };
+        Self::update_members(&mut *conn, group_id, &req).await?;
+
+        sqlx::query("DELETE FROM group_members WHERE group_id = $1 AND user_id <> ALL($2)")
+            .bind(group_id)
+            .bind(user_ids)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    // 把用户的直接所属分组设置为group_ids，用于创建或更新用户时一并调整成员关系
+    pub async fn set_user_groups(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        group_ids: &[Uuid],
+    ) -> Result<(), GroupError> {
+        let found = sqlx::query_scalar::<_, Uuid>("SELECT id FROM groups WHERE id = ANY($1)")
+            .bind(group_ids)
+            .fetch_all(&mut *conn)
+            .await?;
+        if group_ids.iter().any(|group_id| !found.contains(group_id)) {
+            return Err(GroupError::NotFound);
+        }
+
+        sqlx::query("DELETE FROM group_members WHERE user_id = $1 AND group_id <> ALL($2)")
+            .bind(user_id)
+            .bind(group_ids)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query(r#"
+            INSERT INTO group_members (group_id, user_id)
+            SELECT group_id, $1 FROM UNNEST($2::uuid[]) AS group_id
+            ON CONFLICT DO NOTHING
+            "#)
+            .bind(user_id)
+            .bind(group_ids)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    // 获取用户所属的全部分组：直接加入的分组及其所有上级分组
+    pub async fn find_by_user(conn: &mut PgConnection, user_id: Uuid) -> Result<Vec<UserGroup>, sqlx::Error> {
+        let groups = sqlx::query_as::<_, UserGroup>(r#"
+            WITH RECURSIVE memberships AS (
+                SELECT m.group_id AS id, TRUE AS direct
+                FROM group_members m
+                WHERE m.user_id = $1
+                UNION
+                SELECT g.parent_id, FALSE
+                FROM groups g
+                JOIN memberships ON g.id = memberships.id
+                WHERE g.parent_id IS NOT NULL
+            )
+            SELECT g.id, g.organization_id, g.parent_id, g.name, g.description, g.created_at, g.updated_at,
+                   bool_or(memberships.direct) AS direct
+            FROM memberships
+            JOIN groups g ON g.id = memberships.id
+            GROUP BY g.id
+            ORDER BY g.name
+            "#)
+            .bind(user_id)
+            .fetch_all(&mut *conn)
+            .await?;
+
+        Ok(groups)
+    }
+}
@@ -0,0 +1,525 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+use chrono::{DateTime, Duration, Utc};
+use thiserror::Error;
+use crate::mailer::app_base_url;
+use crate::model::{audit::AuditStore, CreateUserRequest, User, UserError, UserStore, ROLE_ADMIN};
+use crate::token::{generate_token, hash_token};
+
+// 外部登录流程有效期（分钟）
+pub const EXTERNAL_LOGIN_TTL_MINUTES: i64 = 10;
+
+// 绑定发起登录的浏览器的Cookie名称
+pub const EXTERNAL_LOGIN_STATE_COOKIE: &str = "oidc_login_state";
+
+// 外部身份提供方错误类型
+#[derive(Error, Debug)]
+pub enum IdentityProviderError {
+    #[error("身份提供方不存在")]
+    NotFound,
+    #[error("身份提供方标识已存在")]
+    SlugExists,
+    #[error("登录状态无效或已过期")]
+    InvalidState,
+    #[error("身份提供方请求失败: {0}")]
+    Provider(String),
+    #[error("ID令牌无效: {0}")]
+    InvalidIdToken(String),
+    #[error("身份提供方未确认该邮箱")]
+    EmailNotVerified,
+    #[error("没有与该身份对应的账号")]
+    NoAccount,
+    #[error("管理员账号不能通过邮箱自动关联，请登录后手动绑定")]
+    AdminEmailLink,
+    #[error("该外部身份已绑定其他账号")]
+    IdentityLinked,
+    #[error("外部身份不存在")]
+    IdentityNotFound,
+    #[error("{0}")]
+    User(#[from] UserError),
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl From<reqwest::Error> for IdentityProviderError {
+    fn from(err: reqwest::Error) -> Self {
+        IdentityProviderError::Provider(err.to_string())
+    }
+}
+
+// 外部OIDC身份提供方配置
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct IdentityProvider {
+    pub id: Uuid,
+    // 只有该组织的用户可以通过它登录，自动创建的用户也归属该组织
+    pub organization_id: Uuid,
+    pub slug: String,
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    #[serde(skip_serializing)]
+    pub client_secret: Option<String>,
+    pub scopes: Vec<String>,
+    // 没有对应账号时是否自动创建用户
+    pub jit_provisioning: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+// 添加身份提供方请求
+#[derive(Debug, Deserialize)]
+pub struct CreateIdentityProviderRequest {
+    pub slug: String,
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub scopes: Option<Vec<String>>,
+    pub jit_provisioning: Option<bool>,
+}
+
+// 用户已绑定的外部身份
+#[derive(Debug, Serialize, FromRow)]
+pub struct ExternalIdentity {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub provider_slug: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// 发起外部登录的响应
+#[derive(Debug, Serialize)]
+pub struct ExternalLoginStart {
+    pub authorization_url: String,
+}
+
+// 身份提供方回调参数
+#[derive(Debug, Deserialize)]
+pub struct ExternalLoginCallback {
+    pub code: Option<String>,
+    pub state: String,
+    pub error: Option<String>,
+}
+
+// 身份提供方发现文档中用到的字段
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProviderTokenResponse {
+    id_token: String,
+}
+
+// 外部ID令牌中用到的声明
+#[derive(Debug, Deserialize)]
+struct ExternalIdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    name: Option<String>,
+}
+
+// 进行中的外部登录
+#[derive(Debug, FromRow)]
+struct ExternalLoginState {
+    user_id: Option<Uuid>,
+    code_verifier: String,
+    nonce: String,
+}
+
+// 回调处理结果：登录得到用户，绑定得到新的外部身份
+pub enum ExternalLoginOutcome {
+    Login(User),
+    Linked(ExternalIdentity),
+}
+
+// 回调地址
+fn callback_url(slug: &str) -> String {
+    format!("{}/auth/oidc/{}/callback", app_base_url(), slug)
+}
+
+// 外部身份提供方存储实现
+pub struct IdentityProviderStore;
+
+impl IdentityProviderStore {
+    // 为组织添加身份提供方，保存前先确认发现文档可用
+    pub async fn create(
+        pool: &PgPool,
+        http: &reqwest::Client,
+        organization_id: Uuid,
+        actor_id: Uuid,
+        req: &CreateIdentityProviderRequest,
+    ) -> Result<IdentityProvider, IdentityProviderError> {
+        if Self::find_by_slug(pool, &req.slug).await?.is_some() {
+            return Err(IdentityProviderError::SlugExists);
+        }
+
+        let issuer = req.issuer.trim_end_matches('/').to_string();
+        Self::discover(http, &issuer).await?;
+
+        let scopes = req.scopes.clone().unwrap_or_else(|| {
+            ["openid", "email", "profile"].iter().map(|scope| scope.to_string()).collect()
+        });
+
+        let provider = sqlx::query_as::<_, IdentityProvider>(r#"
+            INSERT INTO identity_providers (organization_id, slug, name, issuer, client_id, client_secret, scopes, jit_provisioning)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id, organization_id, slug, name, issuer, client_id, client_secret, scopes, jit_provisioning, created_at
+            "#)
+            .bind(organization_id)
+            .bind(&req.slug)
+            .bind(&req.name)
+            .bind(&issuer)
+            .bind(&req.client_id)
+            .bind(&req.client_secret)
+            .bind(&scopes)
+            .bind(req.jit_provisioning.unwrap_or(false))
+            .fetch_one(pool)
+            .await?;
+
+        AuditStore::record(pool, Some(actor_id), None, "identity_provider_created", Some(&provider.slug)).await?;
+
+        Ok(provider)
+    }
+
+    // 获取组织的所有身份提供方
+    pub async fn find_all(pool: &PgPool, organization_id: Uuid) -> Result<Vec<IdentityProvider>, sqlx::Error> {
+        let providers = sqlx::query_as::<_, IdentityProvider>(r#"
+            SELECT id, organization_id, slug, name, issuer, client_id, client_secret, scopes, jit_provisioning, created_at
+            FROM identity_providers
+            WHERE organization_id = $1
+            ORDER BY created_at DESC
+            "#)
+            .bind(organization_id)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(providers)
+    }
+
+    // 根据标识查找身份提供方
+    pub async fn find_by_slug(pool: &PgPool, slug: &str) -> Result<Option<IdentityProvider>, sqlx::Error> {
+        let provider = sqlx::query_as::<_, IdentityProvider>(r#"
+            SELECT id, organization_id, slug, name, issuer, client_id, client_secret, scopes, jit_provisioning, created_at
+            FROM identity_providers
+            WHERE slug = $1
+            "#)
+            .bind(slug)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(provider)
+    }
+
+    // 删除组织中的身份提供方，绑定在其上的外部身份一并删除
+    pub async fn delete(pool: &PgPool, organization_id: Uuid, actor_id: Uuid, slug: &str) -> Result<(), IdentityProviderError> {
+        let result = sqlx::query("DELETE FROM identity_providers WHERE slug = $1 AND organization_id = $2")
+            .bind(slug)
+            .bind(organization_id)
+            .execute(pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(IdentityProviderError::NotFound);
+        }
+
+        AuditStore::record(pool, Some(actor_id), None, "identity_provider_deleted", Some(slug)).await?;
+
+        Ok(())
+    }
+
+    // 读取发现文档，issuer必须与配置一致
+    async fn discover(http: &reqwest::Client, issuer: &str) -> Result<ProviderMetadata, IdentityProviderError> {
+        let metadata: ProviderMetadata = http
+            .get(format!("{}/.well-known/openid-configuration", issuer))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        if metadata.issuer.trim_end_matches('/') != issuer {
+            return Err(IdentityProviderError::Provider("发现文档中的issuer不匹配".to_string()));
+        }
+
+        Ok(metadata)
+    }
+
+    // 发起外部登录，link_user_id不为空时表示为已登录用户绑定新的外部身份
+    // 返回授权地址和需要写入Cookie的state
+    pub async fn start_login(
+        pool: &PgPool,
+        http: &reqwest::Client,
+        slug: &str,
+        link_user_id: Option<Uuid>,
+    ) -> Result<(ExternalLoginStart, String), IdentityProviderError> {
+        let provider = Self::find_by_slug(pool, slug).await?.ok_or(IdentityProviderError::NotFound)?;
+        let metadata = Self::discover(http, &provider.issuer).await?;
+
+        let state = generate_token();
+        let nonce = generate_token();
+        let code_verifier = generate_token();
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+        sqlx::query(r#"
+            INSERT INTO external_login_states (state_hash, provider_id, user_id, code_verifier, nonce, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#)
+            .bind(hash_token(&state))
+            .bind(provider.id)
+            .bind(link_user_id)
+            .bind(&code_verifier)
+            .bind(&nonce)
+            .bind(Utc::now() + Duration::minutes(EXTERNAL_LOGIN_TTL_MINUTES))
+            .execute(pool)
+            .await?;
+
+        let mut url = reqwest::Url::parse(&metadata.authorization_endpoint)
+            .map_err(|err| IdentityProviderError::Provider(err.to_string()))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &provider.client_id)
+            .append_pair("redirect_uri", &callback_url(&provider.slug))
+            .append_pair("scope", &provider.scopes.join(" "))
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        Ok((ExternalLoginStart { authorization_url: url.to_string() }, state))
+    }
+
+    // 处理身份提供方回调：state必须与Cookie中的一致，只能使用一次
+    pub async fn finish_login(
+        pool: &PgPool,
+        http: &reqwest::Client,
+        slug: &str,
+        callback: &ExternalLoginCallback,
+        cookie_state: &str,
+    ) -> Result<ExternalLoginOutcome, IdentityProviderError> {
+        if callback.state != cookie_state {
+            return Err(IdentityProviderError::InvalidState);
+        }
+        let provider = Self::find_by_slug(pool, slug).await?.ok_or(IdentityProviderError::NotFound)?;
+
+        let login = sqlx::query_as::<_, ExternalLoginState>(r#"
+            DELETE FROM external_login_states
+            WHERE state_hash = $1 AND provider_id = $2 AND expires_at > CURRENT_TIMESTAMP
+            RETURNING user_id, code_verifier, nonce
+            "#)
+            .bind(hash_token(&callback.state))
+            .bind(provider.id)
+            .fetch_optional(pool)
+            .await?
+            .ok_or(IdentityProviderError::InvalidState)?;
+
+        if let Some(error) = &callback.error {
+            return Err(IdentityProviderError::Provider(error.clone()));
+        }
+        let code = callback.code.as_deref().ok_or(IdentityProviderError::InvalidState)?;
+
+        let metadata = Self::discover(http, &provider.issuer).await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code".to_string()),
+            ("code", code.to_string()),
+            ("redirect_uri", callback_url(&provider.slug)),
+            ("code_verifier", login.code_verifier.clone()),
+            ("client_id", provider.client_id.clone()),
+        ];
+        if let Some(secret) = &provider.client_secret {
+            form.push(("client_secret", secret.clone()));
+        }
+        let tokens: ProviderTokenResponse = http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let claims = Self::validate_id_token(http, &provider, &metadata, &tokens.id_token).await?;
+        if claims.nonce.as_deref() != Some(login.nonce.as_str()) {
+            return Err(IdentityProviderError::InvalidIdToken("nonce不匹配".to_string()));
+        }
+
+        Self::resolve_identity(pool, &provider, &claims, login.user_id).await
+    }
+
+    // 使用身份提供方的JWKS校验ID令牌的签名、issuer、audience和有效期
+    async fn validate_id_token(
+        http: &reqwest::Client,
+        provider: &IdentityProvider,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+    ) -> Result<ExternalIdTokenClaims, IdentityProviderError> {
+        let invalid = |err: jsonwebtoken::errors::Error| IdentityProviderError::InvalidIdToken(err.to_string());
+
+        let header = jsonwebtoken::decode_header(id_token).map_err(invalid)?;
+        if !matches!(header.alg, Algorithm::RS256 | Algorithm::ES256) {
+            return Err(IdentityProviderError::InvalidIdToken("不支持的签名算法".to_string()));
+        }
+
+        let jwks: JwkSet = http.get(&metadata.jwks_uri).send().await?.error_for_status()?.json().await?;
+        let jwk = match &header.kid {
+            Some(kid) => jwks.find(kid),
+            None => jwks.keys.first(),
+        }
+        .ok_or_else(|| IdentityProviderError::InvalidIdToken("找不到对应的公钥".to_string()))?;
+        let key = DecodingKey::from_jwk(jwk).map_err(invalid)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&provider.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let data = jsonwebtoken::decode::<ExternalIdTokenClaims>(id_token, &key, &validation).map_err(invalid)?;
+
+        Ok(data.claims)
+    }
+
+    // 根据外部身份找到或创建本地用户
+    async fn resolve_identity(
+        pool: &PgPool,
+        provider: &IdentityProvider,
+        claims: &ExternalIdTokenClaims,
+        link_user_id: Option<Uuid>,
+    ) -> Result<ExternalLoginOutcome, IdentityProviderError> {
+        let existing: Option<Uuid> = sqlx::query_scalar(r#"
+            SELECT user_id FROM external_identities WHERE provider_id = $1 AND subject = $2
+            "#)
+            .bind(provider.id)
+            .bind(&claims.sub)
+            .fetch_optional(pool)
+            .await?;
+
+        // 为已登录用户绑定，只能绑定本组织的身份提供方
+        if let Some(user_id) = link_user_id {
+            if existing.is_some() {
+                return Err(IdentityProviderError::IdentityLinked);
+            }
+            UserStore::find_by_id(pool, user_id)
+                .await?
+                .filter(|user| user.organization_id == provider.organization_id)
+                .ok_or(IdentityProviderError::NotFound)?;
+            let identity = Self::link(pool, provider, claims, user_id).await?;
+            return Ok(ExternalLoginOutcome::Linked(identity));
+        }
+
+        if let Some(user_id) = existing {
+            let user = UserStore::find_by_id(pool, user_id)
+                .await?
+                .filter(|user| user.organization_id == provider.organization_id)
+                .ok_or(IdentityProviderError::NoAccount)?;
+            AuditStore::record(pool, Some(user.id), Some(user.id), "external_login", Some(&provider.slug)).await?;
+            return Ok(ExternalLoginOutcome::Login(user));
+        }
+
+        // 首次登录只能通过身份提供方确认过的邮箱关联账号
+        let email = match (&claims.email, claims.email_verified) {
+            (Some(email), true) => email,
+            _ => return Err(IdentityProviderError::EmailNotVerified),
+        };
+
+        // 邮箱全局唯一：属于其他组织的账号既不能关联，也不能再自动创建
+        let user = match UserStore::find_by_email(pool, email).await? {
+            Some(user) if user.organization_id != provider.organization_id => {
+                return Err(IdentityProviderError::NoAccount);
+            }
+            // 管理员账号被外部身份接管的代价太高，必须由本人登录后手动绑定
+            Some(user) if user.role == ROLE_ADMIN => return Err(IdentityProviderError::AdminEmailLink),
+            Some(user) => user,
+            None if provider.jit_provisioning => {
+                let mut tx = pool.begin().await?;
+                let user = UserStore::create(&mut tx, provider.organization_id, &CreateUserRequest {
+                    name: claims.name.clone().unwrap_or_else(|| email.clone()),
+                    email: email.clone(),
+                    // 随机密码，用户之后可以通过忘记密码设置
+                    password: generate_token(),
+                    role: None,
+                    group_ids: Vec::new(),
+                })
+                .await?;
+                tx.commit().await?;
+                AuditStore::record(pool, None, Some(user.id), "external_user_provisioned", Some(&provider.slug)).await?;
+                user
+            }
+            None => return Err(IdentityProviderError::NoAccount),
+        };
+
+        Self::link(pool, provider, claims, user.id).await?;
+        AuditStore::record(pool, Some(user.id), Some(user.id), "external_login", Some(&provider.slug)).await?;
+
+        Ok(ExternalLoginOutcome::Login(user))
+    }
+
+    // 保存外部身份与用户的绑定
+    async fn link(
+        pool: &PgPool,
+        provider: &IdentityProvider,
+        claims: &ExternalIdTokenClaims,
+        user_id: Uuid,
+    ) -> Result<ExternalIdentity, IdentityProviderError> {
+        let identity = sqlx::query_as::<_, ExternalIdentity>(r#"
+            INSERT INTO external_identities (user_id, provider_id, subject, email)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, $5::TEXT AS provider_slug, subject, email, created_at
+            "#)
+            .bind(user_id)
+            .bind(provider.id)
+            .bind(&claims.sub)
+            .bind(&claims.email)
+            .bind(&provider.slug)
+            .fetch_one(pool)
+            .await?;
+
+        AuditStore::record(pool, Some(user_id), Some(user_id), "external_identity_linked", Some(&provider.slug)).await?;
+
+        Ok(identity)
+    }
+
+    // 获取用户绑定的外部身份
+    pub async fn list_identities(pool: &PgPool, user_id: Uuid) -> Result<Vec<ExternalIdentity>, sqlx::Error> {
+        let identities = sqlx::query_as::<_, ExternalIdentity>(r#"
+            SELECT i.id, i.user_id, p.slug AS provider_slug, i.subject, i.email, i.created_at
+            FROM external_identities i
+            JOIN identity_providers p ON p.id = i.provider_id
+            WHERE i.user_id = $1
+            ORDER BY i.created_at
+            "#)
+            .bind(user_id)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(identities)
+    }
+
+    // 解除外部身份绑定
+    pub async fn unlink(pool: &PgPool, user_id: Uuid, identity_id: Uuid) -> Result<(), IdentityProviderError> {
+        let result = sqlx::query("DELETE FROM external_identities WHERE id = $1 AND user_id = $2")
+            .bind(identity_id)
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(IdentityProviderError::IdentityNotFound);
+        }
+
+        AuditStore::record(pool, Some(user_id), Some(user_id), "external_identity_unlinked", None).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,349 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+use chrono::{DateTime, Duration, Utc};
+use thiserror::Error;
+use crate::db::begin_tenant;
+use crate::model::{audit::AuditStore, group::{GroupError, GroupStore}, CreateUserRequest, User, UserError, UserStore, ROLES, ROLE_USER};
+use crate::model::oidc::issuer;
+use crate::model::password_reset::MIN_PASSWORD_LENGTH;
+use crate::model::signing_key::{SigningKeyError, SigningKeyStore};
+
+// 邀请有效期（天）
+pub const INVITATION_TTL_DAYS: i64 = 7;
+
+// 邀请令牌的受众，避免与ID令牌等其他JWT混用
+const INVITATION_AUDIENCE: &str = "invitation";
+
+// 邀请错误类型
+#[derive(Error, Debug)]
+pub enum InvitationError {
+    #[error("邀请不存在")]
+    NotFound,
+    #[error("邀请无效、已撤销或已过期")]
+    InvalidToken,
+    #[error("该邮箱已有待接受的邀请")]
+    AlreadyInvited,
+    #[error("邮箱已存在")]
+    EmailExists,
+    #[error("无效的角色")]
+    InvalidRole,
+    #[error("分组不存在")]
+    GroupNotFound,
+    #[error("密码长度不能少于{MIN_PASSWORD_LENGTH}位")]
+    WeakPassword,
+    #[error("签名失败: {0}")]
+    Signing(#[from] SigningKeyError),
+    #[error("用户错误: {0}")]
+    User(UserError),
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl From<UserError> for InvitationError {
+    fn from(err: UserError) -> Self {
+        match err {
+            UserError::EmailExists => InvitationError::EmailExists,
+            UserError::Database(err) => InvitationError::Database(err),
+            err => InvitationError::User(err),
+        }
+    }
+}
+
+impl From<GroupError> for InvitationError {
+    fn from(err: GroupError) -> Self {
+        match err {
+            GroupError::Database(err) => InvitationError::Database(err),
+            _ => InvitationError::GroupNotFound,
+        }
+    }
+}
+
+// 待接受的邀请
+#[derive(Debug, Serialize, FromRow)]
+pub struct Invitation {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub email: String,
+    pub role: String,
+    pub group_id: Option<Uuid>,
+    pub invited_by: Option<Uuid>,
+    pub expires_at: DateTime<Utc>,
+    pub sent_count: i32,
+    pub last_sent_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+// 创建邀请请求
+#[derive(Debug, Deserialize)]
+pub struct CreateInvitationRequest {
+    pub email: String,
+    pub role: Option<String>,
+    pub group_id: Option<Uuid>,
+}
+
+// 接受邀请请求，由被邀请人设置姓名和密码
+#[derive(Debug, Deserialize)]
+pub struct AcceptInvitationRequest {
+    pub token: String,
+    pub name: String,
+    pub password: String,
+}
+
+// 邀请链接中的令牌
+#[derive(Debug, Deserialize)]
+pub struct InvitationTokenQuery {
+    pub token: String,
+}
+
+// 邀请令牌，jti在重新发送时更换，使之前发出的链接失效
+#[derive(Debug, Serialize, Deserialize)]
+struct InvitationClaims {
+    iss: String,
+    aud: String,
+    sub: Uuid,
+    jti: Uuid,
+    email: String,
+    exp: i64,
+}
+
+// 已签发的邀请及其令牌，令牌只用于发送邮件
+pub struct IssuedInvitation {
+    pub invitation: Invitation,
+    pub token: String,
+}
+
+// 邀请存储实现
+// 管理接口在邀请人所在组织的租户事务中执行，只能看到和操作当前组织的邀请
+pub struct InvitationStore;
+
+impl InvitationStore {
+    // 签发邀请令牌
+    async fn sign(pool: &PgPool, invitation: Invitation, token_id: Uuid) -> Result<IssuedInvitation, InvitationError> {
+        let token = SigningKeyStore::sign(pool, &InvitationClaims {
+            iss: issuer(),
+            aud: INVITATION_AUDIENCE.to_string(),
+            sub: invitation.id,
+            jti: token_id,
+            email: invitation.email.clone(),
+            exp: invitation.expires_at.timestamp(),
+        })
+        .await?;
+
+        Ok(IssuedInvitation { invitation, token })
+    }
+
+    // 创建邀请，同一组织内同一邮箱只能有一个待接受的邀请
+    pub async fn create(
+        pool: &PgPool,
+        organization_id: Uuid,
+        actor_id: Uuid,
+        req: &CreateInvitationRequest,
+    ) -> Result<IssuedInvitation, InvitationError> {
+        let role = req.role.as_deref().unwrap_or(ROLE_USER);
+        if !ROLES.contains(&role) {
+            return Err(InvitationError::InvalidRole);
+        }
+        // 邮箱在所有组织中唯一，需要在租户事务之外检查
+        if UserStore::find_by_email(pool, &req.email).await?.is_some() {
+            return Err(InvitationError::EmailExists);
+        }
+
+        let mut tx = begin_tenant(pool, organization_id).await?;
+
+        if let Some(group_id) = req.group_id {
+            if GroupStore::find_by_id(&mut *tx, group_id).await?.is_none() {
+                return Err(InvitationError::GroupNotFound);
+            }
+        }
+
+        // 过期未接受的邀请不再占用该邮箱
+        sqlx::query("DELETE FROM invitations WHERE lower(email) = lower($1) AND expires_at <= CURRENT_TIMESTAMP")
+            .bind(&req.email)
+            .execute(&mut *tx)
+            .await?;
+
+        let token_id = Uuid::new_v4();
+        let invitation = sqlx::query_as::<_, Invitation>(r#"
+            INSERT INTO invitations (organization_id, email, role, group_id, invited_by, token_id, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT DO NOTHING
+            RETURNING id, organization_id, email, role, group_id, invited_by, expires_at, sent_count,
+                      last_sent_at, created_at
+            "#)
+            .bind(organization_id)
+            .bind(&req.email)
+            .bind(role)
+            .bind(req.group_id)
+            .bind(actor_id)
+            .bind(token_id)
+            .bind(Utc::now() + Duration::days(INVITATION_TTL_DAYS))
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(InvitationError::AlreadyInvited)?;
+
+        let issued = Self::sign(pool, invitation, token_id).await?;
+        AuditStore::record(&mut *tx, Some(actor_id), None, "invitation_created", Some(&req.email)).await?;
+
+        tx.commit().await?;
+
+        Ok(issued)
+    }
+
+    // 获取当前组织未过期的邀请
+    pub async fn find_pending(pool: &PgPool, organization_id: Uuid) -> Result<Vec<Invitation>, sqlx::Error> {
+        let mut tx = begin_tenant(pool, organization_id).await?;
+        let invitations = sqlx::query_as::<_, Invitation>(r#"
+            SELECT id, organization_id, email, role, group_id, invited_by, expires_at, sent_count,
+                   last_sent_at, created_at
+            FROM invitations
+            WHERE expires_at > CURRENT_TIMESTAMP
+            ORDER BY created_at DESC
+            "#)
+            .fetch_all(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(invitations)
+    }
+
+    // 重新发送邀请，之前发出的链接随之失效
+    pub async fn resend(
+        pool: &PgPool,
+        organization_id: Uuid,
+        actor_id: Uuid,
+        invitation_id: Uuid,
+    ) -> Result<IssuedInvitation, InvitationError> {
+        let mut tx = begin_tenant(pool, organization_id).await?;
+
+        // 更换jti并重新计算有效期，已过期但尚未清理的邀请也可以重新发送
+        let token_id = Uuid::new_v4();
+        let invitation = sqlx::query_as::<_, Invitation>(r#"
+            UPDATE invitations
+            SET token_id = $1, expires_at = $2, sent_count = sent_count + 1, last_sent_at = CURRENT_TIMESTAMP
+            WHERE id = $3
+            RETURNING id, organization_id, email, role, group_id, invited_by, expires_at, sent_count,
+                      last_sent_at, created_at
+            "#)
+            .bind(token_id)
+            .bind(Utc::now() + Duration::days(INVITATION_TTL_DAYS))
+            .bind(invitation_id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(InvitationError::NotFound)?;
+
+        AuditStore::record(&mut *tx, Some(actor_id), None, "invitation_resent", Some(&invitation.email)).await?;
+        let issued = Self::sign(pool, invitation, token_id).await?;
+
+        tx.commit().await?;
+
+        Ok(issued)
+    }
+
+    // 撤销邀请
+    pub async fn revoke(
+        pool: &PgPool,
+        organization_id: Uuid,
+        actor_id: Uuid,
+        invitation_id: Uuid,
+    ) -> Result<(), InvitationError> {
+        let mut tx = begin_tenant(pool, organization_id).await?;
+
+        let email = sqlx::query_scalar::<_, String>("DELETE FROM invitations WHERE id = $1 RETURNING email")
+            .bind(invitation_id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(InvitationError::NotFound)?;
+        AuditStore::record(&mut *tx, Some(actor_id), None, "invitation_revoked", Some(&email)).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    // 校验邀请令牌的签名、受众和签发者
+    async fn verify_token(pool: &PgPool, token: &str) -> Result<InvitationClaims, InvitationError> {
+        let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::RS256);
+        validation.set_audience(&[INVITATION_AUDIENCE]);
+        validation.set_issuer(&[issuer()]);
+        SigningKeyStore::verify::<InvitationClaims>(pool, token, &validation)
+            .await
+            .map_err(|_| InvitationError::InvalidToken)
+    }
+
+    // 根据链接中的令牌查找仍可接受的邀请，用于显示接受页面
+    pub async fn find_by_token(pool: &PgPool, token: &str) -> Result<Invitation, InvitationError> {
+        let claims = Self::verify_token(pool, token).await?;
+        let invitation = sqlx::query_as::<_, Invitation>(r#"
+            SELECT id, organization_id, email, role, group_id, invited_by, expires_at, sent_count,
+                   last_sent_at, created_at
+            FROM invitations
+            WHERE id = $1 AND token_id = $2 AND expires_at > CURRENT_TIMESTAMP
+            "#)
+            .bind(claims.sub)
+            .bind(claims.jti)
+            .fetch_optional(pool)
+            .await?
+            .ok_or(InvitationError::InvalidToken)?;
+
+        Ok(invitation)
+    }
+
+    // 接受邀请：校验签名和有效期，按邀请的角色和分组创建用户，邀请随之删除
+    pub async fn accept(pool: &PgPool, req: &AcceptInvitationRequest) -> Result<User, InvitationError> {
+        if req.password.chars().count() < MIN_PASSWORD_LENGTH {
+            return Err(InvitationError::WeakPassword);
+        }
+
+        let claims = Self::verify_token(pool, &req.token).await?;
+
+        let organization_id = sqlx::query_scalar::<_, Uuid>("SELECT organization_id FROM invitations WHERE id = $1")
+            .bind(claims.sub)
+            .fetch_optional(pool)
+            .await?
+            .ok_or(InvitationError::InvalidToken)?;
+
+        let mut tx = begin_tenant(pool, organization_id).await?;
+
+        // 重新发送或撤销后旧令牌不可用，删除行同时防止重复接受
+        let invitation = sqlx::query_as::<_, Invitation>(r#"
+            DELETE FROM invitations
+            WHERE id = $1 AND token_id = $2 AND expires_at > CURRENT_TIMESTAMP
+            RETURNING id, organization_id, email, role, group_id, invited_by, expires_at, sent_count,
+                      last_sent_at, created_at
+            "#)
+            .bind(claims.sub)
+            .bind(claims.jti)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(InvitationError::InvalidToken)?;
+
+        let group_ids: Vec<Uuid> = invitation.group_id.into_iter().collect();
+        let user = UserStore::create(&mut tx, organization_id, &CreateUserRequest {
+            name: req.name.clone(),
+            email: invitation.email.clone(),
+            password: req.password.clone(),
+            role: Some(invitation.role.clone()),
+            group_ids: group_ids.clone(),
+        })
+        .await?;
+        if !group_ids.is_empty() {
+            GroupStore::set_user_groups(&mut tx, user.id, &group_ids).await?;
+        }
+
+        AuditStore::record(&mut *tx, invitation.invited_by, Some(user.id), "invitation_accepted", Some(&user.email)).await?;
+
+        tx.commit().await?;
+
+        Ok(user)
+    }
+
+    // 删除过期未接受的邀请，返回删除数量
+    pub async fn cleanup_expired(pool: &PgPool) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM invitations WHERE expires_at <= CURRENT_TIMESTAMP")
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+use chrono::{DateTime, Duration, Utc};
+use crate::model::audit::AuditStore;
+
+// 统计失败次数的时间窗口（分钟），超过窗口未再失败则重新计数
+const FAILURE_WINDOW_MINUTES: i64 = 15;
+
+// 账号：失败3次后开始递增延迟，失败10次锁定15分钟
+const ACCOUNT_DELAY_AFTER: i32 = 3;
+const ACCOUNT_LOCK_AFTER: i32 = 10;
+
+// IP：失败20次后开始递增延迟，失败50次锁定15分钟
+const IP_DELAY_AFTER: i32 = 20;
+const IP_LOCK_AFTER: i32 = 50;
+
+const LOCK_MINUTES: i64 = 15;
+
+// 递增延迟的上限（秒）
+const MAX_DELAY_SECONDS: i64 = 30;
+
+pub const SCOPE_ACCOUNT: &str = "account";
+pub const SCOPE_IP: &str = "ip";
+
+// 登录限制记录
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct LoginThrottle {
+    pub scope: String,
+    pub key: String,
+    pub failures: i32,
+    pub last_failure_at: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+// 解除锁定请求，邮箱和IP二选一
+#[derive(Debug, Deserialize)]
+pub struct UnlockRequest {
+    pub email: Option<String>,
+    pub ip: Option<String>,
+}
+
+// 被限制时还需等待的秒数，locked表示已被锁定，否则为失败后的递增延迟
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryAfter {
+    pub seconds: i64,
+    pub locked: bool,
+}
+
+impl LoginThrottle {
+    // 距离允许下一次尝试还需等待的时间，以及是否处于锁定期
+    fn retry_after(&self, now: DateTime<Utc>) -> Option<(Duration, bool)> {
+        if let Some(locked_until) = self.locked_until {
+            if locked_until > now {
+                return Some((locked_until - now, true));
+            }
+        }
+
+        let delay_after = if self.scope == SCOPE_IP { IP_DELAY_AFTER } else { ACCOUNT_DELAY_AFTER };
+        if self.failures < delay_after
+            || self.last_failure_at < now - Duration::minutes(FAILURE_WINDOW_MINUTES)
+        {
+            return None;
+        }
+
+        // 每多失败一次延迟翻倍：1s, 2s, 4s ... 最多30s
+        let exponent = (self.failures - delay_after).min(5) as u32;
+        let delay = Duration::seconds((1i64 << exponent).min(MAX_DELAY_SECONDS));
+        let allowed_at = self.last_failure_at + delay;
+        (allowed_at > now).then(|| (allowed_at - now, false))
+    }
+}
+
+// 规范化邮箱，避免大小写差异绕过计数
+pub fn account_key(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+// 登录限制存储实现
+pub struct LoginThrottleStore;
+
+impl LoginThrottleStore {
+    // 检查账号和IP是否允许登录，不允许时返回需要等待的时间；ip为空时只检查账号
+    pub async fn check(pool: &PgPool, email: &str, ip: Option<&str>) -> Result<Option<RetryAfter>, sqlx::Error> {
+        let throttles = sqlx::query_as::<_, LoginThrottle>(r#"
+            SELECT scope, key, failures, last_failure_at, locked_until
+            FROM login_throttles
+            WHERE (scope = $1 AND key = $2) OR (scope = $3 AND key = $4)
+            "#)
+            .bind(SCOPE_ACCOUNT)
+            .bind(account_key(email))
+            .bind(SCOPE_IP)
+            .bind(ip)
+            .fetch_all(pool)
+            .await?;
+
+        let now = Utc::now();
+        let waits: Vec<(Duration, bool)> = throttles.iter().filter_map(|throttle| throttle.retry_after(now)).collect();
+        let retry_after = waits.iter().map(|(wait, _)| *wait).max().map(|wait| RetryAfter {
+            // 向上取整，客户端按Retry-After等待后一定可以重试
+            seconds: ((wait.num_milliseconds() + 999) / 1000).max(1),
+            locked: waits.iter().any(|(_, locked)| *locked),
+        });
+
+        Ok(retry_after)
+    }
+
+    // 记录一次失败登录，达到阈值时锁定，返回本次被锁定的范围
+    pub async fn record_failure(pool: &PgPool, email: &str, ip: &str) -> Result<Vec<&'static str>, sqlx::Error> {
+        let mut locked_scopes = Vec::new();
+        for (scope, key, lock_after) in [
+            (SCOPE_ACCOUNT, account_key(email), ACCOUNT_LOCK_AFTER),
+            (SCOPE_IP, ip.to_string(), IP_LOCK_AFTER),
+        ] {
+            let locked: bool = sqlx::query_scalar(r#"
+                INSERT INTO login_throttles (scope, key, failures, last_failure_at)
+                VALUES ($1, $2, 1, CURRENT_TIMESTAMP)
+                ON CONFLICT (scope, key) DO UPDATE
+                SET failures = CASE
+                        WHEN login_throttles.last_failure_at < CURRENT_TIMESTAMP - make_interval(mins => $3)
+                        THEN 1
+                        ELSE login_throttles.failures + 1
+                    END,
+                    last_failure_at = CURRENT_TIMESTAMP
+                RETURNING failures >= $4
+                "#)
+                .bind(scope)
+                .bind(&key)
+                .bind(FAILURE_WINDOW_MINUTES as i32)
+                .bind(lock_after)
+                .fetch_one(pool)
+                .await?;
+
+            if locked {
+                // 锁定后清零计数，解锁后重新开始计算
+                sqlx::query(r#"
+                    UPDATE login_throttles
+                    SET failures = 0, locked_until = CURRENT_TIMESTAMP + make_interval(mins => $3)
+                    WHERE scope = $1 AND key = $2
+                    "#)
+                    .bind(scope)
+                    .bind(&key)
+                    .bind(LOCK_MINUTES as i32)
+                    .execute(pool)
+                    .await?;
+
+                let detail = format!("scope={} key={}", scope, key);
+                AuditStore::record(pool, None, None, "login_locked", Some(&detail)).await?;
+                locked_scopes.push(scope);
+            }
+        }
+
+        Ok(locked_scopes)
+    }
+
+    // 登录成功后清除账号的失败记录
+    pub async fn record_success(pool: &PgPool, email: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM login_throttles WHERE scope = $1 AND key = $2")
+            .bind(SCOPE_ACCOUNT)
+            .bind(account_key(email))
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // 获取当前被锁定或正在计数的记录
+    pub async fn list_active(pool: &PgPool) -> Result<Vec<LoginThrottle>, sqlx::Error> {
+        let throttles = sqlx::query_as::<_, LoginThrottle>(r#"
+            SELECT scope, key, failures, last_failure_at, locked_until
+            FROM login_throttles
+            WHERE locked_until > CURRENT_TIMESTAMP
+               OR last_failure_at > CURRENT_TIMESTAMP - make_interval(mins => $1)
+            ORDER BY last_failure_at DESC
+            "#)
+            .bind(FAILURE_WINDOW_MINUTES as i32)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(throttles)
+    }
+
+    // 管理员解除锁定，返回是否存在对应记录
+    pub async fn unlock(pool: &PgPool, actor_id: Uuid, scope: &str, key: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM login_throttles WHERE scope = $1 AND key = $2")
+            .bind(scope)
+            .bind(key)
+            .execute(pool)
+            .await?;
+
+        let detail = format!("scope={} key={}", scope, key);
+        AuditStore::record(pool, Some(actor_id), None, "login_unlocked", Some(&detail)).await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
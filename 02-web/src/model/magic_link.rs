@@ -0,0 +1,136 @@
+use serde::Deserialize;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+use chrono::{DateTime, Duration, Utc};
+use crate::model::{audit::AuditStore, login_throttle::account_key};
+use crate::token::{generate_token, hash_token};
+
+// 登录链接有效期（分钟）
+pub const MAGIC_LINK_TTL_MINUTES: i64 = 15;
+
+// 绑定请求浏览器的Cookie名称
+pub const MAGIC_LINK_NONCE_COOKIE: &str = "magic_link_nonce";
+
+// 同一邮箱两次申请之间的最短间隔（秒），以及每小时的申请上限
+const EMAIL_COOLDOWN_SECONDS: i64 = 60;
+const EMAIL_HOURLY_LIMIT: i64 = 5;
+
+// 同一IP每小时的申请上限
+const IP_HOURLY_LIMIT: i64 = 20;
+
+// 申请登录链接请求
+#[derive(Debug, Deserialize)]
+pub struct MagicLinkRequest {
+    pub email: String,
+}
+
+// 打开登录链接的查询参数
+#[derive(Debug, Deserialize)]
+pub struct MagicLinkQuery {
+    pub token: String,
+}
+
+// 最近1小时内同一邮箱和同一IP的申请情况
+#[derive(FromRow)]
+struct RecentRequests {
+    by_email: i64,
+    first_by_email: Option<DateTime<Utc>>,
+    last_by_email: Option<DateTime<Utc>>,
+    by_ip: i64,
+    first_by_ip: Option<DateTime<Utc>>,
+}
+
+impl RecentRequests {
+    // 超出限制时还需等待的时间
+    fn retry_after(&self, now: DateTime<Utc>) -> Option<Duration> {
+        let hour = Duration::hours(1);
+        let waits = [
+            self.last_by_email.map(|last| last + Duration::seconds(EMAIL_COOLDOWN_SECONDS) - now),
+            self.first_by_email.filter(|_| self.by_email >= EMAIL_HOURLY_LIMIT).map(|first| first + hour - now),
+            self.first_by_ip.filter(|_| self.by_ip >= IP_HOURLY_LIMIT).map(|first| first + hour - now),
+        ];
+        waits.into_iter().flatten().filter(|wait| *wait > Duration::zero()).max()
+    }
+}
+
+// 登录链接存储实现
+pub struct MagicLinkStore;
+
+impl MagicLinkStore {
+    // 检查并记录一次申请，超出邮箱冷却时间、邮箱或IP的每小时上限时返回需要等待的秒数。
+    // 不论邮箱是否存在都会记录，响应不会暴露邮箱是否注册
+    pub async fn record_request(pool: &PgPool, email: &str, ip: &str) -> Result<Option<i64>, sqlx::Error> {
+        let email_key = account_key(email);
+
+        sqlx::query("DELETE FROM magic_link_requests WHERE created_at < CURRENT_TIMESTAMP - INTERVAL '1 hour'")
+            .execute(pool)
+            .await?;
+
+        let recent = sqlx::query_as::<_, RecentRequests>(r#"
+            SELECT COUNT(*) FILTER (WHERE email_key = $1) AS by_email,
+                   MIN(created_at) FILTER (WHERE email_key = $1) AS first_by_email,
+                   MAX(created_at) FILTER (WHERE email_key = $1) AS last_by_email,
+                   COUNT(*) FILTER (WHERE ip = $2) AS by_ip,
+                   MIN(created_at) FILTER (WHERE ip = $2) AS first_by_ip
+            FROM magic_link_requests
+            WHERE email_key = $1 OR ip = $2
+            "#)
+            .bind(&email_key)
+            .bind(ip)
+            .fetch_one(pool)
+            .await?;
+        if let Some(wait) = recent.retry_after(Utc::now()) {
+            return Ok(Some(((wait.num_milliseconds() + 999) / 1000).max(1)));
+        }
+
+        sqlx::query("INSERT INTO magic_link_requests (email_key, ip) VALUES ($1, $2)")
+            .bind(&email_key)
+            .bind(ip)
+            .execute(pool)
+            .await?;
+
+        Ok(None)
+    }
+
+    // 签发登录链接令牌，与请求浏览器的nonce绑定，返回明文令牌
+    pub async fn issue(pool: &PgPool, user_id: Uuid, nonce: &str) -> Result<String, sqlx::Error> {
+        let token = generate_token();
+        let expires_at = Utc::now() + Duration::minutes(MAGIC_LINK_TTL_MINUTES);
+
+        sqlx::query(r#"
+            INSERT INTO magic_links (user_id, token_hash, nonce_hash, expires_at)
+            VALUES ($1, $2, $3, $4)
+            "#)
+            .bind(user_id)
+            .bind(hash_token(&token))
+            .bind(hash_token(nonce))
+            .bind(expires_at)
+            .execute(pool)
+            .await?;
+
+        Ok(token)
+    }
+
+    // 使用登录链接，令牌和nonce必须同时匹配，成功后返回用户ID
+    pub async fn redeem(pool: &PgPool, token: &str, nonce: &str) -> Result<Option<Uuid>, sqlx::Error> {
+        let user_id: Option<Uuid> = sqlx::query_scalar(r#"
+            UPDATE magic_links
+            SET used_at = CURRENT_TIMESTAMP
+            WHERE token_hash = $1
+              AND nonce_hash = $2
+              AND used_at IS NULL
+              AND expires_at > CURRENT_TIMESTAMP
+            RETURNING user_id
+            "#)
+            .bind(hash_token(token))
+            .bind(hash_token(nonce))
+            .fetch_optional(pool)
+            .await?;
+
+        if let Some(user_id) = user_id {
+            AuditStore::record(pool, Some(user_id), Some(user_id), "magic_link_login", None).await?;
+        }
+
+        Ok(user_id)
+    }
+}
@@ -0,0 +1,283 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+use crate::model::audit::AuditStore;
+use crate::token::{generate_token, hash_token};
+
+// OpenID Connect支持的scope
+pub const OIDC_SCOPES: [&str; 3] = ["openid", "profile", "email"];
+
+// 服务间调用/users接口的scope
+pub const SCOPE_USERS_READ: &str = "users:read";
+pub const SCOPE_USERS_WRITE: &str = "users:write";
+pub const API_SCOPES: [&str; 2] = [SCOPE_USERS_READ, SCOPE_USERS_WRITE];
+
+pub const GRANT_AUTHORIZATION_CODE: &str = "authorization_code";
+pub const GRANT_CLIENT_CREDENTIALS: &str = "client_credentials";
+pub const GRANT_TYPES: [&str; 2] = [GRANT_AUTHORIZATION_CODE, GRANT_CLIENT_CREDENTIALS];
+
+// 刷新令牌只随授权码签发，注册时不需要单独声明
+pub const GRANT_REFRESH_TOKEN: &str = "refresh_token";
+
+// 客户端错误类型
+#[derive(Error, Debug)]
+pub enum OAuthClientError {
+    #[error("客户端不存在")]
+    NotFound,
+    #[error("至少需要一个回调地址")]
+    MissingRedirectUri,
+    #[error("不支持的scope: {0}")]
+    InvalidScope(String),
+    #[error("不支持的grant_type: {0}")]
+    InvalidGrantType(String),
+    #[error("公开客户端不能使用client_credentials，也没有可轮换的密钥")]
+    PublicClient,
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+// OAuth客户端
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct OAuthClient {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub client_id: String,
+    #[serde(skip_serializing)]
+    pub client_secret_hash: Option<String>,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+    pub grant_types: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+// 创建客户端请求
+#[derive(Debug, Deserialize)]
+pub struct CreateClientRequest {
+    pub name: String,
+    // 只有authorization_code客户端需要回调地址
+    #[serde(default)]
+    pub redirect_uris: Vec<String>,
+    pub scopes: Option<Vec<String>>,
+    // 默认为authorization_code
+    pub grant_types: Option<Vec<String>>,
+    // 公开客户端（如SPA、移动端）没有密钥，只能依赖PKCE
+    pub public: Option<bool>,
+}
+
+// 创建客户端响应，密钥只返回这一次
+#[derive(Debug, Serialize)]
+pub struct CreatedClientResponse {
+    #[serde(flatten)]
+    pub client: OAuthClient,
+    pub client_secret: Option<String>,
+}
+
+// 轮换密钥响应，新密钥只返回这一次
+#[derive(Debug, Serialize)]
+pub struct RotatedSecretResponse {
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+impl OAuthClient {
+    // 校验客户端密钥，公开客户端不能携带密钥，已吊销的客户端一律失败
+    pub fn verify_secret(&self, secret: Option<&str>) -> bool {
+        if self.revoked_at.is_some() {
+            return false;
+        }
+        match (&self.client_secret_hash, secret) {
+            (Some(hash), Some(secret)) => hash == &hash_token(secret),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    pub fn allows_redirect_uri(&self, redirect_uri: &str) -> bool {
+        self.redirect_uris.iter().any(|uri| uri == redirect_uri)
+    }
+
+    pub fn allows_grant_type(&self, grant_type: &str) -> bool {
+        let grant_type = match grant_type {
+            GRANT_REFRESH_TOKEN => GRANT_AUTHORIZATION_CODE,
+            other => other,
+        };
+        self.grant_types.iter().any(|grant| grant == grant_type)
+    }
+}
+
+// OAuth客户端存储实现
+pub struct OAuthClientStore;
+
+impl OAuthClientStore {
+    // 在组织中注册客户端
+    pub async fn create(
+        pool: &PgPool,
+        organization_id: Uuid,
+        actor_id: Uuid,
+        req: &CreateClientRequest,
+    ) -> Result<CreatedClientResponse, OAuthClientError> {
+        let grant_types = req
+            .grant_types
+            .clone()
+            .unwrap_or_else(|| vec![GRANT_AUTHORIZATION_CODE.to_string()]);
+        if let Some(grant) = grant_types.iter().find(|grant| !GRANT_TYPES.contains(&grant.as_str())) {
+            return Err(OAuthClientError::InvalidGrantType(grant.clone()));
+        }
+        let authorization_code = grant_types.iter().any(|grant| grant == GRANT_AUTHORIZATION_CODE);
+        let client_credentials = grant_types.iter().any(|grant| grant == GRANT_CLIENT_CREDENTIALS);
+        if authorization_code && req.redirect_uris.is_empty() {
+            return Err(OAuthClientError::MissingRedirectUri);
+        }
+        let public = req.public.unwrap_or(false);
+        if public && client_credentials {
+            return Err(OAuthClientError::PublicClient);
+        }
+
+        // 默认scope：登录客户端为OIDC scope，服务客户端为/users接口scope
+        let mut allowed: Vec<&str> = Vec::new();
+        if authorization_code {
+            allowed.extend(OIDC_SCOPES);
+        }
+        if client_credentials {
+            allowed.extend(API_SCOPES);
+        }
+        let scopes = req
+            .scopes
+            .clone()
+            .unwrap_or_else(|| allowed.iter().map(|scope| scope.to_string()).collect());
+        if let Some(scope) = scopes.iter().find(|scope| !allowed.contains(&scope.as_str())) {
+            return Err(OAuthClientError::InvalidScope(scope.clone()));
+        }
+
+        let client_secret = (!public).then(generate_token);
+
+        let client = sqlx::query_as::<_, OAuthClient>(r#"
+            INSERT INTO oauth_clients (organization_id, client_id, client_secret_hash, name, redirect_uris, scopes, grant_types)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, organization_id, client_id, client_secret_hash, name, redirect_uris, scopes, grant_types, created_at, revoked_at
+            "#)
+            .bind(organization_id)
+            .bind(Uuid::new_v4().simple().to_string())
+            .bind(client_secret.as_deref().map(hash_token))
+            .bind(&req.name)
+            .bind(&req.redirect_uris)
+            .bind(&scopes)
+            .bind(&grant_types)
+            .fetch_one(pool)
+            .await?;
+
+        AuditStore::record(pool, Some(actor_id), None, "oauth_client_created", Some(&client.client_id)).await?;
+
+        Ok(CreatedClientResponse { client, client_secret })
+    }
+
+    // 获取组织的所有客户端
+    pub async fn find_all(pool: &PgPool, organization_id: Uuid) -> Result<Vec<OAuthClient>, sqlx::Error> {
+        let clients = sqlx::query_as::<_, OAuthClient>(r#"
+            SELECT id, organization_id, client_id, client_secret_hash, name, redirect_uris, scopes, grant_types, created_at, revoked_at
+            FROM oauth_clients
+            WHERE organization_id = $1
+            ORDER BY created_at DESC
+            "#)
+            .bind(organization_id)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(clients)
+    }
+
+    // 根据client_id查找客户端，不限组织，用于令牌端点等客户端自身发起的请求
+    pub async fn find_by_client_id(pool: &PgPool, client_id: &str) -> Result<Option<OAuthClient>, sqlx::Error> {
+        let client = sqlx::query_as::<_, OAuthClient>(r#"
+            SELECT id, organization_id, client_id, client_secret_hash, name, redirect_uris, scopes, grant_types, created_at, revoked_at
+            FROM oauth_clients
+            WHERE client_id = $1
+            "#)
+            .bind(client_id)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(client)
+    }
+
+    // 轮换组织中客户端的密钥，返回新密钥，旧密钥立即失效
+    pub async fn rotate_secret(
+        pool: &PgPool,
+        organization_id: Uuid,
+        actor_id: Uuid,
+        client_id: &str,
+    ) -> Result<String, OAuthClientError> {
+        let client = Self::find_by_client_id(pool, client_id)
+            .await?
+            .filter(|client| client.organization_id == organization_id && client.revoked_at.is_none())
+            .ok_or(OAuthClientError::NotFound)?;
+        if client.client_secret_hash.is_none() {
+            return Err(OAuthClientError::PublicClient);
+        }
+
+        let client_secret = generate_token();
+        sqlx::query("UPDATE oauth_clients SET client_secret_hash = $1 WHERE id = $2")
+            .bind(hash_token(&client_secret))
+            .bind(client.id)
+            .execute(pool)
+            .await?;
+
+        AuditStore::record(pool, Some(actor_id), None, "oauth_client_secret_rotated", Some(client_id)).await?;
+
+        Ok(client_secret)
+    }
+
+    // 吊销组织中的客户端：保留记录用于审计，已签发的访问令牌和刷新令牌立即失效
+    pub async fn revoke(pool: &PgPool, organization_id: Uuid, actor_id: Uuid, client_id: &str) -> Result<(), OAuthClientError> {
+        let mut tx = pool.begin().await?;
+
+        let result = sqlx::query(r#"
+            UPDATE oauth_clients
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE client_id = $1 AND organization_id = $2 AND revoked_at IS NULL
+            "#)
+            .bind(client_id)
+            .bind(organization_id)
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(OAuthClientError::NotFound);
+        }
+
+        sqlx::query("DELETE FROM oauth_access_tokens WHERE client_id = $1")
+            .bind(client_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM oauth_refresh_tokens WHERE client_id = $1")
+            .bind(client_id)
+            .execute(&mut *tx)
+            .await?;
+
+        AuditStore::record(&mut *tx, Some(actor_id), None, "oauth_client_revoked", Some(client_id)).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    // 删除组织中的客户端，已签发的授权码和令牌一并失效
+    pub async fn delete(pool: &PgPool, organization_id: Uuid, actor_id: Uuid, client_id: &str) -> Result<(), OAuthClientError> {
+        let result = sqlx::query("DELETE FROM oauth_clients WHERE client_id = $1 AND organization_id = $2")
+            .bind(client_id)
+            .bind(organization_id)
+            .execute(pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(OAuthClientError::NotFound);
+        }
+
+        AuditStore::record(pool, Some(actor_id), None, "oauth_client_deleted", Some(client_id)).await?;
+
+        Ok(())
+    }
+}
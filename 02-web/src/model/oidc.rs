@@ -0,0 +1,611 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgConnection, PgPool};
+use uuid::Uuid;
+use chrono::{DateTime, Duration, Utc};
+use thiserror::Error;
+use crate::mailer::app_base_url;
+use crate::model::{audit::AuditStore, oauth_client::{OAuthClient, GRANT_REFRESH_TOKEN, GRANT_TYPES}, signing_key::{SigningKeyError, SigningKeyStore}, User, STATUS_ACTIVE};
+use crate::token::{generate_token, hash_token};
+
+// 授权码有效期（分钟）
+const AUTHORIZATION_CODE_TTL_MINUTES: i64 = 10;
+
+// 访问令牌和ID令牌有效期（秒）
+pub const ACCESS_TOKEN_TTL_SECONDS: i64 = 3600;
+
+// 刷新令牌有效期（天），每次使用都会轮换
+const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+
+// PKCE只接受S256
+pub const CODE_CHALLENGE_METHOD: &str = "S256";
+
+// 签发者标识，默认与对外地址一致
+pub fn issuer() -> String {
+    std::env::var("OIDC_ISSUER").unwrap_or_else(|_| app_base_url())
+}
+
+// 令牌端点错误，error字段取值遵循RFC 6749
+#[derive(Error, Debug)]
+pub enum TokenError {
+    #[error("invalid_request")]
+    InvalidRequest(&'static str),
+    #[error("invalid_client")]
+    InvalidClient,
+    #[error("invalid_grant")]
+    InvalidGrant(&'static str),
+    #[error("unsupported_grant_type")]
+    UnsupportedGrantType,
+    #[error("unauthorized_client")]
+    UnauthorizedClient,
+    #[error("invalid_scope")]
+    InvalidScope,
+    #[error("签名失败: {0}")]
+    Signing(#[from] SigningKeyError),
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+// 授权请求参数
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthorizeRequest {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub state: Option<String>,
+    pub nonce: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+}
+
+// 用户对授权请求的确认
+#[derive(Debug, Deserialize)]
+pub struct ConsentRequest {
+    #[serde(flatten)]
+    pub authorize: AuthorizeRequest,
+    pub approve: bool,
+}
+
+// 需要用户确认时返回给前端展示的信息
+#[derive(Debug, Serialize)]
+pub struct ConsentRequiredResponse {
+    pub consent_required: bool,
+    pub client_id: String,
+    pub client_name: String,
+    pub scopes: Vec<String>,
+}
+
+// 令牌请求（application/x-www-form-urlencoded）
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    // client_credentials和refresh_token模式申请的scope，空格分隔
+    pub scope: Option<String>,
+    pub refresh_token: Option<String>,
+}
+
+// 令牌响应
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: i64,
+    // 只有授权码模式签发ID令牌
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+    // 授权码模式和刷新时返回新的刷新令牌
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub scope: String,
+}
+
+// ID令牌声明
+#[derive(Debug, Serialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    pub auth_time: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    // 包含sub以及按scope返回的email/name
+    #[serde(flatten)]
+    pub profile: UserInfo,
+}
+
+// 按scope返回的用户信息
+#[derive(Debug, Serialize)]
+pub struct UserInfo {
+    pub sub: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl UserInfo {
+    pub fn new(user: &User, scopes: &[String]) -> Self {
+        let has = |scope: &str| scopes.iter().any(|s| s == scope);
+        UserInfo {
+            sub: user.id.to_string(),
+            email: has("email").then(|| user.email.clone()),
+            name: has("profile").then(|| user.name.clone()),
+        }
+    }
+}
+
+// 发现文档
+#[derive(Debug, Serialize)]
+pub struct DiscoveryDocument {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+    pub jwks_uri: String,
+    pub response_types_supported: Vec<&'static str>,
+    pub grant_types_supported: Vec<&'static str>,
+    pub subject_types_supported: Vec<&'static str>,
+    pub id_token_signing_alg_values_supported: Vec<&'static str>,
+    pub scopes_supported: Vec<&'static str>,
+    pub claims_supported: Vec<&'static str>,
+    pub token_endpoint_auth_methods_supported: Vec<&'static str>,
+    pub code_challenge_methods_supported: Vec<&'static str>,
+}
+
+impl DiscoveryDocument {
+    pub fn new(scopes: &[&'static str]) -> Self {
+        let issuer = issuer();
+        DiscoveryDocument {
+            authorization_endpoint: format!("{}/oauth2/authorize", issuer),
+            token_endpoint: format!("{}/oauth2/token", issuer),
+            userinfo_endpoint: format!("{}/oauth2/userinfo", issuer),
+            jwks_uri: format!("{}/oauth2/jwks", issuer),
+            issuer,
+            response_types_supported: vec!["code"],
+            grant_types_supported: GRANT_TYPES.iter().copied().chain([GRANT_REFRESH_TOKEN]).collect(),
+            subject_types_supported: vec!["public"],
+            id_token_signing_alg_values_supported: vec!["RS256"],
+            scopes_supported: scopes.to_vec(),
+            claims_supported: vec!["sub", "iss", "aud", "exp", "iat", "auth_time", "nonce", "email", "name"],
+            token_endpoint_auth_methods_supported: vec!["client_secret_basic", "client_secret_post", "none"],
+            code_challenge_methods_supported: vec![CODE_CHALLENGE_METHOD],
+        }
+    }
+}
+
+// 已兑换的授权码
+#[derive(Debug, FromRow)]
+struct AuthorizationCode {
+    id: Uuid,
+    client_id: String,
+    user_id: Uuid,
+    redirect_uri: String,
+    scopes: Vec<String>,
+    nonce: Option<String>,
+    code_challenge: String,
+    auth_time: DateTime<Utc>,
+}
+
+// 未使用的刷新令牌
+#[derive(Debug, FromRow)]
+struct RefreshGrant {
+    client_id: String,
+    user_id: Uuid,
+    authorization_code_id: Uuid,
+    scopes: Vec<String>,
+}
+
+// 访问令牌对应的授权
+#[derive(Debug, FromRow)]
+pub struct AccessGrant {
+    // client_credentials签发的令牌不属于任何用户
+    pub user_id: Option<Uuid>,
+    // 签发令牌的客户端所属的组织
+    pub organization_id: Uuid,
+    pub scopes: Vec<String>,
+}
+
+// 校验PKCE：BASE64URL(SHA256(code_verifier)) == code_challenge
+fn verify_pkce(code_verifier: &str, code_challenge: &str) -> bool {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes())) == code_challenge
+}
+
+// 签发刷新令牌，返回明文令牌
+async fn issue_refresh_token(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    client_id: &str,
+    user_id: Uuid,
+    authorization_code_id: Uuid,
+    scopes: &[String],
+) -> Result<String, sqlx::Error> {
+    let refresh_token = generate_token();
+    sqlx::query(r#"
+        INSERT INTO oauth_refresh_tokens (token_hash, client_id, user_id, authorization_code_id, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#)
+        .bind(hash_token(&refresh_token))
+        .bind(client_id)
+        .bind(user_id)
+        .bind(authorization_code_id)
+        .bind(scopes)
+        .bind(Utc::now() + Duration::days(REFRESH_TOKEN_TTL_DAYS))
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(refresh_token)
+}
+
+// OpenID Connect存储实现
+pub struct OidcStore;
+
+impl OidcStore {
+    // 会话的登录时间，作为ID令牌的auth_time
+    pub async fn session_auth_time(pool: &PgPool, session_id: Uuid) -> Result<DateTime<Utc>, sqlx::Error> {
+        sqlx::query_scalar("SELECT created_at FROM sessions WHERE id = $1")
+            .bind(session_id)
+            .fetch_one(pool)
+            .await
+    }
+
+    // 吊销用户的全部访问令牌和刷新令牌，可在事务中调用
+    pub async fn revoke_all_for_user(conn: &mut PgConnection, user_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM oauth_access_tokens WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("DELETE FROM oauth_refresh_tokens WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    // 用户是否已同意客户端申请的全部scope
+    pub async fn has_consent(
+        pool: &PgPool,
+        user_id: Uuid,
+        client_id: &str,
+        scopes: &[String],
+    ) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar(r#"
+            SELECT EXISTS (
+                SELECT 1 FROM oidc_consents
+                WHERE user_id = $1 AND client_id = $2 AND scopes @> $3
+            )
+            "#)
+            .bind(user_id)
+            .bind(client_id)
+            .bind(scopes)
+            .fetch_one(pool)
+            .await
+    }
+
+    // 记录用户同意，与已有的scope合并
+    pub async fn grant_consent(
+        pool: &PgPool,
+        user_id: Uuid,
+        client_id: &str,
+        scopes: &[String],
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            INSERT INTO oidc_consents (user_id, client_id, scopes)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, client_id) DO UPDATE
+            SET scopes = ARRAY(SELECT DISTINCT unnest(oidc_consents.scopes || EXCLUDED.scopes)),
+                updated_at = CURRENT_TIMESTAMP
+            "#)
+            .bind(user_id)
+            .bind(client_id)
+            .bind(scopes)
+            .execute(pool)
+            .await?;
+
+        AuditStore::record(pool, Some(user_id), Some(user_id), "oidc_consent_granted", Some(client_id)).await?;
+
+        Ok(())
+    }
+
+    // 签发授权码，返回明文授权码
+    pub async fn issue_code(
+        pool: &PgPool,
+        user_id: Uuid,
+        auth_time: DateTime<Utc>,
+        req: &AuthorizeRequest,
+        scopes: &[String],
+        code_challenge: &str,
+    ) -> Result<String, sqlx::Error> {
+        let code = generate_token();
+        let expires_at = Utc::now() + Duration::minutes(AUTHORIZATION_CODE_TTL_MINUTES);
+
+        sqlx::query(r#"
+            INSERT INTO oauth_authorization_codes
+                (code_hash, client_id, user_id, redirect_uri, scopes, nonce, code_challenge, auth_time, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#)
+            .bind(hash_token(&code))
+            .bind(&req.client_id)
+            .bind(user_id)
+            .bind(&req.redirect_uri)
+            .bind(scopes)
+            .bind(&req.nonce)
+            .bind(code_challenge)
+            .bind(auth_time)
+            .bind(expires_at)
+            .execute(pool)
+            .await?;
+
+        Ok(code)
+    }
+
+    // 用授权码换取访问令牌和ID令牌，授权码只能使用一次
+    pub async fn exchange_code(
+        pool: &PgPool,
+        client_id: &str,
+        req: &TokenRequest,
+    ) -> Result<TokenResponse, TokenError> {
+        let code = req.code.as_deref().ok_or(TokenError::InvalidRequest("缺少code"))?;
+        let redirect_uri = req.redirect_uri.as_deref().ok_or(TokenError::InvalidRequest("缺少redirect_uri"))?;
+        let code_verifier = req.code_verifier.as_deref().ok_or(TokenError::InvalidRequest("缺少code_verifier"))?;
+
+        let mut tx = pool.begin().await?;
+
+        let grant = sqlx::query_as::<_, AuthorizationCode>(r#"
+            UPDATE oauth_authorization_codes
+            SET used_at = CURRENT_TIMESTAMP
+            WHERE code_hash = $1 AND used_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+            RETURNING id, client_id, user_id, redirect_uri, scopes, nonce, code_challenge, auth_time
+            "#)
+            .bind(hash_token(code))
+            .fetch_optional(&mut *tx)
+            .await?;
+
+        let Some(grant) = grant else {
+            // 授权码被重复使用时，吊销用它签发过的访问令牌和刷新令牌
+            let revoked = sqlx::query(r#"
+                DELETE FROM oauth_access_tokens
+                WHERE authorization_code_id IN (
+                    SELECT id FROM oauth_authorization_codes WHERE code_hash = $1 AND used_at IS NOT NULL
+                )
+                "#)
+                .bind(hash_token(code))
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(r#"
+                DELETE FROM oauth_refresh_tokens
+                WHERE authorization_code_id IN (
+                    SELECT id FROM oauth_authorization_codes WHERE code_hash = $1 AND used_at IS NOT NULL
+                )
+                "#)
+                .bind(hash_token(code))
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            if revoked.rows_affected() > 0 {
+                tracing::warn!(client_id, "授权码被重复使用，已吊销相关令牌");
+            }
+            return Err(TokenError::InvalidGrant("授权码无效或已过期"));
+        };
+
+        // 校验失败时授权码同样作废，避免反复猜测code_verifier
+        if grant.client_id != client_id || grant.redirect_uri != redirect_uri {
+            tx.commit().await?;
+            return Err(TokenError::InvalidGrant("授权码与客户端或回调地址不匹配"));
+        }
+        if !verify_pkce(code_verifier, &grant.code_challenge) {
+            tx.commit().await?;
+            return Err(TokenError::InvalidGrant("code_verifier校验失败"));
+        }
+
+        let user = sqlx::query_as::<_, User>(r#"
+            SELECT id, name, email, password, role, status, organization_id, created_at, updated_at
+            FROM users
+            WHERE id = $1
+            "#)
+            .bind(grant.user_id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .filter(User::is_active)
+            .ok_or(TokenError::InvalidGrant("用户不存在或已停用"))?;
+
+        let access_token = generate_token();
+        let now = Utc::now();
+        sqlx::query(r#"
+            INSERT INTO oauth_access_tokens (token_hash, client_id, user_id, authorization_code_id, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#)
+            .bind(hash_token(&access_token))
+            .bind(client_id)
+            .bind(user.id)
+            .bind(grant.id)
+            .bind(&grant.scopes)
+            .bind(now + Duration::seconds(ACCESS_TOKEN_TTL_SECONDS))
+            .execute(&mut *tx)
+            .await?;
+
+        let claims = IdTokenClaims {
+            iss: issuer(),
+            aud: client_id.to_string(),
+            exp: (now + Duration::seconds(ACCESS_TOKEN_TTL_SECONDS)).timestamp(),
+            iat: now.timestamp(),
+            auth_time: grant.auth_time.timestamp(),
+            nonce: grant.nonce,
+            profile: UserInfo::new(&user, &grant.scopes),
+        };
+        let id_token = SigningKeyStore::sign(pool, &claims).await?;
+        let refresh_token = issue_refresh_token(&mut tx, client_id, user.id, grant.id, &grant.scopes).await?;
+
+        AuditStore::record(&mut *tx, Some(user.id), Some(user.id), "oidc_token_issued", Some(client_id)).await?;
+
+        tx.commit().await?;
+
+        Ok(TokenResponse {
+            access_token,
+            token_type: "Bearer",
+            expires_in: ACCESS_TOKEN_TTL_SECONDS,
+            id_token: Some(id_token),
+            refresh_token: Some(refresh_token),
+            scope: grant.scopes.join(" "),
+        })
+    }
+
+    // 用刷新令牌换取新的访问令牌，刷新令牌同时轮换；旧令牌被重复使用时吊销同一授权下的全部令牌
+    pub async fn refresh(
+        pool: &PgPool,
+        client_id: &str,
+        req: &TokenRequest,
+    ) -> Result<TokenResponse, TokenError> {
+        let refresh_token = req.refresh_token.as_deref().ok_or(TokenError::InvalidRequest("缺少refresh_token"))?;
+
+        let mut tx = pool.begin().await?;
+
+        let grant = sqlx::query_as::<_, RefreshGrant>(r#"
+            UPDATE oauth_refresh_tokens
+            SET used_at = CURRENT_TIMESTAMP
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+            RETURNING client_id, user_id, authorization_code_id, scopes
+            "#)
+            .bind(hash_token(refresh_token))
+            .fetch_optional(&mut *tx)
+            .await?;
+
+        let Some(grant) = grant else {
+            let family: Option<Uuid> = sqlx::query_scalar(r#"
+                SELECT authorization_code_id FROM oauth_refresh_tokens
+                WHERE token_hash = $1 AND used_at IS NOT NULL
+                "#)
+                .bind(hash_token(refresh_token))
+                .fetch_optional(&mut *tx)
+                .await?;
+            if let Some(family) = family {
+                sqlx::query("DELETE FROM oauth_access_tokens WHERE authorization_code_id = $1")
+                    .bind(family)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("DELETE FROM oauth_refresh_tokens WHERE authorization_code_id = $1")
+                    .bind(family)
+                    .execute(&mut *tx)
+                    .await?;
+                tx.commit().await?;
+                tracing::warn!(client_id, "刷新令牌被重复使用，已吊销相关令牌");
+            }
+            return Err(TokenError::InvalidGrant("刷新令牌无效或已过期"));
+        };
+
+        // 令牌与客户端不匹配时同样作废
+        if grant.client_id != client_id {
+            tx.commit().await?;
+            return Err(TokenError::InvalidGrant("刷新令牌与客户端不匹配"));
+        }
+
+        // 可以缩小scope，不能超出原授权；scope不合法时回滚，刷新令牌仍可使用
+        let scopes: Vec<String> = match req.scope.as_deref() {
+            Some(scope) => scope.split_whitespace().map(str::to_string).collect(),
+            None => grant.scopes.clone(),
+        };
+        if scopes.is_empty() || scopes.iter().any(|scope| !grant.scopes.contains(scope)) {
+            return Err(TokenError::InvalidScope);
+        }
+
+        let active: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE id = $1 AND status = $2)")
+            .bind(grant.user_id)
+            .bind(STATUS_ACTIVE)
+            .fetch_one(&mut *tx)
+            .await?;
+        if !active {
+            tx.commit().await?;
+            return Err(TokenError::InvalidGrant("用户不存在或已停用"));
+        }
+
+        let access_token = generate_token();
+        sqlx::query(r#"
+            INSERT INTO oauth_access_tokens (token_hash, client_id, user_id, authorization_code_id, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#)
+            .bind(hash_token(&access_token))
+            .bind(client_id)
+            .bind(grant.user_id)
+            .bind(grant.authorization_code_id)
+            .bind(&scopes)
+            .bind(Utc::now() + Duration::seconds(ACCESS_TOKEN_TTL_SECONDS))
+            .execute(&mut *tx)
+            .await?;
+        // 新的刷新令牌保留原授权的scope，之后仍可按原范围刷新
+        let refresh_token =
+            issue_refresh_token(&mut tx, client_id, grant.user_id, grant.authorization_code_id, &grant.scopes).await?;
+
+        AuditStore::record(&mut *tx, Some(grant.user_id), Some(grant.user_id), "oidc_token_refreshed", Some(client_id)).await?;
+
+        tx.commit().await?;
+
+        Ok(TokenResponse {
+            access_token,
+            token_type: "Bearer",
+            expires_in: ACCESS_TOKEN_TTL_SECONDS,
+            id_token: None,
+            refresh_token: Some(refresh_token),
+            scope: scopes.join(" "),
+        })
+    }
+
+    // client_credentials模式：直接为客户端签发访问令牌，scope缺省为客户端注册的全部scope
+    pub async fn issue_client_token(
+        pool: &PgPool,
+        client: &OAuthClient,
+        scope: Option<&str>,
+    ) -> Result<TokenResponse, TokenError> {
+        let scopes: Vec<String> = match scope {
+            Some(scope) => scope.split_whitespace().map(str::to_string).collect(),
+            None => client.scopes.clone(),
+        };
+        if scopes.is_empty() || scopes.iter().any(|scope| !client.scopes.contains(scope)) {
+            return Err(TokenError::InvalidScope);
+        }
+
+        let access_token = generate_token();
+        sqlx::query(r#"
+            INSERT INTO oauth_access_tokens (token_hash, client_id, user_id, authorization_code_id, scopes, expires_at)
+            VALUES ($1, $2, NULL, NULL, $3, $4)
+            "#)
+            .bind(hash_token(&access_token))
+            .bind(&client.client_id)
+            .bind(&scopes)
+            .bind(Utc::now() + Duration::seconds(ACCESS_TOKEN_TTL_SECONDS))
+            .execute(pool)
+            .await?;
+
+        AuditStore::record(pool, None, None, "oauth_client_token_issued", Some(&client.client_id)).await?;
+
+        Ok(TokenResponse {
+            access_token,
+            token_type: "Bearer",
+            expires_in: ACCESS_TOKEN_TTL_SECONDS,
+            id_token: None,
+            refresh_token: None,
+            scope: scopes.join(" "),
+        })
+    }
+
+    // 根据访问令牌查找授权
+    pub async fn find_access_token(pool: &PgPool, token: &str) -> Result<Option<AccessGrant>, sqlx::Error> {
+        let grant = sqlx::query_as::<_, AccessGrant>(r#"
+            SELECT t.user_id, c.organization_id, t.scopes
+            FROM oauth_access_tokens t
+            JOIN oauth_clients c ON c.client_id = t.client_id
+            WHERE t.token_hash = $1 AND t.expires_at > CURRENT_TIMESTAMP
+            "#)
+            .bind(hash_token(token))
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(grant)
+    }
+}
@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+use crate::model::audit::AuditStore;
+
+// 迁移时创建的默认组织，其管理员为平台管理员，可以访问其他组织
+pub const DEFAULT_ORGANIZATION_SLUG: &str = "default";
+
+// 组织错误类型
+#[derive(Error, Debug)]
+pub enum OrganizationError {
+    #[error("组织标识只能包含小写字母、数字和连字符")]
+    InvalidSlug,
+    #[error("组织标识已存在")]
+    SlugExists,
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+// 组织（租户）
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Organization {
+    pub id: Uuid,
+    pub slug: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// 创建组织请求
+#[derive(Debug, Deserialize)]
+pub struct CreateOrganizationRequest {
+    pub slug: String,
+    pub name: String,
+}
+
+// 当前请求所属的组织，由scope中间件解析后放入请求扩展
+#[derive(Debug, Clone, Copy)]
+pub struct Tenant {
+    pub organization_id: Uuid,
+}
+
+// 组织标识同时用作子域名，只允许小写字母、数字和连字符
+fn valid_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && slug.len() <= 63
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+        && slug.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+// 组织存储实现
+pub struct OrganizationStore;
+
+impl OrganizationStore {
+    // 创建组织
+    pub async fn create(
+        pool: &PgPool,
+        actor_id: Uuid,
+        req: &CreateOrganizationRequest,
+    ) -> Result<Organization, OrganizationError> {
+        if !valid_slug(&req.slug) {
+            return Err(OrganizationError::InvalidSlug);
+        }
+
+        let organization = sqlx::query_as::<_, Organization>(r#"
+            INSERT INTO organizations (slug, name)
+            VALUES ($1, $2)
+            ON CONFLICT (slug) DO NOTHING
+            RETURNING id, slug, name, created_at
+            "#)
+            .bind(&req.slug)
+            .bind(&req.name)
+            .fetch_optional(pool)
+            .await?
+            .ok_or(OrganizationError::SlugExists)?;
+
+        AuditStore::record(pool, Some(actor_id), None, "organization_created", Some(&organization.slug)).await?;
+
+        Ok(organization)
+    }
+
+    // 获取所有组织
+    pub async fn find_all(pool: &PgPool) -> Result<Vec<Organization>, sqlx::Error> {
+        let organizations = sqlx::query_as::<_, Organization>(r#"
+            SELECT id, slug, name, created_at
+            FROM organizations
+            ORDER BY created_at
+            "#)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(organizations)
+    }
+
+    // 根据标识查找组织
+    pub async fn find_by_slug(pool: &PgPool, slug: &str) -> Result<Option<Organization>, sqlx::Error> {
+        let organization = sqlx::query_as::<_, Organization>(r#"
+            SELECT id, slug, name, created_at
+            FROM organizations
+            WHERE slug = $1
+            "#)
+            .bind(slug)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(organization)
+    }
+
+    // 默认组织ID
+    pub async fn default_id(pool: &PgPool) -> Result<Uuid, sqlx::Error> {
+        sqlx::query_scalar::<_, Uuid>("SELECT id FROM organizations WHERE slug = $1")
+            .bind(DEFAULT_ORGANIZATION_SLUG)
+            .fetch_one(pool)
+            .await
+    }
+}
@@ -0,0 +1,248 @@
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{types::Json, FromRow, PgExecutor, PgPool};
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use crate::model::User;
+use crate::publisher::EventPublisher;
+
+// 聚合类型
+pub const AGGREGATE_USER: &str = "user";
+
+// 每轮领取的最多事件数
+const RELAY_BATCH: i64 = 100;
+
+// 已发布事件保留的天数，可通过OUTBOX_RETENTION_DAYS配置
+pub fn retention_days() -> i64 {
+    std::env::var("OUTBOX_RETENTION_DAYS").ok().and_then(|v| v.parse().ok()).unwrap_or(7)
+}
+
+// 用户的领域事件，序列化后type字段为事件名；User序列化时不包含密码。
+// 变体名就是对外发布的事件名，以后加入其他聚合时沿用同样的命名
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub enum DomainEvent {
+    UserCreated { user: User },
+    // changed为发生变化的资料字段
+    UserUpdated { user: User, changed: Vec<&'static str> },
+    UserDeleted { user_id: Uuid, email: String },
+    UserStatusChanged { user_id: Uuid, from: String, to: String, reason: String },
+    UserPasswordChanged { user_id: Uuid },
+}
+
+impl DomainEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DomainEvent::UserCreated { .. } => "UserCreated",
+            DomainEvent::UserUpdated { .. } => "UserUpdated",
+            DomainEvent::UserDeleted { .. } => "UserDeleted",
+            DomainEvent::UserStatusChanged { .. } => "UserStatusChanged",
+            DomainEvent::UserPasswordChanged { .. } => "UserPasswordChanged",
+        }
+    }
+
+    pub fn aggregate_id(&self) -> Uuid {
+        match self {
+            DomainEvent::UserCreated { user } | DomainEvent::UserUpdated { user, .. } => user.id,
+            DomainEvent::UserDeleted { user_id, .. }
+            | DomainEvent::UserStatusChanged { user_id, .. }
+            | DomainEvent::UserPasswordChanged { user_id } => *user_id,
+        }
+    }
+}
+
+// 发件箱中的事件，id在同一聚合内的顺序就是事件发生的顺序
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct OutboxEvent {
+    pub id: i64,
+    pub organization_id: Uuid,
+    pub aggregate_type: String,
+    pub aggregate_id: Uuid,
+    pub event_type: String,
+    pub payload: Value,
+    pub created_at: DateTime<Utc>,
+}
+
+// 发件箱存储实现。
+// Webhook和SSE推送不经过发件箱：它们由users表上的触发器写入user_events，SCIM同步、批量导入等
+// 直接修改users表的操作也会产生事件，而发件箱只包含应用代码显式写入的领域事件；
+// 两者面向组织内的订阅方，需要按组织过滤、按订阅记录投递状态，发件箱则由部署方配置的一个发布器整体消费
+pub struct OutboxStore;
+
+impl OutboxStore {
+    // 在调用方的事务中写入事件，事务回滚时事件一并丢弃，提交后事件一定会被发布。
+    // 应在修改用户的语句之后调用：此时已持有该用户的行锁，同一用户的事件序号与提交顺序一致
+    pub async fn append<'e, E: PgExecutor<'e>>(
+        executor: E,
+        organization_id: Uuid,
+        event: &DomainEvent,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            INSERT INTO outbox_events (organization_id, aggregate_type, aggregate_id, event_type, payload)
+            VALUES ($1, $2, $3, $4, $5)
+            "#)
+            .bind(organization_id)
+            .bind(AGGREGATE_USER)
+            .bind(event.aggregate_id())
+            .bind(event.name())
+            .bind(Json(event))
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+
+    // 批量写入事件，事件序号与切片中的顺序一致，用于批量导入等一次修改大量用户的操作
+    pub async fn append_all<'e, E: PgExecutor<'e>>(
+        executor: E,
+        organization_id: Uuid,
+        events: &[DomainEvent],
+    ) -> Result<(), sqlx::Error> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let aggregate_ids: Vec<Uuid> = events.iter().map(DomainEvent::aggregate_id).collect();
+        let event_types: Vec<&str> = events.iter().map(DomainEvent::name).collect();
+
+        sqlx::query(r#"
+            INSERT INTO outbox_events (organization_id, aggregate_type, aggregate_id, event_type, payload)
+            SELECT $1, $2, e.aggregate_id, e.event_type, p.payload
+            FROM UNNEST($3::UUID[], $4::TEXT[]) WITH ORDINALITY AS e(aggregate_id, event_type, n)
+            JOIN jsonb_array_elements($5) WITH ORDINALITY AS p(payload, n) USING (n)
+            ORDER BY n
+            "#)
+            .bind(organization_id)
+            .bind(AGGREGATE_USER)
+            .bind(&aggregate_ids)
+            .bind(&event_types)
+            .bind(Json(events))
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+}
+
+// 发件箱中继：把未发布的事件交给发布器，发布成功后在同一事务中标记，每个事件只会被标记一次。
+// 同一聚合只领取最早的未发布事件，并锁定到标记完成，因此即使多个实例同时运行，同一用户的事件也按顺序发布；
+// 发布后、提交前实例退出时事件会被再次发布，消费方可按id去重
+pub struct OutboxRelay;
+
+impl OutboxRelay {
+    // 发布所有未发布的事件，返回发布的数量；发布失败的事件留到下一轮重试，该聚合之后的事件随之等待
+    pub async fn run_pending(pool: &PgPool, publisher: &dyn EventPublisher) -> Result<usize, sqlx::Error> {
+        let mut total = 0;
+        loop {
+            let mut tx = pool.begin().await?;
+            let events = sqlx::query_as::<_, OutboxEvent>(r#"
+                SELECT id, organization_id, aggregate_type, aggregate_id, event_type, payload, created_at
+                FROM outbox_events o
+                WHERE published_at IS NULL
+                  AND NOT EXISTS (
+                      SELECT 1
+                      FROM outbox_events earlier
+                      WHERE earlier.aggregate_type = o.aggregate_type
+                        AND earlier.aggregate_id = o.aggregate_id
+                        AND earlier.published_at IS NULL
+                        AND earlier.id < o.id
+                  )
+                ORDER BY id
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+                "#)
+                .bind(RELAY_BATCH)
+                .fetch_all(&mut *tx)
+                .await?;
+            if events.is_empty() {
+                return Ok(total);
+            }
+
+            let mut published = 0;
+            for event in &events {
+                match publisher.publish(event).await {
+                    Ok(()) => {
+                        sqlx::query("UPDATE outbox_events SET published_at = CURRENT_TIMESTAMP, attempts = attempts + 1 WHERE id = $1")
+                            .bind(event.id)
+                            .execute(&mut *tx)
+                            .await?;
+                        published += 1;
+                    }
+                    Err(err) => {
+                        tracing::warn!(event_id = event.id, event_type = %event.event_type, "发布领域事件失败: {}", err);
+                        sqlx::query("UPDATE outbox_events SET attempts = attempts + 1, last_error = $2 WHERE id = $1")
+                            .bind(event.id)
+                            .bind(err.to_string())
+                            .execute(&mut *tx)
+                            .await?;
+                    }
+                }
+            }
+            tx.commit().await?;
+
+            total += published;
+            // 本轮全部失败时等待下一轮，避免连续重试
+            if published == 0 {
+                return Ok(total);
+            }
+        }
+    }
+
+    // 删除发布超过保留天数的事件，未发布的事件不会被删除
+    pub async fn prune(pool: &PgPool, retention_days: i64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM outbox_events WHERE published_at < CURRENT_TIMESTAMP - make_interval(days => $1::int)")
+            .bind(retention_days)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::ROLE_USER;
+    use crate::publisher::{from_env, MemoryPublisher};
+    use crate::test_support::*;
+    use super::OutboxRelay;
+
+    #[tokio::test]
+    async fn relay_publishes_and_prunes() {
+        let Some(pool) = pool().await else { return };
+        let (organization_id, _) = create_organization(&pool).await;
+        let user = create_user(&pool, organization_id, ROLE_USER).await;
+
+        let publisher = MemoryPublisher::default();
+        OutboxRelay::run_pending(&pool, &publisher).await.unwrap();
+        let event_id = publisher
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|event| event.aggregate_id == user.id && event.event_type == "UserCreated")
+            .expect("没有发布UserCreated事件")
+            .id;
+
+        sqlx::query("UPDATE outbox_events SET published_at = CURRENT_TIMESTAMP - INTERVAL '30 days' WHERE id = $1")
+            .bind(event_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(OutboxRelay::prune(&pool, 7).await.unwrap() >= 1);
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM outbox_events WHERE id = $1")
+            .bind(event_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 0);
+    }
+
+    // 内存发布器的事件没有读取方，不能通过配置启用
+    #[tokio::test]
+    async fn memory_publisher_is_rejected() {
+        std::env::set_var("OUTBOX_PUBLISHER", "memory");
+        let result = from_env().await;
+        std::env::remove_var("OUTBOX_PUBLISHER");
+        assert!(result.is_err());
+    }
+}
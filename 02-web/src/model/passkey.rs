@@ -0,0 +1,325 @@
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+use chrono::{DateTime, Duration, Utc};
+use thiserror::Error;
+use webauthn_rs::prelude::*;
+use crate::model::{audit::AuditStore, User};
+
+// 注册/认证挑战的有效期（分钟）
+const CHALLENGE_TTL_MINUTES: i64 = 5;
+
+const KIND_REGISTRATION: &str = "registration";
+const KIND_AUTHENTICATION: &str = "authentication";
+
+pub type SharedWebauthn = Arc<Webauthn>;
+
+// 根据环境变量创建WebAuthn依赖方配置
+pub fn create_webauthn() -> anyhow::Result<Webauthn> {
+    let rp_id = std::env::var("WEBAUTHN_RP_ID").unwrap_or_else(|_| "localhost".to_string());
+    let origin = std::env::var("WEBAUTHN_ORIGIN").unwrap_or_else(|_| "http://localhost:3000".to_string());
+    let origin = Url::parse(&origin)?;
+
+    let webauthn = WebauthnBuilder::new(&rp_id, &origin)?
+        .rp_name("UserCRUD")
+        .build()?;
+    Ok(webauthn)
+}
+
+// 通行密钥错误类型
+#[derive(Error, Debug)]
+pub enum PasskeyError {
+    #[error("通行密钥不存在")]
+    NotFound,
+    #[error("挑战无效或已过期")]
+    InvalidChallenge,
+    #[error("该通行密钥已注册")]
+    CredentialExists,
+    #[error("签名计数器异常，通行密钥可能已被复制")]
+    CounterRegression,
+    #[error("WebAuthn验证失败: {0}")]
+    Webauthn(#[from] WebauthnError),
+    #[error("序列化失败: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+// 通行密钥信息
+#[derive(Debug, Serialize, FromRow)]
+pub struct PasskeyInfo {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+// 开始注册响应
+#[derive(Debug, Serialize)]
+pub struct StartRegistrationResponse {
+    pub challenge_id: Uuid,
+    pub options: CreationChallengeResponse,
+}
+
+// 完成注册请求
+#[derive(Debug, Deserialize)]
+pub struct FinishRegistrationRequest {
+    pub challenge_id: Uuid,
+    pub name: Option<String>,
+    pub credential: RegisterPublicKeyCredential,
+}
+
+// 开始登录请求
+#[derive(Debug, Deserialize)]
+pub struct StartLoginRequest {
+    pub email: String,
+}
+
+// 开始登录响应
+#[derive(Debug, Serialize)]
+pub struct StartLoginResponse {
+    pub challenge_id: Uuid,
+    pub options: RequestChallengeResponse,
+}
+
+// 完成登录请求
+#[derive(Debug, Deserialize)]
+pub struct FinishLoginRequest {
+    pub challenge_id: Uuid,
+    pub credential: PublicKeyCredential,
+}
+
+#[derive(FromRow)]
+struct StoredPasskey {
+    id: Uuid,
+    passkey: String,
+    sign_count: i64,
+}
+
+// 通行密钥存储实现
+pub struct PasskeyStore;
+
+impl PasskeyStore {
+    // 开始注册，排除用户已有的通行密钥
+    pub async fn start_registration(
+        pool: &PgPool,
+        webauthn: &Webauthn,
+        user: &User,
+    ) -> Result<StartRegistrationResponse, PasskeyError> {
+        let existing: Vec<Vec<u8>> = sqlx::query_scalar("SELECT credential_id FROM passkeys WHERE user_id = $1")
+            .bind(user.id)
+            .fetch_all(pool)
+            .await?;
+        let exclude = existing.into_iter().map(CredentialID::from).collect::<Vec<_>>();
+
+        let (options, state) = webauthn.start_passkey_registration(
+            user.id,
+            &user.email,
+            &user.name,
+            Some(exclude).filter(|ids| !ids.is_empty()),
+        )?;
+        let challenge_id = Self::save_challenge(pool, Some(user.id), KIND_REGISTRATION, &serde_json::to_string(&state)?).await?;
+
+        Ok(StartRegistrationResponse { challenge_id, options })
+    }
+
+    // 完成注册并保存通行密钥
+    pub async fn finish_registration(
+        pool: &PgPool,
+        webauthn: &Webauthn,
+        user_id: Uuid,
+        req: &FinishRegistrationRequest,
+    ) -> Result<PasskeyInfo, PasskeyError> {
+        let (owner, state) = Self::take_challenge(pool, req.challenge_id, KIND_REGISTRATION).await?;
+        if owner != Some(user_id) {
+            return Err(PasskeyError::InvalidChallenge);
+        }
+        let state: PasskeyRegistration = serde_json::from_str(&state)?;
+        let passkey = webauthn.finish_passkey_registration(&req.credential, &state)?;
+
+        let name = req.name.clone().unwrap_or_else(|| "通行密钥".to_string());
+        let info = sqlx::query_as::<_, PasskeyInfo>(r#"
+            INSERT INTO passkeys (user_id, credential_id, passkey, name)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (credential_id) DO NOTHING
+            RETURNING id, name, created_at, last_used_at
+            "#)
+            .bind(user_id)
+            .bind(passkey.cred_id().as_ref())
+            .bind(serde_json::to_string(&passkey)?)
+            .bind(&name)
+            .fetch_optional(pool)
+            .await?
+            .ok_or(PasskeyError::CredentialExists)?;
+
+        AuditStore::record(pool, Some(user_id), Some(user_id), "passkey_registered", Some(&info.id.to_string())).await?;
+
+        Ok(info)
+    }
+
+    // 开始登录，用户没有通行密钥时返回NotFound
+    pub async fn start_login(
+        pool: &PgPool,
+        webauthn: &Webauthn,
+        user_id: Uuid,
+    ) -> Result<StartLoginResponse, PasskeyError> {
+        let stored: Vec<String> = sqlx::query_scalar("SELECT passkey FROM passkeys WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_all(pool)
+            .await?;
+        if stored.is_empty() {
+            return Err(PasskeyError::NotFound);
+        }
+        let passkeys = stored
+            .iter()
+            .map(|passkey| serde_json::from_str::<Passkey>(passkey))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (options, state) = webauthn.start_passkey_authentication(&passkeys)?;
+        let challenge_id = Self::save_challenge(pool, Some(user_id), KIND_AUTHENTICATION, &serde_json::to_string(&state)?).await?;
+
+        Ok(StartLoginResponse { challenge_id, options })
+    }
+
+    // 登录挑战所属的用户，用于在校验断言前检查该用户是否被锁定；不会消耗挑战
+    pub async fn login_challenge_user(pool: &PgPool, challenge_id: Uuid) -> Result<Option<Uuid>, sqlx::Error> {
+        let user_id = sqlx::query_scalar(r#"
+            SELECT user_id
+            FROM webauthn_challenges
+            WHERE id = $1 AND kind = $2 AND expires_at > CURRENT_TIMESTAMP
+            "#)
+            .bind(challenge_id)
+            .bind(KIND_AUTHENTICATION)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(user_id.flatten())
+    }
+
+    // 完成登录，校验签名计数器后返回用户ID
+    pub async fn finish_login(
+        pool: &PgPool,
+        webauthn: &Webauthn,
+        req: &FinishLoginRequest,
+    ) -> Result<Uuid, PasskeyError> {
+        let (user_id, state) = Self::take_challenge(pool, req.challenge_id, KIND_AUTHENTICATION).await?;
+        let user_id = user_id.ok_or(PasskeyError::InvalidChallenge)?;
+        let state: PasskeyAuthentication = serde_json::from_str(&state)?;
+
+        let result = match webauthn.finish_passkey_authentication(&req.credential, &state) {
+            Ok(result) => result,
+            Err(WebauthnError::CredentialPossibleCompromise) => {
+                AuditStore::record(pool, None, Some(user_id), "passkey_counter_regression", None).await?;
+                return Err(PasskeyError::CounterRegression);
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let stored = sqlx::query_as::<_, StoredPasskey>(r#"
+            SELECT id, passkey, sign_count
+            FROM passkeys
+            WHERE user_id = $1 AND credential_id = $2
+            "#)
+            .bind(user_id)
+            .bind(result.cred_id().as_ref())
+            .fetch_optional(pool)
+            .await?
+            .ok_or(PasskeyError::NotFound)?;
+
+        // 计数器不为0时必须严格递增，否则可能是被复制的凭据
+        let counter = i64::from(result.counter());
+        if (counter > 0 || stored.sign_count > 0) && counter <= stored.sign_count {
+            AuditStore::record(pool, None, Some(user_id), "passkey_counter_regression", Some(&stored.id.to_string())).await?;
+            return Err(PasskeyError::CounterRegression);
+        }
+
+        let mut passkey: Passkey = serde_json::from_str(&stored.passkey)?;
+        passkey.update_credential(&result);
+
+        sqlx::query(r#"
+            UPDATE passkeys
+            SET passkey = $1, sign_count = $2, last_used_at = CURRENT_TIMESTAMP
+            WHERE id = $3
+            "#)
+            .bind(serde_json::to_string(&passkey)?)
+            .bind(counter)
+            .bind(stored.id)
+            .execute(pool)
+            .await?;
+
+        Ok(user_id)
+    }
+
+    // 获取用户的通行密钥
+    pub async fn list(pool: &PgPool, user_id: Uuid) -> Result<Vec<PasskeyInfo>, sqlx::Error> {
+        let passkeys = sqlx::query_as::<_, PasskeyInfo>(r#"
+            SELECT id, name, created_at, last_used_at
+            FROM passkeys
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#)
+            .bind(user_id)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(passkeys)
+    }
+
+    // 撤销通行密钥
+    pub async fn delete(pool: &PgPool, user_id: Uuid, passkey_id: Uuid) -> Result<(), PasskeyError> {
+        let result = sqlx::query("DELETE FROM passkeys WHERE id = $1 AND user_id = $2")
+            .bind(passkey_id)
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(PasskeyError::NotFound);
+        }
+
+        AuditStore::record(pool, Some(user_id), Some(user_id), "passkey_revoked", Some(&passkey_id.to_string())).await?;
+
+        Ok(())
+    }
+
+    async fn save_challenge(
+        pool: &PgPool,
+        user_id: Option<Uuid>,
+        kind: &str,
+        state: &str,
+    ) -> Result<Uuid, sqlx::Error> {
+        let expires_at = Utc::now() + Duration::minutes(CHALLENGE_TTL_MINUTES);
+
+        sqlx::query_scalar(r#"
+            INSERT INTO webauthn_challenges (user_id, kind, state, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+            "#)
+            .bind(user_id)
+            .bind(kind)
+            .bind(state)
+            .bind(expires_at)
+            .fetch_one(pool)
+            .await
+    }
+
+    // 取出并删除挑战，保证只能使用一次
+    async fn take_challenge(
+        pool: &PgPool,
+        challenge_id: Uuid,
+        kind: &str,
+    ) -> Result<(Option<Uuid>, String), PasskeyError> {
+        let challenge = sqlx::query_as::<_, (Option<Uuid>, String)>(r#"
+            DELETE FROM webauthn_challenges
+            WHERE id = $1 AND kind = $2 AND expires_at > CURRENT_TIMESTAMP
+            RETURNING user_id, state
+            "#)
+            .bind(challenge_id)
+            .bind(kind)
+            .fetch_optional(pool)
+            .await?;
+
+        challenge.ok_or(PasskeyError::InvalidChallenge)
+    }
+}
@@ -0,0 +1,161 @@
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+use chrono::{Duration, Utc};
+use thiserror::Error;
+use crate::model::{audit::AuditStore, oidc::OidcStore, outbox::{DomainEvent, OutboxStore}, session::SessionStore};
+use crate::password::hash_password;
+use crate::token::{generate_token, hash_token};
+
+// 重置令牌有效期（分钟）
+pub const RESET_TOKEN_TTL_MINUTES: i64 = 30;
+
+// 密码最小长度
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+// 密码重置错误类型
+#[derive(Error, Debug)]
+pub enum PasswordResetError {
+    #[error("重置令牌无效或已过期")]
+    InvalidToken,
+    #[error("密码长度不能少于{MIN_PASSWORD_LENGTH}位")]
+    WeakPassword,
+    #[error("密码加密失败")]
+    PasswordHash,
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+// 忘记密码请求
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+// 重置密码请求
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+}
+
+// 重置链接中的令牌
+#[derive(Debug, Deserialize)]
+pub struct ResetTokenQuery {
+    pub token: String,
+}
+
+// 密码重置令牌存储实现
+pub struct PasswordResetStore;
+
+impl PasswordResetStore {
+    // 为用户签发新的重置令牌，返回明文令牌（仅此一次可见）
+    pub async fn issue(pool: &PgPool, user_id: Uuid) -> Result<String, sqlx::Error> {
+        let token = generate_token();
+        let expires_at = Utc::now() + Duration::minutes(RESET_TOKEN_TTL_MINUTES);
+
+        let mut tx = pool.begin().await?;
+
+        // 新令牌签发后，之前未使用的令牌全部作废
+        sqlx::query(r#"
+            UPDATE password_reset_tokens
+            SET used_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND used_at IS NULL
+            "#)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(r#"
+            INSERT INTO password_reset_tokens (user_id, token_hash, expires_at)
+            VALUES ($1, $2, $3)
+            "#)
+            .bind(user_id)
+            .bind(hash_token(&token))
+            .bind(expires_at)
+            .execute(&mut *tx)
+            .await?;
+
+        AuditStore::record(&mut *tx, None, Some(user_id), "password_reset_requested", None).await?;
+
+        tx.commit().await?;
+
+        Ok(token)
+    }
+
+    // 令牌是否仍可用于重置密码，用于重置页面在提交前提示链接已失效
+    pub async fn is_valid(pool: &PgPool, token: &str) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar(r#"
+            SELECT EXISTS (
+                SELECT 1
+                FROM password_reset_tokens t
+                JOIN users u ON u.id = t.user_id
+                WHERE t.token_hash = $1
+                  AND t.used_at IS NULL
+                  AND t.expires_at > CURRENT_TIMESTAMP
+                  AND t.created_at >= u.password_changed_at
+            )
+            "#)
+            .bind(hash_token(token))
+            .fetch_one(pool)
+            .await
+    }
+
+    // 使用令牌重置密码，成功后返回用户ID
+    pub async fn reset(pool: &PgPool, token: &str, new_password: &str) -> Result<Uuid, PasswordResetError> {
+        if new_password.chars().count() < MIN_PASSWORD_LENGTH {
+            return Err(PasswordResetError::WeakPassword);
+        }
+        let password = hash_password(new_password).map_err(|_| PasswordResetError::PasswordHash)?;
+
+        let mut tx = pool.begin().await?;
+
+        // 令牌必须未使用、未过期，且签发于最近一次修改密码之后
+        let user_id: Option<Uuid> = sqlx::query_scalar(r#"
+            SELECT t.user_id
+            FROM password_reset_tokens t
+            JOIN users u ON u.id = t.user_id
+            WHERE t.token_hash = $1
+              AND t.used_at IS NULL
+              AND t.expires_at > CURRENT_TIMESTAMP
+              AND t.created_at >= u.password_changed_at
+            FOR UPDATE OF t
+            "#)
+            .bind(hash_token(token))
+            .fetch_optional(&mut *tx)
+            .await?;
+        let user_id = user_id.ok_or(PasswordResetError::InvalidToken)?;
+
+        let organization_id = sqlx::query_scalar::<_, Uuid>(r#"
+            UPDATE users
+            SET password = $1, password_changed_at = CURRENT_TIMESTAMP
+            WHERE id = $2
+            RETURNING organization_id
+            "#)
+            .bind(&password)
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        OutboxStore::append(&mut *tx, organization_id, &DomainEvent::UserPasswordChanged { user_id }).await?;
+
+        // 该用户所有未使用的令牌一并失效
+        sqlx::query(r#"
+            UPDATE password_reset_tokens
+            SET used_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND used_at IS NULL
+            "#)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        // 重置密码后注销该用户的全部会话，并吊销已签发给第三方客户端的令牌
+        SessionStore::revoke_all_for_user(&mut *tx, user_id).await?;
+        OidcStore::revoke_all_for_user(&mut tx, user_id).await?;
+
+        AuditStore::record(&mut *tx, None, Some(user_id), "password_reset", None).await?;
+
+        tx.commit().await?;
+
+        Ok(user_id)
+    }
+}
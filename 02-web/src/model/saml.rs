@@ -0,0 +1,509 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use flate2::{write::DeflateEncoder, Compression};
+use openssl::x509::X509;
+use roxmltree::{Document, Node};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::io::Write;
+use uuid::Uuid;
+use chrono::{DateTime, Duration, Utc};
+use thiserror::Error;
+use crate::mailer::app_base_url;
+use crate::model::{audit::AuditStore, CreateUserRequest, User, UserError, UserStore};
+use crate::token::generate_token;
+use crate::xmldsig::{self, child, SignatureError};
+
+const NS_PROTOCOL: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+const NS_ASSERTION: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+const NS_METADATA: &str = "urn:oasis:names:tc:SAML:2.0:metadata";
+const STATUS_SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const BINDING_HTTP_REDIRECT: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-Redirect";
+const BINDING_HTTP_POST: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+const CONFIRMATION_BEARER: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+const NAMEID_EMAIL: &str = "urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress";
+
+// 允许的时钟偏差（秒）
+const CLOCK_SKEW_SECONDS: i64 = 120;
+
+// SP发起的登录请求有效期（分钟）
+const AUTHN_REQUEST_TTL_MINUTES: i64 = 10;
+
+// SAML错误类型
+#[derive(Error, Debug)]
+pub enum SamlError {
+    #[error("SAML连接不存在")]
+    NotFound,
+    #[error("该租户标识已被其他组织使用")]
+    TenantTaken,
+    #[error("IdP元数据无效: {0}")]
+    InvalidMetadata(&'static str),
+    #[error("至少需要一个允许的邮箱域名")]
+    MissingEmailDomain,
+    #[error("SAML响应无效: {0}")]
+    InvalidResponse(&'static str),
+    #[error("SAML签名无效: {0}")]
+    Signature(#[from] SignatureError),
+    #[error("断言已被使用")]
+    Replay,
+    #[error("邮箱不属于该连接允许的域名")]
+    EmailDomainNotAllowed,
+    #[error("没有与该身份对应的账号")]
+    NoAccount,
+    #[error("{0}")]
+    User(#[from] UserError),
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+// 租户的SAML连接配置
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SamlConnection {
+    pub id: Uuid,
+    // 只有该组织的用户可以通过该连接登录，自动创建的用户也归属该组织
+    pub organization_id: Uuid,
+    pub tenant: String,
+    pub idp_entity_id: String,
+    pub idp_sso_url: Option<String>,
+    #[serde(skip_serializing)]
+    pub idp_certificates: Vec<String>,
+    pub email_domains: Vec<String>,
+    pub email_attribute: String,
+    pub name_attribute: String,
+    pub jit_provisioning: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// 配置SAML连接请求
+#[derive(Debug, Deserialize)]
+pub struct SamlConnectionRequest {
+    // IdP提供的元数据XML
+    pub idp_metadata: String,
+    pub email_domains: Vec<String>,
+    pub email_attribute: Option<String>,
+    pub name_attribute: Option<String>,
+    pub jit_provisioning: Option<bool>,
+}
+
+// ACS接收的HTTP-POST表单
+#[derive(Debug, Deserialize)]
+pub struct SamlPostForm {
+    #[serde(rename = "SAMLResponse")]
+    pub saml_response: String,
+}
+
+// 从IdP元数据中解析出的配置
+struct IdpMetadata {
+    entity_id: String,
+    sso_url: Option<String>,
+    certificates: Vec<String>,
+}
+
+// SP实体ID，同时也是元数据地址
+pub fn sp_entity_id(tenant: &str) -> String {
+    format!("{}/saml/{}/metadata", app_base_url(), tenant)
+}
+
+// 断言消费服务地址
+pub fn acs_url(tenant: &str) -> String {
+    format!("{}/saml/{}/acs", app_base_url(), tenant)
+}
+
+fn parse_time(value: Option<&str>) -> Result<Option<DateTime<Utc>>, SamlError> {
+    value
+        .map(|value| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|time| time.with_timezone(&Utc))
+                .map_err(|_| SamlError::InvalidResponse("时间格式错误"))
+        })
+        .transpose()
+}
+
+fn text_of(node: Option<Node>) -> Option<String> {
+    node.and_then(|node| node.text()).map(|text| text.trim().to_string())
+}
+
+fn parse_idp_metadata(xml: &str) -> Result<IdpMetadata, SamlError> {
+    let doc = Document::parse(xml).map_err(|_| SamlError::InvalidMetadata("XML格式错误"))?;
+    let root = doc.root_element();
+    if !root.has_tag_name((NS_METADATA, "EntityDescriptor")) {
+        return Err(SamlError::InvalidMetadata("缺少EntityDescriptor"));
+    }
+    let entity_id = root.attribute("entityID").ok_or(SamlError::InvalidMetadata("缺少entityID"))?;
+    let idp = child(root, NS_METADATA, "IDPSSODescriptor").ok_or(SamlError::InvalidMetadata("缺少IDPSSODescriptor"))?;
+
+    let sso_url = idp
+        .children()
+        .filter(|node| node.has_tag_name((NS_METADATA, "SingleSignOnService")))
+        .find(|node| node.attribute("Binding") == Some(BINDING_HTTP_REDIRECT))
+        .and_then(|node| node.attribute("Location"))
+        .map(str::to_string);
+
+    let certificates: Vec<String> = idp
+        .children()
+        .filter(|node| node.has_tag_name((NS_METADATA, "KeyDescriptor")))
+        .filter(|node| node.attribute("use").is_none_or(|key_use| key_use == "signing"))
+        .flat_map(|node| node.descendants().filter(|node| node.has_tag_name((xmldsig::NS_DSIG, "X509Certificate"))))
+        .filter_map(|node| node.text())
+        .map(|text| text.chars().filter(|c| !c.is_ascii_whitespace()).collect())
+        .collect();
+    if certificates.is_empty() {
+        return Err(SamlError::InvalidMetadata("缺少签名证书"));
+    }
+    for certificate in &certificates {
+        let der = STANDARD.decode(certificate).map_err(|_| SamlError::InvalidMetadata("证书不是有效的Base64"))?;
+        X509::from_der(&der).map_err(|_| SamlError::InvalidMetadata("证书格式错误"))?;
+    }
+
+    Ok(IdpMetadata { entity_id: entity_id.to_string(), sso_url, certificates })
+}
+
+// SAML存储实现
+pub struct SamlStore;
+
+impl SamlStore {
+    // 为组织创建或替换租户的SAML连接，不能覆盖其他组织的连接
+    pub async fn upsert(
+        pool: &PgPool,
+        organization_id: Uuid,
+        actor_id: Uuid,
+        tenant: &str,
+        req: &SamlConnectionRequest,
+    ) -> Result<SamlConnection, SamlError> {
+        let metadata = parse_idp_metadata(&req.idp_metadata)?;
+        let email_domains: Vec<String> = req
+            .email_domains
+            .iter()
+            .map(|domain| domain.trim().to_lowercase())
+            .filter(|domain| !domain.is_empty())
+            .collect();
+        if email_domains.is_empty() {
+            return Err(SamlError::MissingEmailDomain);
+        }
+
+        let connection = sqlx::query_as::<_, SamlConnection>(r#"
+            INSERT INTO saml_connections
+                (organization_id, tenant, idp_entity_id, idp_sso_url, idp_certificates, email_domains, email_attribute, name_attribute, jit_provisioning)
+            VALUES ($9, $1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (tenant) DO UPDATE
+            SET idp_entity_id = EXCLUDED.idp_entity_id,
+                idp_sso_url = EXCLUDED.idp_sso_url,
+                idp_certificates = EXCLUDED.idp_certificates,
+                email_domains = EXCLUDED.email_domains,
+                email_attribute = EXCLUDED.email_attribute,
+                name_attribute = EXCLUDED.name_attribute,
+                jit_provisioning = EXCLUDED.jit_provisioning,
+                updated_at = CURRENT_TIMESTAMP
+            WHERE saml_connections.organization_id = EXCLUDED.organization_id
+            RETURNING id, organization_id, tenant, idp_entity_id, idp_sso_url, idp_certificates, email_domains,
+                      email_attribute, name_attribute, jit_provisioning, created_at, updated_at
+            "#)
+            .bind(tenant)
+            .bind(&metadata.entity_id)
+            .bind(&metadata.sso_url)
+            .bind(&metadata.certificates)
+            .bind(&email_domains)
+            .bind(req.email_attribute.as_deref().unwrap_or("email"))
+            .bind(req.name_attribute.as_deref().unwrap_or("name"))
+            .bind(req.jit_provisioning.unwrap_or(false))
+            .bind(organization_id)
+            .fetch_optional(pool)
+            .await?
+            .ok_or(SamlError::TenantTaken)?;
+
+        AuditStore::record(pool, Some(actor_id), None, "saml_connection_updated", Some(tenant)).await?;
+
+        Ok(connection)
+    }
+
+    // 获取组织的所有SAML连接
+    pub async fn find_all(pool: &PgPool, organization_id: Uuid) -> Result<Vec<SamlConnection>, sqlx::Error> {
+        let connections = sqlx::query_as::<_, SamlConnection>(r#"
+            SELECT id, organization_id, tenant, idp_entity_id, idp_sso_url, idp_certificates, email_domains,
+                   email_attribute, name_attribute, jit_provisioning, created_at, updated_at
+            FROM saml_connections
+            WHERE organization_id = $1
+            ORDER BY tenant
+            "#)
+            .bind(organization_id)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(connections)
+    }
+
+    // 根据租户查找SAML连接
+    pub async fn find_by_tenant(pool: &PgPool, tenant: &str) -> Result<Option<SamlConnection>, sqlx::Error> {
+        let connection = sqlx::query_as::<_, SamlConnection>(r#"
+            SELECT id, organization_id, tenant, idp_entity_id, idp_sso_url, idp_certificates, email_domains,
+                   email_attribute, name_attribute, jit_provisioning, created_at, updated_at
+            FROM saml_connections
+            WHERE tenant = $1
+            "#)
+            .bind(tenant)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(connection)
+    }
+
+    // 删除组织中的SAML连接
+    pub async fn delete(pool: &PgPool, organization_id: Uuid, actor_id: Uuid, tenant: &str) -> Result<(), SamlError> {
+        let result = sqlx::query("DELETE FROM saml_connections WHERE tenant = $1 AND organization_id = $2")
+            .bind(tenant)
+            .bind(organization_id)
+            .execute(pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(SamlError::NotFound);
+        }
+
+        AuditStore::record(pool, Some(actor_id), None, "saml_connection_deleted", Some(tenant)).await?;
+
+        Ok(())
+    }
+
+    // 生成SP元数据
+    pub fn sp_metadata(tenant: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<md:EntityDescriptor xmlns:md="{}" entityID="{}">
+  <md:SPSSODescriptor AuthnRequestsSigned="false" WantAssertionsSigned="true" protocolSupportEnumeration="{}">
+    <md:NameIDFormat>{}</md:NameIDFormat>
+    <md:AssertionConsumerService Binding="{}" Location="{}" index="0" isDefault="true"/>
+  </md:SPSSODescriptor>
+</md:EntityDescriptor>
+"#,
+            NS_METADATA,
+            sp_entity_id(tenant),
+            NS_PROTOCOL,
+            NAMEID_EMAIL,
+            BINDING_HTTP_POST,
+            acs_url(tenant),
+        )
+    }
+
+    // SP发起登录：生成HTTP-Redirect绑定的AuthnRequest并记录请求ID
+    pub async fn start_login(pool: &PgPool, tenant: &str) -> Result<String, SamlError> {
+        let connection = Self::find_by_tenant(pool, tenant).await?.ok_or(SamlError::NotFound)?;
+        let sso_url = connection
+            .idp_sso_url
+            .as_deref()
+            .ok_or(SamlError::InvalidMetadata("IdP未提供HTTP-Redirect登录地址"))?;
+
+        // SAML ID不能以数字开头
+        let request_id = format!("_{}", generate_token());
+        let request = format!(
+            r#"<samlp:AuthnRequest xmlns:samlp="{}" xmlns:saml="{}" ID="{}" Version="2.0" IssueInstant="{}" Destination="{}" AssertionConsumerServiceURL="{}" ProtocolBinding="{}"><saml:Issuer>{}</saml:Issuer></samlp:AuthnRequest>"#,
+            NS_PROTOCOL,
+            NS_ASSERTION,
+            request_id,
+            Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+            sso_url,
+            acs_url(tenant),
+            BINDING_HTTP_POST,
+            sp_entity_id(tenant),
+        );
+
+        // HTTP-Redirect绑定：DEFLATE压缩后Base64编码
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        let deflated = encoder
+            .write_all(request.as_bytes())
+            .and_then(|_| encoder.finish())
+            .map_err(|_| SamlError::InvalidResponse("AuthnRequest编码失败"))?;
+        let mut url = reqwest::Url::parse(sso_url).map_err(|_| SamlError::InvalidMetadata("登录地址无效"))?;
+        url.query_pairs_mut().append_pair("SAMLRequest", &STANDARD.encode(deflated));
+
+        sqlx::query(r#"
+            INSERT INTO saml_requests (id, connection_id, expires_at)
+            VALUES ($1, $2, $3)
+            "#)
+            .bind(&request_id)
+            .bind(connection.id)
+            .bind(Utc::now() + Duration::minutes(AUTHN_REQUEST_TTL_MINUTES))
+            .execute(pool)
+            .await?;
+
+        Ok(url.to_string())
+    }
+
+    // 处理IdP通过HTTP-POST绑定提交的SAML响应，返回登录用户
+    pub async fn consume_response(pool: &PgPool, tenant: &str, saml_response: &str) -> Result<User, SamlError> {
+        let connection = Self::find_by_tenant(pool, tenant).await?.ok_or(SamlError::NotFound)?;
+
+        let encoded: String = saml_response.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+        let xml = STANDARD
+            .decode(encoded)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or(SamlError::InvalidResponse("SAMLResponse不是有效的Base64"))?;
+        // roxmltree默认拒绝DTD，避免实体扩展和XXE
+        let doc = Document::parse(&xml).map_err(|_| SamlError::InvalidResponse("XML格式错误"))?;
+
+        let response = doc.root_element();
+        if !response.has_tag_name((NS_PROTOCOL, "Response")) {
+            return Err(SamlError::InvalidResponse("根元素不是Response"));
+        }
+        let acs = acs_url(tenant);
+        if response.attribute("Destination").is_some_and(|destination| destination != acs) {
+            return Err(SamlError::InvalidResponse("Destination不匹配"));
+        }
+        if text_of(child(response, NS_ASSERTION, "Issuer")).is_some_and(|issuer| issuer != connection.idp_entity_id) {
+            return Err(SamlError::InvalidResponse("Issuer不匹配"));
+        }
+        let status = child(response, NS_PROTOCOL, "Status")
+            .and_then(|status| child(status, NS_PROTOCOL, "StatusCode"))
+            .and_then(|code| code.attribute("Value"));
+        if status != Some(STATUS_SUCCESS) {
+            return Err(SamlError::InvalidResponse("IdP返回了失败状态"));
+        }
+
+        if child(response, NS_ASSERTION, "EncryptedAssertion").is_some() {
+            return Err(SamlError::InvalidResponse("不支持加密断言"));
+        }
+        let mut assertions = response.children().filter(|node| node.has_tag_name((NS_ASSERTION, "Assertion")));
+        let assertion = assertions.next().ok_or(SamlError::InvalidResponse("缺少断言"))?;
+        if assertions.next().is_some() {
+            return Err(SamlError::InvalidResponse("只支持一个断言"));
+        }
+
+        // 断言自身有签名时校验断言签名，否则要求整个响应已签名
+        if xmldsig::has_signature(assertion) {
+            xmldsig::verify_element(assertion, &connection.idp_certificates)?;
+        } else {
+            xmldsig::verify_element(response, &connection.idp_certificates)?;
+        }
+
+        // 以下只读取已签名断言中的内容
+        if text_of(child(assertion, NS_ASSERTION, "Issuer")).as_deref() != Some(connection.idp_entity_id.as_str()) {
+            return Err(SamlError::InvalidResponse("断言Issuer不匹配"));
+        }
+        let assertion_id = assertion.attribute("ID").ok_or(SamlError::InvalidResponse("断言缺少ID"))?;
+
+        let now = Utc::now();
+        let skew = Duration::seconds(CLOCK_SKEW_SECONDS);
+        let conditions = child(assertion, NS_ASSERTION, "Conditions").ok_or(SamlError::InvalidResponse("缺少Conditions"))?;
+        if parse_time(conditions.attribute("NotBefore"))?.is_some_and(|not_before| now + skew < not_before) {
+            return Err(SamlError::InvalidResponse("断言尚未生效"));
+        }
+        let conditions_expiry = parse_time(conditions.attribute("NotOnOrAfter"))?;
+        if conditions_expiry.is_some_and(|not_on_or_after| now - skew >= not_on_or_after) {
+            return Err(SamlError::InvalidResponse("断言已过期"));
+        }
+        let sp_entity = sp_entity_id(tenant);
+        let audience_ok = conditions
+            .children()
+            .filter(|node| node.has_tag_name((NS_ASSERTION, "AudienceRestriction")))
+            .any(|restriction| {
+                restriction
+                    .children()
+                    .filter(|node| node.has_tag_name((NS_ASSERTION, "Audience")))
+                    .any(|audience| text_of(Some(audience)).as_deref() == Some(sp_entity.as_str()))
+            });
+        if !audience_ok {
+            return Err(SamlError::InvalidResponse("Audience不匹配"));
+        }
+
+        let subject = child(assertion, NS_ASSERTION, "Subject").ok_or(SamlError::InvalidResponse("缺少Subject"))?;
+        let confirmation = subject
+            .children()
+            .filter(|node| node.has_tag_name((NS_ASSERTION, "SubjectConfirmation")))
+            .find(|node| node.attribute("Method") == Some(CONFIRMATION_BEARER))
+            .and_then(|node| child(node, NS_ASSERTION, "SubjectConfirmationData"))
+            .ok_or(SamlError::InvalidResponse("缺少bearer SubjectConfirmation"))?;
+        if confirmation.attribute("Recipient") != Some(acs.as_str()) {
+            return Err(SamlError::InvalidResponse("Recipient不匹配"));
+        }
+        let confirmation_expiry = parse_time(confirmation.attribute("NotOnOrAfter"))?
+            .ok_or(SamlError::InvalidResponse("SubjectConfirmationData缺少NotOnOrAfter"))?;
+        if now - skew >= confirmation_expiry {
+            return Err(SamlError::InvalidResponse("断言已过期"));
+        }
+
+        // SP发起的登录必须对应一个未使用的AuthnRequest；没有InResponseTo时视为IdP发起
+        if let Some(in_response_to) = confirmation.attribute("InResponseTo").or(response.attribute("InResponseTo")) {
+            let consumed = sqlx::query(r#"
+                DELETE FROM saml_requests
+                WHERE id = $1 AND connection_id = $2 AND expires_at > CURRENT_TIMESTAMP
+                "#)
+                .bind(in_response_to)
+                .bind(connection.id)
+                .execute(pool)
+                .await?;
+            if consumed.rows_affected() == 0 {
+                return Err(SamlError::InvalidResponse("InResponseTo无效"));
+            }
+        }
+
+        // 防重放：断言ID在有效期内只能使用一次
+        sqlx::query("DELETE FROM saml_assertions WHERE expires_at < CURRENT_TIMESTAMP")
+            .execute(pool)
+            .await?;
+        let expires_at = conditions_expiry.map_or(confirmation_expiry, |expiry| expiry.max(confirmation_expiry)) + skew;
+        let inserted = sqlx::query(r#"
+            INSERT INTO saml_assertions (connection_id, assertion_id, expires_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#)
+            .bind(connection.id)
+            .bind(assertion_id)
+            .bind(expires_at)
+            .execute(pool)
+            .await?;
+        if inserted.rows_affected() == 0 {
+            return Err(SamlError::Replay);
+        }
+
+        // 属性映射
+        let attribute = |name: &str| {
+            child(assertion, NS_ASSERTION, "AttributeStatement").and_then(|statement| {
+                statement
+                    .children()
+                    .filter(|node| node.has_tag_name((NS_ASSERTION, "Attribute")))
+                    .find(|node| node.attribute("Name") == Some(name))
+                    .and_then(|node| text_of(child(node, NS_ASSERTION, "AttributeValue")))
+            })
+        };
+        let name_id = child(subject, NS_ASSERTION, "NameID");
+        let email = attribute(&connection.email_attribute)
+            .or_else(|| {
+                name_id
+                    .filter(|node| node.attribute("Format") == Some(NAMEID_EMAIL))
+                    .and_then(|node| text_of(Some(node)))
+            })
+            .ok_or(SamlError::InvalidResponse("断言中没有邮箱"))?;
+        let name = attribute(&connection.name_attribute).unwrap_or_else(|| email.clone());
+
+        // 只接受该连接允许的邮箱域名，避免IdP冒充其他租户的用户
+        let domain = email.rsplit_once('@').map(|(_, domain)| domain.to_lowercase()).unwrap_or_default();
+        if !connection.email_domains.contains(&domain) {
+            return Err(SamlError::EmailDomainNotAllowed);
+        }
+
+        // 邮箱全局唯一：属于其他组织的账号不能通过该连接登录，也不能再自动创建
+        let user = match UserStore::find_by_email(pool, &email).await? {
+            Some(user) if user.organization_id != connection.organization_id => return Err(SamlError::NoAccount),
+            Some(user) => user,
+            None if connection.jit_provisioning => {
+                let mut tx = pool.begin().await?;
+                let user = UserStore::create(&mut tx, connection.organization_id, &CreateUserRequest {
+                    name,
+                    email,
+                    password: generate_token(),
+                    role: None,
+                    group_ids: Vec::new(),
+                })
+                .await?;
+                tx.commit().await?;
+                AuditStore::record(pool, None, Some(user.id), "saml_user_provisioned", Some(tenant)).await?;
+                user
+            }
+            None => return Err(SamlError::NoAccount),
+        };
+
+        AuditStore::record(pool, Some(user.id), Some(user.id), "saml_login", Some(tenant)).await?;
+
+        Ok(user)
+    }
+}
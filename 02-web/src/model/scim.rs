@@ -0,0 +1,1015 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{FromRow, PgConnection, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+use crate::mailer::app_base_url;
+use crate::model::{audit::AuditStore, CreateUserRequest, UpdateUserRequest, UserError, UserStore};
+use crate::model::{STATUS_ACTIVE, STATUS_DELETED, STATUS_LOCKED, STATUS_PENDING, STATUS_SUSPENDED};
+use crate::model::group::{CreateGroupRequest, GroupError, GroupStore, UpdateGroupRequest};
+use crate::model::password_reset::MIN_PASSWORD_LENGTH;
+use crate::model::user_status::{Transition, UserStatusError, UserStatusStore};
+use crate::scim_filter::{self, Attribute, AttributeKind, FilterError, PatchPath};
+use crate::token::{generate_token, hash_token};
+
+// SCIM消息和资源的schema
+pub const SCHEMA_USER: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+pub const SCHEMA_GROUP: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+pub const SCHEMA_LIST_RESPONSE: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+pub const SCHEMA_PATCH_OP: &str = "urn:ietf:params:scim:api:messages:2.0:PatchOp";
+pub const SCHEMA_ERROR: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+
+// SCIM令牌前缀
+pub const SCIM_TOKEN_PREFIX: &str = "scim_";
+
+// 单页最多返回的资源数
+pub const MAX_RESULTS: i64 = 100;
+
+// SCIM发起的账号状态转换记录的原因
+const SCIM_REASON: &str = "SCIM同步";
+
+// SCIM接口的对外地址
+pub fn scim_base_url() -> String {
+    format!("{}/scim/v2", app_base_url())
+}
+
+// SCIM错误类型，处理函数按RFC 7644 3.12转换为状态码和scimType
+#[derive(Error, Debug)]
+pub enum ScimError {
+    #[error("资源不存在")]
+    NotFound,
+    #[error("{0}")]
+    Uniqueness(&'static str),
+    #[error("{0}")]
+    InvalidFilter(#[from] FilterError),
+    #[error("{0}")]
+    InvalidValue(String),
+    #[error("{0}")]
+    InvalidSyntax(String),
+    #[error("{0}")]
+    NoTarget(String),
+    #[error("资源已被修改，请重新获取后再试")]
+    PreconditionFailed,
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl From<UserError> for ScimError {
+    fn from(err: UserError) -> Self {
+        match err {
+            UserError::NotFound => ScimError::NotFound,
+            UserError::EmailExists => ScimError::Uniqueness("userName已存在"),
+            UserError::Database(err) => ScimError::Database(err),
+            err => ScimError::InvalidValue(err.to_string()),
+        }
+    }
+}
+
+impl From<GroupError> for ScimError {
+    fn from(err: GroupError) -> Self {
+        match err {
+            GroupError::NotFound => ScimError::NotFound,
+            GroupError::NameExists => ScimError::Uniqueness("displayName已存在"),
+            GroupError::Database(err) => ScimError::Database(err),
+            err => ScimError::InvalidValue(err.to_string()),
+        }
+    }
+}
+
+impl From<UserStatusError> for ScimError {
+    fn from(err: UserStatusError) -> Self {
+        match err {
+            UserStatusError::NotFound => ScimError::NotFound,
+            UserStatusError::Database(err) => ScimError::Database(err),
+            err => ScimError::InvalidValue(err.to_string()),
+        }
+    }
+}
+
+// SCIM令牌信息，不包含令牌本身
+#[derive(Debug, Serialize, FromRow)]
+pub struct ScimToken {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+// 创建SCIM令牌请求
+#[derive(Debug, Deserialize)]
+pub struct CreateScimTokenRequest {
+    pub name: String,
+}
+
+// 创建SCIM令牌响应，令牌只返回这一次
+#[derive(Debug, Serialize)]
+pub struct CreatedScimToken {
+    #[serde(flatten)]
+    pub scim_token: ScimToken,
+    pub token: String,
+}
+
+// 资源元数据，version同时作为ETag
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    pub resource_type: &'static str,
+    pub created: DateTime<Utc>,
+    pub last_modified: DateTime<Utc>,
+    pub location: String,
+    pub version: String,
+}
+
+impl Meta {
+    fn new(resource_type: &'static str, endpoint: &str, id: Uuid, created: DateTime<Utc>, last_modified: DateTime<Utc>) -> Self {
+        Meta {
+            resource_type,
+            created,
+            last_modified,
+            location: format!("{}/{}/{}", scim_base_url(), endpoint, id),
+            version: format!("W/\"{}\"", last_modified.timestamp_micros()),
+        }
+    }
+}
+
+// 姓名
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimName {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub formatted: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub given_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub family_name: Option<String>,
+}
+
+// 邮箱
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScimEmail {
+    pub value: String,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub email_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary: Option<bool>,
+}
+
+// SCIM用户资源：userName对应登录邮箱，active对应账号是否为正常状态
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUser {
+    pub schemas: [&'static str; 1],
+    pub id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    pub user_name: String,
+    pub name: ScimName,
+    pub display_name: String,
+    pub emails: Vec<ScimEmail>,
+    pub active: bool,
+    pub meta: Meta,
+}
+
+// 创建或替换用户的请求体
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUserInput {
+    pub user_name: Option<String>,
+    pub external_id: Option<String>,
+    pub name: Option<ScimName>,
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub emails: Vec<ScimEmail>,
+    #[serde(default, deserialize_with = "bool_or_string")]
+    pub active: Option<bool>,
+    pub password: Option<String>,
+}
+
+// 分组成员，type为User或Group（下级分组）
+#[derive(Debug, Serialize)]
+pub struct ScimMember {
+    pub value: Uuid,
+    pub display: String,
+    #[serde(rename = "type")]
+    pub member_type: &'static str,
+    #[serde(rename = "$ref")]
+    pub reference: String,
+}
+
+// SCIM分组资源，成员可以是用户或下级分组
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroup {
+    pub schemas: [&'static str; 1],
+    pub id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    pub display_name: String,
+    pub members: Vec<ScimMember>,
+    pub meta: Meta,
+}
+
+// 创建或替换分组的请求体
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroupInput {
+    pub display_name: Option<String>,
+    pub external_id: Option<String>,
+    #[serde(default)]
+    pub members: Vec<ScimMemberInput>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScimMemberInput {
+    pub value: String,
+}
+
+// 列表响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListResponse<T> {
+    pub schemas: [&'static str; 1],
+    pub total_results: i64,
+    pub start_index: i64,
+    pub items_per_page: usize,
+    #[serde(rename = "Resources")]
+    pub resources: Vec<T>,
+}
+
+// 列表查询参数，startIndex从1开始
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListQuery {
+    pub filter: Option<String>,
+    pub start_index: Option<i64>,
+    pub count: Option<i64>,
+}
+
+impl ListQuery {
+    fn page(&self) -> (i64, i64) {
+        (self.start_index.unwrap_or(1).max(1), self.count.unwrap_or(MAX_RESULTS).clamp(0, MAX_RESULTS))
+    }
+}
+
+// PATCH请求
+#[derive(Debug, Deserialize)]
+pub struct PatchRequest {
+    #[serde(default)]
+    pub schemas: Vec<String>,
+    #[serde(rename = "Operations")]
+    pub operations: Vec<PatchOperation>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatchOperation {
+    pub op: String,
+    pub path: Option<String>,
+    pub value: Option<Value>,
+}
+
+#[derive(Clone, FromRow)]
+struct UserRow {
+    id: Uuid,
+    name: String,
+    email: String,
+    status: String,
+    external_id: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<UserRow> for ScimUser {
+    fn from(row: UserRow) -> Self {
+        ScimUser {
+            schemas: [SCHEMA_USER],
+            id: row.id,
+            external_id: row.external_id,
+            user_name: row.email.clone(),
+            name: ScimName { formatted: Some(row.name.clone()), ..Default::default() },
+            display_name: row.name,
+            emails: vec![ScimEmail { value: row.email, email_type: Some("work".to_string()), primary: Some(true) }],
+            active: row.status == STATUS_ACTIVE,
+            meta: Meta::new("User", "Users", row.id, row.created_at, row.updated_at),
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct GroupRow {
+    id: Uuid,
+    name: String,
+    external_id: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+const USER_COLUMNS: &str = "id, name, email, status, external_id, created_at, updated_at";
+const GROUP_COLUMNS: &str = "id, name, external_id, created_at, updated_at";
+
+// 部分IdP把布尔值作为字符串发送，如 "active": "False"
+fn bool_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<bool>, D::Error> {
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Bool(value)) => Ok(Some(value)),
+        Some(Value::String(value)) if value.eq_ignore_ascii_case("true") => Ok(Some(true)),
+        Some(Value::String(value)) if value.eq_ignore_ascii_case("false") => Ok(Some(false)),
+        Some(_) => Err(serde::de::Error::custom("active必须是布尔值")),
+    }
+}
+
+// 可过滤的用户属性
+fn user_attribute(path: &str) -> Option<Attribute> {
+    let (expression, kind) = match path {
+        "id" => ("id", AttributeKind::Id),
+        "username" | "emails" | "emails.value" => ("email", AttributeKind::Text),
+        "displayname" | "name.formatted" => ("name", AttributeKind::Text),
+        "externalid" => ("external_id", AttributeKind::CaseExactText),
+        "active" => ("(status = 'active')", AttributeKind::Boolean),
+        "meta.created" => ("created_at", AttributeKind::Timestamp),
+        "meta.lastmodified" => ("updated_at", AttributeKind::Timestamp),
+        _ => return None,
+    };
+    Some(Attribute { expression, kind })
+}
+
+// 可过滤的分组属性
+fn group_attribute(path: &str) -> Option<Attribute> {
+    let (expression, kind) = match path {
+        "id" => ("id", AttributeKind::Id),
+        "displayname" => ("name", AttributeKind::Text),
+        "externalid" => ("external_id", AttributeKind::CaseExactText),
+        "meta.created" => ("created_at", AttributeKind::Timestamp),
+        "meta.lastmodified" => ("updated_at", AttributeKind::Timestamp),
+        _ => return None,
+    };
+    Some(Attribute { expression, kind })
+}
+
+// If-Match为空或为*时不检查，否则必须与当前版本一致
+fn check_version(version: &str, if_match: Option<&str>) -> Result<(), ScimError> {
+    match if_match {
+        Some(if_match) if if_match.trim() != "*" && !if_match.split(',').any(|tag| tag.trim() == version) => {
+            Err(ScimError::PreconditionFailed)
+        }
+        _ => Ok(()),
+    }
+}
+
+// 从候选值中选出第一个与当前值不同的，用于合并同一字段的多个SCIM属性（如displayName和name.formatted）
+fn changed_value(current: Option<&str>, candidates: Vec<Option<String>>) -> Option<String> {
+    let candidates: Vec<String> = candidates.into_iter().flatten().filter(|value| !value.trim().is_empty()).collect();
+    candidates
+        .iter()
+        .find(|value| Some(value.as_str()) != current)
+        .or_else(|| candidates.first())
+        .cloned()
+}
+
+fn user_name_candidates(input: &ScimUserInput) -> Vec<Option<String>> {
+    let name = input.name.clone().unwrap_or_default();
+    let joined = match (&name.given_name, &name.family_name) {
+        (None, None) => None,
+        (given, family) => Some(
+            [given.as_deref(), family.as_deref()].into_iter().flatten().collect::<Vec<_>>().join(" "),
+        ),
+    };
+    vec![input.display_name.clone(), name.formatted, joined]
+}
+
+fn email_candidates(input: &ScimUserInput) -> Vec<Option<String>> {
+    let primary = input
+        .emails
+        .iter()
+        .find(|email| email.primary == Some(true))
+        .or(input.emails.first())
+        .map(|email| email.value.clone());
+    vec![input.user_name.clone(), primary]
+}
+
+// SCIM属性名不区分大小写，写回JSON时使用规范的大小写以便反序列化
+fn canonical_key(object: &Map<String, Value>, name: &str) -> String {
+    const CANONICAL: [&str; 15] = [
+        "userName", "externalId", "displayName", "name", "emails", "active", "password", "members",
+        "formatted", "givenName", "familyName", "value", "type", "primary", "display",
+    ];
+    object
+        .keys()
+        .find(|key| key.eq_ignore_ascii_case(name))
+        .cloned()
+        .or_else(|| CANONICAL.iter().find(|key| key.eq_ignore_ascii_case(name)).map(|key| key.to_string()))
+        .unwrap_or_else(|| name.to_string())
+}
+
+// add合并数组和对象，replace合并对象、替换其他值
+fn merge_value(target: &mut Value, value: Value, add: bool) {
+    match (target, value) {
+        (Value::Object(target), Value::Object(value)) => {
+            for (key, value) in value {
+                let key = canonical_key(target, &key);
+                target.insert(key, value);
+            }
+        }
+        (Value::Array(target), Value::Array(values)) if add => {
+            for value in values {
+                if !target.contains(&value) {
+                    target.push(value);
+                }
+            }
+        }
+        (target, value) => *target = value,
+    }
+}
+
+// 对资源的JSON表示执行单个PATCH操作
+fn apply_operation(resource: &mut Map<String, Value>, op: &str, path: Option<&str>, value: Option<Value>) -> Result<(), ScimError> {
+    let Some(path) = path else {
+        // 没有path时value为属性集合，键也可以是路径
+        if op == "remove" {
+            return Err(ScimError::NoTarget("remove操作必须指定path".to_string()));
+        }
+        let Some(Value::Object(values)) = value else {
+            return Err(ScimError::InvalidValue("没有path时value必须是对象".to_string()));
+        };
+        for (key, value) in values {
+            apply_operation(resource, op, Some(&key), Some(value))?;
+        }
+        return Ok(());
+    };
+
+    let PatchPath { attribute, filter, sub_attribute } = scim_filter::parse_path(path)?;
+    let key = canonical_key(resource, &attribute);
+    if op != "remove" && value.is_none() {
+        return Err(ScimError::InvalidValue(format!("{}操作缺少value", op)));
+    }
+
+    match (filter, sub_attribute) {
+        (None, None) => match (op, value) {
+            ("remove", Some(Value::Array(removed))) => {
+                // 按value字段移除多值属性中的元素，如移除指定成员
+                if let Some(Value::Array(items)) = resource.get_mut(&key) {
+                    items.retain(|item| {
+                        !removed.iter().any(|removed| {
+                            scim_filter::get_field(removed, "value").is_some_and(|value| Some(value) == scim_filter::get_field(item, "value"))
+                        })
+                    });
+                }
+            }
+            ("remove", _) => {
+                resource.remove(&key);
+            }
+            (_, Some(value)) => match resource.get_mut(&key) {
+                Some(target) => merge_value(target, value, op == "add"),
+                None => {
+                    resource.insert(key, value);
+                }
+            },
+            _ => unreachable!(),
+        },
+        (None, Some(sub_attribute)) => {
+            let target = resource.entry(key).or_insert_with(|| Value::Object(Map::new()));
+            let Value::Object(target) = target else {
+                return Err(ScimError::NoTarget(format!("{}不是复合属性", path)));
+            };
+            let sub_key = canonical_key(target, &sub_attribute);
+            match value {
+                Some(value) if op != "remove" => {
+                    target.insert(sub_key, value);
+                }
+                _ => {
+                    target.remove(&sub_key);
+                }
+            }
+        }
+        (Some(filter), sub_attribute) => {
+            let Some(Value::Array(items)) = resource.get_mut(&key) else {
+                return match op {
+                    "remove" => Ok(()),
+                    _ => Err(ScimError::NoTarget(format!("没有匹配{}的元素", path))),
+                };
+            };
+            let matched = items.iter().filter(|item| scim_filter::matches(&filter, item)).count();
+            if matched == 0 && op != "remove" {
+                return Err(ScimError::NoTarget(format!("没有匹配{}的元素", path)));
+            }
+            match (op, sub_attribute) {
+                ("remove", None) => items.retain(|item| !scim_filter::matches(&filter, item)),
+                (_, sub_attribute) => {
+                    for item in items.iter_mut().filter(|item| scim_filter::matches(&filter, item)) {
+                        let Value::Object(item) = item else { continue };
+                        match (&sub_attribute, &value) {
+                            (Some(sub_attribute), Some(value)) if op != "remove" => {
+                                let sub_key = canonical_key(item, sub_attribute);
+                                item.insert(sub_key, value.clone());
+                            }
+                            (Some(sub_attribute), _) => {
+                                let sub_key = canonical_key(item, sub_attribute);
+                                item.remove(&sub_key);
+                            }
+                            (None, Some(Value::Object(value))) => {
+                                for (key, value) in value {
+                                    let key = canonical_key(item, key);
+                                    item.insert(key, value.clone());
+                                }
+                            }
+                            (None, _) => return Err(ScimError::InvalidValue("value必须是对象".to_string())),
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// 依次执行PATCH操作，任一操作失败时整体不生效
+fn apply_patch<T: Serialize>(resource: &T, request: &PatchRequest) -> Result<Value, ScimError> {
+    let mut resource = serde_json::to_value(resource).map_err(|err| ScimError::InvalidValue(err.to_string()))?;
+    if !request.schemas.iter().any(|schema| schema == SCHEMA_PATCH_OP) {
+        return Err(ScimError::InvalidSyntax(format!("schemas必须包含{}", SCHEMA_PATCH_OP)));
+    }
+    let Value::Object(object) = &mut resource else { unreachable!() };
+    for operation in &request.operations {
+        let op = operation.op.to_ascii_lowercase();
+        if !matches!(op.as_str(), "add" | "replace" | "remove") {
+            return Err(ScimError::InvalidSyntax(format!("不支持的操作: {}", operation.op)));
+        }
+        apply_operation(object, &op, operation.path.as_deref(), operation.value.clone())?;
+    }
+    Ok(resource)
+}
+
+fn from_json<T: for<'de> Deserialize<'de>>(value: Value) -> Result<T, ScimError> {
+    serde_json::from_value(value).map_err(|err| ScimError::InvalidValue(err.to_string()))
+}
+
+// 在查询后追加过滤条件
+fn push_filter(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    filter: Option<&str>,
+    attribute: &dyn Fn(&str) -> Option<Attribute>,
+) -> Result<(), ScimError> {
+    if let Some(filter) = filter {
+        let filter = scim_filter::parse_filter(filter)?;
+        builder.push(" AND ");
+        scim_filter::push_sql(&filter, attribute, builder)?;
+    }
+    Ok(())
+}
+
+// SCIM令牌存储实现，每个令牌属于一个组织，SCIM请求只能访问该组织的用户和分组
+pub struct ScimTokenStore;
+
+impl ScimTokenStore {
+    // 创建SCIM令牌，只保存摘要
+    pub async fn create(
+        pool: &PgPool,
+        organization_id: Uuid,
+        actor_id: Uuid,
+        req: &CreateScimTokenRequest,
+    ) -> Result<CreatedScimToken, sqlx::Error> {
+        let token = format!("{}{}", SCIM_TOKEN_PREFIX, generate_token());
+
+        let scim_token = sqlx::query_as::<_, ScimToken>(r#"
+            INSERT INTO scim_tokens (organization_id, name, token_hash)
+            VALUES ($1, $2, $3)
+            RETURNING id, name, created_at, last_used_at
+            "#)
+            .bind(organization_id)
+            .bind(&req.name)
+            .bind(hash_token(&token))
+            .fetch_one(pool)
+            .await?;
+
+        AuditStore::record(pool, Some(actor_id), None, "scim_token_created", Some(&req.name)).await?;
+
+        Ok(CreatedScimToken { scim_token, token })
+    }
+
+    // 获取组织的SCIM令牌
+    pub async fn find_by_organization(pool: &PgPool, organization_id: Uuid) -> Result<Vec<ScimToken>, sqlx::Error> {
+        let tokens = sqlx::query_as::<_, ScimToken>(r#"
+            SELECT id, name, created_at, last_used_at
+            FROM scim_tokens
+            WHERE organization_id = $1
+            ORDER BY created_at DESC
+            "#)
+            .bind(organization_id)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(tokens)
+    }
+
+    // 删除SCIM令牌，返回是否存在
+    pub async fn revoke(pool: &PgPool, organization_id: Uuid, actor_id: Uuid, token_id: Uuid) -> Result<bool, sqlx::Error> {
+        let name = sqlx::query_scalar::<_, String>(r#"
+            DELETE FROM scim_tokens
+            WHERE id = $1 AND organization_id = $2
+            RETURNING name
+            "#)
+            .bind(token_id)
+            .bind(organization_id)
+            .fetch_optional(pool)
+            .await?;
+
+        match name {
+            Some(name) => {
+                AuditStore::record(pool, Some(actor_id), None, "scim_token_revoked", Some(&name)).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // 校验SCIM令牌并记录使用时间，返回所属组织
+    pub async fn authenticate(pool: &PgPool, token: &str) -> Result<Option<Uuid>, sqlx::Error> {
+        sqlx::query_scalar::<_, Uuid>(r#"
+            UPDATE scim_tokens
+            SET last_used_at = CURRENT_TIMESTAMP
+            WHERE token_hash = $1
+            RETURNING organization_id
+            "#)
+            .bind(hash_token(token))
+            .fetch_optional(pool)
+            .await
+    }
+}
+
+// SCIM资源存储实现，把SCIM用户和分组映射到UserStore和GroupStore。
+// 需要在db::begin_tenant开启的租户事务中调用；已标记删除的用户对SCIM不可见
+pub struct ScimStore;
+
+impl ScimStore {
+    // 查询用户列表
+    pub async fn list_users(conn: &mut PgConnection, query: &ListQuery) -> Result<ListResponse<ScimUser>, ScimError> {
+        let (start_index, count) = query.page();
+
+        let mut builder = QueryBuilder::new("SELECT count(*) FROM users WHERE status <> ");
+        builder.push_bind(STATUS_DELETED);
+        push_filter(&mut builder, query.filter.as_deref(), &user_attribute)?;
+        let total_results = builder.build_query_scalar::<i64>().fetch_one(&mut *conn).await?;
+
+        let mut builder = QueryBuilder::new(format!("SELECT {} FROM users WHERE status <> ", USER_COLUMNS));
+        builder.push_bind(STATUS_DELETED);
+        push_filter(&mut builder, query.filter.as_deref(), &user_attribute)?;
+        builder.push(" ORDER BY created_at, id LIMIT ").push_bind(count).push(" OFFSET ").push_bind(start_index - 1);
+        let rows = builder.build_query_as::<UserRow>().fetch_all(&mut *conn).await?;
+
+        let resources: Vec<ScimUser> = rows.into_iter().map(ScimUser::from).collect();
+        Ok(ListResponse {
+            schemas: [SCHEMA_LIST_RESPONSE],
+            total_results,
+            start_index,
+            items_per_page: resources.len(),
+            resources,
+        })
+    }
+
+    async fn find_user_row(conn: &mut PgConnection, user_id: Uuid, lock: bool) -> Result<UserRow, ScimError> {
+        let row = sqlx::query_as::<_, UserRow>(&format!(
+            "SELECT {} FROM users WHERE id = $1 AND status <> $2{}",
+            USER_COLUMNS,
+            if lock { " FOR UPDATE" } else { "" }
+        ))
+        .bind(user_id)
+        .bind(STATUS_DELETED)
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or(ScimError::NotFound)?;
+
+        Ok(row)
+    }
+
+    // 获取单个用户
+    pub async fn get_user(conn: &mut PgConnection, user_id: Uuid) -> Result<ScimUser, ScimError> {
+        Ok(Self::find_user_row(conn, user_id, false).await?.into())
+    }
+
+    // 按active调整账号状态：停用对应suspended，恢复时按当前状态选择转换
+    async fn set_active(conn: &mut PgConnection, user_id: Uuid, status: &str, active: bool) -> Result<(), ScimError> {
+        let transition = match (active, status) {
+            (true, STATUS_PENDING) => Some(Transition::Activate),
+            (true, STATUS_SUSPENDED) => Some(Transition::Reactivate),
+            (true, STATUS_LOCKED) => Some(Transition::Unlock),
+            (false, STATUS_ACTIVE | STATUS_LOCKED) => Some(Transition::Suspend),
+            _ => None,
+        };
+        if let Some(transition) = transition {
+            UserStatusStore::apply(conn, None, user_id, transition, SCIM_REASON).await?;
+        }
+        Ok(())
+    }
+
+    async fn set_external_id(conn: &mut PgConnection, table: &str, id: Uuid, external_id: Option<&str>) -> Result<(), sqlx::Error> {
+        sqlx::query(&format!(
+            "UPDATE {} SET external_id = $1 WHERE id = $2 AND external_id IS DISTINCT FROM $1",
+            table
+        ))
+        .bind(external_id)
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    // userName不区分大小写，email列的唯一约束区分大小写，需要额外检查；
+    // 邮箱在所有组织中唯一，通过email_taken检查其他组织的用户，返回uniqueness而不是数据库错误
+    async fn ensure_unique_user_name(conn: &mut PgConnection, email: &str, user_id: Option<Uuid>) -> Result<(), ScimError> {
+        let exists = sqlx::query_scalar::<_, bool>("SELECT email_taken($1, $2)")
+        .bind(email)
+        .bind(user_id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        match exists {
+            true => Err(ScimError::Uniqueness("userName已存在")),
+            false => Ok(()),
+        }
+    }
+
+    // 同步的密码与其他入口一样要求最小长度
+    fn check_password(password: Option<&str>) -> Result<(), ScimError> {
+        match password {
+            Some(password) if password.chars().count() < MIN_PASSWORD_LENGTH => {
+                Err(ScimError::InvalidValue(format!("密码长度不能少于{}位", MIN_PASSWORD_LENGTH)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // 创建用户，未提供密码时生成随机密码，用户之后可以通过忘记密码设置
+    pub async fn create_user(conn: &mut PgConnection, organization_id: Uuid, input: ScimUserInput) -> Result<ScimUser, ScimError> {
+        Self::check_password(input.password.as_deref())?;
+        let email = changed_value(None, email_candidates(&input))
+            .ok_or_else(|| ScimError::InvalidValue("缺少userName".to_string()))?;
+        let name = changed_value(None, user_name_candidates(&input)).unwrap_or_else(|| email.clone());
+        Self::ensure_unique_user_name(conn, &email, None).await?;
+
+        let user = UserStore::create(&mut *conn, organization_id, &CreateUserRequest {
+            name,
+            email,
+            password: input.password.clone().unwrap_or_else(generate_token),
+            role: None,
+            group_ids: Vec::new(),
+        })
+        .await?;
+        Self::set_external_id(conn, "users", user.id, input.external_id.as_deref()).await?;
+        Self::set_active(conn, user.id, &user.status, input.active.unwrap_or(true)).await?;
+
+        Self::get_user(conn, user.id).await
+    }
+
+    async fn write_user(conn: &mut PgConnection, current: UserRow, input: ScimUserInput) -> Result<ScimUser, ScimError> {
+        Self::check_password(input.password.as_deref())?;
+        let email = changed_value(Some(&current.email), email_candidates(&input))
+            .ok_or_else(|| ScimError::InvalidValue("缺少userName".to_string()))?;
+        let name = changed_value(Some(&current.name), user_name_candidates(&input)).unwrap_or(current.name);
+        Self::ensure_unique_user_name(conn, &email, Some(current.id)).await?;
+
+        UserStore::update(&mut *conn, current.id, &UpdateUserRequest {
+            name: Some(name),
+            email: Some(email),
+            password: input.password.clone(),
+            role: None,
+            group_ids: None,
+        })
+        .await?;
+        Self::set_external_id(conn, "users", current.id, input.external_id.as_deref()).await?;
+        Self::set_active(conn, current.id, &current.status, input.active.unwrap_or(true)).await?;
+
+        Self::get_user(conn, current.id).await
+    }
+
+    // 替换用户（PUT），请求中未出现的可选属性会被清空
+    pub async fn replace_user(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        if_match: Option<&str>,
+        input: ScimUserInput,
+    ) -> Result<ScimUser, ScimError> {
+        let current = Self::find_user_row(conn, user_id, true).await?;
+        check_version(&ScimUser::from(current.clone()).meta.version, if_match)?;
+        Self::write_user(conn, current, input).await
+    }
+
+    // 修改用户（PATCH）：在当前资源上依次执行操作，再按替换写回
+    pub async fn patch_user(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        if_match: Option<&str>,
+        request: &PatchRequest,
+    ) -> Result<ScimUser, ScimError> {
+        let current = Self::find_user_row(conn, user_id, true).await?;
+        let resource = ScimUser::from(current.clone());
+        check_version(&resource.meta.version, if_match)?;
+
+        let input = from_json::<ScimUserInput>(apply_patch(&resource, request)?)?;
+        Self::write_user(conn, current, input).await
+    }
+
+    // 删除用户：标记为deleted并注销会话，保留审计记录
+    pub async fn delete_user(conn: &mut PgConnection, user_id: Uuid, if_match: Option<&str>) -> Result<(), ScimError> {
+        let current = Self::find_user_row(conn, user_id, true).await?;
+        check_version(&ScimUser::from(current).meta.version, if_match)?;
+        UserStatusStore::apply(conn, None, user_id, Transition::Delete, SCIM_REASON).await?;
+        Ok(())
+    }
+
+    async fn group_members(conn: &mut PgConnection, group_id: Uuid) -> Result<Vec<ScimMember>, sqlx::Error> {
+        let rows = sqlx::query_as::<_, (Uuid, String, bool)>(r#"
+            SELECT users.id, users.name, TRUE
+            FROM group_members m
+            JOIN users ON users.id = m.user_id
+            WHERE m.group_id = $1 AND users.status <> $2
+            UNION ALL
+            SELECT id, name, FALSE
+            FROM groups
+            WHERE parent_id = $1
+            "#)
+            .bind(group_id)
+            .bind(STATUS_DELETED)
+            .fetch_all(&mut *conn)
+            .await?;
+
+        let base_url = scim_base_url();
+        Ok(rows
+            .into_iter()
+            .map(|(id, display, is_user)| {
+                let (member_type, endpoint) = if is_user { ("User", "Users") } else { ("Group", "Groups") };
+                ScimMember {
+                    value: id,
+                    display,
+                    member_type,
+                    reference: format!("{}/{}/{}", base_url, endpoint, id),
+                }
+            })
+            .collect())
+    }
+
+    async fn to_scim_group(conn: &mut PgConnection, row: GroupRow) -> Result<ScimGroup, ScimError> {
+        let members = Self::group_members(conn, row.id).await?;
+        Ok(ScimGroup {
+            schemas: [SCHEMA_GROUP],
+            id: row.id,
+            external_id: row.external_id,
+            display_name: row.name,
+            members,
+            meta: Meta::new("Group", "Groups", row.id, row.created_at, row.updated_at),
+        })
+    }
+
+    // 查询分组列表
+    pub async fn list_groups(conn: &mut PgConnection, query: &ListQuery) -> Result<ListResponse<ScimGroup>, ScimError> {
+        let (start_index, count) = query.page();
+
+        let mut builder = QueryBuilder::new("SELECT count(*) FROM groups WHERE TRUE");
+        push_filter(&mut builder, query.filter.as_deref(), &group_attribute)?;
+        let total_results = builder.build_query_scalar::<i64>().fetch_one(&mut *conn).await?;
+
+        let mut builder = QueryBuilder::new(format!("SELECT {} FROM groups WHERE TRUE", GROUP_COLUMNS));
+        push_filter(&mut builder, query.filter.as_deref(), &group_attribute)?;
+        builder.push(" ORDER BY created_at, id LIMIT ").push_bind(count).push(" OFFSET ").push_bind(start_index - 1);
+        let rows = builder.build_query_as::<GroupRow>().fetch_all(&mut *conn).await?;
+
+        let mut resources = Vec::with_capacity(rows.len());
+        for row in rows {
+            resources.push(Self::to_scim_group(conn, row).await?);
+        }
+        Ok(ListResponse {
+            schemas: [SCHEMA_LIST_RESPONSE],
+            total_results,
+            start_index,
+            items_per_page: resources.len(),
+            resources,
+        })
+    }
+
+    async fn find_group_row(conn: &mut PgConnection, group_id: Uuid, lock: bool) -> Result<GroupRow, ScimError> {
+        let row = sqlx::query_as::<_, GroupRow>(&format!(
+            "SELECT {} FROM groups WHERE id = $1{}",
+            GROUP_COLUMNS,
+            if lock { " FOR UPDATE" } else { "" }
+        ))
+        .bind(group_id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or(ScimError::NotFound)?;
+
+        Ok(row)
+    }
+
+    // 获取单个分组
+    pub async fn get_group(conn: &mut PgConnection, group_id: Uuid) -> Result<ScimGroup, ScimError> {
+        let row = Self::find_group_row(conn, group_id, false).await?;
+        Self::to_scim_group(conn, row).await
+    }
+
+    // 按成员列表替换分组的用户成员和下级分组
+    async fn replace_members(conn: &mut PgConnection, group_id: Uuid, members: &[ScimMemberInput]) -> Result<(), ScimError> {
+        let ids = members
+            .iter()
+            .map(|member| member.value.parse::<Uuid>().map_err(|_| ScimError::InvalidValue(format!("成员不存在: {}", member.value))))
+            .collect::<Result<Vec<Uuid>, ScimError>>()?;
+
+        let user_ids = sqlx::query_scalar::<_, Uuid>("SELECT id FROM users WHERE id = ANY($1) AND status <> $2")
+            .bind(&ids)
+            .bind(STATUS_DELETED)
+            .fetch_all(&mut *conn)
+            .await?;
+        let child_ids = sqlx::query_scalar::<_, Uuid>("SELECT id FROM groups WHERE id = ANY($1)")
+            .bind(&ids)
+            .fetch_all(&mut *conn)
+            .await?;
+        if let Some(missing) = ids.iter().find(|id| !user_ids.contains(id) && !child_ids.contains(id)) {
+            return Err(ScimError::InvalidValue(format!("成员不存在: {}", missing)));
+        }
+
+        GroupStore::replace_members(&mut *conn, group_id, &user_ids).await?;
+
+        // 下级分组通过parent_id表示，移动时由GroupStore检查是否形成环
+        let current_children = sqlx::query_scalar::<_, Uuid>("SELECT id FROM groups WHERE parent_id = $1")
+            .bind(group_id)
+            .fetch_all(&mut *conn)
+            .await?;
+        for child_id in child_ids.iter().filter(|id| !current_children.contains(id)) {
+            let req = UpdateGroupRequest { name: None, description: None, parent_id: Some(Some(group_id)) };
+            GroupStore::update(&mut *conn, *child_id, &req).await?;
+        }
+        for child_id in current_children.iter().filter(|id| !child_ids.contains(id)) {
+            let req = UpdateGroupRequest { name: None, description: None, parent_id: Some(None) };
+            GroupStore::update(&mut *conn, *child_id, &req).await?;
+        }
+
+        // 成员变化也要更新版本
+        sqlx::query("UPDATE groups SET updated_at = CURRENT_TIMESTAMP WHERE id = $1")
+            .bind(group_id)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    // 创建分组
+    pub async fn create_group(conn: &mut PgConnection, organization_id: Uuid, input: ScimGroupInput) -> Result<ScimGroup, ScimError> {
+        let name = input.display_name.clone().ok_or_else(|| ScimError::InvalidValue("缺少displayName".to_string()))?;
+
+        let group = GroupStore::create(&mut *conn, organization_id, &CreateGroupRequest {
+            name,
+            description: None,
+            parent_id: None,
+        })
+        .await?;
+        Self::set_external_id(conn, "groups", group.id, input.external_id.as_deref()).await?;
+        Self::replace_members(conn, group.id, &input.members).await?;
+
+        Self::get_group(conn, group.id).await
+    }
+
+    async fn write_group(conn: &mut PgConnection, group_id: Uuid, input: ScimGroupInput) -> Result<ScimGroup, ScimError> {
+        let name = input.display_name.clone().ok_or_else(|| ScimError::InvalidValue("缺少displayName".to_string()))?;
+
+        let req = UpdateGroupRequest { name: Some(name), description: None, parent_id: None };
+        GroupStore::update(&mut *conn, group_id, &req).await?;
+        Self::set_external_id(conn, "groups", group_id, input.external_id.as_deref()).await?;
+        Self::replace_members(conn, group_id, &input.members).await?;
+
+        Self::get_group(conn, group_id).await
+    }
+
+    // 替换分组（PUT）
+    pub async fn replace_group(
+        conn: &mut PgConnection,
+        group_id: Uuid,
+        if_match: Option<&str>,
+        input: ScimGroupInput,
+    ) -> Result<ScimGroup, ScimError> {
+        let row = Self::find_group_row(conn, group_id, true).await?;
+        let current = Self::to_scim_group(conn, row).await?;
+        check_version(&current.meta.version, if_match)?;
+        Self::write_group(conn, group_id, input).await
+    }
+
+    // 修改分组（PATCH），常用于增删成员
+    pub async fn patch_group(
+        conn: &mut PgConnection,
+        group_id: Uuid,
+        if_match: Option<&str>,
+        request: &PatchRequest,
+    ) -> Result<ScimGroup, ScimError> {
+        let row = Self::find_group_row(conn, group_id, true).await?;
+        let current = Self::to_scim_group(conn, row).await?;
+        check_version(&current.meta.version, if_match)?;
+
+        let input = from_json::<ScimGroupInput>(apply_patch(&current, request)?)?;
+        Self::write_group(conn, group_id, input).await
+    }
+
+    // 删除分组，下级分组移动到顶层
+    pub async fn delete_group(conn: &mut PgConnection, group_id: Uuid, if_match: Option<&str>) -> Result<(), ScimError> {
+        let row = Self::find_group_row(conn, group_id, true).await?;
+        check_version(&Meta::new("Group", "Groups", row.id, row.created_at, row.updated_at).version, if_match)?;
+        GroupStore::delete(conn, group_id).await?;
+        Ok(())
+    }
+}
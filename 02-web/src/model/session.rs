@@ -0,0 +1,233 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgExecutor, PgPool};
+use uuid::Uuid;
+use chrono::{DateTime, Duration, Utc};
+use crate::token::{generate_token, hash_token};
+
+// 会话绝对有效期（小时）
+pub const SESSION_TTL_HOURS: i64 = 24;
+
+// 会话空闲超时（分钟），超过该时间未使用的会话失效
+const SESSION_IDLE_MINUTES: i64 = 30;
+
+// 浏览器会话Cookie，以及供页面脚本读取的CSRF令牌Cookie
+pub const SESSION_COOKIE: &str = "session";
+pub const CSRF_COOKIE: &str = "csrf_token";
+
+// 使用Cookie会话发起修改类请求时，需要在该请求头中携带CSRF令牌
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+// 登录请求
+#[derive(Debug, Deserialize)]
+pub struct LoginRequest {
+    pub email: String,
+    pub password: String,
+}
+
+// 登录响应，需要两步验证时只返回mfa_token
+#[derive(Debug, Default, Serialize)]
+pub struct LoginResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub csrf_token: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub mfa_required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mfa_token: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub mfa_enrollment_required: bool,
+}
+
+// 发起登录的客户端信息，用于展示会话的设备和IP
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+// 已验证的会话
+#[derive(Debug, Clone, FromRow)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub mfa_enrollment_required: bool,
+    pub csrf_token: String,
+}
+
+// 会话列表项
+#[derive(Debug, Serialize, FromRow)]
+pub struct SessionInfo {
+    pub id: Uuid,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    #[sqlx(skip)]
+    pub current: bool,
+}
+
+// 会话存储实现
+pub struct SessionStore;
+
+impl SessionStore {
+    // 创建会话，返回明文令牌、CSRF令牌和过期时间
+    pub async fn create(
+        pool: &PgPool,
+        user_id: Uuid,
+        mfa_enrollment_required: bool,
+        client: &ClientInfo,
+    ) -> Result<LoginResponse, sqlx::Error> {
+        let token = generate_token();
+        let csrf_token = generate_token();
+        let expires_at = Utc::now() + Duration::hours(SESSION_TTL_HOURS);
+
+        sqlx::query(r#"
+            INSERT INTO sessions (user_id, token_hash, csrf_token, mfa_enrollment_required, ip_address, user_agent, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#)
+            .bind(user_id)
+            .bind(hash_token(&token))
+            .bind(&csrf_token)
+            .bind(mfa_enrollment_required)
+            .bind(&client.ip_address)
+            .bind(&client.user_agent)
+            .bind(expires_at)
+            .execute(pool)
+            .await?;
+
+        Ok(LoginResponse {
+            token: Some(token),
+            expires_at: Some(expires_at),
+            csrf_token: Some(csrf_token),
+            mfa_enrollment_required,
+            ..Default::default()
+        })
+    }
+
+    // 根据令牌查找有效会话：同时检查绝对过期和空闲超时，并刷新最近使用时间
+    pub async fn find_by_token(pool: &PgPool, token: &str) -> Result<Option<Session>, sqlx::Error> {
+        let session = sqlx::query_as::<_, Session>(r#"
+            UPDATE sessions
+            SET last_seen_at = CURRENT_TIMESTAMP
+            WHERE token_hash = $1
+              AND revoked_at IS NULL
+              AND expires_at > CURRENT_TIMESTAMP
+              AND last_seen_at > CURRENT_TIMESTAMP - make_interval(mins => $2)
+            RETURNING id, user_id, mfa_enrollment_required, csrf_token
+            "#)
+            .bind(hash_token(token))
+            .bind(SESSION_IDLE_MINUTES as i32)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(session)
+    }
+
+    // 获取用户当前有效的会话
+    pub async fn find_active_by_user(pool: &PgPool, user_id: Uuid) -> Result<Vec<SessionInfo>, sqlx::Error> {
+        let sessions = sqlx::query_as::<_, SessionInfo>(r#"
+            SELECT id, ip_address, user_agent, created_at, last_seen_at, expires_at
+            FROM sessions
+            WHERE user_id = $1
+              AND revoked_at IS NULL
+              AND expires_at > CURRENT_TIMESTAMP
+              AND last_seen_at > CURRENT_TIMESTAMP - make_interval(mins => $2)
+            ORDER BY last_seen_at DESC
+            "#)
+            .bind(user_id)
+            .bind(SESSION_IDLE_MINUTES as i32)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(sessions)
+    }
+
+    // 根据令牌注销会话，用于登录时轮换旧的会话Cookie
+    pub async fn revoke_by_token(pool: &PgPool, token: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            UPDATE sessions
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE token_hash = $1 AND revoked_at IS NULL
+            "#)
+            .bind(hash_token(token))
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // 注销用户自己的某个会话，返回是否存在
+    pub async fn revoke_for_user(pool: &PgPool, user_id: Uuid, session_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(r#"
+            UPDATE sessions
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+            "#)
+            .bind(session_id)
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // 注销用户除当前会话以外的全部会话
+    pub async fn revoke_others(pool: &PgPool, user_id: Uuid, current_session_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            UPDATE sessions
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND id <> $2 AND revoked_at IS NULL
+            "#)
+            .bind(user_id)
+            .bind(current_session_id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // 用户完成两步验证绑定后解除会话限制
+    pub async fn clear_mfa_enrollment(pool: &PgPool, user_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            UPDATE sessions
+            SET mfa_enrollment_required = FALSE
+            WHERE user_id = $1 AND revoked_at IS NULL
+            "#)
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // 注销单个会话
+    pub async fn revoke(pool: &PgPool, session_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP WHERE id = $1")
+            .bind(session_id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // 注销用户的全部会话，可在事务中调用
+    pub async fn revoke_all_for_user<'e, E: PgExecutor<'e>>(
+        executor: E,
+        user_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            UPDATE sessions
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND revoked_at IS NULL
+            "#)
+            .bind(user_id)
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+}
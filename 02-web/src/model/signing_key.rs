@@ -0,0 +1,164 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use openssl::rsa::Rsa;
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+use chrono::{DateTime, Duration, Utc};
+use thiserror::Error;
+
+// 签名密钥最长使用时间（天），超过后自动轮换
+const KEY_MAX_AGE_DAYS: i64 = 30;
+
+// JWKS中保留的密钥数量，轮换后旧密钥仍可用于验证已签发的令牌
+const PUBLISHED_KEY_COUNT: i64 = 3;
+
+// 签名密钥错误类型
+#[derive(Error, Debug)]
+pub enum SigningKeyError {
+    #[error("没有可用的签名密钥")]
+    NoActiveKey,
+    #[error("未知的签名密钥")]
+    UnknownKey,
+    #[error("密钥生成失败: {0}")]
+    OpenSsl(#[from] openssl::error::ErrorStack),
+    #[error("JWT签名失败: {0}")]
+    Jwt(#[from] jsonwebtoken::errors::Error),
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+// 签名密钥
+#[derive(Debug, FromRow)]
+pub struct SigningKey {
+    pub kid: String,
+    pub private_key_pem: String,
+    pub modulus: String,
+    pub exponent: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// JWKS中的单个公钥
+#[derive(Debug, Serialize)]
+pub struct Jwk {
+    pub kty: &'static str,
+    #[serde(rename = "use")]
+    pub key_use: &'static str,
+    pub alg: &'static str,
+    pub kid: String,
+    pub n: String,
+    pub e: String,
+}
+
+// JWKS响应
+#[derive(Debug, Serialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+// 签名密钥存储实现
+pub struct SigningKeyStore;
+
+impl SigningKeyStore {
+    // 生成新的RS256密钥并设为当前签名密钥
+    pub async fn rotate(pool: &PgPool) -> Result<SigningKey, SigningKeyError> {
+        let rsa = Rsa::generate(2048)?;
+        let private_key_pem = String::from_utf8_lossy(&rsa.private_key_to_pem()?).to_string();
+        let modulus = URL_SAFE_NO_PAD.encode(rsa.n().to_vec());
+        let exponent = URL_SAFE_NO_PAD.encode(rsa.e().to_vec());
+
+        let key = sqlx::query_as::<_, SigningKey>(r#"
+            INSERT INTO signing_keys (kid, private_key_pem, modulus, exponent)
+            VALUES ($1, $2, $3, $4)
+            RETURNING kid, private_key_pem, modulus, exponent, created_at
+            "#)
+            .bind(Uuid::new_v4().to_string())
+            .bind(private_key_pem)
+            .bind(modulus)
+            .bind(exponent)
+            .fetch_one(pool)
+            .await?;
+
+        tracing::info!(kid = %key.kid, "已生成新的签名密钥");
+
+        Ok(key)
+    }
+
+    // 当前用于签名的密钥（最新生成的一个）
+    pub async fn active(pool: &PgPool) -> Result<SigningKey, SigningKeyError> {
+        let key = sqlx::query_as::<_, SigningKey>(r#"
+            SELECT kid, private_key_pem, modulus, exponent, created_at
+            FROM signing_keys
+            ORDER BY created_at DESC
+            LIMIT 1
+            "#)
+            .fetch_optional(pool)
+            .await?;
+
+        key.ok_or(SigningKeyError::NoActiveKey)
+    }
+
+    // 没有密钥或当前密钥过旧时轮换
+    pub async fn rotate_if_due(pool: &PgPool) -> Result<(), SigningKeyError> {
+        match Self::active(pool).await {
+            Ok(key) if key.created_at > Utc::now() - Duration::days(KEY_MAX_AGE_DAYS) => Ok(()),
+            Ok(_) | Err(SigningKeyError::NoActiveKey) => Self::rotate(pool).await.map(|_| ()),
+            Err(err) => Err(err),
+        }
+    }
+
+    // 对外公布的公钥集合
+    pub async fn jwks(pool: &PgPool) -> Result<JwkSet, SigningKeyError> {
+        let keys = sqlx::query_as::<_, SigningKey>(r#"
+            SELECT kid, private_key_pem, modulus, exponent, created_at
+            FROM signing_keys
+            ORDER BY created_at DESC
+            LIMIT $1
+            "#)
+            .bind(PUBLISHED_KEY_COUNT)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(JwkSet {
+            keys: keys
+                .into_iter()
+                .map(|key| Jwk {
+                    kty: "RSA",
+                    key_use: "sig",
+                    alg: "RS256",
+                    kid: key.kid,
+                    n: key.modulus,
+                    e: key.exponent,
+                })
+                .collect(),
+        })
+    }
+
+    // 使用当前密钥签发JWT
+    pub async fn sign<T: Serialize>(pool: &PgPool, claims: &T) -> Result<String, SigningKeyError> {
+        let key = Self::active(pool).await?;
+
+        let mut header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256);
+        header.kid = Some(key.kid);
+        let encoding_key = jsonwebtoken::EncodingKey::from_rsa_pem(key.private_key_pem.as_bytes())?;
+
+        Ok(jsonwebtoken::encode(&header, claims, &encoding_key)?)
+    }
+
+    // 使用JWKS中仍公布的密钥校验本服务签发的JWT，validation指定受众等要求
+    pub async fn verify<T: DeserializeOwned>(
+        pool: &PgPool,
+        token: &str,
+        validation: &jsonwebtoken::Validation,
+    ) -> Result<T, SigningKeyError> {
+        let kid = jsonwebtoken::decode_header(token)?.kid.ok_or(SigningKeyError::UnknownKey)?;
+        let key = Self::jwks(pool)
+            .await?
+            .keys
+            .into_iter()
+            .find(|key| key.kid == kid)
+            .ok_or(SigningKeyError::UnknownKey)?;
+
+        let decoding_key = jsonwebtoken::DecodingKey::from_rsa_components(&key.n, &key.e)?;
+        Ok(jsonwebtoken::decode::<T>(token, &decoding_key, validation)?.claims)
+    }
+}
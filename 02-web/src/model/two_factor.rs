@@ -0,0 +1,404 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+use chrono::{Duration, Utc};
+use rand::Rng;
+use thiserror::Error;
+use totp_rs::{Algorithm, Secret, TOTP};
+use crate::model::audit::AuditStore;
+use crate::token::{generate_token, hash_token};
+
+// TOTP参数：6位数字，30秒步长，允许前后各1个步长的时钟偏差
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP: u64 = 30;
+const TOTP_SKEW: i64 = 1;
+
+// 恢复码数量
+const RECOVERY_CODE_COUNT: usize = 10;
+
+// 登录二次验证的有效期（分钟）和最大尝试次数
+const MFA_CHALLENGE_TTL_MINUTES: i64 = 5;
+const MFA_CHALLENGE_MAX_ATTEMPTS: i32 = 5;
+
+// 两步验证错误类型
+#[derive(Error, Debug)]
+pub enum TwoFactorError {
+    #[error("已启用两步验证")]
+    AlreadyEnabled,
+    #[error("未启用两步验证")]
+    NotEnabled,
+    #[error("验证码无效")]
+    InvalidCode,
+    #[error("当前角色要求启用两步验证")]
+    RequiredByRole,
+    #[error("TOTP错误: {0}")]
+    Totp(String),
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+// 绑定响应
+#[derive(Debug, Serialize)]
+pub struct EnrollResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+    // Base64编码的PNG二维码
+    pub qr_code: String,
+}
+
+// 验证码请求
+#[derive(Debug, Deserialize)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+// 确认绑定响应，恢复码只返回这一次
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+// 登录二次验证请求，验证码和恢复码二选一
+#[derive(Debug, Deserialize)]
+pub struct LoginTotpRequest {
+    pub mfa_token: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+// 角色的两步验证策略
+#[derive(Debug, Serialize, FromRow)]
+pub struct RoleMfaPolicy {
+    pub role: String,
+    pub require_totp: bool,
+}
+
+// 设置角色策略请求
+#[derive(Debug, Deserialize)]
+pub struct RoleMfaPolicyRequest {
+    pub require_totp: bool,
+}
+
+// 用户的TOTP配置
+#[derive(Debug, FromRow)]
+struct UserTotp {
+    secret: String,
+    enabled: bool,
+    last_used_step: Option<i64>,
+}
+
+// 两步验证存储实现
+pub struct TwoFactorStore;
+
+impl TwoFactorStore {
+    // 生成新的密钥，确认前不会生效
+    pub async fn enroll(pool: &PgPool, user_id: Uuid, email: &str) -> Result<EnrollResponse, TwoFactorError> {
+        if Self::is_enabled(pool, user_id).await? {
+            return Err(TwoFactorError::AlreadyEnabled);
+        }
+
+        let secret = match Secret::generate_secret().to_encoded() {
+            Secret::Encoded(secret) => secret,
+            Secret::Raw(_) => unreachable!(),
+        };
+        let totp = build_totp(&secret, email)?;
+        let qr_code = totp.get_qr_base64().map_err(TwoFactorError::Totp)?;
+
+        sqlx::query(r#"
+            INSERT INTO user_totp (user_id, secret)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE
+            SET secret = EXCLUDED.secret, enabled_at = NULL, last_used_step = NULL
+            "#)
+            .bind(user_id)
+            .bind(&secret)
+            .execute(pool)
+            .await?;
+
+        Ok(EnrollResponse {
+            otpauth_uri: totp.get_url(),
+            secret,
+            qr_code,
+        })
+    }
+
+    // 使用第一个验证码确认绑定，返回恢复码
+    pub async fn confirm(
+        pool: &PgPool,
+        user_id: Uuid,
+        email: &str,
+        code: &str,
+    ) -> Result<Vec<String>, TwoFactorError> {
+        let totp = Self::find(pool, user_id).await?.ok_or(TwoFactorError::NotEnabled)?;
+        if totp.enabled {
+            return Err(TwoFactorError::AlreadyEnabled);
+        }
+        let step = match_step(&totp, email, code)?;
+
+        let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| generate_recovery_code()).collect();
+
+        let mut tx = pool.begin().await?;
+
+        sqlx::query(r#"
+            UPDATE user_totp
+            SET enabled_at = CURRENT_TIMESTAMP, last_used_step = $2
+            WHERE user_id = $1
+            "#)
+            .bind(user_id)
+            .bind(step)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        for code in &recovery_codes {
+            sqlx::query("INSERT INTO recovery_codes (user_id, code_hash) VALUES ($1, $2)")
+                .bind(user_id)
+                .bind(hash_token(code))
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        AuditStore::record(&mut *tx, Some(user_id), Some(user_id), "2fa_enabled", None).await?;
+
+        tx.commit().await?;
+
+        Ok(recovery_codes)
+    }
+
+    // 是否已启用两步验证
+    pub async fn is_enabled(pool: &PgPool, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        let enabled: Option<bool> = sqlx::query_scalar(
+            "SELECT enabled_at IS NOT NULL FROM user_totp WHERE user_id = $1",
+        )
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(enabled.unwrap_or(false))
+    }
+
+    // 校验验证码，同一个时间步长内的验证码不能重复使用
+    pub async fn verify(pool: &PgPool, user_id: Uuid, email: &str, code: &str) -> Result<(), TwoFactorError> {
+        let totp = Self::find(pool, user_id).await?
+            .filter(|totp| totp.enabled)
+            .ok_or(TwoFactorError::NotEnabled)?;
+        let step = match_step(&totp, email, code)?;
+
+        let result = sqlx::query(r#"
+            UPDATE user_totp
+            SET last_used_step = $2
+            WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)
+            "#)
+            .bind(user_id)
+            .bind(step)
+            .execute(pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(TwoFactorError::InvalidCode);
+        }
+
+        Ok(())
+    }
+
+    // 使用一次性恢复码
+    pub async fn use_recovery_code(pool: &PgPool, user_id: Uuid, code: &str) -> Result<(), TwoFactorError> {
+        let result = sqlx::query(r#"
+            UPDATE recovery_codes
+            SET used_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+            "#)
+            .bind(user_id)
+            .bind(hash_token(code.trim()))
+            .execute(pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(TwoFactorError::InvalidCode);
+        }
+
+        AuditStore::record(pool, Some(user_id), Some(user_id), "2fa_recovery_code_used", None).await?;
+
+        Ok(())
+    }
+
+    // 关闭两步验证，需要提供当前有效的验证码
+    pub async fn disable(
+        pool: &PgPool,
+        user_id: Uuid,
+        role: &str,
+        email: &str,
+        code: &str,
+    ) -> Result<(), TwoFactorError> {
+        if Self::role_requires_totp(pool, role).await? {
+            return Err(TwoFactorError::RequiredByRole);
+        }
+        Self::verify(pool, user_id, email, code).await?;
+
+        let mut tx = pool.begin().await?;
+
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM user_totp WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        AuditStore::record(&mut *tx, Some(user_id), Some(user_id), "2fa_disabled", None).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    // 创建登录二次验证，返回明文令牌
+    pub async fn create_challenge(pool: &PgPool, user_id: Uuid) -> Result<String, sqlx::Error> {
+        let token = generate_token();
+        let expires_at = Utc::now() + Duration::minutes(MFA_CHALLENGE_TTL_MINUTES);
+
+        sqlx::query(r#"
+            INSERT INTO mfa_challenges (user_id, token_hash, expires_at)
+            VALUES ($1, $2, $3)
+            "#)
+            .bind(user_id)
+            .bind(hash_token(&token))
+            .bind(expires_at)
+            .execute(pool)
+            .await?;
+
+        Ok(token)
+    }
+
+    // 查找有效的二次验证，并计入一次尝试
+    pub async fn attempt_challenge(pool: &PgPool, token: &str) -> Result<Option<Uuid>, sqlx::Error> {
+        let user_id = sqlx::query_scalar(r#"
+            UPDATE mfa_challenges
+            SET attempts = attempts + 1
+            WHERE token_hash = $1
+              AND expires_at > CURRENT_TIMESTAMP
+              AND attempts < $2
+            RETURNING user_id
+            "#)
+            .bind(hash_token(token))
+            .bind(MFA_CHALLENGE_MAX_ATTEMPTS)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(user_id)
+    }
+
+    // 二次验证通过后删除
+    pub async fn complete_challenge(pool: &PgPool, token: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM mfa_challenges WHERE token_hash = $1")
+            .bind(hash_token(token))
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // 角色是否强制要求两步验证
+    pub async fn role_requires_totp(pool: &PgPool, role: &str) -> Result<bool, sqlx::Error> {
+        let required: Option<bool> = sqlx::query_scalar(
+            "SELECT require_totp FROM role_mfa_policies WHERE role = $1",
+        )
+            .bind(role)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(required.unwrap_or(false))
+    }
+
+    // 获取所有角色策略
+    pub async fn list_role_policies(pool: &PgPool) -> Result<Vec<RoleMfaPolicy>, sqlx::Error> {
+        let policies = sqlx::query_as::<_, RoleMfaPolicy>(r#"
+            SELECT role, require_totp
+            FROM role_mfa_policies
+            ORDER BY role
+            "#)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(policies)
+    }
+
+    // 设置角色策略
+    pub async fn set_role_policy(
+        pool: &PgPool,
+        actor_id: Uuid,
+        role: &str,
+        require_totp: bool,
+    ) -> Result<RoleMfaPolicy, sqlx::Error> {
+        let policy = sqlx::query_as::<_, RoleMfaPolicy>(r#"
+            INSERT INTO role_mfa_policies (role, require_totp)
+            VALUES ($1, $2)
+            ON CONFLICT (role) DO UPDATE SET require_totp = EXCLUDED.require_totp
+            RETURNING role, require_totp
+            "#)
+            .bind(role)
+            .bind(require_totp)
+            .fetch_one(pool)
+            .await?;
+
+        let detail = format!("role={} require_totp={}", role, require_totp);
+        AuditStore::record(pool, Some(actor_id), None, "2fa_role_policy_changed", Some(&detail)).await?;
+
+        Ok(policy)
+    }
+
+    async fn find(pool: &PgPool, user_id: Uuid) -> Result<Option<UserTotp>, sqlx::Error> {
+        let totp = sqlx::query_as::<_, UserTotp>(r#"
+            SELECT secret, enabled_at IS NOT NULL AS enabled, last_used_step
+            FROM user_totp
+            WHERE user_id = $1
+            "#)
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(totp)
+    }
+}
+
+fn build_totp(secret: &str, email: &str) -> Result<TOTP, TwoFactorError> {
+    let issuer = std::env::var("TOTP_ISSUER").unwrap_or_else(|_| "UserCRUD".to_string());
+    let bytes = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|err| TwoFactorError::Totp(format!("{:?}", err)))?;
+
+    TOTP::new(Algorithm::SHA1, TOTP_DIGITS, TOTP_SKEW as u8, TOTP_STEP, bytes, Some(issuer), email.to_string())
+        .map_err(|err| TwoFactorError::Totp(err.to_string()))
+}
+
+// 在允许的时钟偏差内查找验证码对应的时间步长，已使用过的步长视为无效
+fn match_step(totp: &UserTotp, email: &str, code: &str) -> Result<i64, TwoFactorError> {
+    let generator = build_totp(&totp.secret, email)?;
+    let current = Utc::now().timestamp() / TOTP_STEP as i64;
+
+    (current - TOTP_SKEW..=current + TOTP_SKEW)
+        .filter(|step| totp.last_used_step.is_none_or(|last| *step > last))
+        .find(|step| code_matches(&generator.generate((*step as u64) * TOTP_STEP), code.trim()))
+        .ok_or(TwoFactorError::InvalidCode)
+}
+
+// 以固定时间比较验证码，避免通过响应时间逐位猜测
+fn code_matches(expected: &str, code: &str) -> bool {
+    expected.len() == code.len() && openssl::memcmp::eq(expected.as_bytes(), code.as_bytes())
+}
+
+// 生成形如 abcd-efgh 的恢复码
+fn generate_recovery_code() -> String {
+    const CHARSET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+    let mut rng = rand::thread_rng();
+    let mut part = || -> String {
+        (0..4).map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char).collect()
+    };
+    format!("{}-{}", part(), part())
+}
@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgExecutor, PgPool};
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+
+// 事件类型，与触发器写入的值一致
+pub const EVENT_CREATED: &str = "created";
+pub const EVENT_UPDATED: &str = "updated";
+pub const EVENT_DELETED: &str = "deleted";
+
+// 每次从事件表补发的最多条数
+const BACKFILL_BATCH: i64 = 500;
+
+// 事件保留的天数，可通过USER_EVENT_RETENTION_DAYS配置
+pub fn retention_days() -> i64 {
+    std::env::var("USER_EVENT_RETENTION_DAYS").ok().and_then(|v| v.parse().ok()).unwrap_or(7)
+}
+
+// 用户事件错误类型
+#[derive(Error, Debug)]
+pub enum UserEventError {
+    #[error("无效的事件类型: {0}")]
+    InvalidType(String),
+    #[error("无效的Last-Event-ID")]
+    InvalidLastEventId,
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+// 用户变更事件，id是全局递增的事件序号，只包含对外可见的字段
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct UserEvent {
+    pub id: i64,
+    #[serde(skip_serializing)]
+    pub tx_id: i64,
+    #[serde(skip_serializing)]
+    pub organization_id: Uuid,
+    pub user_id: Uuid,
+    pub event_type: String,
+    pub name: String,
+    pub email: String,
+    pub role: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl UserEvent {
+    pub fn position(&self) -> EventPosition {
+        EventPosition { tx_id: self.tx_id, id: self.id }
+    }
+}
+
+// 事件在推送顺序中的位置：先按写入事件的事务，再按事件序号排序
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EventPosition {
+    pub tx_id: i64,
+    pub id: i64,
+}
+
+// 订阅参数：types为逗号分隔的事件类型，last_event_id用于无法设置请求头的WebSocket客户端
+#[derive(Debug, Deserialize)]
+pub struct UserEventQuery {
+    pub user_id: Option<Uuid>,
+    pub types: Option<String>,
+    pub last_event_id: Option<i64>,
+}
+
+// 订阅的过滤条件，未指定的条件不过滤
+#[derive(Debug, Clone, Default)]
+pub struct UserEventFilter {
+    pub user_id: Option<Uuid>,
+    pub types: Option<Vec<String>>,
+}
+
+impl UserEventFilter {
+    pub fn parse(query: &UserEventQuery) -> Result<Self, UserEventError> {
+        let types = match &query.types {
+            Some(types) => {
+                let types: Vec<String> = types
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|t| !t.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                if let Some(invalid) = types.iter().find(|t| ![EVENT_CREATED, EVENT_UPDATED, EVENT_DELETED].contains(&t.as_str())) {
+                    return Err(UserEventError::InvalidType(invalid.clone()));
+                }
+                Some(types)
+            }
+            None => None,
+        };
+        Ok(UserEventFilter { user_id: query.user_id, types })
+    }
+
+    pub fn matches(&self, event: &UserEvent) -> bool {
+        self.user_id.is_none_or(|user_id| event.user_id == user_id)
+            && self.types.as_ref().is_none_or(|types| types.contains(&event.event_type))
+    }
+}
+
+const EVENT_COLUMNS: &str = "id, tx_id, organization_id, user_id, event_type, name, email, role, status, created_at";
+
+// 比所有未结束事务都早的事务ID，写入事务小于它的事件都已提交或回滚，不会再有新的事件排到它们之间
+const SETTLED_BEFORE: &str = "pg_snapshot_xmin(pg_current_snapshot())::text::bigint";
+
+// 用户事件存储实现
+// 事件由users表上的触发器写入，这里只负责按推送顺序读取和清理
+pub struct UserEventStore;
+
+impl UserEventStore {
+    // 当前已确定的位置：之后读取到的事件都排在它之后
+    pub async fn head<'e, E: PgExecutor<'e>>(executor: E) -> Result<EventPosition, sqlx::Error> {
+        let tx_id = sqlx::query_scalar::<_, i64>(&format!("SELECT {} - 1", SETTLED_BEFORE))
+            .fetch_one(executor)
+            .await?;
+        Ok(EventPosition { tx_id, id: i64::MAX })
+    }
+
+    // 客户端按Last-Event-ID恢复时的起点，需要在租户事务中调用。
+    // 事件已被清理或不属于当前组织时，从序号更大的事件中最早的事务开始，可能重复推送但不会遗漏
+    pub async fn resume_position(conn: &mut PgConnection, last_event_id: i64) -> Result<EventPosition, sqlx::Error> {
+        let tx_id = sqlx::query_scalar::<_, i64>("SELECT tx_id FROM user_events WHERE id = $1")
+            .bind(last_event_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+        if let Some(tx_id) = tx_id {
+            return Ok(EventPosition { tx_id, id: last_event_id });
+        }
+
+        let tx_id = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT LEAST(MIN(tx_id), {}) - 1 FROM user_events WHERE id > $1",
+            SETTLED_BEFORE
+        ))
+        .bind(last_event_id)
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(EventPosition { tx_id, id: i64::MAX })
+    }
+
+    // 所有组织中排在after之后、已确定的一批事件，用于各实例按顺序广播
+    pub async fn after<'e, E: PgExecutor<'e>>(executor: E, after: EventPosition) -> Result<Vec<UserEvent>, sqlx::Error> {
+        sqlx::query_as::<_, UserEvent>(&format!(r#"
+            SELECT {}
+            FROM user_events
+            WHERE (tx_id, id) > ($1, $2) AND tx_id < {}
+            ORDER BY tx_id, id
+            LIMIT $3
+            "#, EVENT_COLUMNS, SETTLED_BEFORE))
+            .bind(after.tx_id)
+            .bind(after.id)
+            .bind(BACKFILL_BATCH)
+            .fetch_all(executor)
+            .await
+    }
+
+    // 当前组织中排在after之后、已确定且符合过滤条件的一批事件，需要在租户事务中调用
+    pub async fn after_filtered(
+        conn: &mut PgConnection,
+        after: EventPosition,
+        filter: &UserEventFilter,
+    ) -> Result<Vec<UserEvent>, sqlx::Error> {
+        sqlx::query_as::<_, UserEvent>(&format!(r#"
+            SELECT {}
+            FROM user_events
+            WHERE (tx_id, id) > ($1, $2)
+              AND tx_id < {}
+              AND ($3::uuid IS NULL OR user_id = $3)
+              AND ($4::text[] IS NULL OR event_type = ANY($4))
+            ORDER BY tx_id, id
+            LIMIT $5
+            "#, EVENT_COLUMNS, SETTLED_BEFORE))
+            .bind(after.tx_id)
+            .bind(after.id)
+            .bind(filter.user_id)
+            .bind(&filter.types)
+            .bind(BACKFILL_BATCH)
+            .fetch_all(&mut *conn)
+            .await
+    }
+
+    // 删除超过保留天数的事件，更早的Last-Event-ID只能从仍保留的事件开始补发
+    pub async fn prune(pool: &PgPool, retention_days: i64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM user_events WHERE created_at < CURRENT_TIMESTAMP - make_interval(days => $1::int)")
+            .bind(retention_days)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
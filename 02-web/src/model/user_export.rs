@@ -0,0 +1,432 @@
+use std::io::Write;
+use std::sync::{Arc, OnceLock};
+use flate2::{write::GzEncoder, Compression};
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::{FromRow, Postgres, Transaction};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use crate::model::{STATUSES, STATUS_DELETED};
+
+// 每次从游标读取的行数
+const FETCH_SIZE: i64 = 1000;
+
+// 已编码但客户端尚未读取的数据块上限，客户端读取慢时游标随之暂停
+const PENDING_CHUNKS: usize = 4;
+
+// 单次读取游标的超时时间（毫秒）
+const STATEMENT_TIMEOUT_MS: u64 = 30_000;
+
+// 客户端停止读取后事务保持空闲的最长时间（毫秒），超时后数据库断开连接，导出中断
+const IDLE_TIMEOUT_MS: u64 = 60_000;
+
+// 可导出的列，不包含密码哈希；未指定columns时按此顺序导出全部列
+pub const EXPORT_COLUMNS: [&str; 9] = [
+    "id", "name", "email", "role", "status", "organization_id", "external_id", "created_at", "updated_at",
+];
+
+// 同时进行的导出数量上限，每个导出在结束前一直占用一个数据库连接，由USER_EXPORT_MAX_CONCURRENT配置
+fn max_concurrent_exports() -> usize {
+    std::env::var("USER_EXPORT_MAX_CONCURRENT").ok().and_then(|v| v.parse().ok()).unwrap_or(2)
+}
+
+// 占用一个导出名额，名额用完时返回None；名额在导出结束或客户端断开时释放
+pub fn try_acquire_permit() -> Option<OwnedSemaphorePermit> {
+    static PERMITS: OnceLock<Arc<Semaphore>> = OnceLock::new();
+    PERMITS
+        .get_or_init(|| Arc::new(Semaphore::new(max_concurrent_exports())))
+        .clone()
+        .try_acquire_owned()
+        .ok()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UserExportError {
+    #[error("无效的导出格式: {0}，可选csv或ndjson")]
+    InvalidFormat(String),
+    #[error("无效的导出列: {0}")]
+    InvalidColumn(String),
+    #[error("至少需要导出一列")]
+    NoColumns,
+    #[error("无效的账号状态")]
+    InvalidStatus,
+    #[error("写入导出数据失败: {0}")]
+    Encode(#[from] std::io::Error),
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+// 导出参数，status与用户列表的过滤条件相同；columns为逗号分隔的列名
+#[derive(Debug, Deserialize)]
+pub struct UserExportQuery {
+    pub format: Option<String>,
+    pub status: Option<String>,
+    pub columns: Option<String>,
+    #[serde(default)]
+    pub gzip: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Ndjson,
+}
+
+#[derive(Debug, FromRow)]
+struct ExportRow {
+    id: Uuid,
+    name: String,
+    email: String,
+    role: String,
+    status: String,
+    organization_id: Uuid,
+    external_id: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl ExportRow {
+    fn value(&self, column: &str) -> Value {
+        match column {
+            "id" => Value::String(self.id.to_string()),
+            "name" => Value::String(self.name.clone()),
+            "email" => Value::String(self.email.clone()),
+            "role" => Value::String(self.role.clone()),
+            "status" => Value::String(self.status.clone()),
+            "organization_id" => Value::String(self.organization_id.to_string()),
+            "external_id" => self.external_id.clone().map_or(Value::Null, Value::String),
+            "created_at" => Value::String(self.created_at.to_rfc3339()),
+            "updated_at" => Value::String(self.updated_at.to_rfc3339()),
+            _ => Value::Null,
+        }
+    }
+}
+
+// 输出端：不压缩时原样输出，压缩时每批数据写入gzip流后取出已压缩的部分
+enum Sink {
+    Plain,
+    Gzip(GzEncoder<Vec<u8>>),
+}
+
+impl Sink {
+    fn chunk(&mut self, data: Vec<u8>) -> std::io::Result<Vec<u8>> {
+        match self {
+            Sink::Plain => Ok(data),
+            Sink::Gzip(encoder) => {
+                encoder.write_all(&data)?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+        }
+    }
+
+    fn finish(self) -> std::io::Result<Vec<u8>> {
+        match self {
+            Sink::Plain => Ok(Vec::new()),
+            Sink::Gzip(encoder) => encoder.finish(),
+        }
+    }
+}
+
+// 一次用户导出
+#[derive(Debug)]
+pub struct UserExport {
+    pub format: ExportFormat,
+    pub gzip: bool,
+    columns: Vec<&'static str>,
+    status: Option<String>,
+}
+
+impl UserExport {
+    pub fn parse(query: &UserExportQuery) -> Result<Self, UserExportError> {
+        let format = match query.format.as_deref().unwrap_or("csv") {
+            "csv" => ExportFormat::Csv,
+            "ndjson" => ExportFormat::Ndjson,
+            other => return Err(UserExportError::InvalidFormat(other.to_string())),
+        };
+
+        if let Some(status) = &query.status {
+            if !STATUSES.contains(&status.as_str()) {
+                return Err(UserExportError::InvalidStatus);
+            }
+        }
+
+        let columns = match &query.columns {
+            None => EXPORT_COLUMNS.to_vec(),
+            Some(columns) => {
+                let mut selected = Vec::new();
+                for name in columns.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                    let column = EXPORT_COLUMNS
+                        .into_iter()
+                        .find(|column| *column == name)
+                        .ok_or_else(|| UserExportError::InvalidColumn(name.to_string()))?;
+                    if !selected.contains(&column) {
+                        selected.push(column);
+                    }
+                }
+                selected
+            }
+        };
+        if columns.is_empty() {
+            return Err(UserExportError::NoColumns);
+        }
+
+        Ok(UserExport { format, gzip: query.gzip, columns, status: query.status.clone() })
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match (self.gzip, self.format) {
+            (true, _) => "application/gzip",
+            (false, ExportFormat::Csv) => "text/csv; charset=utf-8",
+            (false, ExportFormat::Ndjson) => "application/x-ndjson",
+        }
+    }
+
+    pub fn file_name(&self) -> String {
+        let extension = match self.format {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        };
+        if self.gzip {
+            format!("users.{}.gz", extension)
+        } else {
+            format!("users.{}", extension)
+        }
+    }
+
+    // 在租户事务中声明游标，按批读取用户并编码，数据块依次发送到返回的通道，结束后释放导出名额。
+    // 通道容量有限，内存占用与导出的行数无关；客户端断开后停止读取，事务随之回滚。
+    // 读取或编码失败时发送错误并结束，响应会被中断而不是看起来完整
+    pub fn stream(
+        self,
+        tx: Transaction<'static, Postgres>,
+        permit: OwnedSemaphorePermit,
+    ) -> mpsc::Receiver<Result<Vec<u8>, UserExportError>> {
+        let (sender, receiver) = mpsc::channel(PENDING_CHUNKS);
+        tokio::spawn(async move {
+            if let Err(err) = self.write_all(tx, &sender).await {
+                tracing::error!("导出用户失败: {}", err);
+                let _ = sender.send(Err(err)).await;
+            }
+            drop(permit);
+        });
+        receiver
+    }
+
+    async fn write_all(
+        &self,
+        mut tx: Transaction<'static, Postgres>,
+        sender: &mpsc::Sender<Result<Vec<u8>, UserExportError>>,
+    ) -> Result<(), UserExportError> {
+        let mut sink = if self.gzip {
+            Sink::Gzip(GzEncoder::new(Vec::new(), Compression::default()))
+        } else {
+            Sink::Plain
+        };
+
+        // 导出期间一直占用连接，限制每次读取的时间和客户端不读取时事务的空闲时间
+        sqlx::query(&format!("SET LOCAL statement_timeout = {}", STATEMENT_TIMEOUT_MS))
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&format!("SET LOCAL idle_in_transaction_session_timeout = {}", IDLE_TIMEOUT_MS))
+            .execute(&mut *tx)
+            .await?;
+
+        // 游标只在事务内有效，整个导出读取的是同一个快照
+        sqlx::query(r#"
+            DECLARE user_export NO SCROLL CURSOR FOR
+            SELECT id, name, email, role, status, organization_id, external_id, created_at, updated_at
+            FROM users
+            WHERE CASE WHEN $1::TEXT IS NULL THEN status <> $2 ELSE status = $1 END
+            ORDER BY created_at DESC, id
+            "#)
+            .bind(&self.status)
+            .bind(STATUS_DELETED)
+            .execute(&mut *tx)
+            .await?;
+
+        let mut pending = match self.format {
+            ExportFormat::Csv => self.encode_csv(None)?,
+            ExportFormat::Ndjson => Vec::new(),
+        };
+        loop {
+            let rows = sqlx::query_as::<_, ExportRow>(&format!("FETCH {} FROM user_export", FETCH_SIZE))
+                .fetch_all(&mut *tx)
+                .await?;
+            if rows.is_empty() {
+                break;
+            }
+
+            match self.format {
+                ExportFormat::Csv => pending.extend(self.encode_csv(Some(&rows))?),
+                ExportFormat::Ndjson => pending.extend(self.encode_ndjson(&rows)?),
+            }
+            let chunk = sink.chunk(std::mem::take(&mut pending))?;
+            if !chunk.is_empty() && sender.send(Ok(chunk)).await.is_err() {
+                return Ok(());
+            }
+        }
+
+        let mut chunk = sink.chunk(pending)?;
+        chunk.extend(sink.finish()?);
+        if !chunk.is_empty() {
+            let _ = sender.send(Ok(chunk)).await;
+        }
+        Ok(())
+    }
+
+    // rows为None时输出表头
+    fn encode_csv(&self, rows: Option<&[ExportRow]>) -> Result<Vec<u8>, UserExportError> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        match rows {
+            None => writer.write_record(&self.columns).map_err(std::io::Error::from)?,
+            Some(rows) => {
+                for row in rows {
+                    let record = self.columns.iter().map(|column| match row.value(column) {
+                        Value::String(value) => value,
+                        Value::Null => String::new(),
+                        value => value.to_string(),
+                    });
+                    writer.write_record(record).map_err(std::io::Error::from)?;
+                }
+            }
+        }
+        Ok(writer.into_inner().map_err(|err| err.into_error())?)
+    }
+
+    // 按选择的列顺序输出对象的字段
+    fn encode_ndjson(&self, rows: &[ExportRow]) -> Result<Vec<u8>, UserExportError> {
+        let mut data = Vec::new();
+        for row in rows {
+            data.push(b'{');
+            for (index, column) in self.columns.iter().enumerate() {
+                if index > 0 {
+                    data.push(b',');
+                }
+                serde_json::to_writer(&mut data, column).map_err(std::io::Error::from)?;
+                data.push(b':');
+                serde_json::to_writer(&mut data, &row.value(column)).map_err(std::io::Error::from)?;
+            }
+            data.extend_from_slice(b"}\n");
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use chrono::{TimeZone, Utc};
+    use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+    use serde_json::Value;
+    use uuid::Uuid;
+    use super::*;
+
+    fn export(columns: Option<&str>) -> UserExport {
+        UserExport::parse(&UserExportQuery {
+            format: None,
+            status: None,
+            columns: columns.map(str::to_string),
+            gzip: false,
+        })
+        .unwrap()
+    }
+
+    fn row(name: &str, external_id: Option<&str>) -> ExportRow {
+        let at = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        ExportRow {
+            id: Uuid::nil(),
+            name: name.to_string(),
+            email: "zhangsan@example.com".to_string(),
+            role: "user".to_string(),
+            status: "active".to_string(),
+            organization_id: Uuid::nil(),
+            external_id: external_id.map(str::to_string),
+            created_at: at,
+            updated_at: at,
+        }
+    }
+
+    #[test]
+    fn parse_validates_query() {
+        let parse = |format: Option<&str>, status: Option<&str>, columns: Option<&str>| {
+            UserExport::parse(&UserExportQuery {
+                format: format.map(str::to_string),
+                status: status.map(str::to_string),
+                columns: columns.map(str::to_string),
+                gzip: false,
+            })
+        };
+
+        let default = parse(None, None, None).unwrap();
+        assert_eq!(default.format, ExportFormat::Csv);
+        assert_eq!(default.columns, EXPORT_COLUMNS);
+        assert_eq!(parse(None, None, Some(" email, name ,email,")).unwrap().columns, ["email", "name"]);
+
+        assert!(matches!(parse(Some("xml"), None, None), Err(UserExportError::InvalidFormat(format)) if format == "xml"));
+        assert!(matches!(parse(None, None, Some("name,password")), Err(UserExportError::InvalidColumn(column)) if column == "password"));
+        assert!(matches!(parse(None, None, Some(" , ")), Err(UserExportError::NoColumns)));
+        assert!(matches!(parse(None, Some("unknown"), None), Err(UserExportError::InvalidStatus)));
+    }
+
+    #[test]
+    fn content_type_and_file_name() {
+        let mut export = export(None);
+        assert_eq!((export.content_type(), export.file_name().as_str()), ("text/csv; charset=utf-8", "users.csv"));
+        export.format = ExportFormat::Ndjson;
+        assert_eq!((export.content_type(), export.file_name().as_str()), ("application/x-ndjson", "users.ndjson"));
+        export.gzip = true;
+        assert_eq!((export.content_type(), export.file_name().as_str()), ("application/gzip", "users.ndjson.gz"));
+    }
+
+    // 表头按选择的列顺序输出，含逗号、引号和换行的值加引号转义，空值输出为空字段
+    #[test]
+    fn csv_quotes_values_and_keeps_column_order() {
+        let export = export(Some("name,external_id,created_at"));
+        let header = export.encode_csv(None).unwrap();
+        assert_eq!(String::from_utf8(header).unwrap(), "name,external_id,created_at\n");
+
+        let rows = [row("张三", None), row("李, \"四\"\n", Some("ext-1"))];
+        let body = String::from_utf8(export.encode_csv(Some(&rows)).unwrap()).unwrap();
+        assert_eq!(body, "张三,,2024-01-02T03:04:05+00:00\n\"李, \"\"四\"\"\n\",ext-1,2024-01-02T03:04:05+00:00\n");
+
+        let mut reader = csv::ReaderBuilder::new().has_headers(false).from_reader(body.as_bytes());
+        let records: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(&records[1][0], "李, \"四\"\n");
+    }
+
+    // 每行一个对象，字段顺序与选择的列相同，空值为null
+    #[test]
+    fn ndjson_writes_one_object_per_line() {
+        let export = export(Some("email,external_id,name"));
+        let rows = [row("张三", None), row("line\n\"quoted\"", Some("ext-1"))];
+        let body = String::from_utf8(export.encode_ndjson(&rows).unwrap()).unwrap();
+
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], r#"{"email":"zhangsan@example.com","external_id":null,"name":"张三"}"#);
+        let second: Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(second["name"], "line\n\"quoted\"");
+        assert_eq!(second["external_id"], "ext-1");
+        assert!(export.encode_ndjson(&[]).unwrap().is_empty());
+    }
+
+    // 分块取出的压缩数据拼接后是完整的gzip流
+    #[test]
+    fn gzip_chunks_form_one_stream() {
+        let mut sink = Sink::Gzip(GzEncoder::new(Vec::new(), Compression::default()));
+        let mut compressed = Vec::new();
+        for part in ["name\n", "张三\n", "李四\n"] {
+            compressed.extend(sink.chunk(part.as_bytes().to_vec()).unwrap());
+        }
+        compressed.extend(sink.finish().unwrap());
+
+        let mut decoded = String::new();
+        GzDecoder::new(compressed.as_slice()).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, "name\n张三\n李四\n");
+
+        let mut plain = Sink::Plain;
+        assert_eq!(plain.chunk(b"abc".to_vec()).unwrap(), b"abc");
+        assert!(plain.finish().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,204 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{FromRow, PgConnection};
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+use crate::model::{audit::AuditStore, UpdateUserRequest, User, UserError, UserRepository};
+
+// 版本的操作类型，与触发器写入的值一致
+pub const OPERATION_DELETE: &str = "delete";
+
+// 用户历史错误类型
+#[derive(Error, Debug)]
+pub enum UserHistoryError {
+    #[error("用户不存在")]
+    NotFound,
+    #[error("版本不存在")]
+    VersionNotFound,
+    #[error("不能恢复到删除时的版本")]
+    DeletedVersion,
+    #[error("{0}")]
+    User(UserError),
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl From<UserError> for UserHistoryError {
+    fn from(err: UserError) -> Self {
+        match err {
+            UserError::NotFound => UserHistoryError::NotFound,
+            UserError::Database(err) => UserHistoryError::Database(err),
+            err => UserHistoryError::User(err),
+        }
+    }
+}
+
+// 用户的一个版本，valid_from为该版本开始生效的时间
+#[derive(Debug, Serialize, FromRow)]
+pub struct UserVersion {
+    pub version: i32,
+    pub operation: String,
+    pub name: String,
+    pub email: String,
+    pub role: String,
+    pub status: String,
+    pub external_id: Option<String>,
+    pub password_changed_at: DateTime<Utc>,
+    pub valid_from: DateTime<Utc>,
+}
+
+// 与上一版本相比变化的字段，密码只记录修改时间
+#[derive(Debug, Serialize)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub from: Value,
+    pub to: Value,
+}
+
+// 历史记录中的一项，第一个版本的changes包含所有字段
+#[derive(Debug, Serialize)]
+pub struct UserHistoryEntry {
+    #[serde(flatten)]
+    pub version: UserVersion,
+    pub changes: Vec<FieldChange>,
+}
+
+// 查询单个用户时的时间点参数
+#[derive(Debug, Deserialize)]
+pub struct AsOfQuery {
+    pub as_of: Option<DateTime<Utc>>,
+}
+
+// 恢复用户请求
+#[derive(Debug, Deserialize)]
+pub struct RevertUserRequest {
+    pub version: i32,
+}
+
+fn fields(version: &UserVersion) -> [(&'static str, Value); 6] {
+    [
+        ("name", json!(version.name)),
+        ("email", json!(version.email)),
+        ("role", json!(version.role)),
+        ("status", json!(version.status)),
+        ("external_id", json!(version.external_id)),
+        ("password_changed_at", json!(version.password_changed_at)),
+    ]
+}
+
+fn diff(previous: Option<&UserVersion>, current: &UserVersion) -> Vec<FieldChange> {
+    let before = previous.map(fields);
+    fields(current)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, (field, to))| {
+            let from = before.as_ref().map(|before| before[i].1.clone()).unwrap_or(Value::Null);
+            (from != to).then_some(FieldChange { field, from, to })
+        })
+        .collect()
+}
+
+// 用户历史存储实现
+// 版本由users表上的触发器写入，这里只负责查询和恢复；需要在租户事务中调用
+pub struct UserHistoryStore;
+
+impl UserHistoryStore {
+    // 获取用户的全部版本及字段级差异，最新的版本在前；用户被删除后仍可查询
+    pub async fn history(conn: &mut PgConnection, user_id: Uuid) -> Result<Vec<UserHistoryEntry>, UserHistoryError> {
+        let versions = sqlx::query_as::<_, UserVersion>(r#"
+            SELECT version, operation, name, email, role, status, external_id, password_changed_at, valid_from
+            FROM user_versions
+            WHERE user_id = $1
+            ORDER BY version
+            "#)
+            .bind(user_id)
+            .fetch_all(&mut *conn)
+            .await?;
+        if versions.is_empty() {
+            return Err(UserHistoryError::NotFound);
+        }
+
+        let changes: Vec<Vec<FieldChange>> = versions
+            .iter()
+            .enumerate()
+            .map(|(i, version)| diff(i.checked_sub(1).map(|p| &versions[p]), version))
+            .collect();
+        Ok(versions
+            .into_iter()
+            .zip(changes)
+            .rev()
+            .map(|(version, changes)| UserHistoryEntry { version, changes })
+            .collect())
+    }
+
+    // 获取用户在某一时间点的状态，当时尚未创建或已被删除时返回None
+    pub async fn as_of(conn: &mut PgConnection, user_id: Uuid, at: DateTime<Utc>) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as::<_, User>(r#"
+            SELECT user_id AS id, name, email, '' AS password, role, status, organization_id,
+                   created_at, valid_from AS updated_at
+            FROM (
+                SELECT *
+                FROM user_versions
+                WHERE user_id = $1 AND valid_from <= $2
+                ORDER BY version DESC
+                LIMIT 1
+            ) latest
+            WHERE operation <> $3
+            "#)
+            .bind(user_id)
+            .bind(at)
+            .bind(OPERATION_DELETE)
+            .fetch_optional(&mut *conn)
+            .await
+    }
+
+    // 把用户的姓名、邮箱、角色和外部ID恢复为指定版本的值，恢复本身会生成一个新版本。
+    // 姓名、邮箱和角色通过users写入，启用事件溯源时恢复同样记录到事件流；
+    // 账号状态需要通过状态转换接口修改，不在恢复范围内
+    pub async fn revert(
+        conn: &mut PgConnection,
+        users: &dyn UserRepository,
+        actor_id: Uuid,
+        user_id: Uuid,
+        req: &RevertUserRequest,
+    ) -> Result<User, UserHistoryError> {
+        let target = sqlx::query_as::<_, UserVersion>(r#"
+            SELECT version, operation, name, email, role, status, external_id, password_changed_at, valid_from
+            FROM user_versions
+            WHERE user_id = $1 AND version = $2
+            "#)
+            .bind(user_id)
+            .bind(req.version)
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or(UserHistoryError::VersionNotFound)?;
+        if target.operation == OPERATION_DELETE {
+            return Err(UserHistoryError::DeletedVersion);
+        }
+
+        users.update(&mut *conn, user_id, &UpdateUserRequest {
+            name: Some(target.name.clone()),
+            email: Some(target.email.clone()),
+            password: None,
+            role: Some(target.role.clone()),
+            group_ids: None,
+        })
+        .await?;
+        let user = sqlx::query_as::<_, User>(r#"
+            UPDATE users
+            SET external_id = $1
+            WHERE id = $2
+            RETURNING id, name, email, password, role, status, organization_id, created_at, updated_at
+            "#)
+            .bind(&target.external_id)
+            .bind(user_id)
+            .fetch_one(&mut *conn)
+            .await?;
+
+        let details = format!("恢复到版本{}", req.version);
+        AuditStore::record(&mut *conn, Some(actor_id), Some(user_id), "user_reverted", Some(&details)).await?;
+
+        Ok(user)
+    }
+}
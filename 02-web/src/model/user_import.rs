@@ -0,0 +1,1077 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgConnection, PgPool};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use crate::db;
+use crate::model::{
+    audit::AuditStore,
+    outbox::{DomainEvent, OutboxStore},
+    password_reset::MIN_PASSWORD_LENGTH,
+    User, ROLES, ROLE_USER,
+};
+use crate::name_pinyin::transliterate;
+use crate::password::hash_password;
+
+// 可映射的用户字段，email必填；name未提供时新用户使用邮箱作为姓名
+pub const IMPORT_FIELDS: [&str; 5] = ["email", "name", "role", "password", "external_id"];
+
+// 邮箱已存在时的处理方式：跳过、更新已有用户，或作为该行的错误
+pub const ON_DUPLICATE_SKIP: &str = "skip";
+pub const ON_DUPLICATE_UPDATE: &str = "update";
+pub const ON_DUPLICATE_FAIL: &str = "fail";
+pub const ON_DUPLICATE: [&str; 3] = [ON_DUPLICATE_SKIP, ON_DUPLICATE_UPDATE, ON_DUPLICATE_FAIL];
+
+// 导入任务状态
+pub const IMPORT_PENDING: &str = "pending";
+pub const IMPORT_RUNNING: &str = "running";
+pub const IMPORT_COMPLETED: &str = "completed";
+pub const IMPORT_FAILED: &str = "failed";
+
+// 上传文件的大小上限
+pub const MAX_IMPORT_BYTES: usize = 100 * 1024 * 1024;
+
+// 每批读取、校验并写入的行数，每批结束后更新一次进度
+const BATCH_SIZE: usize = 1000;
+
+// 未提供密码的新用户保存的密码，不是有效的Argon2哈希，任何密码都无法通过校验，用户需要通过忘记密码设置。
+// 与逐行计算随机密码的哈希相比，大批量导入时省去了大部分计算
+const UNUSABLE_PASSWORD: &str = "!";
+
+// 错误报告每次读取的行数
+const REPORT_PAGE_SIZE: i64 = 1000;
+
+// 与users表的字段长度一致
+const MAX_NAME_LEN: usize = 100;
+const MAX_EMAIL_LEN: usize = 100;
+const MAX_EXTERNAL_ID_LEN: usize = 255;
+
+#[derive(Debug, thiserror::Error)]
+pub enum UserImportError {
+    #[error("导入任务不存在")]
+    NotFound,
+    #[error("无效的导入格式: {0}，可选csv或ndjson")]
+    InvalidFormat(String),
+    #[error("无效的重复处理方式: {0}，可选skip、update或fail")]
+    InvalidOnDuplicate(String),
+    #[error("无效的列映射: {0}")]
+    InvalidMapping(String),
+    #[error("文件缺少列: {0}")]
+    MissingColumn(String),
+    #[error("导入文件不能超过{}MB", MAX_IMPORT_BYTES / 1024 / 1024)]
+    TooLarge,
+    #[error("读取导入文件失败: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("密码加密失败")]
+    PasswordHash,
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl From<csv::Error> for UserImportError {
+    fn from(err: csv::Error) -> Self {
+        UserImportError::Io(err.into())
+    }
+}
+
+impl From<tokio::task::JoinError> for UserImportError {
+    fn from(err: tokio::task::JoinError) -> Self {
+        UserImportError::Io(std::io::Error::other(err))
+    }
+}
+
+// 导入参数。mapping为逗号分隔的 字段:列名，例如 email:E-mail,name:姓名，未映射的字段按同名的列读取
+#[derive(Debug, Deserialize)]
+pub struct UserImportQuery {
+    pub format: Option<String>,
+    pub on_duplicate: Option<String>,
+    #[serde(default)]
+    pub dry_run: bool,
+    pub mapping: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    Csv,
+    Ndjson,
+}
+
+impl ImportFormat {
+    pub fn parse(format: &str) -> Result<Self, UserImportError> {
+        match format {
+            "csv" => Ok(ImportFormat::Csv),
+            "ndjson" => Ok(ImportFormat::Ndjson),
+            other => Err(UserImportError::InvalidFormat(other.to_string())),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            ImportFormat::Csv => "csv",
+            ImportFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    pub format: ImportFormat,
+    pub on_duplicate: &'static str,
+    pub dry_run: bool,
+    // 字段到列名（NDJSON为键名）的映射
+    pub mapping: HashMap<&'static str, String>,
+}
+
+impl ImportOptions {
+    pub fn parse(format: &str, on_duplicate: Option<&str>, dry_run: bool, mapping: Option<&str>) -> Result<Self, UserImportError> {
+        let format = ImportFormat::parse(format)?;
+        let on_duplicate = match on_duplicate {
+            None => ON_DUPLICATE_SKIP,
+            Some(value) => ON_DUPLICATE
+                .into_iter()
+                .find(|option| *option == value)
+                .ok_or_else(|| UserImportError::InvalidOnDuplicate(value.to_string()))?,
+        };
+
+        let mut fields = HashMap::new();
+        for pair in mapping.unwrap_or_default().split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (field, column) = pair
+                .split_once(':')
+                .map(|(field, column)| (field.trim(), column.trim()))
+                .filter(|(_, column)| !column.is_empty())
+                .ok_or_else(|| UserImportError::InvalidMapping(pair.to_string()))?;
+            let field = IMPORT_FIELDS
+                .into_iter()
+                .find(|candidate| *candidate == field)
+                .ok_or_else(|| UserImportError::InvalidMapping(pair.to_string()))?;
+            fields.insert(field, column.to_string());
+        }
+
+        Ok(ImportOptions { format, on_duplicate, dry_run, mapping: fields })
+    }
+
+    fn column(&self, field: &'static str) -> &str {
+        self.mapping.get(field).map_or(field, String::as_str)
+    }
+}
+
+// 导入任务，counts在每批处理后更新；dry_run的任务只统计结果，不会写入用户
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct UserImport {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub created_by: Option<Uuid>,
+    pub format: String,
+    pub on_duplicate: String,
+    pub dry_run: bool,
+    pub status: String,
+    pub total_rows: i64,
+    pub processed_rows: i64,
+    pub created_count: i64,
+    pub updated_count: i64,
+    pub skipped_count: i64,
+    pub error_count: i64,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+// 某一行的错误，row为文件中的行号（CSV的表头为第1行）
+#[derive(Debug, Clone, FromRow)]
+pub struct ImportRowError {
+    pub row_number: i64,
+    pub email: Option<String>,
+    pub message: String,
+}
+
+impl ImportRowError {
+    fn new(row_number: i64, email: Option<String>, message: impl Into<String>) -> Self {
+        ImportRowError { row_number, email, message: message.into() }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ImportCounts {
+    processed: i64,
+    created: i64,
+    updated: i64,
+    skipped: i64,
+    errors: i64,
+}
+
+// 从文件中读出、按映射取出字段后的一行，字段值已去除首尾空白，空值不出现
+struct RawRow {
+    row_number: i64,
+    fields: Result<HashMap<&'static str, String>, String>,
+}
+
+// 通过校验的一行
+struct ImportRow {
+    row_number: i64,
+    email: String,
+    name: Option<String>,
+    role: Option<String>,
+    password: Option<String>,
+    external_id: Option<String>,
+}
+
+fn valid_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !domain.contains('@')
+                && !email.chars().any(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+fn validate(row_number: i64, mut fields: HashMap<&'static str, String>) -> Result<ImportRow, ImportRowError> {
+    let email = fields.remove("email");
+    let error = |message: String| ImportRowError::new(row_number, email.clone(), message);
+
+    let Some(address) = email.clone() else {
+        return Err(error("缺少邮箱".to_string()));
+    };
+    if address.chars().count() > MAX_EMAIL_LEN {
+        return Err(error(format!("邮箱不能超过{}个字符", MAX_EMAIL_LEN)));
+    }
+    if !valid_email(&address) {
+        return Err(error("邮箱格式无效".to_string()));
+    }
+
+    let name = fields.remove("name");
+    if name.as_ref().is_some_and(|name| name.chars().count() > MAX_NAME_LEN) {
+        return Err(error(format!("姓名不能超过{}个字符", MAX_NAME_LEN)));
+    }
+    let role = fields.remove("role");
+    if let Some(role) = &role {
+        if !ROLES.contains(&role.as_str()) {
+            return Err(error(format!("无效的角色: {}", role)));
+        }
+    }
+    let password = fields.remove("password");
+    if password.as_ref().is_some_and(|password| password.chars().count() < MIN_PASSWORD_LENGTH) {
+        return Err(error(format!("密码长度不能少于{}位", MIN_PASSWORD_LENGTH)));
+    }
+    let external_id = fields.remove("external_id");
+    if external_id.as_ref().is_some_and(|id| id.chars().count() > MAX_EXTERNAL_ID_LEN) {
+        return Err(error(format!("外部ID不能超过{}个字符", MAX_EXTERNAL_ID_LEN)));
+    }
+
+    Ok(ImportRow { row_number, email: address, name, role, password, external_id })
+}
+
+// 按批读取导入文件。读取是阻塞的，在spawn_blocking中调用
+enum RowReader {
+    Csv {
+        reader: csv::Reader<File>,
+        // 字段及其所在列的下标
+        columns: Vec<(&'static str, usize)>,
+    },
+    Ndjson {
+        reader: BufReader<File>,
+        line: i64,
+        keys: Vec<(&'static str, String)>,
+    },
+}
+
+impl RowReader {
+    // 打开文件并按映射定位各字段所在的列，CSV缺少email列或显式映射的列时返回MissingColumn
+    fn open(path: &Path, options: &ImportOptions) -> Result<Self, UserImportError> {
+        match options.format {
+            ImportFormat::Csv => {
+                let mut reader = csv::ReaderBuilder::new().flexible(true).from_path(path)?;
+                let headers = reader.headers()?.clone();
+                let mut columns = Vec::new();
+                for field in IMPORT_FIELDS {
+                    let column = options.column(field);
+                    let index = headers
+                        .iter()
+                        .position(|header| header.trim_start_matches('\u{feff}').trim().eq_ignore_ascii_case(column));
+                    match index {
+                        Some(index) => columns.push((field, index)),
+                        None if field == "email" || options.mapping.contains_key(field) => {
+                            return Err(UserImportError::MissingColumn(column.to_string()))
+                        }
+                        None => {}
+                    }
+                }
+                Ok(RowReader::Csv { reader, columns })
+            }
+            ImportFormat::Ndjson => Ok(RowReader::Ndjson {
+                reader: BufReader::new(File::open(path)?),
+                line: 0,
+                keys: IMPORT_FIELDS.into_iter().map(|field| (field, options.column(field).to_string())).collect(),
+            }),
+        }
+    }
+
+    // 统计数据行数，用于显示进度
+    fn count(path: &Path, format: ImportFormat) -> Result<i64, UserImportError> {
+        match format {
+            ImportFormat::Csv => {
+                let mut reader = csv::ReaderBuilder::new().flexible(true).from_path(path)?;
+                Ok(reader.byte_records().count() as i64)
+            }
+            ImportFormat::Ndjson => {
+                let mut count = 0;
+                for line in BufReader::new(File::open(path)?).split(b'\n') {
+                    if !line?.trim_ascii().is_empty() {
+                        count += 1;
+                    }
+                }
+                Ok(count)
+            }
+        }
+    }
+
+    // 读取最多size行，返回空时表示已读完；单行的格式错误作为该行的错误返回
+    fn next_batch(&mut self, size: usize) -> Result<Vec<RawRow>, UserImportError> {
+        let mut rows = Vec::with_capacity(size);
+        match self {
+            RowReader::Csv { reader, columns } => {
+                let mut record = csv::StringRecord::new();
+                while rows.len() < size {
+                    match reader.read_record(&mut record) {
+                        Ok(false) => break,
+                        Ok(true) => {
+                            let fields = columns
+                                .iter()
+                                .filter_map(|(field, index)| {
+                                    let value = record.get(*index)?.trim();
+                                    (!value.is_empty()).then(|| (*field, value.to_string()))
+                                })
+                                .collect();
+                            let row_number = record.position().map_or(0, |position| position.line() as i64);
+                            rows.push(RawRow { row_number, fields: Ok(fields) });
+                        }
+                        Err(err) if matches!(err.kind(), csv::ErrorKind::Io(_)) => return Err(err.into()),
+                        Err(err) => {
+                            let row_number = err.position().map_or(0, |position| position.line() as i64);
+                            rows.push(RawRow { row_number, fields: Err(format!("无法解析该行: {}", err)) });
+                        }
+                    }
+                }
+            }
+            RowReader::Ndjson { reader, line, keys } => {
+                let mut buffer = Vec::new();
+                while rows.len() < size {
+                    buffer.clear();
+                    if reader.read_until(b'\n', &mut buffer)? == 0 {
+                        break;
+                    }
+                    *line += 1;
+                    if buffer.trim_ascii().is_empty() {
+                        continue;
+                    }
+                    rows.push(RawRow { row_number: *line, fields: parse_json_line(&buffer, keys) });
+                }
+            }
+        }
+        Ok(rows)
+    }
+}
+
+fn parse_json_line(line: &[u8], keys: &[(&'static str, String)]) -> Result<HashMap<&'static str, String>, String> {
+    let value: Value = serde_json::from_slice(line).map_err(|err| format!("不是有效的JSON: {}", err))?;
+    let Value::Object(object) = value else {
+        return Err("每行必须是一个JSON对象".to_string());
+    };
+
+    let mut fields = HashMap::new();
+    for (field, key) in keys {
+        let value = match object.get(key) {
+            None | Some(Value::Null) => continue,
+            Some(Value::String(value)) => value.trim().to_string(),
+            Some(value @ (Value::Number(_) | Value::Bool(_))) => value.to_string(),
+            Some(_) => return Err(format!("字段{}必须是字符串", key)),
+        };
+        if !value.is_empty() {
+            fields.insert(*field, value);
+        }
+    }
+    Ok(fields)
+}
+
+// 待写入临时表的一行，is_update表示更新已有用户
+struct StagedRow {
+    row: ImportRow,
+    is_update: bool,
+}
+
+impl StagedRow {
+    // 只计算提供了密码的行；新用户未提供密码时插入UNUSABLE_PASSWORD，更新时未提供密码则保留原密码
+    fn password_hash(&self) -> Result<Option<String>, UserImportError> {
+        match &self.row.password {
+            Some(password) => hash_password(password).map(Some).map_err(|_| UserImportError::PasswordHash),
+            None => Ok(None),
+        }
+    }
+}
+
+// 计算密码哈希和姓名拼音，编码为COPY使用的CSV数据，空字段即NULL。
+// Argon2较慢，按CPU核数分组并行计算；试运行不会写入数据，不计算哈希
+fn encode_staged(rows: &[StagedRow], dry_run: bool) -> Result<Vec<u8>, UserImportError> {
+    let hashes: Vec<Option<String>> = if dry_run || rows.iter().all(|staged| staged.row.password.is_none()) {
+        vec![None; rows.len()]
+    } else {
+        let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+        let chunk_size = rows.len().div_ceil(threads).max(1);
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = rows
+                .chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || chunk.iter().map(StagedRow::password_hash).collect::<Result<Vec<_>, _>>()))
+                .collect();
+            let mut hashes = Vec::with_capacity(rows.len());
+            for handle in handles {
+                hashes.extend(handle.join().map_err(|_| UserImportError::PasswordHash)??);
+            }
+            Ok::<_, UserImportError>(hashes)
+        })?
+    };
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for (staged, hash) in rows.iter().zip(hashes) {
+        let row = &staged.row;
+        // 更新时未提供姓名则保留原姓名和拼音
+        let name = row.name.as_deref().or((!staged.is_update).then_some(row.email.as_str()));
+        let pinyin = name.map(transliterate);
+        writer.write_record([
+            row.row_number.to_string().as_str(),
+            &row.email,
+            row.name.as_deref().unwrap_or_default(),
+            row.role.as_deref().unwrap_or_default(),
+            hash.as_deref().unwrap_or_default(),
+            if row.password.is_some() { "t" } else { "f" },
+            row.external_id.as_deref().unwrap_or_default(),
+            pinyin.as_ref().map_or("", |pinyin| pinyin.full.as_str()),
+            pinyin.as_ref().map_or("", |pinyin| pinyin.initials.as_str()),
+            if staged.is_update { "t" } else { "f" },
+        ])?;
+    }
+    writer.into_inner().map_err(|err| UserImportError::Io(err.into_error()))
+}
+
+#[derive(Debug, FromRow)]
+struct UpdatedUser {
+    #[sqlx(flatten)]
+    user: User,
+    old_name: String,
+    old_role: String,
+    password_changed: bool,
+}
+
+// 导入任务存储实现，所有操作都限定在调用方的组织内
+pub struct UserImportStore;
+
+impl UserImportStore {
+    pub async fn create(
+        pool: &PgPool,
+        organization_id: Uuid,
+        created_by: Option<Uuid>,
+        options: &ImportOptions,
+    ) -> Result<UserImport, sqlx::Error> {
+        sqlx::query_as::<_, UserImport>(r#"
+            INSERT INTO user_imports (organization_id, created_by, format, on_duplicate, dry_run, status)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#)
+            .bind(organization_id)
+            .bind(created_by)
+            .bind(options.format.as_str())
+            .bind(options.on_duplicate)
+            .bind(options.dry_run)
+            .bind(IMPORT_PENDING)
+            .fetch_one(pool)
+            .await
+    }
+
+    pub async fn find_by_id(pool: &PgPool, organization_id: Uuid, import_id: Uuid) -> Result<UserImport, UserImportError> {
+        sqlx::query_as::<_, UserImport>("SELECT * FROM user_imports WHERE id = $1 AND organization_id = $2")
+            .bind(import_id)
+            .bind(organization_id)
+            .fetch_optional(pool)
+            .await?
+            .ok_or(UserImportError::NotFound)
+    }
+
+    // 最近的导入任务，最新的在前
+    pub async fn find_by_organization(pool: &PgPool, organization_id: Uuid) -> Result<Vec<UserImport>, sqlx::Error> {
+        sqlx::query_as::<_, UserImport>(
+            "SELECT * FROM user_imports WHERE organization_id = $1 ORDER BY created_at DESC LIMIT 100",
+        )
+        .bind(organization_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    async fn start(pool: &PgPool, import_id: Uuid, total_rows: i64) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE user_imports SET status = $2, total_rows = $3, started_at = CURRENT_TIMESTAMP WHERE id = $1")
+            .bind(import_id)
+            .bind(IMPORT_RUNNING)
+            .bind(total_rows)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn progress(pool: &PgPool, import_id: Uuid, counts: &ImportCounts) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"
+            UPDATE user_imports
+            SET processed_rows = $2, created_count = $3, updated_count = $4, skipped_count = $5, error_count = $6
+            WHERE id = $1
+            "#)
+            .bind(import_id)
+            .bind(counts.processed)
+            .bind(counts.created)
+            .bind(counts.updated)
+            .bind(counts.skipped)
+            .bind(counts.errors)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn finish(pool: &PgPool, import_id: Uuid, status: &str, error: Option<&str>) -> Result<UserImport, sqlx::Error> {
+        sqlx::query_as::<_, UserImport>(r#"
+            UPDATE user_imports
+            SET status = $2, error = $3, finished_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+            RETURNING *
+            "#)
+            .bind(import_id)
+            .bind(status)
+            .bind(error)
+            .fetch_one(pool)
+            .await
+    }
+
+    // 错误在导入事务之外写入，试运行或导入失败回滚后仍可下载
+    async fn record_errors(pool: &PgPool, import_id: Uuid, errors: &[ImportRowError]) -> Result<(), sqlx::Error> {
+        if errors.is_empty() {
+            return Ok(());
+        }
+        let row_numbers: Vec<i64> = errors.iter().map(|error| error.row_number).collect();
+        let emails: Vec<Option<&str>> = errors.iter().map(|error| error.email.as_deref()).collect();
+        let messages: Vec<&str> = errors.iter().map(|error| error.message.as_str()).collect();
+
+        sqlx::query(r#"
+            INSERT INTO user_import_errors (import_id, row_number, email, message)
+            SELECT $1, * FROM UNNEST($2::BIGINT[], $3::TEXT[], $4::TEXT[])
+            ON CONFLICT (import_id, row_number) DO NOTHING
+            "#)
+            .bind(import_id)
+            .bind(&row_numbers)
+            .bind(&emails)
+            .bind(&messages)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    // 以CSV流式输出错误报告（行号、邮箱、错误），按行号分页读取，数据块依次发送到返回的通道
+    pub async fn error_report(
+        pool: &PgPool,
+        organization_id: Uuid,
+        import_id: Uuid,
+    ) -> Result<mpsc::Receiver<Result<Vec<u8>, UserImportError>>, UserImportError> {
+        Self::find_by_id(pool, organization_id, import_id).await?;
+
+        let (sender, receiver) = mpsc::channel(4);
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            if let Err(err) = Self::write_report(&pool, import_id, &sender).await {
+                tracing::error!(%import_id, "生成导入错误报告失败: {}", err);
+                let _ = sender.send(Err(err)).await;
+            }
+        });
+        Ok(receiver)
+    }
+
+    async fn write_report(
+        pool: &PgPool,
+        import_id: Uuid,
+        sender: &mpsc::Sender<Result<Vec<u8>, UserImportError>>,
+    ) -> Result<(), UserImportError> {
+        let mut last_row = 0;
+        loop {
+            let errors = sqlx::query_as::<_, ImportRowError>(r#"
+                SELECT row_number, email, message
+                FROM user_import_errors
+                WHERE import_id = $1 AND row_number > $2
+                ORDER BY row_number
+                LIMIT $3
+                "#)
+                .bind(import_id)
+                .bind(last_row)
+                .bind(REPORT_PAGE_SIZE)
+                .fetch_all(pool)
+                .await?;
+
+            // 第一页之前输出表头
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            if last_row == 0 {
+                writer.write_record(["row", "email", "error"])?;
+            }
+            for error in &errors {
+                writer.write_record([
+                    error.row_number.to_string().as_str(),
+                    error.email.as_deref().unwrap_or_default(),
+                    &error.message,
+                ])?;
+                last_row = error.row_number;
+            }
+            let chunk = writer.into_inner().map_err(|err| UserImportError::Io(err.into_error()))?;
+            if !chunk.is_empty() && sender.send(Ok(chunk)).await.is_err() {
+                return Ok(());
+            }
+            if (errors.len() as i64) < REPORT_PAGE_SIZE {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// 批量导入用户：在一个租户事务中按批校验，把有效的行COPY到临时表，再插入或更新users表，
+// 因此导入要么全部生效，要么在出错时全部回滚；试运行执行同样的步骤后回滚，得到的统计和错误与实际导入一致。
+// 导入直接写入users表，与UserStore一样写入领域事件，但不会记录到USER_STORE=events的事件流中
+pub struct UserImporter;
+
+impl UserImporter {
+    // 检查文件的列后创建任务
+    pub async fn create_job(
+        pool: &PgPool,
+        organization_id: Uuid,
+        created_by: Option<Uuid>,
+        options: &ImportOptions,
+        path: &Path,
+    ) -> Result<UserImport, UserImportError> {
+        RowReader::open(path, options)?;
+        Ok(UserImportStore::create(pool, organization_id, created_by, options).await?)
+    }
+
+    // 在后台执行任务，完成后删除上传的临时文件
+    pub fn spawn(pool: PgPool, job: UserImport, options: ImportOptions, path: PathBuf) {
+        tokio::spawn(async move {
+            if let Err(err) = Self::run(&pool, &job, &options, &path).await {
+                tracing::error!(import_id = %job.id, "更新导入任务失败: {}", err);
+            }
+            if let Err(err) = tokio::fs::remove_file(&path).await {
+                tracing::warn!(import_id = %job.id, "删除导入文件失败: {}", err);
+            }
+        });
+    }
+
+    // 执行任务并返回最终状态；导入本身出错时任务标记为failed，返回错误只表示无法更新任务
+    pub async fn run(pool: &PgPool, job: &UserImport, options: &ImportOptions, path: &Path) -> Result<UserImport, UserImportError> {
+        match Self::process(pool, job, options, path).await {
+            Ok(()) => Ok(UserImportStore::finish(pool, job.id, IMPORT_COMPLETED, None).await?),
+            Err(err) => {
+                tracing::error!(import_id = %job.id, "导入用户失败: {}", err);
+                Ok(UserImportStore::finish(pool, job.id, IMPORT_FAILED, Some(&err.to_string())).await?)
+            }
+        }
+    }
+
+    async fn process(pool: &PgPool, job: &UserImport, options: &ImportOptions, path: &Path) -> Result<(), UserImportError> {
+        let count_path = path.to_path_buf();
+        let format = options.format;
+        let total_rows = tokio::task::spawn_blocking(move || RowReader::count(&count_path, format)).await??;
+        UserImportStore::start(pool, job.id, total_rows).await?;
+
+        let mut reader = RowReader::open(path, options)?;
+        let mut tx = db::begin_tenant(pool, job.organization_id).await?;
+        sqlx::query(r#"
+            CREATE TEMP TABLE user_import_rows (
+                row_number BIGINT NOT NULL,
+                email TEXT NOT NULL,
+                name TEXT,
+                role TEXT,
+                password TEXT,
+                has_password BOOLEAN NOT NULL,
+                external_id TEXT,
+                name_pinyin TEXT,
+                name_initials TEXT,
+                is_update BOOLEAN NOT NULL
+            ) ON COMMIT DROP
+            "#)
+            .execute(&mut *tx)
+            .await?;
+
+        let mut counts = ImportCounts::default();
+        // 文件中已出现的邮箱及其行号
+        let mut seen: HashMap<String, i64> = HashMap::new();
+        loop {
+            let (returned, batch) = tokio::task::spawn_blocking(move || {
+                let batch = reader.next_batch(BATCH_SIZE);
+                (reader, batch)
+            })
+            .await?;
+            reader = returned;
+            let batch = batch?;
+            if batch.is_empty() {
+                break;
+            }
+            counts.processed += batch.len() as i64;
+
+            let mut rows = Vec::with_capacity(batch.len());
+            let mut errors = Vec::new();
+            for raw in batch {
+                let row = raw
+                    .fields
+                    .map_err(|message| ImportRowError::new(raw.row_number, None, message))
+                    .and_then(|fields| validate(raw.row_number, fields));
+                match row {
+                    Ok(row) => match seen.get(&row.email) {
+                        Some(first) => errors.push(ImportRowError::new(
+                            row.row_number,
+                            Some(row.email),
+                            format!("与第{}行的邮箱重复", first),
+                        )),
+                        None => {
+                            seen.insert(row.email.clone(), row.row_number);
+                            rows.push(row);
+                        }
+                    },
+                    Err(error) => errors.push(error),
+                }
+            }
+
+            Self::write_batch(&mut tx, job.organization_id, options, rows, &mut counts, &mut errors).await?;
+
+            counts.errors += errors.len() as i64;
+            UserImportStore::record_errors(pool, job.id, &errors).await?;
+            UserImportStore::progress(pool, job.id, &counts).await?;
+            tracing::info!(import_id = %job.id, processed = counts.processed, total = total_rows, "导入进度");
+        }
+
+        // 试运行时丢弃事务，回滚所有修改
+        if !options.dry_run {
+            let detail = format!(
+                "created={} updated={} skipped={} errors={}",
+                counts.created, counts.updated, counts.skipped, counts.errors
+            );
+            AuditStore::record(&mut *tx, job.created_by, None, "users_imported", Some(&detail)).await?;
+            tx.commit().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write_batch(
+        conn: &mut PgConnection,
+        organization_id: Uuid,
+        options: &ImportOptions,
+        rows: Vec<ImportRow>,
+        counts: &mut ImportCounts,
+        errors: &mut Vec<ImportRowError>,
+    ) -> Result<(), UserImportError> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        // 租户事务中只能看到当前组织的用户；其他组织使用的邮箱在插入时由唯一约束发现
+        let emails: Vec<&str> = rows.iter().map(|row| row.email.as_str()).collect();
+        let existing: HashSet<String> = sqlx::query_scalar::<_, String>("SELECT email FROM users WHERE email = ANY($1)")
+            .bind(&emails)
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .collect();
+
+        let mut staged = Vec::with_capacity(rows.len());
+        for row in rows {
+            if !existing.contains(&row.email) {
+                staged.push(StagedRow { row, is_update: false });
+                continue;
+            }
+            match options.on_duplicate {
+                ON_DUPLICATE_UPDATE => staged.push(StagedRow { row, is_update: true }),
+                ON_DUPLICATE_FAIL => errors.push(ImportRowError::new(row.row_number, Some(row.email), "邮箱已存在")),
+                _ => counts.skipped += 1,
+            }
+        }
+        if staged.is_empty() {
+            return Ok(());
+        }
+
+        let dry_run = options.dry_run;
+        let (staged, data) = tokio::task::spawn_blocking(move || {
+            let data = encode_staged(&staged, dry_run);
+            (staged, data)
+        })
+        .await?;
+        let mut copy = conn
+            .copy_in_raw(r#"
+                COPY user_import_rows (row_number, email, name, role, password, has_password, external_id, name_pinyin, name_initials, is_update)
+                FROM STDIN (FORMAT csv)
+                "#)
+            .await?;
+        copy.send(data?).await?;
+        copy.finish().await?;
+
+        let mut events = Vec::new();
+
+        // 更新已有用户，未提供的字段保持不变，没有变化的行计为跳过
+        let updated = sqlx::query_as::<_, UpdatedUser>(r#"
+            UPDATE users u
+            SET name = COALESCE(s.name, u.name),
+                role = COALESCE(s.role, u.role),
+                external_id = COALESCE(s.external_id, u.external_id),
+                password = COALESCE(s.password, u.password),
+                password_changed_at = CASE WHEN s.has_password THEN CURRENT_TIMESTAMP ELSE u.password_changed_at END,
+                name_pinyin = CASE WHEN s.name IS NULL THEN u.name_pinyin ELSE COALESCE(s.name_pinyin, '') END,
+                name_initials = CASE WHEN s.name IS NULL THEN u.name_initials ELSE COALESCE(s.name_initials, '') END
+            FROM user_import_rows s, users old
+            WHERE s.is_update
+              AND u.email = s.email
+              AND old.id = u.id
+              AND ((COALESCE(s.name, u.name), COALESCE(s.role, u.role), COALESCE(s.external_id, u.external_id))
+                       IS DISTINCT FROM (u.name, u.role, u.external_id)
+                   OR s.has_password)
+            RETURNING u.id, u.name, u.email, u.password, u.role, u.status, u.organization_id, u.created_at, u.updated_at,
+                      old.name AS old_name, old.role AS old_role, s.has_password AS password_changed
+            "#)
+            .fetch_all(&mut *conn)
+            .await?;
+        let update_count = staged.iter().filter(|staged| staged.is_update).count() as i64;
+        counts.updated += updated.len() as i64;
+        counts.skipped += update_count - updated.len() as i64;
+        for UpdatedUser { user, old_name, old_role, password_changed } in updated {
+            let changed: Vec<&'static str> = [("name", user.name != old_name), ("role", user.role != old_role)]
+                .into_iter()
+                .filter_map(|(field, changed)| changed.then_some(field))
+                .collect();
+            let user_id = user.id;
+            if !changed.is_empty() {
+                events.push(DomainEvent::UserUpdated { user, changed });
+            }
+            if password_changed {
+                events.push(DomainEvent::UserPasswordChanged { user_id });
+            }
+        }
+
+        // 插入新用户，邮箱已被其他组织使用的行不会插入，作为该行的错误
+        let created = sqlx::query_as::<_, User>(r#"
+            INSERT INTO users (name, email, password, role, organization_id, external_id, name_pinyin, name_initials)
+            SELECT COALESCE(name, email), email, COALESCE(password, $3), COALESCE(role, $2), $1,
+                   external_id, COALESCE(name_pinyin, ''), COALESCE(name_initials, '')
+            FROM user_import_rows
+            WHERE NOT is_update
+            ORDER BY row_number
+            ON CONFLICT (email) DO NOTHING
+            RETURNING id, name, email, password, role, status, organization_id, created_at, updated_at
+            "#)
+            .bind(organization_id)
+            .bind(ROLE_USER)
+            .bind(UNUSABLE_PASSWORD)
+            .fetch_all(&mut *conn)
+            .await?;
+        counts.created += created.len() as i64;
+        let inserted: HashSet<&str> = created.iter().map(|user| user.email.as_str()).collect();
+        for StagedRow { row, .. } in staged.iter().filter(|staged| !staged.is_update) {
+            if !inserted.contains(row.email.as_str()) {
+                errors.push(ImportRowError::new(row.row_number, Some(row.email.clone()), "邮箱已存在"));
+            }
+        }
+        events.extend(created.into_iter().map(|user| DomainEvent::UserCreated { user }));
+
+        OutboxStore::append_all(&mut *conn, organization_id, &events).await?;
+
+        sqlx::query("TRUNCATE user_import_rows").execute(&mut *conn).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use crate::model::{password_reset::MIN_PASSWORD_LENGTH, ROLE_ADMIN};
+    use crate::test_support::unique;
+    use super::*;
+
+    fn fields(pairs: &[(&'static str, &str)]) -> HashMap<&'static str, String> {
+        pairs.iter().map(|(field, value)| (*field, value.to_string())).collect()
+    }
+
+    fn message(result: Result<ImportRow, ImportRowError>) -> String {
+        match result {
+            Ok(row) => panic!("第{}行应当校验失败", row.row_number),
+            Err(err) => err.message,
+        }
+    }
+
+    // 临时文件在测试结束后删除
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(content: &str) -> Self {
+            let path = std::env::temp_dir().join(unique("user-import"));
+            std::fs::write(&path, content).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn read_all(content: &str, options: &ImportOptions) -> Vec<RawRow> {
+        let file = TempFile::new(content);
+        let mut reader = RowReader::open(&file.0, options).unwrap();
+        let rows = reader.next_batch(100).unwrap();
+        assert!(reader.next_batch(100).unwrap().is_empty());
+        rows
+    }
+
+    #[test]
+    fn email_format() {
+        for email in ["zhangsan@example.com", "a.b+tag@mail.example.cn"] {
+            assert!(valid_email(email), "{}", email);
+        }
+        for email in ["", "zhangsan", "@example.com", "zhangsan@example", "zhangsan@.example.com", "zhangsan@example.com.", "a@b@example.com", "zhang san@example.com"] {
+            assert!(!valid_email(email), "{}", email);
+        }
+    }
+
+    #[test]
+    fn valid_row_keeps_optional_fields() {
+        let row = validate(2, fields(&[
+            ("email", "zhangsan@example.com"),
+            ("name", "张三"),
+            ("role", ROLE_ADMIN),
+            ("password", "long-enough-password"),
+            ("external_id", "ext-1"),
+        ]))
+        .unwrap();
+        assert_eq!(row.row_number, 2);
+        assert_eq!(row.email, "zhangsan@example.com");
+        assert_eq!((row.name.as_deref(), row.role.as_deref()), (Some("张三"), Some(ROLE_ADMIN)));
+        assert_eq!((row.password.as_deref(), row.external_id.as_deref()), (Some("long-enough-password"), Some("ext-1")));
+
+        let row = validate(3, fields(&[("email", "lisi@example.com")])).unwrap();
+        assert!(row.name.is_none() && row.role.is_none() && row.password.is_none() && row.external_id.is_none());
+    }
+
+    // 每种错误返回对应的提示，并带上行号和邮箱以便写入错误报告
+    #[test]
+    fn invalid_rows_are_reported() {
+        let err = validate(5, fields(&[("name", "张三")])).err().unwrap();
+        assert_eq!((err.row_number, err.email, err.message.as_str()), (5, None, "缺少邮箱"));
+
+        let err = validate(6, fields(&[("email", "bad-email")])).err().unwrap();
+        assert_eq!((err.row_number, err.email.as_deref(), err.message.as_str()), (6, Some("bad-email"), "邮箱格式无效"));
+
+        let long_email = format!("{}@example.com", "a".repeat(MAX_EMAIL_LEN));
+        assert_eq!(message(validate(2, fields(&[("email", &long_email)]))), format!("邮箱不能超过{}个字符", MAX_EMAIL_LEN));
+
+        let email = ("email", "zhangsan@example.com");
+        let name = "张".repeat(MAX_NAME_LEN + 1);
+        assert_eq!(message(validate(2, fields(&[email, ("name", &name)]))), format!("姓名不能超过{}个字符", MAX_NAME_LEN));
+        assert_eq!(message(validate(2, fields(&[email, ("role", "owner")]))), "无效的角色: owner");
+        let password = "x".repeat(MIN_PASSWORD_LENGTH - 1);
+        assert_eq!(message(validate(2, fields(&[email, ("password", &password)]))), format!("密码长度不能少于{}位", MIN_PASSWORD_LENGTH));
+        let external_id = "x".repeat(MAX_EXTERNAL_ID_LEN + 1);
+        assert_eq!(message(validate(2, fields(&[email, ("external_id", &external_id)]))), format!("外部ID不能超过{}个字符", MAX_EXTERNAL_ID_LEN));
+
+        // 长度按字符计算，多字节字符不会被误判
+        let name = "张".repeat(MAX_NAME_LEN);
+        assert!(validate(2, fields(&[email, ("name", &name)])).is_ok());
+    }
+
+    #[test]
+    fn options_parse_mapping() {
+        let options = ImportOptions::parse("csv", None, false, Some(" email : E-mail , name:姓名,")).unwrap();
+        assert_eq!(options.on_duplicate, ON_DUPLICATE_SKIP);
+        assert_eq!((options.column("email"), options.column("name"), options.column("role")), ("E-mail", "姓名", "role"));
+
+        assert!(matches!(ImportOptions::parse("xlsx", None, false, None), Err(UserImportError::InvalidFormat(_))));
+        assert!(matches!(ImportOptions::parse("csv", Some("merge"), false, None), Err(UserImportError::InvalidOnDuplicate(_))));
+        for mapping in ["email", "email:", "password_hash:hash"] {
+            assert!(matches!(ImportOptions::parse("csv", None, false, Some(mapping)), Err(UserImportError::InvalidMapping(_))), "{}", mapping);
+        }
+    }
+
+    #[test]
+    fn json_line_fields() {
+        let keys: Vec<(&'static str, String)> = vec![("email", "mail".to_string()), ("name", "name".to_string()), ("external_id", "id".to_string())];
+        let parsed = parse_json_line(br#"{"mail": " zhangsan@example.com ", "name": "", "id": 42, "extra": [1]}"#, &keys).unwrap();
+        assert_eq!(parsed, fields(&[("email", "zhangsan@example.com"), ("external_id", "42")]));
+        assert!(parse_json_line(br#"{"mail": null}"#, &keys).unwrap().is_empty());
+
+        assert!(parse_json_line(br#"{"mail": {"a": 1}}"#, &keys).unwrap_err().contains("字段mail必须是字符串"));
+        assert_eq!(parse_json_line(b"[1, 2]", &keys).unwrap_err(), "每行必须是一个JSON对象");
+        assert!(parse_json_line(b"{not json", &keys).unwrap_err().starts_with("不是有效的JSON"));
+    }
+
+    // 表头忽略BOM和大小写，行号从表头的第1行开始计算，空值不出现在字段中
+    #[test]
+    fn csv_rows_follow_mapping() {
+        let options = ImportOptions::parse("csv", None, false, Some("name:姓名")).unwrap();
+        let rows = read_all("\u{feff}EMAIL,姓名,role\nzhangsan@example.com, 张三 ,\nlisi@example.com,,admin\n", &options);
+
+        let numbers: Vec<i64> = rows.iter().map(|row| row.row_number).collect();
+        assert_eq!(numbers, [2, 3]);
+        assert_eq!(rows[0].fields.as_ref().unwrap(), &fields(&[("email", "zhangsan@example.com"), ("name", "张三")]));
+        assert_eq!(rows[1].fields.as_ref().unwrap(), &fields(&[("email", "lisi@example.com"), ("role", "admin")]));
+
+        let file = TempFile::new("name,role\n张三,user\n");
+        assert!(matches!(RowReader::open(&file.0, &options), Err(UserImportError::MissingColumn(column)) if column == "email"));
+        let file = TempFile::new("email,name\nzhangsan@example.com,张三\n");
+        assert!(matches!(RowReader::open(&file.0, &options), Err(UserImportError::MissingColumn(column)) if column == "姓名"));
+    }
+
+    // 空行跳过但计入行号，无法解析的行作为该行的错误返回
+    #[test]
+    fn ndjson_rows_keep_line_numbers() {
+        let options = ImportOptions::parse("ndjson", None, false, None).unwrap();
+        let content = "{\"email\": \"zhangsan@example.com\"}\n\n[1]\n{\"email\": \"lisi@example.com\", \"role\": \"admin\"}";
+        let rows = read_all(content, &options);
+
+        let numbers: Vec<i64> = rows.iter().map(|row| row.row_number).collect();
+        assert_eq!(numbers, [1, 3, 4]);
+        assert!(rows[1].fields.is_err());
+        assert_eq!(rows[2].fields.as_ref().unwrap(), &fields(&[("email", "lisi@example.com"), ("role", "admin")]));
+
+        let file = TempFile::new(content);
+        assert_eq!(RowReader::count(&file.0, ImportFormat::Ndjson).unwrap(), 3);
+    }
+
+    // 新用户未提供姓名时以邮箱为姓名计算拼音，更新时保留原姓名；试运行不计算密码哈希
+    #[test]
+    fn staged_rows_encode_for_copy() {
+        let staged = |email: &str, name: Option<&str>, is_update: bool| StagedRow {
+            row: ImportRow {
+                row_number: 2,
+                email: email.to_string(),
+                name: name.map(str::to_string),
+                role: None,
+                password: Some("long-enough-password".to_string()),
+                external_id: None,
+            },
+            is_update,
+        };
+        let rows = [staged("new@example.com", None, false), staged("old@example.com", None, true), staged("zs@example.com", Some("张三"), false)];
+        let data = String::from_utf8(encode_staged(&rows, true).unwrap()).unwrap();
+        let lines: Vec<&str> = data.lines().collect();
+
+        let pinyin = transliterate("new@example.com");
+        assert_eq!(lines[0], format!("2,new@example.com,,,,t,,{},{},f", pinyin.full, pinyin.initials));
+        assert_eq!(lines[1], "2,old@example.com,,,,t,,,,t");
+        assert_eq!(lines[2], "2,zs@example.com,张三,,,t,,zhang san,zs,f");
+    }
+}
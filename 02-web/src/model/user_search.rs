@@ -0,0 +1,238 @@
+use std::ops::Range;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection};
+use thiserror::Error;
+use crate::model::{User, STATUS_DELETED};
+use crate::name_pinyin;
+
+// 默认和最多返回的结果数
+pub const DEFAULT_SEARCH_LIMIT: i64 = 20;
+pub const MAX_SEARCH_LIMIT: i64 = 100;
+
+// 用户搜索错误类型
+#[derive(Error, Debug)]
+pub enum UserSearchError {
+    #[error("搜索关键词不能为空")]
+    EmptyQuery,
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+// 用户搜索参数
+#[derive(Debug, Deserialize)]
+pub struct UserSearchQuery {
+    #[serde(default)]
+    pub q: String,
+    pub limit: Option<i64>,
+}
+
+// 姓名和邮箱中命中关键词的部分用<mark>标出，其余内容已做HTML转义
+#[derive(Debug, Default, Serialize)]
+pub struct Highlights {
+    pub name: String,
+    pub email: String,
+}
+
+// 搜索结果，邮箱完全匹配的排在最前面，其余按相关度排序
+#[derive(Debug, Serialize, FromRow)]
+pub struct UserSearchResult {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub user: User,
+    pub exact_match: bool,
+    pub score: f32,
+    #[sqlx(skip)]
+    pub highlights: Highlights,
+}
+
+// 把关键词拆成词，用于前缀全文检索和高亮
+fn search_terms(q: &str) -> Vec<String> {
+    q.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+// 每个词都按前缀匹配，词只包含字母和数字，不会被当作tsquery运算符
+fn prefix_tsquery(terms: &[String]) -> Option<String> {
+    match terms.is_empty() {
+        true => None,
+        false => Some(terms.iter().map(|term| format!("{}:*", term)).collect::<Vec<_>>().join(" & ")),
+    }
+}
+
+fn escape_like(q: &str) -> String {
+    q.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+fn escape_html(c: char, out: &mut String) {
+    match c {
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '&' => out.push_str("&amp;"),
+        '"' => out.push_str("&quot;"),
+        c => out.push(c),
+    }
+}
+
+// 不区分大小写地标出所有关键词出现的位置，span为按拼音命中的字符范围，相邻的命中合并为一段
+fn highlight(text: &str, terms: &[String], span: Option<Range<usize>>) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let lower: Vec<char> = chars.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+    let mut marked = vec![false; chars.len()];
+    if let Some(span) = span {
+        marked[span].iter_mut().for_each(|m| *m = true);
+    }
+    for term in terms {
+        let term: Vec<char> = term.chars().collect();
+        if term.is_empty() || term.len() > lower.len() {
+            continue;
+        }
+        for start in 0..=lower.len() - term.len() {
+            if lower[start..start + term.len()] == term[..] {
+                marked[start..start + term.len()].iter_mut().for_each(|m| *m = true);
+            }
+        }
+    }
+
+    let mut out = String::with_capacity(text.len());
+    for (i, c) in chars.iter().enumerate() {
+        if marked[i] && (i == 0 || !marked[i - 1]) {
+            out.push_str("<mark>");
+        }
+        escape_html(*c, &mut out);
+        if marked[i] && (i + 1 == chars.len() || !marked[i + 1]) {
+            out.push_str("</mark>");
+        }
+    }
+    out
+}
+
+// 拼音命中的得分，全拼或首字母与关键词完全一致时更高
+fn pinyin_score(pattern: usize, key: usize) -> String {
+    format!(
+        "CASE WHEN name_pinyin ~ ${pattern} THEN 0.8 + CASE WHEN name_initials = ${key} \
+         OR replace(name_pinyin, ' ', '') = ${key} THEN 0.4 ELSE 0 END ELSE 0 END"
+    )
+}
+
+const USER_COLUMNS: &str = "id, name, email, password, role, status, organization_id, created_at, updated_at";
+
+// 用户搜索实现
+// 在租户事务中调用，只搜索当前组织未删除的用户
+pub struct UserSearchStore;
+
+impl UserSearchStore {
+    // 是否启用了pg_trgm扩展，未启用时使用全文检索加子串匹配
+    async fn trigram_available(conn: &mut PgConnection) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'pg_trgm')")
+            .fetch_one(&mut *conn)
+            .await
+    }
+
+    // 按姓名和邮箱搜索用户：全文检索按词前缀匹配，相似度匹配容忍拼写错误，
+    // 姓名还可以按全拼、首字母或两者混合匹配
+    pub async fn search(conn: &mut PgConnection, query: &UserSearchQuery) -> Result<Vec<UserSearchResult>, UserSearchError> {
+        let q = query.q.trim();
+        if q.is_empty() {
+            return Err(UserSearchError::EmptyQuery);
+        }
+        let terms = search_terms(q);
+        let tsquery = prefix_tsquery(&terms);
+        let limit = query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT).clamp(1, MAX_SEARCH_LIMIT);
+        let pinyin_key = name_pinyin::query_key(q);
+        let pinyin_pattern = name_pinyin::match_pattern(&pinyin_key);
+
+        let mut results = if Self::trigram_available(conn).await? {
+            sqlx::query_as::<_, UserSearchResult>(&format!(r#"
+                SELECT {},
+                       lower(email) = lower($1) AS exact_match,
+                       (COALESCE(ts_rank(search_vector, to_tsquery('simple', $2)), 0)
+                        + greatest(similarity(name, $1), word_similarity($1, name))
+                        + greatest(similarity(email, $1), word_similarity($1, email))
+                        + {})::REAL AS score
+                FROM users
+                WHERE status <> $3
+                  AND (lower(email) = lower($1)
+                       OR search_vector @@ to_tsquery('simple', $2)
+                       OR name % $1 OR email % $1
+                       OR $1 <% name OR $1 <% email
+                       OR name_pinyin ~ $5)
+                ORDER BY exact_match DESC, score DESC, name
+                LIMIT $4
+                "#, USER_COLUMNS, pinyin_score(5, 6)))
+                .bind(q)
+                .bind(&tsquery)
+                .bind(STATUS_DELETED)
+                .bind(limit)
+                .bind(&pinyin_pattern)
+                .bind(&pinyin_key)
+                .fetch_all(&mut *conn)
+                .await?
+        } else {
+            sqlx::query_as::<_, UserSearchResult>(&format!(r#"
+                SELECT {},
+                       lower(email) = lower($1) AS exact_match,
+                       (COALESCE(ts_rank(search_vector, to_tsquery('simple', $2)), 0)
+                        + CASE WHEN name ILIKE $3 OR email ILIKE $3 THEN 0.5 ELSE 0 END
+                        + {})::REAL AS score
+                FROM users
+                WHERE status <> $4
+                  AND (lower(email) = lower($1)
+                       OR search_vector @@ to_tsquery('simple', $2)
+                       OR name ILIKE $3 OR email ILIKE $3
+                       OR name_pinyin ~ $6)
+                ORDER BY exact_match DESC, score DESC, name
+                LIMIT $5
+                "#, USER_COLUMNS, pinyin_score(6, 7)))
+                .bind(q)
+                .bind(&tsquery)
+                .bind(format!("%{}%", escape_like(q)))
+                .bind(STATUS_DELETED)
+                .bind(limit)
+                .bind(&pinyin_pattern)
+                .bind(&pinyin_key)
+                .fetch_all(&mut *conn)
+                .await?
+        };
+
+        for result in &mut results {
+            result.highlights = Highlights {
+                name: highlight(&result.user.name, &terms, name_pinyin::match_span(&result.user.name, &pinyin_key)),
+                email: highlight(&result.user.email, &terms, None),
+            };
+        }
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_terms_become_prefix_queries() {
+        let terms = search_terms("Zhang-San  o'Brien");
+        assert_eq!(terms, vec!["zhang", "san", "o", "brien"]);
+        assert_eq!(prefix_tsquery(&terms).unwrap(), "zhang:* & san:* & o:* & brien:*");
+        // 运算符字符不会进入tsquery
+        assert_eq!(prefix_tsquery(&search_terms("a & !b | (c)")).unwrap(), "a:* & b:* & c:*");
+        assert_eq!(prefix_tsquery(&search_terms("&|!")), None);
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like(r"50%_off\"), r"50\%\_off\\");
+    }
+
+    #[test]
+    fn highlight_merges_term_and_pinyin_matches() {
+        assert_eq!(highlight("Zhang San", &["san".to_string()], None), "Zhang <mark>San</mark>");
+        assert_eq!(highlight("张三丰", &[], Some(0..2)), "<mark>张三</mark>丰");
+        assert_eq!(highlight("张三 zs", &["zs".to_string()], Some(0..2)), "<mark>张三</mark> <mark>zs</mark>");
+        assert_eq!(highlight("ab", &["a".to_string(), "b".to_string()], None), "<mark>ab</mark>");
+        assert_eq!(highlight("<b>&\"", &["b".to_string()], None), "&lt;<mark>b</mark>&gt;&amp;&quot;");
+        assert_eq!(highlight("a", &["abc".to_string()], None), "a");
+    }
+}
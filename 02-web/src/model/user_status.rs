@@ -0,0 +1,259 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+use crate::db::begin_tenant;
+use crate::model::{audit::AuditStore, outbox::{DomainEvent, OutboxStore}, session::SessionStore, User, STATUS_ACTIVE, STATUS_DELETED, STATUS_LOCKED, STATUS_PENDING, STATUS_SUSPENDED};
+
+// 账号状态转换
+#[derive(Debug, Clone, Copy)]
+pub enum Transition {
+    Activate,
+    Suspend,
+    Reactivate,
+    Lock,
+    Unlock,
+    Delete,
+}
+
+impl Transition {
+    // 允许的转换：(动作, 起始状态, 目标状态)
+    fn edges(self) -> (&'static str, &'static [&'static str], &'static str) {
+        match self {
+            Transition::Activate => ("activate", &[STATUS_PENDING], STATUS_ACTIVE),
+            Transition::Suspend => ("suspend", &[STATUS_ACTIVE, STATUS_LOCKED], STATUS_SUSPENDED),
+            Transition::Reactivate => ("reactivate", &[STATUS_SUSPENDED], STATUS_ACTIVE),
+            Transition::Lock => ("lock", &[STATUS_ACTIVE], STATUS_LOCKED),
+            Transition::Unlock => ("unlock", &[STATUS_LOCKED], STATUS_ACTIVE),
+            Transition::Delete => (
+                "delete",
+                &[STATUS_PENDING, STATUS_ACTIVE, STATUS_SUSPENDED, STATUS_LOCKED],
+                STATUS_DELETED,
+            ),
+        }
+    }
+
+    // 从from状态执行该转换后的目标状态，不允许的转换返回错误
+    pub fn target(self, from: &str) -> Result<&'static str, UserStatusError> {
+        let (action, from_statuses, to_status) = self.edges();
+        if !from_statuses.contains(&from) {
+            return Err(UserStatusError::InvalidTransition { from: from.to_string(), action });
+        }
+        Ok(to_status)
+    }
+}
+
+// 状态转换错误类型
+#[derive(Error, Debug)]
+pub enum UserStatusError {
+    #[error("用户不存在")]
+    NotFound,
+    #[error("必须填写原因")]
+    MissingReason,
+    #[error("当前状态为{from}，不能执行{action}")]
+    InvalidTransition { from: String, action: &'static str },
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+// 状态转换请求
+#[derive(Debug, Deserialize)]
+pub struct TransitionRequest {
+    pub reason: String,
+}
+
+// 状态转换记录
+#[derive(Debug, Serialize, FromRow)]
+pub struct StatusTransition {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub from_status: String,
+    pub to_status: String,
+    pub actor_id: Option<Uuid>,
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// 账号状态存储实现
+pub struct UserStatusStore;
+
+impl UserStatusStore {
+    // 执行状态转换并记录操作人和原因；离开正常状态时注销该用户的全部会话。
+    // 在操作人所在组织的租户事务中执行，其他组织的用户视为不存在
+    pub async fn transition(
+        pool: &PgPool,
+        organization_id: Uuid,
+        actor_id: Uuid,
+        user_id: Uuid,
+        transition: Transition,
+        reason: &str,
+    ) -> Result<User, UserStatusError> {
+        let mut tx = begin_tenant(pool, organization_id).await?;
+        let user = Self::apply(&mut tx, Some(actor_id), user_id, transition, reason).await?;
+        tx.commit().await?;
+
+        Ok(user)
+    }
+
+    // 在调用方的事务中执行状态转换，actor_id为空表示由系统（如SCIM同步）发起
+    pub async fn apply(
+        conn: &mut PgConnection,
+        actor_id: Option<Uuid>,
+        user_id: Uuid,
+        transition: Transition,
+        reason: &str,
+    ) -> Result<User, UserStatusError> {
+        let reason = reason.trim();
+        if reason.is_empty() {
+            return Err(UserStatusError::MissingReason);
+        }
+        // 锁定该行，避免并发转换基于过期的状态判断
+        let from_status = sqlx::query_scalar::<_, String>("SELECT status FROM users WHERE id = $1 FOR UPDATE")
+            .bind(user_id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or(UserStatusError::NotFound)?;
+        let to_status = transition.target(&from_status)?;
+
+        let user = sqlx::query_as::<_, User>(r#"
+            UPDATE users
+            SET status = $1, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $2
+            RETURNING id, name, email, password, role, status, organization_id, created_at, updated_at
+            "#)
+            .bind(to_status)
+            .bind(user_id)
+            .fetch_one(&mut *conn)
+            .await?;
+
+        sqlx::query(r#"
+            INSERT INTO user_status_transitions (user_id, from_status, to_status, actor_id, reason)
+            VALUES ($1, $2, $3, $4, $5)
+            "#)
+            .bind(user_id)
+            .bind(&from_status)
+            .bind(to_status)
+            .bind(actor_id)
+            .bind(reason)
+            .execute(&mut *conn)
+            .await?;
+
+        let event = DomainEvent::UserStatusChanged {
+            user_id,
+            from: from_status.clone(),
+            to: to_status.to_string(),
+            reason: reason.to_string(),
+        };
+        OutboxStore::append(&mut *conn, user.organization_id, &event).await?;
+
+        if to_status != STATUS_ACTIVE {
+            SessionStore::revoke_all_for_user(&mut *conn, user_id).await?;
+        }
+
+        let detail = format!("{} -> {}: {}", from_status, to_status, reason);
+        AuditStore::record(&mut *conn, actor_id, Some(user_id), "user_status_changed", Some(&detail)).await?;
+
+        Ok(user)
+    }
+
+    // 获取用户的状态转换记录，只能查看当前组织的用户
+    pub async fn history(
+        pool: &PgPool,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Vec<StatusTransition>, sqlx::Error> {
+        let mut tx = begin_tenant(pool, organization_id).await?;
+        let transitions = sqlx::query_as::<_, StatusTransition>(r#"
+            SELECT t.id, t.user_id, t.from_status, t.to_status, t.actor_id, t.reason, t.created_at
+            FROM user_status_transitions t
+            JOIN users ON users.id = t.user_id
+            WHERE t.user_id = $1
+            ORDER BY t.created_at DESC
+            "#)
+            .bind(user_id)
+            .fetch_all(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(transitions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUSES: [&str; 5] = [STATUS_PENDING, STATUS_ACTIVE, STATUS_SUSPENDED, STATUS_LOCKED, STATUS_DELETED];
+
+    // 完整的转换表：(动作, 起始状态, 目标状态)，表外的组合都不允许
+    const ALLOWED: [(&str, &str, &str); 10] = [
+        ("activate", STATUS_PENDING, STATUS_ACTIVE),
+        ("suspend", STATUS_ACTIVE, STATUS_SUSPENDED),
+        ("suspend", STATUS_LOCKED, STATUS_SUSPENDED),
+        ("reactivate", STATUS_SUSPENDED, STATUS_ACTIVE),
+        ("lock", STATUS_ACTIVE, STATUS_LOCKED),
+        ("unlock", STATUS_LOCKED, STATUS_ACTIVE),
+        ("delete", STATUS_PENDING, STATUS_DELETED),
+        ("delete", STATUS_ACTIVE, STATUS_DELETED),
+        ("delete", STATUS_SUSPENDED, STATUS_DELETED),
+        ("delete", STATUS_LOCKED, STATUS_DELETED),
+    ];
+
+    const TRANSITIONS: [Transition; 6] = [
+        Transition::Activate,
+        Transition::Suspend,
+        Transition::Reactivate,
+        Transition::Lock,
+        Transition::Unlock,
+        Transition::Delete,
+    ];
+
+    #[test]
+    fn transition_graph_matches_table() {
+        for transition in TRANSITIONS {
+            let (action, _, _) = transition.edges();
+            for from in STATUSES {
+                let expected = ALLOWED
+                    .iter()
+                    .find(|(allowed_action, allowed_from, _)| *allowed_action == action && *allowed_from == from)
+                    .map(|(_, _, to)| *to);
+                match (transition.target(from), expected) {
+                    (Ok(to), Some(expected)) => assert_eq!(to, expected, "{} {}", action, from),
+                    (Err(UserStatusError::InvalidTransition { from: error_from, action: error_action }), None) => {
+                        assert_eq!(error_from, from);
+                        assert_eq!(error_action, action);
+                    }
+                    (result, expected) => panic!("{} {}: 得到{:?}，期望{:?}", action, from, result, expected),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn deleted_is_terminal() {
+        assert!(TRANSITIONS.iter().all(|transition| transition.target(STATUS_DELETED).is_err()));
+    }
+
+    #[test]
+    fn every_status_can_reach_active_or_deleted() {
+        // 除deleted外的每个状态都能回到active，也都能被删除
+        for from in STATUSES.iter().filter(|status| **status != STATUS_DELETED) {
+            let mut reachable = vec![*from];
+            let mut index = 0;
+            while index < reachable.len() {
+                let current = reachable[index];
+                for transition in TRANSITIONS {
+                    if let Ok(to) = transition.target(current) {
+                        if !reachable.contains(&to) {
+                            reachable.push(to);
+                        }
+                    }
+                }
+                index += 1;
+            }
+            assert!(reachable.contains(&STATUS_ACTIVE), "{}无法回到active", from);
+            assert!(reachable.contains(&STATUS_DELETED), "{}无法删除", from);
+        }
+    }
+}
@@ -0,0 +1,697 @@
+use std::{
+    error::Error as StdError,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgPool};
+use tokio::task::JoinSet;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use crate::model::audit::AuditStore;
+use crate::token::generate_token;
+
+// 可订阅的事件类型
+pub const WEBHOOK_EVENT_TYPES: [&str; 3] = ["user.created", "user.updated", "user.deleted"];
+
+// 投递状态
+pub const DELIVERY_PENDING: &str = "pending";
+pub const DELIVERY_DELIVERED: &str = "delivered";
+pub const DELIVERY_DEAD: &str = "dead";
+
+// 未指定时生成的签名密钥前缀，自定义密钥至少16个字符
+const SECRET_PREFIX: &str = "whsec_";
+const MIN_SECRET_LEN: usize = 16;
+
+// 推送请求头，签名为 t=时间戳,v1=HMAC-SHA256("时间戳.请求体") 的十六进制
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+
+// 每轮最多领取的投递数
+const CLAIM_BATCH: i64 = 20;
+
+// 领取后在该时间内不会被其他实例重复领取，实例在投递中途退出时到期后重试。
+// 一批投递并发发送，每个请求最长REQUEST_TIMEOUT，一轮远短于租期
+const CLAIM_LEASE_SECONDS: i64 = 120;
+
+// 单次推送请求的超时时间
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// 最长重试间隔
+const MAX_RETRY_DELAY_SECONDS: i64 = 6 * 3600;
+
+// 失败多少次后进入死信状态，可通过WEBHOOK_MAX_ATTEMPTS配置
+fn max_attempts() -> i32 {
+    std::env::var("WEBHOOK_MAX_ATTEMPTS").ok().and_then(|v| v.parse().ok()).unwrap_or(8)
+}
+
+// 第一次重试的间隔秒数，之后每次翻倍，可通过WEBHOOK_RETRY_BASE_SECONDS配置
+fn retry_base_seconds() -> i64 {
+    std::env::var("WEBHOOK_RETRY_BASE_SECONDS").ok().and_then(|v| v.parse().ok()).unwrap_or(30)
+}
+
+// 第attempts次失败后等待的秒数
+fn retry_delay_seconds(attempts: i32) -> i64 {
+    let exponent = attempts.saturating_sub(1).clamp(0, 30) as u32;
+    retry_base_seconds().saturating_mul(1i64 << exponent).min(MAX_RETRY_DELAY_SECONDS)
+}
+
+// Webhook错误类型
+#[derive(Error, Debug)]
+pub enum WebhookError {
+    #[error("Webhook不存在")]
+    NotFound,
+    #[error("投递记录不存在")]
+    DeliveryNotFound,
+    #[error("URL必须是http或https地址")]
+    InvalidUrl,
+    #[error("无效的事件类型: {0}")]
+    InvalidEventType(String),
+    #[error("至少需要订阅一种事件")]
+    NoEventTypes,
+    #[error("签名密钥至少需要16个字符")]
+    WeakSecret,
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+// Webhook订阅，列表中不返回签名密钥
+#[derive(Debug, Serialize, FromRow)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub url: String,
+    pub event_types: Vec<String>,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// 创建后返回的订阅，签名密钥只返回这一次
+#[derive(Debug, Serialize)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+// 创建订阅请求，不指定secret时自动生成
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    pub event_types: Vec<String>,
+    pub secret: Option<String>,
+}
+
+// 修改订阅请求，停用后新事件不再生成投递，未完成的投递在重新启用后继续
+#[derive(Debug, Deserialize)]
+pub struct UpdateWebhookRequest {
+    pub url: Option<String>,
+    pub event_types: Option<Vec<String>>,
+    pub active: Option<bool>,
+}
+
+// 投递记录，last_*为最近一次尝试的结果
+#[derive(Debug, Serialize, FromRow)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub event_id: i64,
+    pub event_type: String,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_status_code: Option<i32>,
+    pub last_latency_ms: Option<i32>,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+// 单次投递尝试
+#[derive(Debug, Serialize, FromRow)]
+pub struct WebhookAttempt {
+    pub attempted_at: DateTime<Utc>,
+    pub status_code: Option<i32>,
+    pub latency_ms: i32,
+    pub error: Option<String>,
+}
+
+// 投递详情，包含推送内容和全部尝试记录
+#[derive(Debug, Serialize)]
+pub struct WebhookDeliveryDetail {
+    #[serde(flatten)]
+    pub delivery: WebhookDelivery,
+    pub payload: Value,
+    pub attempts_log: Vec<WebhookAttempt>,
+}
+
+// 投递列表查询参数
+#[derive(Debug, Deserialize)]
+pub struct DeliveryListQuery {
+    pub status: Option<String>,
+    pub limit: Option<i64>,
+}
+
+fn validate_url(url: &str) -> Result<(), WebhookError> {
+    match url::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") && parsed.host().is_some() => Ok(()),
+        _ => Err(WebhookError::InvalidUrl),
+    }
+}
+
+fn validate_event_types(event_types: &[String]) -> Result<(), WebhookError> {
+    if event_types.is_empty() {
+        return Err(WebhookError::NoEventTypes);
+    }
+    match event_types.iter().find(|t| !WEBHOOK_EVENT_TYPES.contains(&t.as_str())) {
+        Some(invalid) => Err(WebhookError::InvalidEventType(invalid.clone())),
+        None => Ok(()),
+    }
+}
+
+// 计算签名请求头的值，接收方用同一密钥按"时间戳.请求体"重新计算并比较，并拒绝时间戳过旧的请求以防重放
+pub fn sign_payload(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC接受任意长度的密钥");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    format!("t={},v1={}", timestamp, hex::encode(mac.finalize().into_bytes()))
+}
+
+const WEBHOOK_COLUMNS: &str = "id, url, event_types, active, created_at, updated_at";
+const DELIVERY_COLUMNS: &str = "id, webhook_id, event_id, event_type, status, attempts, next_attempt_at, \
+     last_status_code, last_latency_ms, last_error, delivered_at, created_at";
+
+// Webhook订阅存储实现，所有操作限定在管理员所在的组织
+pub struct WebhookStore;
+
+impl WebhookStore {
+    // 创建订阅
+    pub async fn create(
+        pool: &PgPool,
+        organization_id: Uuid,
+        actor_id: Uuid,
+        req: &CreateWebhookRequest,
+    ) -> Result<CreatedWebhook, WebhookError> {
+        validate_url(&req.url)?;
+        validate_event_types(&req.event_types)?;
+        let secret = match &req.secret {
+            Some(secret) if secret.chars().count() < MIN_SECRET_LEN => return Err(WebhookError::WeakSecret),
+            Some(secret) => secret.clone(),
+            None => format!("{}{}", SECRET_PREFIX, generate_token()),
+        };
+
+        let webhook = sqlx::query_as::<_, Webhook>(&format!(r#"
+            INSERT INTO webhooks (organization_id, url, event_types, secret)
+            VALUES ($1, $2, $3, $4)
+            RETURNING {}
+            "#, WEBHOOK_COLUMNS))
+            .bind(organization_id)
+            .bind(&req.url)
+            .bind(&req.event_types)
+            .bind(&secret)
+            .fetch_one(pool)
+            .await?;
+
+        AuditStore::record(pool, Some(actor_id), None, "webhook_created", Some(&req.url)).await?;
+
+        Ok(CreatedWebhook { webhook, secret })
+    }
+
+    // 获取组织的全部订阅
+    pub async fn find_by_organization(pool: &PgPool, organization_id: Uuid) -> Result<Vec<Webhook>, sqlx::Error> {
+        sqlx::query_as::<_, Webhook>(&format!(
+            "SELECT {} FROM webhooks WHERE organization_id = $1 ORDER BY created_at DESC",
+            WEBHOOK_COLUMNS
+        ))
+        .bind(organization_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn find_by_id(pool: &PgPool, organization_id: Uuid, webhook_id: Uuid) -> Result<Webhook, WebhookError> {
+        sqlx::query_as::<_, Webhook>(&format!(
+            "SELECT {} FROM webhooks WHERE id = $1 AND organization_id = $2",
+            WEBHOOK_COLUMNS
+        ))
+        .bind(webhook_id)
+        .bind(organization_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(WebhookError::NotFound)
+    }
+
+    // 修改订阅
+    pub async fn update(
+        pool: &PgPool,
+        organization_id: Uuid,
+        actor_id: Uuid,
+        webhook_id: Uuid,
+        req: &UpdateWebhookRequest,
+    ) -> Result<Webhook, WebhookError> {
+        if let Some(url) = &req.url {
+            validate_url(url)?;
+        }
+        if let Some(event_types) = &req.event_types {
+            validate_event_types(event_types)?;
+        }
+
+        let webhook = sqlx::query_as::<_, Webhook>(&format!(r#"
+            UPDATE webhooks
+            SET url = COALESCE($3, url),
+                event_types = COALESCE($4, event_types),
+                active = COALESCE($5, active),
+                updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND organization_id = $2
+            RETURNING {}
+            "#, WEBHOOK_COLUMNS))
+            .bind(webhook_id)
+            .bind(organization_id)
+            .bind(&req.url)
+            .bind(&req.event_types)
+            .bind(req.active)
+            .fetch_optional(pool)
+            .await?
+            .ok_or(WebhookError::NotFound)?;
+
+        AuditStore::record(pool, Some(actor_id), None, "webhook_updated", Some(&webhook.url)).await?;
+
+        Ok(webhook)
+    }
+
+    // 删除订阅及其投递记录
+    pub async fn delete(pool: &PgPool, organization_id: Uuid, actor_id: Uuid, webhook_id: Uuid) -> Result<(), WebhookError> {
+        let url = sqlx::query_scalar::<_, String>(
+            "DELETE FROM webhooks WHERE id = $1 AND organization_id = $2 RETURNING url",
+        )
+        .bind(webhook_id)
+        .bind(organization_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(WebhookError::NotFound)?;
+
+        AuditStore::record(pool, Some(actor_id), None, "webhook_deleted", Some(&url)).await?;
+
+        Ok(())
+    }
+
+    // 订阅的投递记录，最新的在前
+    pub async fn deliveries(
+        pool: &PgPool,
+        organization_id: Uuid,
+        webhook_id: Uuid,
+        query: &DeliveryListQuery,
+    ) -> Result<Vec<WebhookDelivery>, WebhookError> {
+        Self::find_by_id(pool, organization_id, webhook_id).await?;
+        let limit = query.limit.unwrap_or(50).clamp(1, 500);
+
+        let deliveries = sqlx::query_as::<_, WebhookDelivery>(&format!(r#"
+            SELECT {}
+            FROM webhook_deliveries
+            WHERE webhook_id = $1 AND ($2::text IS NULL OR status = $2)
+            ORDER BY created_at DESC
+            LIMIT $3
+            "#, DELIVERY_COLUMNS))
+            .bind(webhook_id)
+            .bind(&query.status)
+            .bind(limit)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(deliveries)
+    }
+
+    // 投递详情
+    pub async fn delivery(
+        pool: &PgPool,
+        organization_id: Uuid,
+        webhook_id: Uuid,
+        delivery_id: Uuid,
+    ) -> Result<WebhookDeliveryDetail, WebhookError> {
+        Self::find_by_id(pool, organization_id, webhook_id).await?;
+
+        let delivery = sqlx::query_as::<_, WebhookDelivery>(&format!(
+            "SELECT {} FROM webhook_deliveries WHERE id = $1 AND webhook_id = $2",
+            DELIVERY_COLUMNS
+        ))
+        .bind(delivery_id)
+        .bind(webhook_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(WebhookError::DeliveryNotFound)?;
+        let payload = sqlx::query_scalar::<_, Value>("SELECT payload FROM webhook_deliveries WHERE id = $1")
+            .bind(delivery_id)
+            .fetch_one(pool)
+            .await?;
+
+        let attempts_log = sqlx::query_as::<_, WebhookAttempt>(r#"
+            SELECT attempted_at, status_code, latency_ms, error
+            FROM webhook_attempts
+            WHERE delivery_id = $1
+            ORDER BY attempted_at
+            "#)
+            .bind(delivery_id)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(WebhookDeliveryDetail { delivery, payload, attempts_log })
+    }
+
+    // 手动重新投递：任何状态的投递都重新进入待投递状态，并获得完整的重试次数
+    pub async fn redeliver(
+        pool: &PgPool,
+        organization_id: Uuid,
+        actor_id: Uuid,
+        webhook_id: Uuid,
+        delivery_id: Uuid,
+    ) -> Result<WebhookDelivery, WebhookError> {
+        Self::find_by_id(pool, organization_id, webhook_id).await?;
+
+        let delivery = sqlx::query_as::<_, WebhookDelivery>(&format!(r#"
+            UPDATE webhook_deliveries
+            SET status = $3, attempts = 0, next_attempt_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND webhook_id = $2
+            RETURNING {}
+            "#, DELIVERY_COLUMNS))
+            .bind(delivery_id)
+            .bind(webhook_id)
+            .bind(DELIVERY_PENDING)
+            .fetch_optional(pool)
+            .await?
+            .ok_or(WebhookError::DeliveryNotFound)?;
+
+        let detail = format!("投递{}", delivery_id);
+        AuditStore::record(pool, Some(actor_id), None, "webhook_redelivered", Some(&detail)).await?;
+
+        Ok(delivery)
+    }
+}
+
+// 领取的一次投递
+#[derive(Debug, FromRow)]
+struct ClaimedDelivery {
+    id: Uuid,
+    event_type: String,
+    payload: Value,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+// 一次请求的结果
+struct AttemptOutcome {
+    status_code: Option<i32>,
+    latency_ms: i32,
+    error: Option<String>,
+}
+
+impl AttemptOutcome {
+    fn succeeded(&self) -> bool {
+        self.status_code.is_some_and(|code| (200..300).contains(&code))
+    }
+}
+
+// 是否是公网地址：回环、私有、链路本地、运营商NAT、组播等地址都不是
+fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 198 && (b == 18 || b == 19))
+                || (a == 192 && b == 0 && c == 0))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(mapped) = ip.to_ipv4_mapped() {
+                return is_public_address(IpAddr::V4(mapped));
+            }
+            // NAT64（64:ff9b::/96）和6to4（2002::/16）地址按其中嵌入的IPv4地址判断
+            let segments = ip.segments();
+            let embedded = |high: u16, low: u16| {
+                let [a, b] = high.to_be_bytes();
+                let [c, d] = low.to_be_bytes();
+                IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+            };
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                return is_public_address(embedded(segments[6], segments[7]));
+            }
+            if segments[0] == 0x2002 {
+                return is_public_address(embedded(segments[1], segments[2]));
+            }
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+                || segments[0] & 0xffc0 == 0xfec0)
+        }
+    }
+}
+
+const PRIVATE_ADDRESS_ERROR: &str = "Webhook地址指向内网地址，已拒绝";
+
+// 解析域名后检查所有地址，只要有一个不是公网地址就拒绝，连接只使用检查过的地址，解析结果变化也无法绕过
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            if addrs.iter().any(|addr| !is_public_address(addr.ip())) {
+                return Err(PRIVATE_ADDRESS_ERROR.into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+// 推送使用的HTTP客户端：不跟随重定向，默认只连接公网地址，防止通过Webhook访问内网服务。
+// 接收方部署在内网时设置WEBHOOK_ALLOW_PRIVATE_NETWORKS=true
+#[derive(Clone)]
+pub struct WebhookClient {
+    http: reqwest::Client,
+    allow_private_networks: bool,
+}
+
+impl WebhookClient {
+    pub fn new(allow_private_networks: bool) -> reqwest::Result<Self> {
+        let mut builder = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none());
+        if !allow_private_networks {
+            builder = builder.dns_resolver(Arc::new(PublicResolver));
+        }
+        Ok(WebhookClient { http: builder.build()?, allow_private_networks })
+    }
+
+    pub fn from_env() -> reqwest::Result<Self> {
+        let allow = std::env::var("WEBHOOK_ALLOW_PRIVATE_NETWORKS").is_ok_and(|v| v == "true");
+        Self::new(allow)
+    }
+
+    // 地址直接写成IP时不经过域名解析，在发送前检查
+    fn check_address(&self, url: &str) -> Result<(), String> {
+        if self.allow_private_networks {
+            return Ok(());
+        }
+        let ip = match url::Url::parse(url).map_err(|err| err.to_string())?.host() {
+            Some(url::Host::Ipv4(ip)) => IpAddr::V4(ip),
+            Some(url::Host::Ipv6(ip)) => IpAddr::V6(ip),
+            _ => return Ok(()),
+        };
+        if is_public_address(ip) {
+            Ok(())
+        } else {
+            Err(PRIVATE_ADDRESS_ERROR.to_string())
+        }
+    }
+}
+
+// 错误及其所有原因，reqwest的错误信息本身不包含连接失败的原因
+fn error_chain(err: &(dyn StdError + 'static)) -> String {
+    std::iter::successors(Some(err), |&err| err.source())
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+// Webhook投递实现：至少投递一次，失败后按指数退避重试，超过次数进入死信状态。
+// 多个实例可以同时运行，领取时跳过其他实例已锁定的投递
+pub struct WebhookDispatcher;
+
+impl WebhookDispatcher {
+    // 投递所有到期的待投递记录，返回本轮处理的数量
+    pub async fn run_due(pool: &PgPool, client: &WebhookClient) -> Result<usize, sqlx::Error> {
+        let mut total = 0;
+        loop {
+            let claimed = Self::claim(pool).await?;
+            if claimed.is_empty() {
+                return Ok(total);
+            }
+            total += claimed.len();
+
+            // 同一批并发发送，慢的接收方不会拖住其他投递
+            let mut sends = JoinSet::new();
+            for delivery in claimed {
+                let pool = pool.clone();
+                let client = client.clone();
+                sends.spawn(async move {
+                    let outcome = Self::send(&client, &delivery).await;
+                    Self::record(&pool, &delivery, &outcome).await
+                });
+            }
+            let mut result = Ok(());
+            while let Some(joined) = sends.join_next().await {
+                match joined {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => result = Err(err),
+                    Err(err) => tracing::error!("Webhook投递任务异常退出: {}", err),
+                }
+            }
+            result?;
+        }
+    }
+
+    // 领取一批到期的投递并增加尝试次数，同时把下次尝试时间推后一个租期
+    async fn claim(pool: &PgPool) -> Result<Vec<ClaimedDelivery>, sqlx::Error> {
+        sqlx::query_as::<_, ClaimedDelivery>(r#"
+            UPDATE webhook_deliveries d
+            SET attempts = d.attempts + 1,
+                next_attempt_at = CURRENT_TIMESTAMP + make_interval(secs => $2)
+            FROM webhooks w
+            WHERE w.id = d.webhook_id
+              AND d.id IN (
+                  SELECT due.id
+                  FROM webhook_deliveries due
+                  JOIN webhooks active_webhook ON active_webhook.id = due.webhook_id AND active_webhook.active
+                  WHERE due.status = $1 AND due.next_attempt_at <= CURRENT_TIMESTAMP
+                  ORDER BY due.next_attempt_at
+                  LIMIT $3
+                  FOR UPDATE OF due SKIP LOCKED
+              )
+            RETURNING d.id, d.event_type, d.payload, d.attempts, w.url, w.secret
+            "#)
+            .bind(DELIVERY_PENDING)
+            .bind(CLAIM_LEASE_SECONDS as f64)
+            .bind(CLAIM_BATCH)
+            .fetch_all(pool)
+            .await
+    }
+
+    async fn send(client: &WebhookClient, delivery: &ClaimedDelivery) -> AttemptOutcome {
+        if let Err(error) = client.check_address(&delivery.url) {
+            return AttemptOutcome { status_code: None, latency_ms: 0, error: Some(error) };
+        }
+
+        let body = delivery.payload.to_string();
+        let signature = sign_payload(&delivery.secret, Utc::now().timestamp(), &body);
+
+        let started = Instant::now();
+        let result = client
+            .http
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, signature)
+            .header(DELIVERY_HEADER, delivery.id.to_string())
+            .header(EVENT_HEADER, &delivery.event_type)
+            .body(body)
+            .send()
+            .await;
+        let latency_ms = started.elapsed().min(Duration::from_millis(i32::MAX as u64)).as_millis() as i32;
+
+        match result {
+            Ok(response) => {
+                let status = response.status();
+                AttemptOutcome {
+                    status_code: Some(status.as_u16() as i32),
+                    latency_ms,
+                    error: (!status.is_success()).then(|| format!("接收方返回{}", status)),
+                }
+            }
+            Err(err) => AttemptOutcome { status_code: None, latency_ms, error: Some(error_chain(&err)) },
+        }
+    }
+
+    // 记录尝试结果：成功则完成，失败则安排下次重试或进入死信状态
+    async fn record(pool: &PgPool, delivery: &ClaimedDelivery, outcome: &AttemptOutcome) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query(r#"
+            INSERT INTO webhook_attempts (delivery_id, status_code, latency_ms, error)
+            VALUES ($1, $2, $3, $4)
+            "#)
+            .bind(delivery.id)
+            .bind(outcome.status_code)
+            .bind(outcome.latency_ms)
+            .bind(&outcome.error)
+            .execute(&mut *tx)
+            .await?;
+
+        let (status, retry_delay) = if outcome.succeeded() {
+            (DELIVERY_DELIVERED, 0)
+        } else if delivery.attempts >= max_attempts() {
+            tracing::warn!(delivery_id = %delivery.id, url = %delivery.url, "Webhook投递失败次数过多，已进入死信状态");
+            (DELIVERY_DEAD, 0)
+        } else {
+            (DELIVERY_PENDING, retry_delay_seconds(delivery.attempts))
+        };
+
+        sqlx::query(r#"
+            UPDATE webhook_deliveries
+            SET status = $2,
+                next_attempt_at = CURRENT_TIMESTAMP + make_interval(secs => $3),
+                last_status_code = $4,
+                last_latency_ms = $5,
+                last_error = $6,
+                delivered_at = CASE WHEN $2 = $7 THEN CURRENT_TIMESTAMP ELSE delivered_at END
+            WHERE id = $1
+            "#)
+            .bind(delivery.id)
+            .bind(status)
+            .bind(retry_delay as f64)
+            .bind(outcome.status_code)
+            .bind(outcome.latency_ms)
+            .bind(&outcome.error)
+            .bind(DELIVERY_DELIVERED)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_public_address;
+
+    #[test]
+    fn private_addresses_are_rejected() {
+        for ip in [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0",
+            "198.18.0.1", "198.19.255.255", "240.0.0.1", "255.255.255.255", "192.0.0.8",
+            "::1", "fd00::1", "fe80::1", "fec0::1", "::ffff:127.0.0.1", "::ffff:169.254.169.254",
+            "64:ff9b::7f00:1", "64:ff9b::a9fe:a9fe", "2002:a00:1::1", "2002:7f00:1::",
+        ] {
+            assert!(!is_public_address(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "93.184.216.34", "8.8.8.8", "198.20.0.1", "192.0.1.1", "2606:4700::1111", "::ffff:8.8.8.8",
+            "64:ff9b::808:808", "2002:808:808::1",
+        ] {
+            assert!(is_public_address(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::{collections::HashMap, env, fs, ops::Range, sync::OnceLock};
+use ::pinyin::ToPinyin;
+
+// 常见多音字姓氏的读音，只在姓名开头生效；拼音库按最常用读音转换，如"曾"默认为ceng
+const SURNAMES: [(&str, &str); 23] = [
+    ("万俟", "mo qi"),
+    ("尉迟", "yu chi"),
+    ("单于", "chan yu"),
+    ("长孙", "zhang sun"),
+    ("曾", "zeng"),
+    ("单", "shan"),
+    ("解", "xie"),
+    ("仇", "qiu"),
+    ("朴", "piao"),
+    ("查", "zha"),
+    ("区", "ou"),
+    ("乐", "yue"),
+    ("翟", "zhai"),
+    ("缪", "miao"),
+    ("盖", "ge"),
+    ("覃", "qin"),
+    ("员", "yun"),
+    ("种", "chong"),
+    ("宿", "su"),
+    ("繁", "po"),
+    ("句", "gou"),
+    ("召", "shao"),
+    ("秘", "bi"),
+];
+
+// 读音覆盖词典：词语 -> 每个字的拼音
+struct Dictionary {
+    surnames: HashMap<Vec<char>, Vec<String>>,
+    phrases: HashMap<Vec<char>, Vec<String>>,
+    max_len: usize,
+}
+
+fn entry(phrase: &str, readings: &str) -> Option<(Vec<char>, Vec<String>)> {
+    let phrase: Vec<char> = phrase.chars().collect();
+    let readings: Vec<String> = readings.split_whitespace().map(normalize_syllable).collect();
+    match !phrase.is_empty() && phrase.len() == readings.len() {
+        true => Some((phrase, readings)),
+        false => None,
+    }
+}
+
+// 从PINYIN_DICTIONARY指定的文件加载覆盖词典，每行为"词语 拼音 拼音..."，#开头为注释
+fn load_phrases() -> HashMap<Vec<char>, Vec<String>> {
+    let Ok(path) = env::var("PINYIN_DICTIONARY") else {
+        return HashMap::new();
+    };
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) => {
+            tracing::warn!("读取拼音词典{}失败: {}", path, err);
+            return HashMap::new();
+        }
+    };
+
+    let mut phrases = HashMap::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (phrase, readings) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        match entry(phrase, readings) {
+            Some((phrase, readings)) => {
+                phrases.insert(phrase, readings);
+            }
+            None => tracing::warn!("拼音词典{}第{}行格式错误，拼音个数必须与字数一致", path, number + 1),
+        }
+    }
+    phrases
+}
+
+fn dictionary() -> &'static Dictionary {
+    static DICTIONARY: OnceLock<Dictionary> = OnceLock::new();
+    DICTIONARY.get_or_init(|| {
+        let surnames: HashMap<_, _> = SURNAMES.iter().filter_map(|(phrase, readings)| entry(phrase, readings)).collect();
+        let phrases = load_phrases();
+        let max_len = surnames.keys().chain(phrases.keys()).map(Vec::len).max().unwrap_or(1);
+        Dictionary { surnames, phrases, max_len }
+    })
+}
+
+// 启动时加载词典，尽早发现格式错误
+pub fn init() {
+    let dictionary = dictionary();
+    if !dictionary.phrases.is_empty() {
+        tracing::info!("已加载{}条拼音覆盖词条", dictionary.phrases.len());
+    }
+}
+
+// ü按输入习惯写作v
+fn normalize_syllable(syllable: &str) -> String {
+    syllable.to_lowercase().replace('ü', "v")
+}
+
+// 姓名的拼音：full为按音节（或非汉字的单词）以空格分隔的全拼，initials为各音节的首字母
+#[derive(Debug, Default, PartialEq)]
+pub struct NamePinyin {
+    pub full: String,
+    pub initials: String,
+}
+
+// 按覆盖词典匹配最长的词语，再逐字转换；字母和数字按单词保留。返回每个音节及其在姓名中的字符范围
+fn syllables(name: &str) -> Vec<(String, Range<usize>)> {
+    let dictionary = dictionary();
+    let chars: Vec<char> = name.chars().collect();
+    let mut syllables = Vec::new();
+    let mut word = String::new();
+    let mut word_start = 0;
+
+    let mut i = 0;
+    while i < chars.len() {
+        // 覆盖词典优先于内置的姓氏读音
+        let matched = (1..=dictionary.max_len.min(chars.len() - i)).rev().find_map(|len| {
+            let phrase = &chars[i..i + len];
+            dictionary
+                .phrases
+                .get(phrase)
+                .or_else(|| if i == 0 { dictionary.surnames.get(phrase) } else { None })
+                .map(|readings| (len, readings))
+        });
+        let c = chars[i];
+        let pinyin = c.to_pinyin();
+        if !word.is_empty() && (matched.is_some() || pinyin.is_some() || !c.is_alphanumeric()) {
+            syllables.push((std::mem::take(&mut word), word_start..i));
+        }
+
+        if let Some((len, readings)) = matched {
+            syllables.extend(readings.iter().enumerate().map(|(n, reading)| (reading.clone(), i + n..i + n + 1)));
+            i += len;
+            continue;
+        }
+        match pinyin {
+            Some(pinyin) => syllables.push((normalize_syllable(pinyin.plain()), i..i + 1)),
+            None if c.is_alphanumeric() => {
+                if word.is_empty() {
+                    word_start = i;
+                }
+                word.extend(c.to_lowercase());
+            }
+            None => {}
+        }
+        i += 1;
+    }
+    if !word.is_empty() {
+        syllables.push((word, word_start..chars.len()));
+    }
+    syllables
+}
+
+// 把姓名转换为拼音
+pub fn transliterate(name: &str) -> NamePinyin {
+    let syllables = syllables(name);
+    NamePinyin {
+        initials: syllables.iter().filter_map(|(syllable, _)| syllable.chars().next()).collect(),
+        full: syllables.into_iter().map(|(syllable, _)| syllable).collect::<Vec<_>>().join(" "),
+    }
+}
+
+// 搜索关键词转换为只含小写字母和数字的拼音串，汉字、全拼、首字母和混合输入都转换为同一形式
+pub fn query_key(q: &str) -> String {
+    transliterate(q).full.chars().filter(|c| c.is_ascii_alphanumeric()).collect()
+}
+
+// 匹配拼音的正则：关键词的每个字符可以接在上一个字符之后，也可以跳过当前音节的剩余部分从下一个音节开头继续，
+// 因此"zhangsan"、"zs"、"zhangs"、"zsan"都能匹配"zhang san"；关键词只含字母和数字，不需要转义
+pub fn match_pattern(key: &str) -> Option<String> {
+    if key.is_empty() {
+        return None;
+    }
+    let body = key.chars().map(String::from).collect::<Vec<_>>().join("(?:[a-z0-9]* )?");
+    Some(format!("(^| ){}", body))
+}
+
+// 从音节start的位置offset开始匹配关键词的剩余部分，返回匹配结束的音节
+fn match_from(syllables: &[Vec<char>], key: &[char], start: usize, offset: usize) -> Option<usize> {
+    let Some(c) = key.first() else {
+        return Some(start);
+    };
+    if syllables[start].get(offset) == Some(c) {
+        if let Some(end) = match_from(syllables, &key[1..], start, offset + 1) {
+            return Some(end);
+        }
+    }
+    match syllables.get(start + 1) {
+        Some(next) if next.first() == Some(c) => match_from(syllables, &key[1..], start + 1, 1),
+        _ => None,
+    }
+}
+
+// 与match_pattern规则相同，返回姓名中被拼音关键词匹配的字符范围，用于高亮
+pub fn match_span(name: &str, key: &str) -> Option<Range<usize>> {
+    let key: Vec<char> = key.chars().collect();
+    let syllables = syllables(name);
+    let letters: Vec<Vec<char>> = syllables.iter().map(|(syllable, _)| syllable.chars().collect()).collect();
+    let first = key.first()?;
+    (0..letters.len())
+        .filter(|&start| letters[start].first() == Some(first))
+        .find_map(|start| {
+            let end = match_from(&letters, &key[1..], start, 1)?;
+            Some(syllables[start].1.start..syllables[end].1.end)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pinyin(name: &str) -> (String, String) {
+        let NamePinyin { full, initials } = transliterate(name);
+        (full, initials)
+    }
+
+    #[test]
+    fn transliterates_names() {
+        assert_eq!(pinyin("张三"), ("zhang san".to_string(), "zs".to_string()));
+        assert_eq!(pinyin("吕布"), ("lv bu".to_string(), "lb".to_string()));
+        assert_eq!(pinyin(""), (String::new(), String::new()));
+    }
+
+    #[test]
+    fn keeps_latin_words_and_digits() {
+        assert_eq!(pinyin("Tom 李"), ("tom li".to_string(), "tl".to_string()));
+        assert_eq!(pinyin("王2号"), ("wang 2 hao".to_string(), "w2h".to_string()));
+        assert_eq!(pinyin("John·Smith"), ("john smith".to_string(), "js".to_string()));
+        assert_eq!(pinyin("李Ann"), ("li ann".to_string(), "la".to_string()));
+    }
+
+    #[test]
+    fn polyphonic_surnames_only_apply_at_start() {
+        assert_eq!(pinyin("曾国藩").0, "zeng guo fan");
+        assert_eq!(pinyin("单田芳").0, "shan tian fang");
+        assert_eq!(pinyin("尉迟恭"), ("yu chi gong".to_string(), "ycg".to_string()));
+        assert_eq!(pinyin("万俟卨").1, "mqx");
+        // 不在开头时按常用读音
+        assert_eq!(pinyin("王曾").0, "wang ceng");
+        assert_eq!(pinyin("李单").0, "li dan");
+    }
+
+    #[test]
+    fn dictionary_entries_need_one_reading_per_character() {
+        assert_eq!(entry("重庆", "Chong QING"), Some((vec!['重', '庆'], vec!["chong".to_string(), "qing".to_string()])));
+        assert_eq!(entry("绿", "lü").map(|(_, readings)| readings), Some(vec!["lv".to_string()]));
+        assert_eq!(entry("重庆", "chong"), None);
+        assert_eq!(entry("", ""), None);
+    }
+
+    #[test]
+    fn query_keys_normalize_every_input_form() {
+        for q in ["张三", "zhang san", "ZhangSan", "张san", "zhang三"] {
+            assert_eq!(query_key(q), "zhangsan", "{}", q);
+        }
+        assert_eq!(query_key("zs"), "zs");
+        assert_eq!(query_key("!@#"), "");
+    }
+
+    #[test]
+    fn match_pattern_allows_skipping_to_next_syllable() {
+        assert_eq!(match_pattern(""), None);
+        assert_eq!(match_pattern("zs").unwrap(), "(^| )z(?:[a-z0-9]* )?s");
+    }
+
+    #[test]
+    fn match_span_covers_matched_characters() {
+        for key in ["zhangsan", "zs", "zhangs", "zsan", "zhsan"] {
+            assert_eq!(match_span("张三", key), Some(0..2), "{}", key);
+        }
+        assert_eq!(match_span("张三", "zhang"), Some(0..1));
+        assert_eq!(match_span("欧阳张三丰", "zsf"), Some(2..5));
+        assert_eq!(match_span("Tom 李", "tl"), Some(0..5));
+        // 只能从音节开头匹配，也不能跳过整个音节
+        assert_eq!(match_span("张三", "hang"), None);
+        assert_eq!(match_span("张三丰", "zf"), None);
+        assert_eq!(match_span("张三", "zsx"), None);
+        assert_eq!(match_span("张三", ""), None);
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt, sync::Mutex as AsyncMutex};
+use crate::model::outbox::OutboxEvent;
+
+// 领域事件发布接口，便于替换为消息队列等实现；发布成功后事件才会被标记为已发布
+#[async_trait]
+pub trait EventPublisher: Send + Sync {
+    async fn publish(&self, event: &OutboxEvent) -> anyhow::Result<()>;
+}
+
+pub type SharedPublisher = Arc<dyn EventPublisher>;
+
+// 不发布，事件直接标记为已发布。默认使用，没有配置消费方时不会把含有用户资料的事件写到任何地方
+pub struct NoopPublisher;
+
+#[async_trait]
+impl EventPublisher for NoopPublisher {
+    async fn publish(&self, _event: &OutboxEvent) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+// 每个事件输出一行JSON到标准输出，事件中有用户的姓名和邮箱，只适用于本地开发
+pub struct StdoutPublisher;
+
+#[async_trait]
+impl EventPublisher for StdoutPublisher {
+    async fn publish(&self, event: &OutboxEvent) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        let mut stdout = tokio::io::stdout();
+        stdout.write_all(&line).await?;
+        stdout.flush().await?;
+        Ok(())
+    }
+}
+
+// 每个事件追加一行JSON到文件
+pub struct FilePublisher {
+    file: AsyncMutex<tokio::fs::File>,
+}
+
+impl FilePublisher {
+    pub async fn open(path: &str) -> anyhow::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path).await?;
+        Ok(FilePublisher { file: AsyncMutex::new(file) })
+    }
+}
+
+#[async_trait]
+impl EventPublisher for FilePublisher {
+    async fn publish(&self, event: &OutboxEvent) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().await;
+        file.write_all(&line).await?;
+        file.sync_data().await?;
+        Ok(())
+    }
+}
+
+// 把事件保存在内存中，测试中直接读取events
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryPublisher {
+    pub events: std::sync::Mutex<Vec<OutboxEvent>>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl EventPublisher for MemoryPublisher {
+    async fn publish(&self, event: &OutboxEvent) -> anyhow::Result<()> {
+        self.events.lock().map_err(|_| anyhow::anyhow!("内存发布器已损坏"))?.push(event.clone());
+        Ok(())
+    }
+}
+
+// 按OUTBOX_PUBLISHER选择发布器：none（默认）、stdout、file（写入OUTBOX_FILE，默认outbox.ndjson）。
+// 内存发布器的事件没有任何读取方，发布后即丢失，只能在测试中使用
+pub async fn from_env() -> anyhow::Result<SharedPublisher> {
+    let kind = std::env::var("OUTBOX_PUBLISHER").unwrap_or_else(|_| "none".to_string());
+    match kind.as_str() {
+        "none" => Ok(Arc::new(NoopPublisher)),
+        "stdout" => Ok(Arc::new(StdoutPublisher)),
+        "file" => {
+            let path = std::env::var("OUTBOX_FILE").unwrap_or_else(|_| "outbox.ndjson".to_string());
+            Ok(Arc::new(FilePublisher::open(&path).await?))
+        }
+        "memory" => anyhow::bail!("OUTBOX_PUBLISHER=memory只能在测试中使用"),
+        other => anyhow::bail!("未知的OUTBOX_PUBLISHER: {}", other),
+    }
+}
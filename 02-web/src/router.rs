@@ -1,21 +1,196 @@
 use axum::{
-    routing::get,
+    middleware::from_fn_with_state,
+    routing::{delete, get, patch, post, put, MethodRouter},
     Router,
     Extension,
 };
 use crate::db::DbPool;
+use crate::event_hub::SharedUserEventHub;
 use crate::handler::{create_user, get_all_users, get_user, update_user, delete_user};
+use crate::handler::auth::{forgot_password, login, login_totp, logout, reset_password, reset_password_page};
+use crate::handler::{api_key, group, identity_provider, invitation, lockout, magic_link, metrics::get_metrics, oidc, organization, passkey, saml, scim, session, two_factor, user_event, user_export, user_history, user_import, user_search, user_status, webhook};
+use crate::mailer::SharedMailer;
+use crate::metrics::SharedMetrics;
+use crate::middleware::require_scope;
+use crate::model::oauth_client::{SCOPE_USERS_READ, SCOPE_USERS_WRITE};
+use crate::model::passkey::SharedWebauthn;
+use crate::model::SharedUserRepository;
+
+// 为单个方法路由加上scope校验
+fn scoped(route: MethodRouter, scope: &'static str) -> MethodRouter {
+    route.route_layer(from_fn_with_state(scope, require_scope))
+}
 
 // 创建路由
-pub fn create_router(pool: DbPool) -> Router {
+pub fn create_router(
+    pool: DbPool,
+    mailer: SharedMailer,
+    metrics: SharedMetrics,
+    webauthn: SharedWebauthn,
+    http: reqwest::Client,
+    user_events: SharedUserEventHub,
+    users: SharedUserRepository,
+) -> Router {
     // 创建路由并添加数据库连接池作为扩展
     Router::new()
-        // 用户CRUD路由
-        .route("/users", get(get_all_users).post(create_user))
+        // 用户CRUD路由，服务调用方需要对应scope，用户会话需要管理员权限
+        .route(
+            "/users",
+            scoped(get(get_all_users), SCOPE_USERS_READ)
+                .merge(scoped(post(create_user), SCOPE_USERS_WRITE)),
+        )
         .route(
             "/users/:id",
-            get(get_user).put(update_user).delete(delete_user),
+            scoped(get(get_user), SCOPE_USERS_READ)
+                .merge(scoped(put(update_user).delete(delete_user), SCOPE_USERS_WRITE)),
         )
+        .route("/users/search", scoped(get(user_search::search_users), SCOPE_USERS_READ))
+        // 用户变更事件推送，SSE与WebSocket使用相同的过滤参数
+        .route("/users/export", scoped(get(user_export::export_users), SCOPE_USERS_READ))
+        .route("/users/events", scoped(get(user_event::user_events), SCOPE_USERS_READ))
+        .route("/users/events/ws", scoped(get(user_event::user_events_ws), SCOPE_USERS_READ))
+        .route("/users/:id/groups", scoped(get(group::list_user_groups), SCOPE_USERS_READ))
+        .route("/users/:id/history", scoped(get(user_history::user_history), SCOPE_USERS_READ))
+        // 分组路由，与用户接口使用相同的scope
+        .route(
+            "/groups",
+            scoped(get(group::list_groups), SCOPE_USERS_READ)
+                .merge(scoped(post(group::create_group), SCOPE_USERS_WRITE)),
+        )
+        .route(
+            "/groups/:id",
+            scoped(get(group::get_group), SCOPE_USERS_READ)
+                .merge(scoped(put(group::update_group).delete(group::delete_group), SCOPE_USERS_WRITE)),
+        )
+        .route(
+            "/groups/:id/members",
+            scoped(get(group::list_members), SCOPE_USERS_READ)
+                .merge(scoped(patch(group::update_members), SCOPE_USERS_WRITE)),
+        )
+        .route(
+            "/groups/:id/members/:user_id",
+            scoped(put(group::add_member).delete(group::remove_member), SCOPE_USERS_WRITE),
+        )
+        // 邀请路由，接受邀请不需要登录
+        .route("/invitations", get(invitation::list_invitations).post(invitation::create_invitation))
+        .route(
+            "/invitations/accept",
+            get(invitation::accept_invitation_page).post(invitation::accept_invitation),
+        )
+        .route("/invitations/:id", delete(invitation::revoke_invitation))
+        .route("/invitations/:id/resend", post(invitation::resend_invitation))
+        // 账号状态转换路由（管理员）
+        .route("/users/:id/activate", post(user_status::activate_user))
+        .route("/users/:id/suspend", post(user_status::suspend_user))
+        .route("/users/:id/reactivate", post(user_status::reactivate_user))
+        .route("/users/:id/lock", post(user_status::lock_user))
+        .route("/users/:id/unlock", post(user_status::unlock_user))
+        .route("/users/:id/delete", post(user_status::soft_delete_user))
+        .route("/users/:id/status-history", get(user_status::status_history))
+        .route("/users/:id/revert", post(user_history::revert_user))
+        // 认证路由
+        .route("/auth/login", post(login))
+        .route("/auth/login/2fa", post(login_totp))
+        .route("/auth/logout", post(logout))
+        .route("/auth/sessions", get(session::list_sessions))
+        .route("/auth/sessions/revoke-others", post(session::revoke_other_sessions))
+        .route("/auth/sessions/:id", delete(session::revoke_session))
+        .route("/auth/password/forgot", post(forgot_password))
+        .route("/auth/password/reset", get(reset_password_page).post(reset_password))
+        .route("/auth/magic-link", post(magic_link::request_magic_link))
+        .route("/auth/magic-link/verify", get(magic_link::verify_magic_link))
+        // API密钥路由
+        .route("/auth/api-keys", get(api_key::list_api_keys).post(api_key::create_api_key))
+        .route("/auth/api-keys/:id", delete(api_key::revoke_api_key))
+        // 外部身份提供方登录路由
+        .route("/auth/oidc/:provider/login", get(identity_provider::start_login))
+        .route("/auth/oidc/:provider/link", post(identity_provider::start_link))
+        .route("/auth/oidc/:provider/callback", get(identity_provider::callback))
+        .route("/auth/identities", get(identity_provider::list_identities))
+        .route("/auth/identities/:id", delete(identity_provider::unlink_identity))
+        .route(
+            "/admin/identity-providers",
+            get(identity_provider::list_providers).post(identity_provider::create_provider),
+        )
+        .route("/admin/identity-providers/:slug", delete(identity_provider::delete_provider))
+        // SAML服务提供方路由
+        .route("/saml/:tenant/metadata", get(saml::metadata))
+        .route("/saml/:tenant/login", get(saml::login))
+        .route("/saml/:tenant/acs", post(saml::acs))
+        .route("/admin/saml", get(saml::list_connections))
+        .route("/admin/saml/:tenant", put(saml::put_connection).delete(saml::delete_connection))
+        // SCIM 2.0路由，使用组织的SCIM令牌认证，发现接口不需要认证
+        .route("/scim/v2/Users", get(scim::list_users).post(scim::create_user))
+        .route(
+            "/scim/v2/Users/:id",
+            get(scim::get_user).put(scim::replace_user).patch(scim::patch_user).delete(scim::delete_user),
+        )
+        .route("/scim/v2/Groups", get(scim::list_groups).post(scim::create_group))
+        .route(
+            "/scim/v2/Groups/:id",
+            get(scim::get_group).put(scim::replace_group).patch(scim::patch_group).delete(scim::delete_group),
+        )
+        .route("/scim/v2/ServiceProviderConfig", get(scim::service_provider_config))
+        .route("/scim/v2/Schemas", get(scim::list_schemas))
+        .route("/scim/v2/Schemas/:id", get(scim::get_schema))
+        .route("/scim/v2/ResourceTypes", get(scim::list_resource_types))
+        .route("/scim/v2/ResourceTypes/:id", get(scim::get_resource_type))
+        .route("/admin/scim/tokens", get(scim::list_tokens).post(scim::create_token))
+        .route("/admin/scim/tokens/:id", delete(scim::revoke_token))
+        // Webhook订阅管理路由（管理员）
+        .route("/admin/webhooks", get(webhook::list_webhooks).post(webhook::create_webhook))
+        .route(
+            "/admin/webhooks/:id",
+            get(webhook::get_webhook).put(webhook::update_webhook).delete(webhook::delete_webhook),
+        )
+        .route("/admin/webhooks/:id/deliveries", get(webhook::list_deliveries))
+        .route("/admin/webhooks/:id/deliveries/:delivery_id", get(webhook::get_delivery))
+        .route("/admin/webhooks/:id/deliveries/:delivery_id/redeliver", post(webhook::redeliver))
+        // 用户批量导入路由（管理员）
+        .route("/admin/user-imports", get(user_import::list_imports).post(user_import::create_import))
+        .route("/admin/user-imports/:id", get(user_import::get_import))
+        .route("/admin/user-imports/:id/errors", get(user_import::import_errors))
+        // 两步验证路由
+        .route("/auth/2fa/enroll", post(two_factor::enroll))
+        .route("/auth/2fa/confirm", post(two_factor::confirm))
+        .route("/auth/2fa/disable", post(two_factor::disable))
+        .route("/admin/roles/2fa", get(two_factor::list_role_policies))
+        .route("/admin/roles/:role/2fa", put(two_factor::set_role_policy))
+        // 通行密钥路由
+        .route("/auth/passkeys", get(passkey::list_passkeys))
+        .route("/auth/passkeys/:id", delete(passkey::delete_passkey))
+        .route("/auth/passkeys/register/start", post(passkey::start_registration))
+        .route("/auth/passkeys/register/finish", post(passkey::finish_registration))
+        .route("/auth/passkeys/login/start", post(passkey::start_login))
+        .route("/auth/passkeys/login/finish", post(passkey::finish_login))
+        // 组织管理路由（平台管理员）
+        .route(
+            "/admin/organizations",
+            get(organization::list_organizations).post(organization::create_organization),
+        )
+        // 登录锁定管理路由
+        .route("/admin/lockouts", get(lockout::list_lockouts))
+        .route("/admin/lockouts/unlock", post(lockout::unlock))
+        // OpenID Connect身份提供方路由
+        .route("/.well-known/openid-configuration", get(oidc::discovery))
+        .route("/oauth2/jwks", get(oidc::jwks))
+        .route("/oauth2/authorize", get(oidc::authorize).post(oidc::consent))
+        .route("/oauth2/token", post(oidc::token))
+        .route("/oauth/token", post(oidc::token))
+        .route("/oauth2/userinfo", get(oidc::userinfo))
+        .route("/admin/oauth/clients", get(oidc::list_clients).post(oidc::create_client))
+        .route("/admin/oauth/clients/:client_id", delete(oidc::delete_client))
+        .route("/admin/oauth/clients/:client_id/rotate", post(oidc::rotate_client_secret))
+        .route("/admin/oauth/clients/:client_id/revoke", post(oidc::revoke_client))
+        .route("/admin/oidc/keys/rotate", post(oidc::rotate_signing_key))
+        // 指标
+        .route("/metrics", get(get_metrics))
         // 添加数据库连接池作为扩展
         .layer(Extension(pool))
-}
+        .layer(Extension(mailer))
+        .layer(Extension(metrics))
+        .layer(Extension(webauthn))
+        .layer(Extension(http))
+        .layer(Extension(user_events))
+        .layer(Extension(users))
+}
\ No newline at end of file
@@ -0,0 +1,616 @@
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{Postgres, QueryBuilder};
+use thiserror::Error;
+use uuid::Uuid;
+
+// SCIM过滤表达式（RFC 7644 3.4.2.2）和PATCH路径的解析，以及把过滤表达式转换为SQL条件
+
+// 过滤表达式的最大嵌套层数，括号、not、方括号和每个and/or都算一层；解析、求值和生成SQL都是递归的，限制层数避免栈溢出
+pub const MAX_FILTER_DEPTH: usize = 32;
+
+// 过滤表达式错误
+#[derive(Error, Debug)]
+pub enum FilterError {
+    #[error("无效的表达式: {0}")]
+    Syntax(String),
+    #[error("不支持按属性{0}过滤")]
+    UnknownAttribute(String),
+    #[error("属性{0}不支持该比较")]
+    UnsupportedComparison(String),
+    #[error("表达式嵌套超过{MAX_FILTER_DEPTH}层")]
+    TooDeep,
+}
+
+// 比较运算符
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Co,
+    Sw,
+    Ew,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl CompareOp {
+    fn parse(word: &str) -> Option<Self> {
+        Some(match word.to_ascii_lowercase().as_str() {
+            "eq" => CompareOp::Eq,
+            "ne" => CompareOp::Ne,
+            "co" => CompareOp::Co,
+            "sw" => CompareOp::Sw,
+            "ew" => CompareOp::Ew,
+            "gt" => CompareOp::Gt,
+            "ge" => CompareOp::Ge,
+            "lt" => CompareOp::Lt,
+            "le" => CompareOp::Le,
+            _ => return None,
+        })
+    }
+
+    fn sql(self) -> &'static str {
+        match self {
+            CompareOp::Eq => "=",
+            CompareOp::Ne => "<>",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Co | CompareOp::Sw | CompareOp::Ew => unreachable!(),
+        }
+    }
+}
+
+// 过滤表达式，属性路径已转为小写并去掉schema前缀
+#[derive(Debug, Clone)]
+pub enum Filter {
+    Present(String),
+    Compare(String, CompareOp, Value),
+    // 多值属性的元素过滤，如 emails[type eq "work"]
+    ValuePath(String, Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+}
+
+// PATCH操作的路径：属性[元素过滤].子属性
+#[derive(Debug)]
+pub struct PatchPath {
+    pub attribute: String,
+    pub filter: Option<Filter>,
+    pub sub_attribute: Option<String>,
+}
+
+// 可过滤属性的类型，决定比较方式
+#[derive(Debug, Clone, Copy)]
+pub enum AttributeKind {
+    // 不区分大小写的字符串
+    Text,
+    // 区分大小写的字符串
+    CaseExactText,
+    Boolean,
+    Timestamp,
+    Id,
+}
+
+// 可过滤属性对应的SQL表达式
+#[derive(Debug, Clone, Copy)]
+pub struct Attribute {
+    pub expression: &'static str,
+    pub kind: AttributeKind,
+}
+
+// 去掉完整schema前缀，如 urn:ietf:params:scim:schemas:core:2.0:User:userName -> username
+pub fn normalize_attribute(path: &str) -> String {
+    let path = match path.rfind(':') {
+        Some(index) => &path[index + 1..],
+        None => path,
+    };
+    path.to_ascii_lowercase()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Literal(Value),
+    Open,
+    Close,
+    OpenBracket,
+    CloseBracket,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, FilterError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' | '[' | ']' => {
+                chars.next();
+                tokens.push(match c {
+                    '(' => Token::Open,
+                    ')' => Token::Close,
+                    '[' => Token::OpenBracket,
+                    _ => Token::CloseBracket,
+                });
+            }
+            '"' => {
+                // 字符串按JSON规则处理转义
+                chars.next();
+                let mut end = None;
+                let mut escaped = false;
+                for (index, c) in chars.by_ref() {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '"' => {
+                            end = Some(index);
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                let end = end.ok_or_else(|| FilterError::Syntax("字符串缺少结束引号".to_string()))?;
+                let literal = serde_json::from_str::<String>(&input[start..=end])
+                    .map_err(|_| FilterError::Syntax("字符串转义无效".to_string()))?;
+                tokens.push(Token::Literal(Value::String(literal)));
+            }
+            _ => {
+                let mut end = input.len();
+                while let Some(&(index, c)) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | '"') {
+                        end = index;
+                        break;
+                    }
+                    chars.next();
+                }
+                let word = &input[start..end];
+                match word.to_ascii_lowercase().as_str() {
+                    "true" | "false" | "null" => tokens.push(Token::Literal(serde_json::from_str(&word.to_ascii_lowercase()).unwrap())),
+                    _ => match word.parse::<f64>() {
+                        Ok(_) => tokens.push(Token::Literal(serde_json::from_str(word).map_err(|_| FilterError::Syntax(word.to_string()))?)),
+                        Err(_) => tokens.push(Token::Word(word.to_string())),
+                    },
+                }
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn expect(&mut self, expected: Token, message: &str) -> Result<(), FilterError> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            _ => Err(FilterError::Syntax(message.to_string())),
+        }
+    }
+
+    // 以下解析函数的level为外层括号、not和方括号的层数，返回表达式及其层数
+    fn parse_or(&mut self, level: usize) -> Result<(Filter, usize), FilterError> {
+        check_depth(level)?;
+        let (mut filter, mut depth) = self.parse_and(level)?;
+        while self.peek_keyword("or") {
+            self.next();
+            let (right, right_depth) = self.parse_and(level)?;
+            depth = check_depth(depth.max(right_depth) + 1)?;
+            filter = Filter::Or(Box::new(filter), Box::new(right));
+        }
+        Ok((filter, depth))
+    }
+
+    fn parse_and(&mut self, level: usize) -> Result<(Filter, usize), FilterError> {
+        let (mut filter, mut depth) = self.parse_unary(level)?;
+        while self.peek_keyword("and") {
+            self.next();
+            let (right, right_depth) = self.parse_unary(level)?;
+            depth = check_depth(depth.max(right_depth) + 1)?;
+            filter = Filter::And(Box::new(filter), Box::new(right));
+        }
+        Ok((filter, depth))
+    }
+
+    fn parse_unary(&mut self, level: usize) -> Result<(Filter, usize), FilterError> {
+        if self.peek_keyword("not") {
+            self.next();
+            self.expect(Token::Open, "not之后需要括号")?;
+            let (filter, depth) = self.parse_or(level + 1)?;
+            self.expect(Token::Close, "缺少右括号")?;
+            return Ok((Filter::Not(Box::new(filter)), check_depth(depth + 1)?));
+        }
+        if self.peek() == Some(&Token::Open) {
+            self.next();
+            let parsed = self.parse_or(level + 1)?;
+            self.expect(Token::Close, "缺少右括号")?;
+            return Ok(parsed);
+        }
+
+        let attribute = match self.next() {
+            Some(Token::Word(word)) => normalize_attribute(&word),
+            _ => return Err(FilterError::Syntax("需要属性名".to_string())),
+        };
+        if self.peek() == Some(&Token::OpenBracket) {
+            self.next();
+            let (filter, depth) = self.parse_or(level + 1)?;
+            self.expect(Token::CloseBracket, "缺少右方括号")?;
+            return Ok((Filter::ValuePath(attribute, Box::new(filter)), check_depth(depth + 1)?));
+        }
+
+        let operator = match self.next() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("pr") => return Ok((Filter::Present(attribute), 1)),
+            Some(Token::Word(word)) => CompareOp::parse(&word),
+            _ => None,
+        }
+        .ok_or_else(|| FilterError::Syntax(format!("属性{}之后需要比较运算符", attribute)))?;
+        match self.next() {
+            Some(Token::Literal(value)) => Ok((Filter::Compare(attribute, operator, value), 1)),
+            _ => Err(FilterError::Syntax("比较运算符之后需要值".to_string())),
+        }
+    }
+}
+
+fn check_depth(depth: usize) -> Result<usize, FilterError> {
+    if depth > MAX_FILTER_DEPTH {
+        return Err(FilterError::TooDeep);
+    }
+    Ok(depth)
+}
+
+// 解析过滤表达式
+pub fn parse_filter(input: &str) -> Result<Filter, FilterError> {
+    let mut parser = Parser { tokens: tokenize(input)?, position: 0 };
+    let (filter, _) = parser.parse_or(0)?;
+    if parser.peek().is_some() {
+        return Err(FilterError::Syntax("表达式末尾有多余内容".to_string()));
+    }
+    Ok(filter)
+}
+
+// 解析PATCH路径，如 name.givenName、members[value eq "..."]、emails[type eq "work"].value
+pub fn parse_path(input: &str) -> Result<PatchPath, FilterError> {
+    let input = input.trim();
+    let (attribute, filter, rest) = match input.find('[') {
+        Some(open) => {
+            let close = input.rfind(']').ok_or_else(|| FilterError::Syntax("缺少右方括号".to_string()))?;
+            if close < open {
+                return Err(FilterError::Syntax("方括号不匹配".to_string()));
+            }
+            let filter = parse_filter(&input[open + 1..close])?;
+            (&input[..open], Some(filter), input[close + 1..].strip_prefix('.'))
+        }
+        None => (input, None, None),
+    };
+
+    let attribute = normalize_attribute(attribute);
+    if attribute.is_empty() {
+        return Err(FilterError::Syntax("路径缺少属性名".to_string()));
+    }
+    match (filter, rest) {
+        (Some(filter), sub_attribute) => Ok(PatchPath {
+            attribute,
+            filter: Some(filter),
+            sub_attribute: sub_attribute.map(str::to_ascii_lowercase),
+        }),
+        (None, _) => match attribute.split_once('.') {
+            Some((attribute, sub_attribute)) => Ok(PatchPath {
+                attribute: attribute.to_string(),
+                filter: None,
+                sub_attribute: Some(sub_attribute.to_string()),
+            }),
+            None => Ok(PatchPath { attribute, filter: None, sub_attribute: None }),
+        },
+    }
+}
+
+// 按不区分大小写的属性名读取JSON对象的字段
+pub fn get_field<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+    value.as_object()?.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value)
+}
+
+fn compare_values(actual: &Value, operator: CompareOp, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::String(actual), Value::String(expected)) => {
+            let (actual, expected) = (actual.to_lowercase(), expected.to_lowercase());
+            match operator {
+                CompareOp::Eq => actual == expected,
+                CompareOp::Ne => actual != expected,
+                CompareOp::Co => actual.contains(&expected),
+                CompareOp::Sw => actual.starts_with(&expected),
+                CompareOp::Ew => actual.ends_with(&expected),
+                CompareOp::Gt => actual > expected,
+                CompareOp::Ge => actual >= expected,
+                CompareOp::Lt => actual < expected,
+                CompareOp::Le => actual <= expected,
+            }
+        }
+        (actual, expected) => match operator {
+            CompareOp::Eq => actual == expected,
+            CompareOp::Ne => actual != expected,
+            _ => false,
+        },
+    }
+}
+
+// 在内存中判断多值属性的元素是否满足过滤条件，用于PATCH路径
+pub fn matches(filter: &Filter, value: &Value) -> bool {
+    match filter {
+        Filter::Present(path) => get_field(value, path).is_some_and(|value| !value.is_null()),
+        Filter::Compare(path, operator, expected) => match get_field(value, path) {
+            Some(actual) => compare_values(actual, *operator, expected),
+            None => *operator == CompareOp::Ne,
+        },
+        Filter::ValuePath(path, inner) => get_field(value, path)
+            .and_then(Value::as_array)
+            .is_some_and(|items| items.iter().any(|item| matches(inner, item))),
+        Filter::And(left, right) => matches(left, value) && matches(right, value),
+        Filter::Or(left, right) => matches(left, value) || matches(right, value),
+        Filter::Not(inner) => !matches(inner, value),
+    }
+}
+
+// 把过滤表达式追加为SQL条件，值全部通过参数绑定；attribute把属性路径映射为SQL表达式
+pub fn push_sql(
+    filter: &Filter,
+    attribute: &dyn Fn(&str) -> Option<Attribute>,
+    builder: &mut QueryBuilder<'_, Postgres>,
+) -> Result<(), FilterError> {
+    match filter {
+        Filter::And(left, right) | Filter::Or(left, right) => {
+            builder.push("(");
+            push_sql(left, attribute, builder)?;
+            builder.push(if matches!(filter, Filter::And(..)) { " AND " } else { " OR " });
+            push_sql(right, attribute, builder)?;
+            builder.push(")");
+        }
+        Filter::Not(inner) => {
+            builder.push("NOT (");
+            push_sql(inner, attribute, builder)?;
+            builder.push(")");
+        }
+        Filter::ValuePath(path, inner) => {
+            let prefixed = |sub: &str| attribute(&format!("{}.{}", path, sub));
+            push_sql(inner, &prefixed, builder)?;
+        }
+        Filter::Present(path) => {
+            let target = attribute(path).ok_or_else(|| FilterError::UnknownAttribute(path.clone()))?;
+            match target.kind {
+                AttributeKind::Text | AttributeKind::CaseExactText => {
+                    builder.push(format!("COALESCE({}, '') <> ''", target.expression));
+                }
+                _ => {
+                    builder.push(format!("{} IS NOT NULL", target.expression));
+                }
+            }
+        }
+        Filter::Compare(path, operator, value) => {
+            let target = attribute(path).ok_or_else(|| FilterError::UnknownAttribute(path.clone()))?;
+            let unsupported = || FilterError::UnsupportedComparison(path.clone());
+            match target.kind {
+                AttributeKind::Text | AttributeKind::CaseExactText => {
+                    let value = value.as_str().ok_or_else(unsupported)?.to_string();
+                    let (column, bound) = match target.kind {
+                        AttributeKind::Text => (format!("lower({})", target.expression), value.to_lowercase()),
+                        _ => (target.expression.to_string(), value),
+                    };
+                    match operator {
+                        CompareOp::Co => builder.push(format!("strpos({}, ", column)).push_bind(bound).push(") > 0"),
+                        CompareOp::Sw => builder.push(format!("starts_with({}, ", column)).push_bind(bound).push(")"),
+                        CompareOp::Ew => builder
+                            .push(format!("right({}, char_length(", column))
+                            .push_bind(bound.clone())
+                            .push(")) = ")
+                            .push_bind(bound),
+                        CompareOp::Ne => builder
+                            .push(format!("({} IS NULL OR {} <> ", column, column))
+                            .push_bind(bound)
+                            .push(")"),
+                        _ => builder.push(format!("{} {} ", column, operator.sql())).push_bind(bound),
+                    };
+                }
+                AttributeKind::Boolean => {
+                    let value = value.as_bool().ok_or_else(unsupported)?;
+                    if !matches!(operator, CompareOp::Eq | CompareOp::Ne) {
+                        return Err(unsupported());
+                    }
+                    builder.push(format!("{} {} ", target.expression, operator.sql())).push_bind(value);
+                }
+                AttributeKind::Timestamp => {
+                    let value = value
+                        .as_str()
+                        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+                        .map(|value| value.with_timezone(&Utc))
+                        .ok_or_else(unsupported)?;
+                    if matches!(operator, CompareOp::Co | CompareOp::Sw | CompareOp::Ew) {
+                        return Err(unsupported());
+                    }
+                    builder.push(format!("{} {} ", target.expression, operator.sql())).push_bind(value);
+                }
+                AttributeKind::Id => {
+                    let value = value.as_str().ok_or_else(unsupported)?;
+                    match (operator, value.parse::<Uuid>()) {
+                        (CompareOp::Eq | CompareOp::Ne, Ok(id)) => {
+                            builder.push(format!("{} {} ", target.expression, operator.sql())).push_bind(id);
+                        }
+                        // 不是合法UUID的ID不可能匹配
+                        (CompareOp::Eq, Err(_)) => {
+                            builder.push("FALSE");
+                        }
+                        (CompareOp::Ne, Err(_)) => {
+                            builder.push("TRUE");
+                        }
+                        _ => return Err(unsupported()),
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn attribute(name: &str) -> Option<Attribute> {
+        let (expression, kind) = match name {
+            "username" => ("u.email", AttributeKind::Text),
+            "externalid" => ("u.external_id", AttributeKind::CaseExactText),
+            "active" => ("u.active", AttributeKind::Boolean),
+            "meta.lastmodified" => ("u.updated_at", AttributeKind::Timestamp),
+            "id" => ("u.id", AttributeKind::Id),
+            "emails.value" => ("u.email", AttributeKind::Text),
+            _ => return None,
+        };
+        Some(Attribute { expression, kind })
+    }
+
+    fn sql(input: &str) -> Result<String, FilterError> {
+        let mut builder = QueryBuilder::new("");
+        push_sql(&parse_filter(input)?, &attribute, &mut builder)?;
+        Ok(builder.sql().to_string())
+    }
+
+    #[test]
+    fn parses_comparisons_and_literals() {
+        let Filter::Compare(path, operator, value) = parse_filter(r#"userName Eq "a\"b@example.com""#).unwrap() else { panic!() };
+        assert_eq!((path.as_str(), operator, value), ("username", CompareOp::Eq, json!("a\"b@example.com")));
+
+        let Filter::Compare(_, _, value) = parse_filter("active eq TRUE").unwrap() else { panic!() };
+        assert_eq!(value, json!(true));
+        let Filter::Compare(_, operator, value) = parse_filter("count ge 10").unwrap() else { panic!() };
+        assert_eq!((operator, value), (CompareOp::Ge, json!(10)));
+
+        let Filter::Compare(path, _, _) = parse_filter(r#"urn:ietf:params:scim:schemas:core:2.0:User:userName sw "a""#).unwrap() else { panic!() };
+        assert_eq!(path, "username");
+        assert!(matches!(parse_filter("title pr").unwrap(), Filter::Present(path) if path == "title"));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let filter = parse_filter(r#"a eq "1" or b eq "2" and c eq "3""#).unwrap();
+        let Filter::Or(left, right) = filter else { panic!("{:?}", filter) };
+        assert!(matches!(*left, Filter::Compare(ref path, ..) if path == "a"));
+        assert!(matches!(*right, Filter::And(..)));
+
+        let filter = parse_filter(r#"(a eq "1" or b eq "2") and not (c pr)"#).unwrap();
+        let Filter::And(left, right) = filter else { panic!("{:?}", filter) };
+        assert!(matches!(*left, Filter::Or(..)));
+        assert!(matches!(*right, Filter::Not(..)));
+    }
+
+    #[test]
+    fn rejects_invalid_syntax() {
+        for input in [
+            "",
+            "userName",
+            r#"userName eq"#,
+            r#"userName zz "a""#,
+            r#"userName eq "a" and"#,
+            r#"(userName eq "a""#,
+            r#"userName eq "a")"#,
+            r#"not userName eq "a""#,
+            r#"emails[type eq "work""#,
+            r#"userName eq "unterminated"#,
+        ] {
+            assert!(matches!(parse_filter(input), Err(FilterError::Syntax(_))), "{}", input);
+        }
+    }
+
+    #[test]
+    fn limits_nesting_depth() {
+        let nested = |depth: usize| format!("{}a pr{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(parse_filter(&nested(MAX_FILTER_DEPTH)).is_ok());
+        assert!(matches!(parse_filter(&nested(MAX_FILTER_DEPTH + 1)), Err(FilterError::TooDeep)));
+        // 超长的嵌套不会导致栈溢出
+        assert!(matches!(parse_filter(&nested(100_000)), Err(FilterError::TooDeep)));
+
+        let nots = |depth: usize| format!("{}a pr{}", "not (".repeat(depth), ")".repeat(depth));
+        assert!(parse_filter(&nots(MAX_FILTER_DEPTH - 1)).is_ok());
+        assert!(matches!(parse_filter(&nots(MAX_FILTER_DEPTH)), Err(FilterError::TooDeep)));
+
+        // 连续的and/or没有括号也会形成深层的表达式树
+        let chain = |terms: usize| vec!["a pr"; terms].join(" and ");
+        assert!(parse_filter(&chain(MAX_FILTER_DEPTH)).is_ok());
+        assert!(matches!(parse_filter(&chain(MAX_FILTER_DEPTH + 1)), Err(FilterError::TooDeep)));
+        assert!(matches!(parse_filter(&chain(50_000)), Err(FilterError::TooDeep)));
+    }
+
+    #[test]
+    fn parses_patch_paths() {
+        let path = parse_path("name.givenName").unwrap();
+        assert_eq!((path.attribute.as_str(), path.sub_attribute.as_deref()), ("name", Some("givenname")));
+        assert!(path.filter.is_none());
+
+        let path = parse_path(r#"emails[type eq "work"].value"#).unwrap();
+        assert_eq!((path.attribute.as_str(), path.sub_attribute.as_deref()), ("emails", Some("value")));
+        assert!(matches!(path.filter, Some(Filter::Compare(ref attribute, CompareOp::Eq, _)) if attribute == "type"));
+
+        let path = parse_path(r#"members[value eq "1"]"#).unwrap();
+        assert_eq!((path.attribute.as_str(), path.sub_attribute), ("members", None));
+
+        assert!(parse_path("").is_err());
+        assert!(parse_path(r#"emails]type eq "work"["#).is_err());
+    }
+
+    #[test]
+    fn matches_values_in_memory() {
+        let email = json!({ "type": "Work", "value": "A@Example.com", "primary": true });
+        let matches_filter = |input: &str| matches(&parse_filter(input).unwrap(), &email);
+        assert!(matches_filter(r#"type eq "work""#));
+        assert!(matches_filter(r#"value ew "example.com" and primary eq true"#));
+        assert!(matches_filter(r#"display ne "x""#));
+        assert!(!matches_filter(r#"display pr"#));
+        assert!(!matches_filter(r#"not (type eq "work")"#));
+        assert!(matches_filter(r#"type eq "home" or value co "@example""#));
+
+        let user = json!({ "emails": [{ "type": "home" }, email] });
+        assert!(matches(&parse_filter(r#"emails[type eq "work"]"#).unwrap(), &user));
+        assert!(!matches(&parse_filter(r#"emails[type eq "other"]"#).unwrap(), &user));
+    }
+
+    #[test]
+    fn builds_parameterized_sql() {
+        assert_eq!(sql(r#"userName eq "A@Example.com""#).unwrap(), "lower(u.email) = $1");
+        assert_eq!(sql(r#"externalId sw "abc""#).unwrap(), "starts_with(u.external_id, $1)");
+        assert_eq!(sql(r#"userName co "x" or active eq false"#).unwrap(), "(strpos(lower(u.email), $1) > 0 OR u.active = $2)");
+        assert_eq!(sql(r#"not (userName ne "x")"#).unwrap(), "NOT ((lower(u.email) IS NULL OR lower(u.email) <> $1))");
+        assert_eq!(sql(r#"emails[value ew "@example.com"]"#).unwrap(), "right(lower(u.email), char_length($1)) = $2");
+        assert_eq!(sql("externalId pr").unwrap(), "COALESCE(u.external_id, '') <> ''");
+        assert_eq!(sql(r#"meta.lastModified gt "2024-01-01T00:00:00Z""#).unwrap(), "u.updated_at > $1");
+        assert_eq!(sql(r#"id eq "not-a-uuid""#).unwrap(), "FALSE");
+
+        assert!(matches!(sql(r#"title eq "x""#), Err(FilterError::UnknownAttribute(path)) if path == "title"));
+        assert!(matches!(sql(r#"active gt true"#), Err(FilterError::UnsupportedComparison(_))));
+        assert!(matches!(sql(r#"userName eq true"#), Err(FilterError::UnsupportedComparison(_))));
+        assert!(matches!(sql(r#"meta.lastModified eq "yesterday""#), Err(FilterError::UnsupportedComparison(_))));
+        assert!(matches!(sql(r#"id sw "a""#), Err(FilterError::UnsupportedComparison(_))));
+    }
+}
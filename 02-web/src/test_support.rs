@@ -0,0 +1,253 @@
+// 测试辅助：使用独立的测试数据库，通过路由直接发送请求。
+// 测试数据库为DATABASE_URL所在服务器上的 <数据库名>_test，每次运行测试时重建；未配置DATABASE_URL时跳过依赖数据库的测试
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use async_trait::async_trait;
+use axum::{
+    body::{to_bytes, Body},
+    extract::connect_info::MockConnectInfo,
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    Router,
+};
+use rand::Rng;
+use serde_json::Value;
+use sqlx::postgres::PgPoolOptions;
+use tower::ServiceExt;
+use uuid::Uuid;
+use crate::db::{self, DbPool};
+use crate::mailer::{Email, Mailer, SharedMailer};
+use crate::model::{CreateUserRequest, SharedUserRepository, User, UserStore};
+
+// 测试用户的默认密码
+pub const PASSWORD: &str = "test-password-123";
+
+// 测试数据库的连接地址，第一次调用时在独立的线程中建库、执行迁移并生成签名密钥
+fn database_url() -> Option<&'static str> {
+    static URL: OnceLock<Option<String>> = OnceLock::new();
+    URL.get_or_init(|| {
+        dotenv::dotenv().ok();
+        let url = std::env::var("DATABASE_URL").ok()?;
+        let (server, name) = url.rsplit_once('/').expect("DATABASE_URL缺少数据库名");
+        let name = name.split('?').next().unwrap_or(name);
+        let test_url = format!("{}/{}_test", server, name);
+
+        let setup_url = test_url.clone();
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new().unwrap().block_on(async {
+                let admin = PgPoolOptions::new().max_connections(1).connect(&url).await.expect("连接数据库失败");
+                let test_name = setup_url.rsplit('/').next().unwrap().to_string();
+                sqlx::query(&format!("DROP DATABASE IF EXISTS \"{}\" WITH (FORCE)", test_name))
+                    .execute(&admin)
+                    .await
+                    .expect("删除测试数据库失败");
+                sqlx::query(&format!("CREATE DATABASE \"{}\"", test_name))
+                    .execute(&admin)
+                    .await
+                    .expect("创建测试数据库失败");
+                admin.close().await;
+
+                let pool = PgPoolOptions::new().max_connections(1).connect(&setup_url).await.expect("连接测试数据库失败");
+                db::run_migrations(&pool).await.expect("测试数据库迁移失败");
+                crate::model::signing_key::SigningKeyStore::rotate_if_due(&pool).await.expect("生成签名密钥失败");
+                pool.close().await;
+            });
+        })
+        .join()
+        .expect("准备测试数据库失败");
+
+        Some(test_url)
+    })
+    .as_deref()
+}
+
+// 连接测试数据库，未配置DATABASE_URL时返回None
+pub async fn pool() -> Option<DbPool> {
+    let Some(url) = database_url() else {
+        eprintln!("未设置DATABASE_URL，跳过依赖数据库的测试");
+        return None;
+    };
+    Some(PgPoolOptions::new().max_connections(5).connect(url).await.expect("连接测试数据库失败"))
+}
+
+// 带随机后缀的标识，测试共用一个数据库，数据之间不能冲突
+pub fn unique(prefix: &str) -> String {
+    format!("{}-{}", prefix, &Uuid::new_v4().simple().to_string()[..12])
+}
+
+// 记录发出的邮件
+#[derive(Default)]
+pub struct RecordingMailer {
+    sent: Mutex<Vec<Email>>,
+}
+
+impl RecordingMailer {
+    // 等待后台任务发出给该地址的邮件
+    pub async fn wait_for(&self, to: &str) -> Email {
+        for _ in 0..100 {
+            if let Some(email) = self.sent.lock().unwrap().iter().rev().find(|email| email.to == to) {
+                return email.clone();
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        panic!("没有发给{}的邮件", to);
+    }
+}
+
+#[async_trait]
+impl Mailer for RecordingMailer {
+    async fn send(&self, email: Email) -> anyhow::Result<()> {
+        self.sent.lock().unwrap().push(email);
+        Ok(())
+    }
+}
+
+// 响应状态、响应头和JSON响应体，响应体不是JSON时为字符串
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Value,
+}
+
+impl TestResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+}
+
+// 与main中相同的路由，客户端地址为随机的内网地址，避免各测试共用IP的登录计数
+pub struct TestApp {
+    pub pool: DbPool,
+    pub mailer: Arc<RecordingMailer>,
+    router: Router,
+}
+
+impl TestApp {
+    pub async fn new() -> Option<TestApp> {
+        Self::with_repository(Arc::new(UserStore)).await
+    }
+
+    pub async fn with_repository(users: SharedUserRepository) -> Option<TestApp> {
+        let pool = pool().await?;
+        let mut rng = rand::thread_rng();
+        let addr = SocketAddr::from(([10, rng.gen(), rng.gen(), rng.gen_range(1..255)], 40000));
+
+        let mailer = Arc::new(RecordingMailer::default());
+        let shared_mailer: SharedMailer = mailer.clone();
+        let router = crate::router::create_router(
+            pool.clone(),
+            shared_mailer,
+            Arc::new(crate::metrics::Metrics::default()),
+            Arc::new(crate::model::passkey::create_webauthn().unwrap()),
+            reqwest::Client::new(),
+            crate::event_hub::UserEventHub::start(pool.clone()),
+            users,
+        )
+        .layer(MockConnectInfo(addr));
+
+        Some(TestApp { pool, mailer, router })
+    }
+
+    // 发送请求，token为会话令牌、访问令牌或API密钥
+    pub async fn request(&self, method: Method, uri: &str, token: Option<&str>, body: Option<Value>) -> TestResponse {
+        self.send(method, uri, token, &[], body).await
+    }
+
+    pub async fn send(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        headers: &[(&str, &str)],
+        body: Option<Value>,
+    ) -> TestResponse {
+        let mut builder = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let request = match body {
+            Some(body) => builder
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+            None => builder.body(Body::empty()).unwrap(),
+        };
+        self.call(request).await
+    }
+
+    pub async fn call(&self, request: Request<Body>) -> TestResponse {
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+        TestResponse { status, headers, body }
+    }
+
+    // 密码登录并返回会话令牌，要求不需要两步验证
+    pub async fn login(&self, email: &str) -> String {
+        let response = self
+            .request(Method::POST, "/auth/login", None, Some(serde_json::json!({ "email": email, "password": PASSWORD })))
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "登录失败: {}", response.body);
+        response.body["token"].as_str().expect("登录响应缺少token").to_string()
+    }
+}
+
+// 默认组织（平台）的ID
+pub async fn default_organization(pool: &DbPool) -> Uuid {
+    crate::model::organization::OrganizationStore::default_id(pool).await.unwrap()
+}
+
+// 创建组织，返回ID和slug
+pub async fn create_organization(pool: &DbPool) -> (Uuid, String) {
+    let slug = unique("org");
+    let id = sqlx::query_scalar("INSERT INTO organizations (slug, name) VALUES ($1, $2) RETURNING id")
+        .bind(&slug)
+        .bind("测试组织")
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    (id, slug)
+}
+
+// 在组织中创建用户，密码为PASSWORD
+pub async fn create_user(pool: &DbPool, organization_id: Uuid, role: &str) -> User {
+    let mut tx = pool.begin().await.unwrap();
+    let user = UserStore::create(&mut tx, organization_id, &CreateUserRequest {
+        name: "测试用户".to_string(),
+        email: format!("{}@example.com", unique("user")),
+        password: PASSWORD.to_string(),
+        role: Some(role.to_string()),
+        group_ids: Vec::new(),
+    })
+    .await
+    .unwrap();
+    tx.commit().await.unwrap();
+    user
+}
+
+// 通过接口为当前会话的用户启用两步验证，返回TOTP密钥
+pub async fn enable_totp(app: &TestApp, token: &str, email: &str) -> String {
+    let response = app.request(Method::POST, "/auth/2fa/enroll", Some(token), None).await;
+    assert_eq!(response.status, StatusCode::OK, "绑定两步验证失败: {}", response.body);
+    let secret = response.body["secret"].as_str().unwrap().to_string();
+
+    let code = totp_code(&secret, email, 0);
+    let response = app
+        .request(Method::POST, "/auth/2fa/confirm", Some(token), Some(serde_json::json!({ "code": code })))
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "确认两步验证失败: {}", response.body);
+    secret
+}
+
+// 计算当前时间偏移offset个步长的验证码
+pub fn totp_code(secret: &str, email: &str, offset: i64) -> String {
+    let bytes = totp_rs::Secret::Encoded(secret.to_string()).to_bytes().unwrap();
+    let totp = totp_rs::TOTP::new(totp_rs::Algorithm::SHA1, 6, 1, 30, bytes, None, email.to_string()).unwrap();
+    let time = chrono::Utc::now().timestamp() + offset * 30;
+    totp.generate(time as u64)
+}
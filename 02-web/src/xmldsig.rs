@@ -0,0 +1,377 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use openssl::{hash::MessageDigest, sha::sha256, sign::Verifier, x509::X509};
+use roxmltree::{Node, NodeId};
+use thiserror::Error;
+
+pub const NS_DSIG: &str = "http://www.w3.org/2000/09/xmldsig#";
+const NS_EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+const ALG_EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+const ALG_ENVELOPED_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+const ALG_RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+const ALG_SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+
+// XML签名错误类型
+#[derive(Error, Debug)]
+pub enum SignatureError {
+    #[error("缺少签名")]
+    Missing,
+    #[error("签名格式错误: {0}")]
+    Malformed(&'static str),
+    #[error("不支持的算法: {0}")]
+    UnsupportedAlgorithm(String),
+    #[error("签名引用的元素ID不唯一")]
+    DuplicateId,
+    #[error("摘要不匹配")]
+    DigestMismatch,
+    #[error("签名校验失败")]
+    InvalidSignature,
+}
+
+// 查找指定命名空间和名称的子元素
+pub fn child<'a, 'input>(node: Node<'a, 'input>, ns: &str, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name((ns, name)))
+}
+
+// 元素是否直接带有签名
+pub fn has_signature(element: Node) -> bool {
+    child(element, NS_DSIG, "Signature").is_some()
+}
+
+// 校验元素上的封装签名（enveloped signature），签名必须引用该元素自身的ID
+// 只支持exc-c14n + rsa-sha256 + sha256，证书为Base64编码的DER
+pub fn verify_element(element: Node, certificates: &[String]) -> Result<(), SignatureError> {
+    let id = element.attribute("ID").ok_or(SignatureError::Malformed("元素缺少ID"))?;
+    let signature = child(element, NS_DSIG, "Signature").ok_or(SignatureError::Missing)?;
+
+    // 同一ID出现多次时无法确定签名覆盖的是哪个元素，直接拒绝，防止签名包装攻击
+    let same_id = element
+        .document()
+        .descendants()
+        .filter(|node| node.attribute("ID") == Some(id))
+        .count();
+    if same_id != 1 {
+        return Err(SignatureError::DuplicateId);
+    }
+
+    let signed_info = child(signature, NS_DSIG, "SignedInfo").ok_or(SignatureError::Malformed("缺少SignedInfo"))?;
+    let c14n_method = child(signed_info, NS_DSIG, "CanonicalizationMethod")
+        .ok_or(SignatureError::Malformed("缺少CanonicalizationMethod"))?;
+    expect_algorithm(c14n_method, ALG_EXC_C14N)?;
+    let signature_method = child(signed_info, NS_DSIG, "SignatureMethod")
+        .ok_or(SignatureError::Malformed("缺少SignatureMethod"))?;
+    expect_algorithm(signature_method, ALG_RSA_SHA256)?;
+
+    let mut references = signed_info.children().filter(|node| node.has_tag_name((NS_DSIG, "Reference")));
+    let reference = references.next().ok_or(SignatureError::Malformed("缺少Reference"))?;
+    if references.next().is_some() {
+        return Err(SignatureError::Malformed("只支持一个Reference"));
+    }
+    if reference.attribute("URI") != Some(format!("#{}", id).as_str()) {
+        return Err(SignatureError::Malformed("Reference未指向被签名的元素"));
+    }
+
+    // 只接受封装签名转换和exc-c14n转换
+    let transforms = child(reference, NS_DSIG, "Transforms").ok_or(SignatureError::Malformed("缺少Transforms"))?;
+    let mut enveloped = false;
+    let mut reference_prefixes = None;
+    for transform in transforms.children().filter(|node| node.is_element()) {
+        match transform.attribute("Algorithm") {
+            Some(ALG_ENVELOPED_SIGNATURE) => enveloped = true,
+            Some(ALG_EXC_C14N) => reference_prefixes = Some(inclusive_prefixes(transform)),
+            other => {
+                return Err(SignatureError::UnsupportedAlgorithm(other.unwrap_or_default().to_string()));
+            }
+        }
+    }
+    let reference_prefixes = match (enveloped, reference_prefixes) {
+        (true, Some(prefixes)) => prefixes,
+        _ => return Err(SignatureError::Malformed("必须使用enveloped-signature和exc-c14n转换")),
+    };
+
+    let digest_method = child(reference, NS_DSIG, "DigestMethod").ok_or(SignatureError::Malformed("缺少DigestMethod"))?;
+    expect_algorithm(digest_method, ALG_SHA256)?;
+    let digest_value = decode_base64(child(reference, NS_DSIG, "DigestValue"))?;
+
+    let canonical = canonicalize(element, Some(signature.id()), &reference_prefixes);
+    if sha256(canonical.as_bytes()).as_slice() != digest_value.as_slice() {
+        return Err(SignatureError::DigestMismatch);
+    }
+
+    let signature_value = decode_base64(child(signature, NS_DSIG, "SignatureValue"))?;
+    let canonical_signed_info = canonicalize(signed_info, None, &inclusive_prefixes(c14n_method));
+
+    let verified = certificates.iter().any(|certificate| {
+        verify_with_certificate(certificate, canonical_signed_info.as_bytes(), &signature_value).unwrap_or(false)
+    });
+    if !verified {
+        return Err(SignatureError::InvalidSignature);
+    }
+
+    Ok(())
+}
+
+fn expect_algorithm(node: Node, expected: &str) -> Result<(), SignatureError> {
+    match node.attribute("Algorithm") {
+        Some(algorithm) if algorithm == expected => Ok(()),
+        other => Err(SignatureError::UnsupportedAlgorithm(other.unwrap_or_default().to_string())),
+    }
+}
+
+fn decode_base64(node: Option<Node>) -> Result<Vec<u8>, SignatureError> {
+    let text: String = node
+        .and_then(|node| node.text())
+        .ok_or(SignatureError::Malformed("缺少Base64内容"))?
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect();
+    STANDARD.decode(text).map_err(|_| SignatureError::Malformed("Base64内容无效"))
+}
+
+fn verify_with_certificate(certificate: &str, data: &[u8], signature: &[u8]) -> Result<bool, openssl::error::ErrorStack> {
+    let der = STANDARD.decode(certificate).unwrap_or_default();
+    let public_key = X509::from_der(&der)?.public_key()?;
+    let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key)?;
+    verifier.update(data)?;
+    verifier.verify(signature)
+}
+
+// exc-c14n转换中InclusiveNamespaces的PrefixList，#default表示默认命名空间
+fn inclusive_prefixes(transform: Node) -> Vec<String> {
+    child(transform, NS_EXC_C14N, "InclusiveNamespaces")
+        .and_then(|node| node.attribute("PrefixList"))
+        .map(|list| {
+            list.split_whitespace()
+                .map(|prefix| if prefix == "#default" { String::new() } else { prefix.to_string() })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// 排他XML规范化（Exclusive XML Canonicalization 1.0，不含注释）
+// exclude为封装签名转换中需要去掉的Signature元素
+pub fn canonicalize(element: Node, exclude: Option<NodeId>, inclusive_prefixes: &[String]) -> String {
+    let mut output = String::new();
+    write_element(&mut output, element, exclude, inclusive_prefixes, &[]);
+    output
+}
+
+// 元素或属性在原文中的限定名，用于取得原始前缀
+fn qname_of(input: &str, start: usize) -> &str {
+    let rest = &input[start..];
+    let end = rest
+        .find(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>' || c == '=')
+        .unwrap_or(rest.len());
+    &rest[..end]
+}
+
+fn prefix_of(qname: &str) -> &str {
+    qname.split_once(':').map(|(prefix, _)| prefix).unwrap_or("")
+}
+
+fn write_element(
+    output: &mut String,
+    node: Node,
+    exclude: Option<NodeId>,
+    inclusive_prefixes: &[String],
+    rendered: &[(String, String)],
+) {
+    let input = node.document().input_text();
+    let qname = qname_of(input, node.range().start + 1);
+
+    // 可见使用的命名空间：元素前缀、带前缀的属性，以及PrefixList中列出的前缀
+    let mut prefixes: Vec<String> = vec![prefix_of(qname).to_string()];
+    let mut attributes: Vec<(&str, &str, &str, String)> = Vec::new();
+    for attribute in node.attributes() {
+        let attribute_qname = &input[attribute.range_qname()];
+        let prefix = prefix_of(attribute_qname);
+        if !prefix.is_empty() {
+            prefixes.push(prefix.to_string());
+        }
+        attributes.push((
+            attribute.namespace().unwrap_or(""),
+            attribute.name(),
+            attribute_qname,
+            escape_attribute(attribute.value()),
+        ));
+    }
+    prefixes.extend(inclusive_prefixes.iter().cloned());
+    prefixes.sort();
+    prefixes.dedup();
+
+    let mut scope = rendered.to_vec();
+    let mut declarations = Vec::new();
+    for prefix in prefixes.into_iter().filter(|prefix| prefix != "xml") {
+        let uri = node
+            .namespaces()
+            .find(|ns| ns.name().unwrap_or("") == prefix)
+            .map(|ns| ns.uri().to_string());
+        // PrefixList中未声明的前缀不输出；未声明默认命名空间时视为空
+        let uri = match uri {
+            Some(uri) => uri,
+            None if prefix.is_empty() => String::new(),
+            None => continue,
+        };
+        let current = scope.iter().find(|(p, _)| *p == prefix).map(|(_, u)| u.as_str()).unwrap_or("");
+        let already_rendered = scope.iter().any(|(p, _)| *p == prefix);
+        if current == uri && (already_rendered || prefix.is_empty()) {
+            continue;
+        }
+        scope.retain(|(p, _)| *p != prefix);
+        scope.push((prefix.clone(), uri.clone()));
+        declarations.push((prefix, uri));
+    }
+
+    output.push('<');
+    output.push_str(qname);
+    for (prefix, uri) in &declarations {
+        if prefix.is_empty() {
+            output.push_str(&format!(" xmlns=\"{}\"", escape_attribute(uri)));
+        } else {
+            output.push_str(&format!(" xmlns:{}=\"{}\"", prefix, escape_attribute(uri)));
+        }
+    }
+    attributes.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+    for (_, _, attribute_qname, value) in &attributes {
+        output.push_str(&format!(" {}=\"{}\"", attribute_qname, value));
+    }
+    output.push('>');
+
+    for child in node.children() {
+        if Some(child.id()) == exclude {
+            continue;
+        }
+        if child.is_element() {
+            write_element(output, child, exclude, inclusive_prefixes, &scope);
+        } else if child.is_text() {
+            output.push_str(&escape_text(child.text().unwrap_or_default()));
+        } else if let Some(pi) = child.pi() {
+            output.push_str("<?");
+            output.push_str(pi.target);
+            if let Some(value) = pi.value {
+                output.push(' ');
+                output.push_str(value);
+            }
+            output.push_str("?>");
+        }
+    }
+
+    output.push_str("</");
+    output.push_str(qname);
+    output.push('>');
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\r', "&#xD;")
+}
+
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+        .replace('\t', "&#x9;")
+        .replace('\n', "&#xA;")
+        .replace('\r', "&#xD;")
+}
+
+#[cfg(test)]
+mod tests {
+    // 样例由 fixtures/xmldsig/generate.py 生成，签名不依赖本模块的规范化实现
+    use roxmltree::Document;
+    use super::*;
+
+    const IDP_CERT: &str = include_str!("../fixtures/xmldsig/idp.crt");
+    const OTHER_CERT: &str = include_str!("../fixtures/xmldsig/other.crt");
+
+    // 校验响应中第一个直接子断言，与SAML处理时读取的元素一致
+    fn verify_fixture(xml: &str, certificate: &str) -> Result<(), SignatureError> {
+        let doc = Document::parse(xml).unwrap();
+        let assertion = doc
+            .root_element()
+            .children()
+            .find(|node| node.tag_name().name() == "Assertion")
+            .unwrap();
+        verify_element(assertion, &[certificate.trim().to_string()])
+    }
+
+    #[test]
+    fn valid_signature_verifies() {
+        let xml = include_str!("../fixtures/xmldsig/valid.xml");
+        assert!(verify_fixture(xml, IDP_CERT).is_ok());
+        // 多个证书中任意一个匹配即可（IdP轮换证书期间）
+        let doc = Document::parse(xml).unwrap();
+        let assertion = doc.root_element().first_element_child().unwrap();
+        assert!(verify_element(assertion, &[OTHER_CERT.trim().to_string(), IDP_CERT.trim().to_string()]).is_ok());
+    }
+
+    #[test]
+    fn signature_from_other_certificate_is_rejected() {
+        let xml = include_str!("../fixtures/xmldsig/valid.xml");
+        assert!(matches!(verify_fixture(xml, OTHER_CERT), Err(SignatureError::InvalidSignature)));
+    }
+
+    #[test]
+    fn tampered_content_is_rejected() {
+        let xml = include_str!("../fixtures/xmldsig/tampered.xml");
+        assert!(matches!(verify_fixture(xml, IDP_CERT), Err(SignatureError::DigestMismatch)));
+    }
+
+    #[test]
+    fn tampered_signed_info_is_rejected() {
+        // 连同摘要一起替换：摘要与内容一致，但SignedInfo的签名不再匹配
+        let original = include_str!("../fixtures/xmldsig/valid.xml");
+        let doc = Document::parse(original).unwrap();
+        let assertion = doc.root_element().first_element_child().unwrap();
+        let canonical = canonicalize(
+            assertion,
+            child(assertion, NS_DSIG, "Signature").map(|node| node.id()),
+            &[],
+        )
+        .replace("alice@example.com", "mallory@example.com");
+        let old_digest = doc.descendants().find(|node| node.has_tag_name((NS_DSIG, "DigestValue"))).unwrap().text().unwrap();
+        let new_digest = STANDARD.encode(sha256(canonical.as_bytes()));
+        let forged = original.replace("alice@example.com", "mallory@example.com").replace(old_digest, &new_digest);
+
+        assert!(matches!(verify_fixture(&forged, IDP_CERT), Err(SignatureError::InvalidSignature)));
+    }
+
+    #[test]
+    fn wrapped_assertion_is_rejected() {
+        let xml = include_str!("../fixtures/xmldsig/wrapped.xml");
+        assert!(matches!(verify_fixture(xml, IDP_CERT), Err(SignatureError::Malformed(_))));
+    }
+
+    #[test]
+    fn duplicate_id_wrapping_is_rejected() {
+        let xml = include_str!("../fixtures/xmldsig/wrapped_duplicate_id.xml");
+        assert!(matches!(verify_fixture(xml, IDP_CERT), Err(SignatureError::DuplicateId)));
+    }
+
+    #[test]
+    fn unsigned_assertion_is_rejected() {
+        let xml = include_str!("../fixtures/xmldsig/unsigned.xml");
+        assert!(matches!(verify_fixture(xml, IDP_CERT), Err(SignatureError::Missing)));
+    }
+
+    #[test]
+    fn canonicalize_follows_exclusive_c14n() {
+        // 只输出可见使用的命名空间，属性按命名空间和名称排序，空元素展开，特殊字符转义
+        let doc = Document::parse(
+            r#"<a:root xmlns:a="urn:a" xmlns:b="urn:b" a:y="2" z="1"><a:child/><b:other flag="x&quot;y">1 &lt; 2</b:other></a:root>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            canonicalize(doc.root_element(), None, &[]),
+            r#"<a:root xmlns:a="urn:a" z="1" a:y="2"><a:child></a:child><b:other xmlns:b="urn:b" flag="x&quot;y">1 &lt; 2</b:other></a:root>"#,
+        );
+
+        // PrefixList中的前缀即使没有被使用也要输出
+        assert_eq!(
+            canonicalize(doc.root_element().first_element_child().unwrap(), None, &["b".to_string()]),
+            r#"<a:child xmlns:a="urn:a" xmlns:b="urn:b"></a:child>"#,
+        );
+    }
+}